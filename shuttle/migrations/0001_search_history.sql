@@ -0,0 +1,23 @@
+-- Searches made through the web app
+CREATE TABLE IF NOT EXISTS searches (
+    id BIGSERIAL PRIMARY KEY,
+    searched_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    query TEXT NOT NULL,
+    result_count BIGINT NOT NULL
+);
+
+-- Each paper once, keyed by its unversioned arXiv id
+CREATE TABLE IF NOT EXISTS papers (
+    arxiv_id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    metadata JSONB NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Which papers each search found, in result order
+CREATE TABLE IF NOT EXISTS search_papers (
+    search_id BIGINT NOT NULL REFERENCES searches (id) ON DELETE CASCADE,
+    arxiv_id TEXT NOT NULL REFERENCES papers (arxiv_id),
+    position INTEGER NOT NULL,
+    PRIMARY KEY (search_id, position)
+);
@@ -0,0 +1,21 @@
+-- Saved searches, re-run in the background
+CREATE TABLE IF NOT EXISTS watches (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    query TEXT NOT NULL,
+    categories TEXT[] NOT NULL DEFAULT '{}',
+    max_results INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- Number of completed refreshes
+    runs INTEGER NOT NULL DEFAULT 0,
+    last_run_at TIMESTAMPTZ
+);
+
+-- Every paper a watch has ever found, and the refresh that first found it
+CREATE TABLE IF NOT EXISTS watch_papers (
+    watch_id BIGINT NOT NULL REFERENCES watches (id) ON DELETE CASCADE,
+    arxiv_id TEXT NOT NULL,
+    metadata JSONB NOT NULL,
+    first_seen_run INTEGER NOT NULL,
+    PRIMARY KEY (watch_id, arxiv_id)
+);
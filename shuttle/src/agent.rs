@@ -0,0 +1,542 @@
+use anyhow::Context;
+use rig::{
+    agent::{Agent, AgentBuilder},
+    completion::{Chat, CompletionModel, Message, Prompt, PromptError},
+    extractor::ExtractionError,
+    providers::{anthropic, openai},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use shuttle_runtime::SecretStore;
+use std::sync::Arc;
+
+use crate::cache::{self, TtlCache};
+use crate::tools::{
+    self, ArxivSearchTool, ComparePapersTool, FindRelatedTool, LatestPapersTool, MultiSearchTool,
+    Paper, PaperComparison, Progress, SearchResult,
+};
+
+// Shared with the CLI, which includes the same file, so the two can't drift apart
+const PREAMBLE: &str = include_str!("preamble.txt");
+
+// Guidance for the tools only the web app registers
+const PREAMBLE_TOOLS: &str = "When asked what is new in a field, use the latest_arxiv tool with \
+                              its arXiv category and return only its raw JSON response.";
+
+const REPROMPT: &str = "Respond with only the raw JSON object returned by the search_arxiv tool, \
+                        without any other text.";
+
+const EXTRACT_PREAMBLE: &str = "Extract the arXiv search results (papers, total result count \
+                                and start offset) from the text.";
+
+const SUMMARY_PREAMBLE: &str = "You are a research assistant writing a short literature summary. \
+                                From the papers provided, write a few paragraphs of plain text \
+                                (no markdown, no JSON) covering the main themes, how the \
+                                approaches differ and what remains open. Refer to papers by \
+                                title. Use the search_arxiv tool only if essential context is \
+                                missing.";
+
+// Keep the summary prompt well inside the context window
+const MAX_SUMMARY_PAPERS: usize = 20;
+
+const DEFAULT_OLLAMA_URL: &str = "http://localhost:11434/v1";
+
+// Anthropic requires an explicit completion budget
+const ANTHROPIC_MAX_TOKENS: u64 = 4096;
+
+// The agent's instructions: ARXIV_AGENT_PREAMBLE if set, as text or the path of a file
+// holding it, otherwise the built-in preamble. A template, see render_preamble
+pub fn resolve_preamble(configured: Option<String>) -> anyhow::Result<String> {
+    let Some(configured) = configured.filter(|value| !value.trim().is_empty()) else {
+        return Ok(format!("{} {}", PREAMBLE.trim(), PREAMBLE_TOOLS));
+    };
+    let path = std::path::Path::new(configured.trim());
+    if path.is_file() {
+        return std::fs::read_to_string(path)
+            .with_context(|| format!("could not read the preamble from {}", path.display()));
+    }
+    Ok(configured)
+}
+
+// Fill in a preamble's {today} and {max_results}, with {{ and }} for literal braces. Any
+// other placeholder is an error, so a typo fails at startup instead of reaching the model
+pub fn render_preamble(template: &str, max_results: i32) -> anyhow::Result<String> {
+    let mut preamble = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        preamble.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        if let Some(after) = tail.strip_prefix("{{").or_else(|| tail.strip_prefix("}}")) {
+            preamble.push_str(&tail[..1]);
+            rest = after;
+            continue;
+        }
+        if tail.starts_with('}') {
+            anyhow::bail!("the preamble has a '}}' without a '{{', write '}}}}' for a literal one");
+        }
+        let Some(end) = tail.find('}') else {
+            anyhow::bail!("the preamble has a '{{' without a '}}', write '{{{{' for a literal one");
+        };
+        match &tail[1..end] {
+            "today" => preamble.push_str(&today()),
+            "max_results" => preamble.push_str(&max_results.to_string()),
+            other => anyhow::bail!(
+                "unknown placeholder {{{}}} in the preamble, expected {{today}} or {{max_results}}",
+                other
+            ),
+        }
+        rest = &tail[end + 1..];
+    }
+    preamble.push_str(rest);
+    Ok(preamble)
+}
+
+// Today's date in UTC, e.g. "16 Oct 2026", cut out of an HTTP date
+fn today() -> String {
+    httpdate::fmt_http_date(std::time::SystemTime::now())[5..16].to_string()
+}
+
+// LLM provider backing the research agent
+#[derive(Clone, Copy)]
+pub enum Provider {
+    Openai,
+    Anthropic,
+    Ollama,
+}
+
+impl std::str::FromStr for Provider {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "openai" => Ok(Provider::Openai),
+            "anthropic" => Ok(Provider::Anthropic),
+            "ollama" => Ok(Provider::Ollama),
+            other => anyhow::bail!(
+                "Unknown provider {:?} (expected openai, anthropic or ollama)",
+                other
+            ),
+        }
+    }
+}
+
+impl Provider {
+    // Model used when none is configured
+    pub fn default_model(self) -> &'static str {
+        match self {
+            Provider::Openai => openai::GPT_4,
+            Provider::Anthropic => anthropic::CLAUDE_3_5_SONNET,
+            Provider::Ollama => "llama3.1",
+        }
+    }
+}
+
+// Sampling temperature unless configured otherwise, low so the assistant reports what it
+// found instead of improvising
+pub const DEFAULT_TEMPERATURE: f64 = 0.2;
+
+// Sampling settings applied to every agent
+#[derive(Clone, Copy, Debug)]
+pub struct ModelParams {
+    pub temperature: f64,
+    // The provider's own limit when unset, except Anthropic's which needs one
+    pub max_tokens: Option<u64>,
+    pub top_p: Option<f64>,
+}
+
+impl Default for ModelParams {
+    fn default() -> Self {
+        Self {
+            temperature: DEFAULT_TEMPERATURE,
+            max_tokens: None,
+            top_p: None,
+        }
+    }
+}
+
+impl ModelParams {
+    // Out of range values are refused here, before any request could be rejected for them
+    pub fn validate(self) -> anyhow::Result<Self> {
+        if !(0.0..=2.0).contains(&self.temperature) {
+            anyhow::bail!(
+                "temperature must be between 0 and 2, got {}",
+                self.temperature
+            );
+        }
+        if let Some(top_p) = self.top_p.filter(|top_p| !(*top_p > 0.0 && *top_p <= 1.0)) {
+            anyhow::bail!("top_p must be above 0 and at most 1, got {}", top_p);
+        }
+        if self.max_tokens == Some(0) {
+            anyhow::bail!("max_tokens must be at least 1");
+        }
+        Ok(self)
+    }
+
+    // LLM_TEMPERATURE, LLM_MAX_TOKENS and LLM_TOP_P, each optional
+    pub fn from_secrets(secrets: &SecretStore) -> anyhow::Result<Self> {
+        let mut params = Self::default();
+        if let Some(temperature) = secrets.get("LLM_TEMPERATURE") {
+            params.temperature = temperature
+                .parse()
+                .with_context(|| format!("LLM_TEMPERATURE {:?} is not a number", temperature))?;
+        }
+        if let Some(max_tokens) = secrets.get("LLM_MAX_TOKENS") {
+            let max_tokens = max_tokens.parse().with_context(|| {
+                format!("LLM_MAX_TOKENS {:?} is not a whole number", max_tokens)
+            })?;
+            params.max_tokens = Some(max_tokens);
+        }
+        if let Some(top_p) = secrets.get("LLM_TOP_P") {
+            let top_p = top_p
+                .parse()
+                .with_context(|| format!("LLM_TOP_P {:?} is not a number", top_p))?;
+            params.top_p = Some(top_p);
+        }
+        params.validate()
+    }
+}
+
+// Lets the params go anywhere in an agent builder chain
+trait WithParams {
+    fn with_params(self, params: &ModelParams) -> Self;
+}
+
+impl<M: CompletionModel> WithParams for AgentBuilder<M> {
+    fn with_params(self, params: &ModelParams) -> Self {
+        let mut builder = self.temperature(params.temperature);
+        if let Some(max_tokens) = params.max_tokens {
+            builder = builder.max_tokens(max_tokens);
+        }
+        if let Some(top_p) = params.top_p {
+            builder = builder.additional_params(serde_json::json!({ "top_p": top_p }));
+        }
+        builder
+    }
+}
+
+// Client for whichever provider was selected, with the sampling settings for its agents
+#[derive(Clone)]
+pub enum ProviderClient {
+    OpenAi(openai::Client, ModelParams),
+    Anthropic(anthropic::Client, ModelParams),
+}
+
+impl ProviderClient {
+    pub fn from_secrets(provider: Provider, secrets: &SecretStore) -> Result<Self, anyhow::Error> {
+        let params = ModelParams::from_secrets(secrets)?;
+        let client = match provider {
+            Provider::Openai => {
+                let api_key = secrets
+                    .get("OPENAI_API_KEY")
+                    .context("OPENAI_API_KEY secret not found")?;
+                match secrets.get("OPENAI_BASE_URL") {
+                    Some(base_url) => ProviderClient::OpenAi(
+                        openai::Client::from_url(&api_key, &base_url),
+                        params,
+                    ),
+                    None => ProviderClient::OpenAi(openai::Client::new(&api_key), params),
+                }
+            }
+            Provider::Anthropic => {
+                let api_key = secrets
+                    .get("ANTHROPIC_API_KEY")
+                    .context("ANTHROPIC_API_KEY secret not found")?;
+                ProviderClient::Anthropic(anthropic::ClientBuilder::new(&api_key).build(), params)
+            }
+            Provider::Ollama => {
+                // Ollama serves an OpenAI-compatible API and ignores the key
+                let base_url = secrets
+                    .get("OLLAMA_BASE_URL")
+                    .unwrap_or_else(|| DEFAULT_OLLAMA_URL.to_string());
+                ProviderClient::OpenAi(openai::Client::from_url("ollama", &base_url), params)
+            }
+        };
+
+        Ok(client)
+    }
+
+    // Create agent with the arXiv tools, registered identically for every provider,
+    // with arXiv responses shared through `fetch_cache`
+    pub fn build_agent(
+        &self,
+        model: &str,
+        preamble: &str,
+        fetch_cache: Option<&Arc<TtlCache<Vec<u8>>>>,
+        progress: Option<Progress>,
+    ) -> ResearchAgent {
+        let mut search = ArxivSearchTool::new(cache::arxiv_source(fetch_cache));
+        if let Some(progress) = progress {
+            search = search.with_progress(progress);
+        }
+        let multi = MultiSearchTool::new(cache::arxiv_source(fetch_cache));
+        let latest = LatestPapersTool::new(cache::arxiv_source(fetch_cache));
+        let related = FindRelatedTool::new(cache::arxiv_source(fetch_cache));
+        let compare = ComparePapersTool::new(self.clone(), model)
+            .with_source(cache::arxiv_source(fetch_cache));
+        match self {
+            ProviderClient::OpenAi(client, params) => ResearchAgent::OpenAi(
+                client
+                    .agent(model)
+                    .preamble(preamble)
+                    .with_params(params)
+                    .tool(search)
+                    .tool(multi)
+                    .tool(latest)
+                    .tool(related)
+                    .tool(compare)
+                    .build(),
+            ),
+            ProviderClient::Anthropic(client, params) => ResearchAgent::Anthropic(
+                client
+                    .agent(model)
+                    .preamble(preamble)
+                    .max_tokens(ANTHROPIC_MAX_TOKENS)
+                    .with_params(params)
+                    .tool(search)
+                    .tool(multi)
+                    .tool(latest)
+                    .tool(related)
+                    .tool(compare)
+                    .build(),
+            ),
+        }
+    }
+
+    // Agent writing prose summaries, with the same arXiv tools available for context
+    pub fn build_summarizer(
+        &self,
+        model: &str,
+        fetch_cache: Option<&Arc<TtlCache<Vec<u8>>>>,
+    ) -> ResearchAgent {
+        let search = ArxivSearchTool::new(cache::arxiv_source(fetch_cache));
+        match self {
+            ProviderClient::OpenAi(client, params) => ResearchAgent::OpenAi(
+                client
+                    .agent(model)
+                    .preamble(SUMMARY_PREAMBLE)
+                    .with_params(params)
+                    .tool(search)
+                    .build(),
+            ),
+            ProviderClient::Anthropic(client, params) => ResearchAgent::Anthropic(
+                client
+                    .agent(model)
+                    .preamble(SUMMARY_PREAMBLE)
+                    .max_tokens(ANTHROPIC_MAX_TOKENS)
+                    .with_params(params)
+                    .tool(search)
+                    .build(),
+            ),
+        }
+    }
+
+    pub async fn extract<T>(
+        &self,
+        model: &str,
+        preamble: &str,
+        text: &str,
+    ) -> Result<T, ExtractionError>
+    where
+        T: schemars::JsonSchema + DeserializeOwned + Serialize + Send + Sync,
+    {
+        match self {
+            ProviderClient::OpenAi(client, _) => {
+                client
+                    .extractor::<T>(model)
+                    .preamble(preamble)
+                    .build()
+                    .extract(text)
+                    .await
+            }
+            ProviderClient::Anthropic(client, _) => {
+                client
+                    .extractor::<T>(model)
+                    .preamble(preamble)
+                    .build()
+                    .extract(text)
+                    .await
+            }
+        }
+    }
+}
+
+// Research agent for whichever provider was selected
+pub enum ResearchAgent {
+    OpenAi(Agent<openai::CompletionModel>),
+    Anthropic(Agent<anthropic::completion::CompletionModel>),
+}
+
+impl ResearchAgent {
+    pub async fn prompt(&self, prompt: &str) -> Result<String, PromptError> {
+        match self {
+            ResearchAgent::OpenAi(agent) => agent.prompt(prompt).await,
+            ResearchAgent::Anthropic(agent) => agent.prompt(prompt).await,
+        }
+    }
+
+    pub async fn chat(&self, prompt: &str, history: Vec<Message>) -> Result<String, PromptError> {
+        match self {
+            ResearchAgent::OpenAi(agent) => agent.chat(prompt, history).await,
+            ResearchAgent::Anthropic(agent) => agent.chat(prompt, history).await,
+        }
+    }
+}
+
+// What the agent gave back, once we've tried our best to read it as search results
+pub enum AgentAnswer {
+    Results(SearchResult),
+    // Boxed, it is many times the size of the other variants
+    Comparison(Box<PaperComparison>),
+    // The agent answered conversationally and no results could be recovered
+    Prose(String),
+}
+
+// Recover typed results from the agent's response: lenient JSON parsing, structured
+// extraction, then a single re-prompt before giving up and keeping the prose
+pub async fn interpret_response(
+    client: &ProviderClient,
+    model: &str,
+    agent: &ResearchAgent,
+    prompt: &str,
+    response: String,
+) -> AgentAnswer {
+    if let Ok(result) = tools::parse_agent_json::<SearchResult>(&response) {
+        return AgentAnswer::Results(result);
+    }
+    if let Ok(comparison) = tools::parse_agent_json::<PaperComparison>(&response) {
+        return AgentAnswer::Comparison(Box::new(comparison));
+    }
+
+    // The agent sometimes drops the wrapper and returns just the papers
+    let papers = tools::extract_json_array(&response).map(serde_json::from_str::<Vec<Paper>>);
+    if let Ok(Ok(papers)) = papers {
+        return AgentAnswer::Results(SearchResult {
+            total_results: papers.len(),
+            start: 0,
+            papers,
+            related_to: None,
+        });
+    }
+
+    match client
+        .extract::<SearchResult>(model, EXTRACT_PREAMBLE, &response)
+        .await
+    {
+        Ok(result) if !result.papers.is_empty() => return AgentAnswer::Results(result),
+        Ok(_) => (),
+        Err(e) => tracing::warn!("Structured extraction failed: {}", e),
+    }
+
+    let history = vec![
+        Message {
+            role: "user".to_string(),
+            content: prompt.to_string(),
+        },
+        Message {
+            role: "assistant".to_string(),
+            content: response.clone(),
+        },
+    ];
+    match agent.chat(REPROMPT, history).await {
+        Ok(retry) => {
+            if let Ok(result) = tools::parse_agent_json::<SearchResult>(&retry) {
+                return AgentAnswer::Results(result);
+            }
+        }
+        Err(e) => tracing::warn!("Re-prompting the agent failed: {}", e),
+    }
+
+    AgentAnswer::Prose(response)
+}
+
+// Plain-text literature summary of the papers
+pub async fn summarize_papers(
+    summarizer: &ResearchAgent,
+    papers: &[Paper],
+) -> Result<String, PromptError> {
+    let papers = &papers[..papers.len().min(MAX_SUMMARY_PAPERS)];
+    let prompt = serde_json::to_string(papers).unwrap_or_default();
+    summarizer.prompt(&prompt).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_the_default_preamble_with_the_configured_one() {
+        let path =
+            std::env::temp_dir().join(format!("arxiv-agent-preamble-{}.txt", std::process::id()));
+        std::fs::write(&path, "Only cite papers from {today}.").unwrap();
+        let file = resolve_preamble(Some(path.display().to_string()));
+        std::fs::remove_file(&path).unwrap();
+
+        let default = resolve_preamble(None).unwrap();
+        assert!(default.starts_with(PREAMBLE.trim()), "{}", default);
+        assert!(default.ends_with(PREAMBLE_TOOLS));
+        assert_eq!(
+            resolve_preamble(Some("Answer in French.".to_string())).unwrap(),
+            "Answer in French."
+        );
+        assert_eq!(file.unwrap(), "Only cite papers from {today}.");
+        assert_eq!(resolve_preamble(Some("  ".to_string())).unwrap(), default);
+    }
+
+    #[test]
+    fn fills_in_placeholders_and_unescapes_braces() {
+        let preamble = render_preamble(
+            "Find {max_results} papers published before {today}. Answer as {{\"papers\": []}}",
+            7,
+        )
+        .unwrap();
+        assert_eq!(
+            preamble,
+            format!(
+                "Find 7 papers published before {}. Answer as {{\"papers\": []}}",
+                today()
+            )
+        );
+        // The shipped preamble is a valid template
+        assert!(render_preamble(PREAMBLE, 5).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders_and_unmatched_braces() {
+        for (template, error) in [
+            ("Search for {topic}", "unknown placeholder {topic}"),
+            ("Answer as {\"papers\": []}", "unknown placeholder"),
+            ("Answer in {today", "a '{' without a '}'"),
+            ("Answer in }", "a '}' without a '{'"),
+        ] {
+            let err = render_preamble(template, 5).unwrap_err().to_string();
+            assert!(err.contains(error), "{}: {}", template, err);
+        }
+    }
+
+    #[test]
+    fn accepts_sampling_settings_only_within_their_bounds() {
+        let params = |temperature, max_tokens, top_p| ModelParams {
+            temperature,
+            max_tokens,
+            top_p,
+        };
+        for valid in [
+            ModelParams::default(),
+            params(0.0, Some(1), Some(1.0)),
+            params(2.0, None, Some(0.01)),
+        ] {
+            assert!(valid.validate().is_ok(), "{:?}", valid);
+        }
+        for (invalid, error) in [
+            (params(-0.1, None, None), "temperature"),
+            (params(2.1, None, None), "temperature"),
+            (params(f64::NAN, None, None), "temperature"),
+            (params(0.2, Some(0), None), "max_tokens"),
+            (params(0.2, None, Some(0.0)), "top_p"),
+            (params(0.2, None, Some(1.5)), "top_p"),
+            (params(0.2, None, Some(f64::NAN)), "top_p"),
+        ] {
+            let err = invalid.validate().unwrap_err().to_string();
+            assert!(err.starts_with(error), "{:?}: {}", invalid, err);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::tools::{self, ArxivSource, FetchFuture, SortBy};
+
+pub const DEFAULT_TTL: Duration = Duration::from_secs(10 * 60);
+pub const DEFAULT_MAX_ENTRIES: usize = 256;
+
+// Values shared between requests until they are older than the TTL
+pub struct TtlCache<V> {
+    entries: Mutex<HashMap<String, (Instant, V)>>,
+    ttl: Duration,
+    max_entries: usize,
+}
+
+impl<V: Clone> TtlCache<V> {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            ttl,
+            max_entries,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((inserted, value)) if inserted.elapsed() < self.ttl => Some(value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, key: String, value: V) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            entries.retain(|_, (inserted, _)| inserted.elapsed() < self.ttl);
+        }
+        // Still full of live entries, so make room by dropping the oldest
+        while entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (inserted, _))| *inserted)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(oldest) => entries.remove(&oldest),
+                None => break,
+            };
+        }
+        entries.insert(key, (Instant::now(), value));
+    }
+}
+
+// Key for a search, so trivially different spellings of the same query share an entry
+pub fn search_key(query: &str, max_results: Option<i32>, sort_by: Option<SortBy>) -> String {
+    let query = query.split_whitespace().collect::<Vec<_>>().join(" ");
+    format!(
+        "{}|{}|{}",
+        query.to_lowercase(),
+        max_results.map_or(String::new(), |n| n.to_string()),
+        sort_by.map_or("", SortBy::as_str)
+    )
+}
+
+// Source serving repeated arXiv fetches from a shared cache of raw responses
+pub struct CachedSource {
+    inner: Box<dyn ArxivSource>,
+    cache: Arc<TtlCache<Vec<u8>>>,
+}
+
+impl CachedSource {
+    pub fn new(inner: Box<dyn ArxivSource>, cache: Arc<TtlCache<Vec<u8>>>) -> Self {
+        Self { inner, cache }
+    }
+
+    fn fetch_cached<'a>(&'a self, key: String, fetch: FetchFuture<'a>) -> FetchFuture<'a> {
+        Box::pin(async move {
+            if let Some(response) = self.cache.get(&key) {
+                return Ok(response);
+            }
+            let response = fetch.await?;
+            self.cache.insert(key, response.clone());
+            Ok(response)
+        })
+    }
+}
+
+impl ArxivSource for CachedSource {
+    fn fetch_page<'a>(
+        &'a self,
+        search_query: &'a str,
+        start: usize,
+        max_results: i32,
+        sort_by: SortBy,
+    ) -> FetchFuture<'a> {
+        let key = format!(
+            "query:{}|{}|{}|{}",
+            search_query,
+            start,
+            max_results,
+            sort_by.as_str()
+        );
+        self.fetch_cached(
+            key,
+            self.inner
+                .fetch_page(search_query, start, max_results, sort_by),
+        )
+    }
+
+    fn fetch_by_id<'a>(&'a self, id: &'a str) -> FetchFuture<'a> {
+        self.fetch_cached(format!("id:{}", id), self.inner.fetch_by_id(id))
+    }
+}
+
+// arXiv source for one tool, sharing the raw response cache unless it is bypassed
+pub fn arxiv_source(cache: Option<&Arc<TtlCache<Vec<u8>>>>) -> Box<dyn ArxivSource> {
+    match cache {
+        Some(cache) => Box::new(CachedSource::new(tools::source_from_env(), cache.clone())),
+        None => tools::source_from_env(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::LiveSource;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn serves_a_repeated_fetch_without_asking_arxiv_again() {
+        let arxiv = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/query"))
+            .and(query_param("search_query", "all:attention"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<feed>attention</feed>"))
+            .expect(1)
+            .mount(&arxiv)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/query"))
+            .and(query_param("search_query", "all:bert"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<feed>bert</feed>"))
+            .expect(1)
+            .mount(&arxiv)
+            .await;
+        let cache = Arc::new(TtlCache::new(DEFAULT_TTL, DEFAULT_MAX_ENTRIES));
+        let source = CachedSource::new(
+            Box::new(LiveSource::new(format!("{}/api/query", arxiv.uri()))),
+            cache.clone(),
+        );
+
+        for _ in 0..2 {
+            let response = source
+                .fetch_page("all:attention", 0, 10, SortBy::Relevance)
+                .await
+                .unwrap();
+            assert_eq!(response, b"<feed>attention</feed>");
+        }
+        // A different query is a different entry, fetched once too
+        let response = source
+            .fetch_page("all:bert", 0, 10, SortBy::Relevance)
+            .await
+            .unwrap();
+        assert_eq!(response, b"<feed>bert</feed>");
+        // The cache is shared, so another tool's source gets the same response
+        let other = CachedSource::new(
+            Box::new(LiveSource::new(format!("{}/api/query", arxiv.uri()))),
+            cache,
+        );
+        other
+            .fetch_page("all:attention", 0, 10, SortBy::Relevance)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn forgets_entries_once_they_outlive_the_ttl() {
+        let cache = TtlCache::new(Duration::from_millis(50), DEFAULT_MAX_ENTRIES);
+        cache.insert("key".to_string(), 1);
+        assert_eq!(cache.get("key"), Some(1));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(cache.get("key"), None);
+    }
+
+    #[test]
+    fn drops_the_oldest_entry_when_full() {
+        let cache = TtlCache::new(DEFAULT_TTL, 2);
+        cache.insert("first".to_string(), 1);
+        cache.insert("second".to_string(), 2);
+        cache.insert("third".to_string(), 3);
+        assert_eq!(cache.get("first"), None);
+        assert_eq!(cache.get("second"), Some(2));
+        assert_eq!(cache.get("third"), Some(3));
+    }
+
+    #[test]
+    fn keys_searches_by_their_normalized_query() {
+        assert_eq!(
+            search_key("  Attention   is all ", Some(5), None),
+            search_key("attention IS all", Some(5), None)
+        );
+        assert_ne!(
+            search_key("attention", Some(5), None),
+            search_key("attention", Some(10), None)
+        );
+    }
+}
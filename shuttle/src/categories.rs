@@ -0,0 +1,210 @@
+// arXiv category codes and their names, for turning what users type into query filters
+const CATEGORIES: &[(&str, &str)] = &[
+    ("cs.AI", "Artificial Intelligence"),
+    ("cs.AR", "Hardware Architecture"),
+    ("cs.CC", "Computational Complexity"),
+    ("cs.CE", "Computational Engineering, Finance, and Science"),
+    ("cs.CG", "Computational Geometry"),
+    ("cs.CL", "Computation and Language"),
+    ("cs.CR", "Cryptography and Security"),
+    ("cs.CV", "Computer Vision and Pattern Recognition"),
+    ("cs.CY", "Computers and Society"),
+    ("cs.DB", "Databases"),
+    ("cs.DC", "Distributed, Parallel, and Cluster Computing"),
+    ("cs.DL", "Digital Libraries"),
+    ("cs.DM", "Discrete Mathematics"),
+    ("cs.DS", "Data Structures and Algorithms"),
+    ("cs.ET", "Emerging Technologies"),
+    ("cs.FL", "Formal Languages and Automata Theory"),
+    ("cs.GR", "Graphics"),
+    ("cs.GT", "Computer Science and Game Theory"),
+    ("cs.HC", "Human-Computer Interaction"),
+    ("cs.IR", "Information Retrieval"),
+    ("cs.IT", "Information Theory"),
+    ("cs.LG", "Machine Learning"),
+    ("cs.LO", "Logic in Computer Science"),
+    ("cs.MA", "Multiagent Systems"),
+    ("cs.MM", "Multimedia"),
+    ("cs.MS", "Mathematical Software"),
+    ("cs.NE", "Neural and Evolutionary Computing"),
+    ("cs.NI", "Networking and Internet Architecture"),
+    ("cs.OS", "Operating Systems"),
+    ("cs.PF", "Performance"),
+    ("cs.PL", "Programming Languages"),
+    ("cs.RO", "Robotics"),
+    ("cs.SC", "Symbolic Computation"),
+    ("cs.SD", "Sound"),
+    ("cs.SE", "Software Engineering"),
+    ("cs.SI", "Social and Information Networks"),
+    ("cs.SY", "Systems and Control"),
+    ("stat.AP", "Applications"),
+    ("stat.CO", "Computation"),
+    ("stat.ME", "Methodology"),
+    ("stat.ML", "Machine Learning (Statistics)"),
+    ("stat.TH", "Statistics Theory"),
+    ("math.NA", "Numerical Analysis"),
+    ("math.OC", "Optimization and Control"),
+    ("math.PR", "Probability"),
+    ("math.CO", "Combinatorics"),
+    ("math.LO", "Logic"),
+    ("eess.AS", "Audio and Speech Processing"),
+    ("eess.IV", "Image and Video Processing"),
+    ("eess.SP", "Signal Processing"),
+    ("eess.SY", "Systems and Control (EESS)"),
+    ("q-bio.NC", "Neurons and Cognition"),
+    ("q-bio.QM", "Quantitative Methods"),
+    ("q-fin.CP", "Computational Finance"),
+    ("q-fin.ST", "Statistical Finance"),
+    ("econ.EM", "Econometrics"),
+    ("physics.comp-ph", "Computational Physics"),
+    ("quant-ph", "Quantum Physics"),
+    ("astro-ph", "Astrophysics"),
+    ("cond-mat", "Condensed Matter"),
+    ("hep-th", "High Energy Physics - Theory"),
+    ("gr-qc", "General Relativity and Quantum Cosmology"),
+];
+
+// Shorthand people use for the common categories
+const ALIASES: &[(&str, &str)] = &[
+    ("ai", "cs.AI"),
+    ("ml", "cs.LG"),
+    ("cs.ml", "cs.LG"),
+    ("deep learning", "cs.LG"),
+    ("reinforcement learning", "cs.LG"),
+    ("nlp", "cs.CL"),
+    ("cs.nlp", "cs.CL"),
+    ("natural language processing", "cs.CL"),
+    ("computational linguistics", "cs.CL"),
+    ("cv", "cs.CV"),
+    ("computer vision", "cs.CV"),
+    ("vision", "cs.CV"),
+    ("security", "cs.CR"),
+    ("cryptography", "cs.CR"),
+    ("hci", "cs.HC"),
+    ("algorithms", "cs.DS"),
+    ("distributed systems", "cs.DC"),
+    ("networking", "cs.NI"),
+    ("search", "cs.IR"),
+    ("multi-agent systems", "cs.MA"),
+    ("pl", "cs.PL"),
+    ("se", "cs.SE"),
+    ("statistics", "stat.ML"),
+    ("optimization", "math.OC"),
+    ("speech", "eess.AS"),
+    ("signal processing", "eess.SP"),
+    ("neuroscience", "q-bio.NC"),
+    ("quantum", "quant-ph"),
+    ("quantum computing", "quant-ph"),
+];
+
+// arXiv category code for a code, name or alias, tolerating case and small typos
+pub fn resolve_category(input: &str) -> Option<&'static str> {
+    let wanted = normalize(input);
+    if wanted.is_empty() {
+        return None;
+    }
+
+    let exact = CATEGORIES
+        .iter()
+        .find(|(code, name)| code.to_lowercase() == wanted || normalize(name) == wanted)
+        .map(|(code, _)| *code)
+        .or_else(|| {
+            ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == wanted)
+                .map(|(_, code)| *code)
+        });
+    if exact.is_some() {
+        return exact;
+    }
+
+    // Only names are matched loosely, codes and short inputs are too close to each other
+    let wanted_chars = wanted.chars().count();
+    if wanted_chars < 5 {
+        return None;
+    }
+    let max_distance = if wanted_chars > 8 { 2 } else { 1 };
+    let names = CATEGORIES
+        .iter()
+        .map(|(code, name)| (normalize(name), *code))
+        .chain(ALIASES.iter().map(|(alias, code)| (alias.to_string(), *code)));
+    let mut best: Option<(usize, &'static str)> = None;
+    let mut ambiguous = false;
+    for (name, code) in names {
+        let distance = edit_distance(&wanted, &name);
+        if distance > max_distance {
+            continue;
+        }
+        match best {
+            Some((best_distance, best_code)) if distance == best_distance => {
+                ambiguous |= best_code != code;
+            }
+            Some((best_distance, _)) if distance > best_distance => (),
+            _ => {
+                best = Some((distance, code));
+                ambiguous = false;
+            }
+        }
+    }
+    best.filter(|_| !ambiguous).map(|(_, code)| code)
+}
+
+// Category filter value for user input, passing unknown input through unchanged
+pub fn category_code(input: &str) -> String {
+    match resolve_category(input) {
+        Some(code) => code.to_string(),
+        None => {
+            tracing::debug!(category = input, "unknown arXiv category, using it as given");
+            input.trim().to_string()
+        }
+    }
+}
+
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Levenshtein distance over chars
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_codes_names_and_aliases_to_a_code() {
+        let cases = [
+            ("cs.CL", "cs.CL"),
+            (" CS.cl ", "cs.CL"),
+            ("Computation and Language", "cs.CL"),
+            ("computation  and language", "cs.CL"),
+            ("nlp", "cs.CL"),
+            ("Machine Learning", "cs.LG"),
+            ("Machine Learning (Statistics)", "stat.ML"),
+            // A small typo in a name is still that name
+            ("Computation and Langauge", "cs.CL"),
+            // Unknown input goes through as typed
+            ("q-fin.XX", "q-fin.XX"),
+        ];
+        for (input, code) in cases {
+            assert_eq!(category_code(input), code, "{:?}", input);
+        }
+        assert_eq!(resolve_category("q-fin.XX"), None);
+        assert_eq!(resolve_category("  "), None);
+    }
+}
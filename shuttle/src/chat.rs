@@ -0,0 +1,152 @@
+use axum::{
+    extract::{
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+};
+use rig::completion::Message;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::Instrument;
+
+use crate::agent::{self, ResearchAgent};
+use crate::tools::{self, Paper, SearchResult};
+use crate::{AppError, AppState};
+
+// Messages kept per session, older ones are dropped to bound the prompt size
+const MAX_CHAT_MESSAGES: usize = 20;
+
+// Frames the client sends
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientFrame {
+    User { content: String },
+}
+
+// Frames the server sends back
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ChatFrame {
+    Assistant { content: String },
+    Papers { papers: Vec<Paper> },
+    Error { error: &'static str, detail: String },
+}
+
+impl From<AppError> for ChatFrame {
+    fn from(err: AppError) -> Self {
+        ChatFrame::Error {
+            error: err.kind(),
+            detail: err.detail(),
+        }
+    }
+}
+
+// Handler for GET /ws/chat
+#[utoipa::path(
+    get,
+    path = "/ws/chat",
+    responses((status = 101, description = "Switched to a WebSocket taking user frames \
+        ({\"type\": \"user\", \"content\": ...}) and answering with assistant, papers or \
+        error frames. Browsers send the API key as the subprotocols [\"bearer\", key]")),
+)]
+pub async fn chat_socket(ws: WebSocketUpgrade, State(state): State<Arc<AppState>>) -> Response {
+    // Browsers close the socket unless one of the subprotocols they offered is accepted
+    ws.protocols(["bearer"]).on_upgrade(move |socket| {
+        run_session(socket, state).instrument(tracing::info_span!("chat"))
+    })
+}
+
+// One conversation: the agent and its history live as long as the socket
+async fn run_session(mut socket: WebSocket, state: Arc<AppState>) {
+    // Checked when the app started, so this never falls back
+    let preamble = agent::render_preamble(&state.preamble, tools::clamp_max_results(None))
+        .unwrap_or_else(|_| state.preamble.clone());
+    let agent = state
+        .client
+        .build_agent(&state.model, &preamble, Some(&state.fetches), None);
+    let mut history = Vec::new();
+
+    while let Some(message) = socket.recv().await {
+        let text = match message {
+            Ok(WsMessage::Text(text)) => text,
+            Ok(WsMessage::Close(_)) | Err(_) => break,
+            // Pings are answered by axum, and there's nothing to do with binary frames
+            Ok(_) => continue,
+        };
+        let frames = match user_message(&text, state.max_query_chars) {
+            Ok(content) => reply(&agent, &mut history, content).await,
+            Err(err) => vec![err.into()],
+        };
+        for frame in frames {
+            let Ok(frame) = serde_json::to_string(&frame) else {
+                continue;
+            };
+            if socket.send(WsMessage::Text(frame)).await.is_err() {
+                return;
+            }
+        }
+    }
+    tracing::debug!(messages = history.len(), "chat session closed");
+}
+
+// The user's message from a frame, held to the same limits as a search query
+fn user_message(text: &str, max_chars: usize) -> Result<String, AppError> {
+    let ClientFrame::User { content } = serde_json::from_str(text).map_err(|e| {
+        AppError::BadRequest(format!("Expected a {{\"type\": \"user\"}} frame: {}", e))
+    })?;
+    let content = content.trim().to_string();
+    if content.is_empty() {
+        return Err(AppError::BadRequest("The message is empty".to_string()));
+    }
+    let chars = content.chars().count();
+    if chars > max_chars {
+        return Err(AppError::BadRequest(format!(
+            "The message is {} characters long, the limit is {}",
+            chars, max_chars
+        )));
+    }
+    Ok(content)
+}
+
+// Prompt the agent with the conversation so far and turn its answer into frames
+async fn reply(
+    agent: &ResearchAgent,
+    history: &mut Vec<Message>,
+    content: String,
+) -> Vec<ChatFrame> {
+    let response = match agent.chat(&content, history.clone()).await {
+        Ok(response) => response,
+        Err(e) => {
+            let err = AppError::from(e);
+            tracing::warn!(error = %err.detail(), "chat prompt failed");
+            return vec![err.into()];
+        }
+    };
+
+    history.push(Message {
+        role: "user".to_string(),
+        content,
+    });
+    history.push(Message {
+        role: "assistant".to_string(),
+        content: response.clone(),
+    });
+    if history.len() > MAX_CHAT_MESSAGES {
+        history.drain(..history.len() - MAX_CHAT_MESSAGES);
+    }
+
+    match response_papers(&response) {
+        Some(papers) => vec![ChatFrame::Papers { papers }],
+        None => vec![ChatFrame::Assistant { content: response }],
+    }
+}
+
+// Papers in the agent's answer, if it answered with search results
+fn response_papers(response: &str) -> Option<Vec<Paper>> {
+    if let Ok(result) = tools::parse_agent_json::<SearchResult>(response) {
+        return Some(result.papers);
+    }
+    let json = tools::extract_json_array(response).ok()?;
+    serde_json::from_str(json).ok()
+}
@@ -0,0 +1,203 @@
+use serde::Serialize;
+use sqlx::{types::Json, PgPool};
+
+use crate::tools::{self, Paper};
+
+// One past search, with the papers it found in their original order
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct HistoryEntry {
+    // Unix timestamp in seconds
+    pub searched_at: i64,
+    pub query: String,
+    pub result_count: i64,
+    pub papers: Vec<Paper>,
+}
+
+#[derive(sqlx::FromRow)]
+struct SearchRow {
+    id: i64,
+    searched_at: i64,
+    query: String,
+    result_count: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct SearchPaperRow {
+    search_id: i64,
+    metadata: Json<Paper>,
+}
+
+// Searches made through the web app and the papers they found, kept in Postgres
+#[derive(Clone)]
+pub struct History {
+    pool: PgPool,
+}
+
+impl History {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn record(&self, query: &str, papers: &[Paper]) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let (search_id,): (i64,) = sqlx::query_as(
+            "INSERT INTO searches (query, result_count) VALUES ($1, $2) RETURNING id",
+        )
+        .bind(query)
+        .bind(papers.len() as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        for (position, paper) in papers.iter().enumerate() {
+            // Keyed by the unversioned id, so a paper found by many searches is stored once
+            let arxiv_id = tools::base_arxiv_id(&paper.url);
+            sqlx::query(
+                "INSERT INTO papers (arxiv_id, title, metadata) VALUES ($1, $2, $3)
+                 ON CONFLICT (arxiv_id) DO UPDATE
+                 SET title = EXCLUDED.title, metadata = EXCLUDED.metadata, updated_at = now()",
+            )
+            .bind(arxiv_id)
+            .bind(&paper.title)
+            .bind(Json(paper))
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query(
+                "INSERT INTO search_papers (search_id, arxiv_id, position) VALUES ($1, $2, $3)
+                 ON CONFLICT DO NOTHING",
+            )
+            .bind(search_id)
+            .bind(arxiv_id)
+            .bind(position as i32)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await
+    }
+
+    // Most recent searches first
+    pub async fn recent(&self, limit: u32) -> Result<Vec<HistoryEntry>, sqlx::Error> {
+        let searches: Vec<SearchRow> = sqlx::query_as(
+            "SELECT id, EXTRACT(EPOCH FROM searched_at)::BIGINT AS searched_at, query, result_count
+             FROM searches ORDER BY id DESC LIMIT $1",
+        )
+        .bind(i64::from(limit))
+        .fetch_all(&self.pool)
+        .await?;
+
+        let ids: Vec<i64> = searches.iter().map(|search| search.id).collect();
+        let papers: Vec<SearchPaperRow> = sqlx::query_as(
+            "SELECT search_papers.search_id, papers.metadata
+             FROM search_papers JOIN papers USING (arxiv_id)
+             WHERE search_papers.search_id = ANY($1)
+             ORDER BY search_papers.search_id, search_papers.position",
+        )
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(entries(searches, papers))
+    }
+}
+
+// Each search with its papers, both lists kept in the order the queries returned them
+fn entries(searches: Vec<SearchRow>, papers: Vec<SearchPaperRow>) -> Vec<HistoryEntry> {
+    searches
+        .into_iter()
+        .map(|search| HistoryEntry {
+            searched_at: search.searched_at,
+            query: search.query,
+            result_count: search.result_count,
+            papers: papers
+                .iter()
+                .filter(|paper| paper.search_id == search.id)
+                .map(|paper| paper.metadata.0.clone())
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paper(id: &str, title: &str) -> Paper {
+        Paper {
+            url: format!("http://arxiv.org/abs/{}", id),
+            ..Paper::fixture(title)
+        }
+    }
+
+    fn titles(entry: &HistoryEntry) -> Vec<&str> {
+        entry
+            .papers
+            .iter()
+            .map(|paper| paper.title.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn puts_each_paper_under_the_search_that_found_it() {
+        let search = |id, query: &str| SearchRow {
+            id,
+            searched_at: 1_700_000_000 + id,
+            query: query.to_string(),
+            result_count: 2,
+        };
+        let found = |search_id, paper| SearchPaperRow {
+            search_id,
+            metadata: Json(paper),
+        };
+        let entries = entries(
+            vec![search(2, "bert"), search(1, "attention")],
+            vec![
+                found(1, paper("1706.03762v7", "Attention Is All You Need")),
+                found(1, paper("1810.04805v2", "BERT")),
+                found(2, paper("1810.04805v2", "BERT")),
+            ],
+        );
+
+        assert_eq!(entries[0].query, "bert");
+        assert_eq!(titles(&entries[0]), ["BERT"]);
+        assert_eq!(entries[1].searched_at, 1_700_000_001);
+        assert_eq!(titles(&entries[1]), ["Attention Is All You Need", "BERT"]);
+    }
+
+    // Creates a scratch database from migrations/, so it needs DATABASE_URL, e.g.
+    // DATABASE_URL=postgres://localhost/postgres cargo test -- --ignored
+    #[ignore = "needs a Postgres server at DATABASE_URL"]
+    #[sqlx::test]
+    async fn reads_back_what_it_recorded(pool: PgPool) {
+        let history = History::new(pool);
+        let attention = paper("1706.03762v7", "Attention Is All You Need");
+        history
+            .record(
+                "attention",
+                &[attention.clone(), paper("1810.04805v1", "BERT")],
+            )
+            .await
+            .unwrap();
+        // A newer version of a paper replaces the stored one, for every search that found it
+        let bert = paper(
+            "1810.04805v2",
+            "BERT: Pre-training of Deep Bidirectional Transformers",
+        );
+        history.record("bert", &[bert]).await.unwrap();
+
+        let recent = history.recent(10).await.unwrap();
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].query, "bert");
+        assert_eq!(recent[0].result_count, 1);
+        assert_eq!(recent[1].query, "attention");
+        assert_eq!(
+            titles(&recent[1]),
+            [
+                "Attention Is All You Need",
+                "BERT: Pre-training of Deep Bidirectional Transformers"
+            ]
+        );
+        assert_eq!(recent[1].papers[0].url, attention.url);
+
+        assert_eq!(history.recent(1).await.unwrap().len(), 1);
+    }
+}
@@ -1,41 +1,458 @@
+use anyhow::Context;
 use axum::{
-    extract::{State, Json},
-    response::{IntoResponse, Response, Html},
+    body::Body,
+    extract::{ConnectInfo, Request, State, Json, Path, Query},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response, Html,
+    },
     routing::{get, post},
     Router,
 };
 
 use tower_http::cors::{CorsLayer, Any};
+use tower_http::services::ServeDir;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tower_http::LatencyUnit;
+use tracing::Instrument;
+use utoipa::{IntoParams, ToSchema};
 
-use rig::{
-    providers::openai::{self, GPT_4},
-    completion::Prompt,
-};
+use futures::Stream;
+use std::convert::Infallible;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use anyhow::Context;
-use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Semaphore, SemaphorePermit};
+use rig::{completion::PromptError, extractor::ExtractionError};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 
 use shuttle_runtime::SecretStore;
 
+mod agent;
+mod cache;
+mod categories;
+mod chat;
+mod history;
+mod openapi;
+mod rate_limit;
 mod tools;
-use tools::{ArxivSearchTool, Paper};
+mod watches;
+use agent::{AgentAnswer, Provider, ProviderClient};
+use cache::TtlCache;
+use history::History;
+use rate_limit::RateLimiter;
+use tools::{GroupBy, Paper, Progress, SearchResult, SortBy, SortKey};
+use watches::{NewWatch, Watch, Watches};
+
+const DEFAULT_MAX_QUERY_CHARS: usize = 500;
+
+// Searches that may be waiting on the agent at once, beyond which they're turned away
+const DEFAULT_MAX_CONCURRENT_SEARCHES: usize = 8;
+
+const MAX_HISTORY_LIMIT: u32 = 100;
+
+// How long a readiness probe result is reused
+const READINESS_CACHE_TTL: Duration = Duration::from_secs(60);
+
+// arXiv rejects requests for more results than this
+const MAX_RESULTS_LIMIT: i32 = 2000;
 
 // Request structure for search endpoint
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct SearchRequest {
     query: String,
+    #[serde(default)]
+    max_results: Option<i32>,
+    #[serde(default)]
+    sort_by: Option<SortBy>,
+    // Re-sorts the papers found, applied to cached results too
+    #[serde(default)]
+    sort: Option<SortKey>,
+    // Sections in the HTML results, e.g. one per primary category
+    #[serde(default)]
+    group_by: Option<GroupBy>,
+    // Output format, overridden by the `format` query parameter and overriding Accept
+    #[serde(default)]
+    #[schema(value_type = Option<OutputFormat>)]
+    format: Option<String>,
+}
+
+impl SearchRequest {
+    // Clean up the query before it goes into a prompt, rejecting what can't be searched for
+    fn validate(mut self, max_query_chars: usize) -> Result<Self, AppError> {
+        self.query = self
+            .query
+            .chars()
+            .filter(|c| !c.is_control())
+            .collect::<String>()
+            .trim()
+            .to_string();
+        if self.query.is_empty() {
+            return Err(AppError::BadRequest("The search query is empty".to_string()));
+        }
+        let query_chars = self.query.chars().count();
+        if query_chars > max_query_chars {
+            return Err(AppError::BadRequest(format!(
+                "The search query is {} characters long, the limit is {}",
+                query_chars, max_query_chars
+            )));
+        }
+        if let Some(max_results) = self.max_results {
+            if !(1..=MAX_RESULTS_LIMIT).contains(&max_results) {
+                return Err(AppError::BadRequest(format!(
+                    "max_results must be between 1 and {}, got {}",
+                    MAX_RESULTS_LIMIT, max_results
+                )));
+            }
+        }
+        Ok(self)
+    }
+
+    // The query with the caller's limits spelled out for the agent
+    fn prompt(&self) -> String {
+        let mut hints = Vec::new();
+        if let Some(max_results) = self.max_results {
+            hints.push(format!("return at most {} papers", max_results));
+        }
+        if let Some(sort_by) = self.sort_by {
+            hints.push(format!("search with sort_by \"{}\"", sort_by.as_str()));
+        }
+        if hints.is_empty() {
+            self.query.clone()
+        } else {
+            format!("{} ({})", self.query, hints.join(", "))
+        }
+    }
+}
+
+// JSON body of a search, echoing the query it answers
+#[derive(Serialize, ToSchema)]
+struct SearchResponse<'a> {
+    query: &'a str,
+    #[serde(flatten)]
+    result: &'a SearchResult,
+}
+
+// Listed when a client asks for a format we don't have
+const SUPPORTED_FORMATS: &str = "html, json, markdown, ris, csv and bibtex";
+
+// Output formats supported by the search endpoint
+#[derive(Deserialize, Default, Clone, Copy, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum OutputFormat {
+    #[default]
+    Html,
+    Json,
+    Markdown,
+    Ris,
+    Csv,
+    Bibtex,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = AppError;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.trim().to_ascii_lowercase().as_str() {
+            "html" => Ok(OutputFormat::Html),
+            "json" => Ok(OutputFormat::Json),
+            "markdown" => Ok(OutputFormat::Markdown),
+            "ris" => Ok(OutputFormat::Ris),
+            "csv" => Ok(OutputFormat::Csv),
+            "bibtex" => Ok(OutputFormat::Bibtex),
+            _ => Err(AppError::BadRequest(format!(
+                "Unknown format {:?}, supported formats are {}",
+                format, SUPPORTED_FORMATS
+            ))),
+        }
+    }
+}
+
+impl OutputFormat {
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "*/*" | "text/*" | "text/html" => Some(OutputFormat::Html),
+            "application/json" => Some(OutputFormat::Json),
+            "text/markdown" => Some(OutputFormat::Markdown),
+            "text/csv" => Some(OutputFormat::Csv),
+            "application/x-research-info-systems" => Some(OutputFormat::Ris),
+            "application/x-bibtex" | "text/x-bibtex" => Some(OutputFormat::Bibtex),
+            _ => None,
+        }
+    }
+
+    // Most preferred format in an Accept header we can produce, HTML if none
+    fn negotiate(headers: &HeaderMap) -> Self {
+        let accept = headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .unwrap_or("*/*");
+
+        let mut candidates: Vec<(f32, OutputFormat)> = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let media_type = parts.next()?.to_ascii_lowercase();
+                let quality = parts
+                    .filter_map(|param| param.strip_prefix("q="))
+                    .find_map(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                Some((quality, OutputFormat::from_media_type(&media_type)?))
+            })
+            .filter(|(quality, _)| *quality > 0.0)
+            .collect();
+        // Stable, so equally preferred types keep the client's order
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+        candidates
+            .first()
+            .map_or(OutputFormat::default(), |(_, format)| *format)
+    }
+
+    // The format asked for by name, or else the one the Accept header prefers
+    fn resolve(requested: Option<&str>, headers: &HeaderMap) -> Result<Self, AppError> {
+        match requested {
+            Some(format) => format.parse(),
+            None => Ok(OutputFormat::negotiate(headers)),
+        }
+    }
+}
+
+// Query parameters for search endpoint
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SearchParams {
+    // Overrides the body's format and the Accept header
+    #[serde(default)]
+    #[param(value_type = Option<OutputFormat>)]
+    format: Option<String>,
+    // Skip the result and arXiv caches, e.g. to pick up papers announced minutes ago
+    #[serde(default)]
+    no_cache: bool,
+    // Indented JSON for people reading it, compact by default
+    #[serde(default)]
+    pretty: bool,
+}
+
+// Query parameters for the GET variant of the search endpoint, for shareable links
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SearchQuery {
+    q: String,
+    #[serde(default)]
+    max_results: Option<i32>,
+    #[serde(default)]
+    sort_by: Option<SortBy>,
+    #[serde(default)]
+    sort: Option<SortKey>,
+    #[serde(default)]
+    group_by: Option<GroupBy>,
+    // Overrides the Accept header
+    #[serde(default)]
+    #[param(value_type = Option<OutputFormat>)]
+    format: Option<String>,
+    #[serde(default)]
+    no_cache: bool,
+    #[serde(default)]
+    pretty: bool,
+}
+
+// Query parameters for the streaming search endpoint
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct StreamQuery {
+    query: String,
+    #[serde(default)]
+    max_results: Option<i32>,
+    #[serde(default)]
+    sort_by: Option<SortBy>,
+    #[serde(default)]
+    sort: Option<SortKey>,
+    #[serde(default)]
+    no_cache: bool,
+}
+
+// Query parameters for the Atom feed endpoint
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct FeedQuery {
+    query: String,
+    #[serde(default)]
+    max_results: Option<i32>,
+}
+
+// Body of every API error response
+#[derive(Serialize, ToSchema)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ErrorDetail {
+    // bad_request, not_found, rate_limited, ... or the arXiv error's kind, e.g. no_results
+    kind: &'static str,
+    message: String,
+}
+
+// Errors grouped by who has to act on them, each with its own status code
+#[derive(Debug)]
+enum AppError {
+    // The client sent something we can't act on
+    BadRequest(String),
+    // No API key, or one that isn't configured
+    Unauthorized(String),
+    // What the client asked for doesn't exist
+    NotFound(String),
+    // The client used up its requests for now, and may retry after this long
+    RateLimited(Duration),
+    // Every search slot is taken, so the agent isn't asked for more than it can answer
+    Overloaded,
+    // Anything arXiv-related, with the status and kind coming from the `ArxivError` inside
+    Arxiv(anyhow::Error),
+    // The LLM failed or returned something we couldn't use
+    LlmError(anyhow::Error),
+    Internal(anyhow::Error),
 }
 
-// Custom error type for the application
-struct AppError(anyhow::Error);
+impl AppError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Overloaded => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Arxiv(err) => arxiv_status(err),
+            AppError::LlmError(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            AppError::Arxiv(err) => arxiv_cause(err).map_or("arxiv", tools::ArxivError::kind),
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::NotFound(_) => "not_found",
+            AppError::RateLimited(_) => "rate_limited",
+            AppError::Overloaded => "overloaded",
+            AppError::LlmError(_) => "llm_error",
+            AppError::Internal(_) => "internal",
+        }
+    }
+
+    fn detail(&self) -> String {
+        match self {
+            AppError::BadRequest(message)
+            | AppError::Unauthorized(message)
+            | AppError::NotFound(message) => message.clone(),
+            AppError::RateLimited(retry_after) => format!(
+                "Too many requests, try again in {} seconds",
+                retry_after_secs(*retry_after)
+            ),
+            AppError::Overloaded => {
+                "Too many searches are in progress, try again shortly".to_string()
+            }
+            AppError::Arxiv(err) | AppError::LlmError(err) | AppError::Internal(err) => {
+                format!("{:#}", err)
+            }
+        }
+    }
+}
+
+// The arXiv error behind `err`, also when it failed one of the agent's tools
+fn arxiv_cause(err: &anyhow::Error) -> Option<&tools::ArxivError> {
+    err.chain().find_map(|cause| cause.downcast_ref())
+}
+
+// Whole seconds for Retry-After, rounded up so a retry on time isn't rejected again
+fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0)
+}
+
+// Missing papers and bad ids are the client's problem, the agent's bad answers are the LLM's.
+// Timeouts, arXiv's own 503s and its rate limiting are passed on, other network errors are
+// a bad gateway
+fn arxiv_status(err: &anyhow::Error) -> StatusCode {
+    let e = match arxiv_cause(err) {
+        Some(
+            tools::ArxivError::NoResults
+            | tools::ArxivError::PaperNotFound(_)
+            | tools::ArxivError::FullTextUnavailable(_),
+        ) => return StatusCode::NOT_FOUND,
+        Some(tools::ArxivError::InvalidId(_) | tools::ArxivError::InvalidSince(_)) => {
+            return StatusCode::BAD_REQUEST
+        }
+        Some(tools::ArxivError::AgentResponseNotJson(_)) => return StatusCode::SERVICE_UNAVAILABLE,
+        Some(tools::ArxivError::Network(e)) => e,
+        Some(tools::ArxivError::RateLimited { .. }) => return StatusCode::SERVICE_UNAVAILABLE,
+        // arXiv sent something that isn't a usable feed
+        Some(tools::ArxivError::MalformedEntry(_)) => return StatusCode::BAD_GATEWAY,
+        _ => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    if e.is_timeout() {
+        StatusCode::GATEWAY_TIMEOUT
+    } else if e.status() == Some(StatusCode::SERVICE_UNAVAILABLE) {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::BAD_GATEWAY
+    }
+}
+
+impl AppError {
+    // JSON error body, for API clients and stream events
+    fn body(&self) -> ErrorBody {
+        ErrorBody {
+            error: ErrorDetail {
+                kind: self.kind(),
+                message: self.detail(),
+            },
+        }
+    }
+
+    fn log(&self) {
+        let status = self.status();
+        if let AppError::Arxiv(err) | AppError::LlmError(err) | AppError::Internal(err) = self {
+            if status.is_server_error() {
+                // `{:?}` on anyhow::Error includes the whole cause chain
+                tracing::error!(%status, error = ?err, "request failed");
+            }
+        }
+    }
+
+    // The error as a fragment the search page can show in place of results
+    fn into_html(self) -> Response {
+        self.log();
+        let html = format!(
+            "<p class=\"error\">{}</p>",
+            tera::escape_html(&self.detail())
+        );
+        (self.status(), Html(html)).into_response()
+    }
+}
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
-        )
-            .into_response()
+        self.log();
+        let status = self.status();
+        let mut response = (status, Json(self.body())).into_response();
+        match self {
+            AppError::Unauthorized(_) => {
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            AppError::RateLimited(retry_after) => {
+                response
+                    .headers_mut()
+                    .insert(header::RETRY_AFTER, retry_after_secs(retry_after).into());
+            }
+            _ => (),
+        }
+        response
     }
 }
 
@@ -44,13 +461,148 @@ where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        let err = err.into();
+        if arxiv_cause(&err).is_some() {
+            return AppError::Arxiv(err);
+        }
+
+        // Bad JSON and failed prompts come from the LLM, not from the client
+        if err.is::<serde_json::Error>() || err.is::<PromptError>() || err.is::<ExtractionError>() {
+            return AppError::LlmError(err);
+        }
+
+        AppError::Internal(err)
     }
 }
 
 // State structure to hold shared data
 struct AppState {
-    openai_client: openai::Client,
+    client: ProviderClient,
+    model: String,
+    // Instructions for the research agent
+    preamble: String,
+    // Parsed search results keyed by normalized query
+    results: TtlCache<SearchResult>,
+    // Raw arXiv responses, shared by every agent's tools
+    fetches: Arc<TtlCache<Vec<u8>>>,
+    max_query_chars: usize,
+    // Past searches and the papers they found
+    history: History,
+    // Saved searches, refreshed in the background
+    watches: Arc<Watches>,
+    // Recent dependency probes for the readiness check
+    readiness: TtlCache<DependencyStatus>,
+    llm_key_configured: bool,
+    // Bearer tokens accepted by the API, which is open when there are none
+    api_keys: Vec<String>,
+    // Requests per minute per client, unless RATE_LIMIT_PER_MINUTE is 0
+    rate_limiter: Option<RateLimiter>,
+    // One permit per search the agent is working on, across every search endpoint
+    search_slots: Semaphore,
+    // Compiled once at startup
+    templates: tera::Tera,
+}
+
+// Reject API requests without a valid bearer token, when any tokens are configured
+async fn require_api_key(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    check_api_key(&state.api_keys, request.headers())?;
+    Ok(next.run(request).await)
+}
+
+fn check_api_key(api_keys: &[String], headers: &HeaderMap) -> Result<(), AppError> {
+    if api_keys.is_empty() {
+        return Ok(());
+    }
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .or_else(|| websocket_token(headers))
+        .ok_or_else(|| AppError::Unauthorized("Missing bearer token".to_string()))?;
+    // Check every key, so the time taken doesn't depend on which one matched
+    let valid = api_keys.iter().fold(false, |valid, key| {
+        valid | constant_time_eq(key.as_bytes(), token.as_bytes())
+    });
+    if !valid {
+        return Err(AppError::Unauthorized("Invalid API key".to_string()));
+    }
+    Ok(())
+}
+
+// Browsers can't set Authorization on a WebSocket handshake, so /ws/chat also takes the key
+// as the subprotocol after "bearer": `new WebSocket(url, ["bearer", key])`. Unlike a query
+// parameter it stays out of the request logs
+fn websocket_token(headers: &HeaderMap) -> Option<&str> {
+    let protocols = headers
+        .get(header::SEC_WEBSOCKET_PROTOCOL)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .map(str::trim);
+    protocols
+        .skip_while(|protocol| *protocol != "bearer")
+        .nth(1)
+}
+
+// Turn clients away once they've used up their requests for the minute
+async fn rate_limit(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if let Some(limiter) = &state.rate_limiter {
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(peer)| *peer);
+        check_rate_limit(limiter, request.headers(), peer)?;
+    }
+    Ok(next.run(request).await)
+}
+
+fn check_rate_limit(
+    limiter: &RateLimiter,
+    headers: &HeaderMap,
+    peer: Option<SocketAddr>,
+) -> Result<(), AppError> {
+    // Lumping clients without an address together would let one of them use up everyone's
+    // requests, so they aren't served at all
+    let ip = client_ip(headers, peer)
+        .ok_or_else(|| AppError::BadRequest("Could not tell the client's address".to_string()))?;
+    limiter.check(&ip).map_err(AppError::RateLimited)
+}
+
+// A slot held for as long as the permit lives, or Overloaded when every one is taken
+fn take_search_slot(slots: &Semaphore) -> Result<SemaphorePermit<'_>, AppError> {
+    slots.try_acquire().map_err(|_| AppError::Overloaded)
+}
+
+// The client's address as seen by the platform's proxy. Behind Shuttle's proxy the peer is
+// always the proxy itself, so X-Forwarded-For is what tells clients apart there. Without the
+// header (run locally, or reached directly) the peer address is the client's
+fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>) -> Option<String> {
+    // Only the last hop was added by the proxy, earlier ones are whatever the client sent
+    headers
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .map(str::to_string)
+        .or_else(|| peer.map(|peer| peer.ip().to_string()))
+}
+
+// Compare secrets without returning early on the first differing byte
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
 }
 
 // Handler for serving the static index.html
@@ -59,59 +611,2178 @@ async fn serve_index() -> impl IntoResponse {
 }
 
 // Handler for the search endpoint
+#[utoipa::path(
+    post,
+    path = "/api/search",
+    params(SearchParams),
+    request_body = SearchRequest,
+    responses(
+        (status = 200, description = "Papers in the requested format", body = SearchResponse,
+            content_type = ["application/json", "text/html", "text/markdown", "text/csv",
+                "application/x-research-info-systems", "application/x-bibtex"]),
+        (status = 400, description = "Invalid search", body = ErrorBody),
+        (status = 404, description = "No papers found", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+        (status = 503, description = "Too many searches in progress", body = ErrorBody),
+    )
+)]
 async fn search_papers(
     State(state): State<Arc<AppState>>,
+    Query(params): Query<SearchParams>,
+    headers: HeaderMap,
     Json(request): Json<SearchRequest>,
-) -> Result<impl IntoResponse, AppError> {
-    let paper_agent = state.openai_client
-        .agent(GPT_4)
-        .preamble(
-            "You are a helpful research assistant that can search and analyze academic papers from arXiv. \
-             When asked about a research topic, use the search_arxiv tool to find relevant papers and \
-             return only the raw JSON response from the tool."
-        )
-        .tool(ArxivSearchTool)
-        .build();
-
-    let response = paper_agent
-        .prompt(&request.query)
+) -> Result<Response, AppError> {
+    let requested = params.format.as_deref().or(request.format.as_deref());
+    let format = OutputFormat::resolve(requested, &headers)?;
+    run_search(&state, request, format, params.no_cache, params.pretty)
+        .await
+        .or_else(|err| html_error(format, err))
+        .map(vary_on_accept)
+}
+
+// Handler for POST /api/search.json, for scripts and other frontends
+#[utoipa::path(
+    post,
+    path = "/api/search.json",
+    params(SearchParams),
+    request_body = SearchRequest,
+    responses(
+        (status = 200, description = "Papers found", body = SearchResponse),
+        (status = 400, description = "Invalid search", body = ErrorBody),
+        (status = 404, description = "No papers found", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+        (status = 503, description = "Too many searches in progress", body = ErrorBody),
+    )
+)]
+async fn search_papers_json(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SearchParams>,
+    Json(request): Json<SearchRequest>,
+) -> Result<Response, AppError> {
+    run_search(
+        &state,
+        request,
+        OutputFormat::Json,
+        params.no_cache,
+        params.pretty,
+    )
+    .await
+}
+
+// Handler for GET /api/search?q=...
+#[utoipa::path(
+    get,
+    path = "/api/search",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "Papers in the requested format", body = SearchResponse,
+            content_type = ["application/json", "text/html", "text/markdown", "text/csv",
+                "application/x-research-info-systems", "application/x-bibtex"]),
+        (status = 400, description = "Invalid search", body = ErrorBody),
+        (status = 404, description = "No papers found", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+        (status = 503, description = "Too many searches in progress", body = ErrorBody),
+    )
+)]
+async fn search_papers_get(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SearchQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let format = OutputFormat::resolve(query.format.as_deref(), &headers)?;
+    let request = SearchRequest {
+        query: query.q,
+        max_results: query.max_results,
+        sort_by: query.sort_by,
+        sort: query.sort,
+        group_by: query.group_by,
+        format: None,
+    };
+    run_search(&state, request, format, query.no_cache, query.pretty)
+        .await
+        .or_else(|err| html_error(format, err))
+        .map(vary_on_accept)
+}
+
+// The search page asks for HTML and shows errors inline, everyone else gets JSON errors
+fn html_error(format: OutputFormat, err: AppError) -> Result<Response, AppError> {
+    match format {
+        OutputFormat::Html => Ok(err.into_html()),
+        _ => Err(err),
+    }
+}
+
+// The same URL serves different formats, so caches must key on Accept too
+fn vary_on_accept(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept"));
+    response
+}
+
+// The agent's answer to a validated search, reusing cached results when allowed
+async fn answer_search(
+    state: &AppState,
+    request: &SearchRequest,
+    no_cache: bool,
+    events: Option<&mpsc::UnboundedSender<SearchEvent>>,
+) -> Result<AgentAnswer, AppError> {
+    let report = |event: SearchEvent| {
+        if let Some(events) = events {
+            // A closed channel only means the client stopped listening
+            let _ = events.send(event);
+        }
+    };
+    let progress = events.map(|events| {
+        let events = events.clone();
+        Arc::new(move |message: String| {
+            let _ = events.send(SearchEvent::Status(message));
+        }) as Progress
+    });
+
+    let key = cache::search_key(&request.query, request.max_results, request.sort_by);
+    let cached = if no_cache { None } else { state.results.get(&key) };
+    let from_cache = cached.is_some();
+    let answer = match cached {
+        Some(result) => {
+            report(SearchEvent::Status("Using cached results".to_string()));
+            AgentAnswer::Results(result)
+        }
+        None => {
+            // Cached answers don't need a slot, only searches that reach the agent do
+            let _slot = take_search_slot(&state.search_slots)?;
+            let fetches = if no_cache { None } else { Some(&state.fetches) };
+            let preamble = agent::render_preamble(
+                &state.preamble,
+                tools::clamp_max_results(request.max_results),
+            )?;
+            let paper_agent = state
+                .client
+                .build_agent(&state.model, &preamble, fetches, progress);
+
+            report(SearchEvent::Status("Asking the research assistant".to_string()));
+            let prompt = request.prompt();
+            let response = paper_agent
+                .prompt(&prompt)
+                .instrument(tracing::info_span!("agent_prompt", model = %state.model))
+                .await?;
+
+            report(SearchEvent::Parsing);
+            agent::interpret_response(
+                &state.client,
+                &state.model,
+                &paper_agent,
+                &prompt,
+                response,
+            )
+            .await
+        }
+    };
+
+    if let AgentAnswer::Results(result) = &answer {
+        if !from_cache {
+            state.results.insert(key, result.clone());
+        }
+        // Losing a history row isn't worth failing the search over
+        if let Err(e) = state.history.record(&request.query, &result.papers).await {
+            tracing::warn!(error = %e, "could not record search history");
+        }
+    }
+    Ok(answer)
+}
+
+// JSON body, indented when `pretty` for people reading it in a terminal
+fn json_response<T: Serialize>(value: &T, pretty: bool) -> Result<Response, AppError> {
+    if !pretty {
+        return Ok(Json(value).into_response());
+    }
+    // Not the LLM's fault, unlike most serde_json errors
+    let body = serde_json::to_string_pretty(value).map_err(|e| AppError::Internal(e.into()))?;
+    Ok(([(header::CONTENT_TYPE, "application/json")], body).into_response())
+}
+
+// Search logic shared by the POST and GET endpoints
+async fn run_search(
+    state: &AppState,
+    request: SearchRequest,
+    format: OutputFormat,
+    no_cache: bool,
+    pretty: bool,
+) -> Result<Response, AppError> {
+    let request = request.validate(state.max_query_chars)?;
+
+    let mut result = match answer_search(state, &request, no_cache, None).await? {
+        AgentAnswer::Results(result) => result,
+        AgentAnswer::Comparison(comparison) => {
+            return match format {
+                OutputFormat::Html => {
+                    Ok(Html(tools::format_comparison_as_html(&comparison)).into_response())
+                }
+                OutputFormat::Json => json_response(&comparison, pretty),
+                _ => Err(AppError::BadRequest(
+                    "Comparisons can only be returned as HTML or JSON".to_string(),
+                )),
+            };
+        }
+        // Show the agent's prose with a banner rather than failing the request
+        AgentAnswer::Prose(text) if matches!(format, OutputFormat::Html) => {
+            return Ok(Html(format!(
+                "<div class=\"agent-warning\">The assistant did not return search results.</div>\
+                 <p>{}</p>",
+                tera::escape_html(&text)
+            ))
+            .into_response());
+        }
+        AgentAnswer::Prose(text) => {
+            return Err(tools::ArxivError::AgentResponseNotJson(text).into());
+        }
+    };
+    if let Some(sort) = request.sort {
+        tools::sort_papers(&mut result.papers, sort);
+    }
+    let papers = &result.papers;
+
+    match format {
+        OutputFormat::Html => {
+            // Format the papers into HTML table
+            let mut html =
+                tools::format_papers_as_html(&state.templates, papers, request.group_by)?;
+            if let Some(seed) = &result.related_to {
+                html.insert_str(
+                    0,
+                    &format!("<p class=\"related-to\">Related to: {}</p>", tera::escape_html(seed)),
+                );
+            }
+            Ok(Html(html).into_response())
+        }
+        OutputFormat::Json => json_response(
+            &SearchResponse {
+                query: &request.query,
+                result: &result,
+            },
+            pretty,
+        ),
+        OutputFormat::Markdown => {
+            let markdown = tools::format_papers_as_markdown(papers);
+            Ok((
+                [(header::CONTENT_TYPE, "text/markdown; charset=utf-8")],
+                markdown,
+            )
+                .into_response())
+        }
+        OutputFormat::Ris => Ok(ris_attachment(tools::format_papers_as_ris(papers))),
+        OutputFormat::Bibtex => Ok((
+            [
+                (header::CONTENT_TYPE, "application/x-bibtex; charset=utf-8"),
+                (header::CONTENT_DISPOSITION, "attachment; filename=\"papers.bib\""),
+            ],
+            tools::format_papers_as_bibtex(papers),
+        )
+            .into_response()),
+        OutputFormat::Csv => {
+            let csv = tools::format_papers_as_csv(papers)?;
+            Ok((
+                [
+                    (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+                    (header::CONTENT_DISPOSITION, "attachment; filename=\"papers.csv\""),
+                ],
+                csv,
+            )
+                .into_response())
+        }
+    }
+}
+
+// Steps of a streamed search, sent to the client as server-sent events
+enum SearchEvent {
+    Searching(String),
+    Status(String),
+    Parsing,
+    // One paper at a time, so the page can render rows as they arrive
+    Paper(serde_json::Value),
+    Papers(serde_json::Value),
+    Comparison(serde_json::Value),
+    Done,
+    Error(serde_json::Value),
+}
+
+impl SearchEvent {
+    fn into_event(self) -> Event {
+        match self {
+            SearchEvent::Searching(query) => Event::default()
+                .event("searching")
+                .data(format!("Searching arXiv for \"{}\"", query)),
+            SearchEvent::Status(message) => Event::default().event("status").data(message),
+            // EventSource drops events without data, so every event carries a message
+            SearchEvent::Parsing => Event::default()
+                .event("parsing")
+                .data("Reading the assistant's answer"),
+            SearchEvent::Paper(paper) => Event::default().event("paper").data(paper.to_string()),
+            SearchEvent::Papers(papers) => {
+                Event::default().event("papers").data(papers.to_string())
+            }
+            SearchEvent::Comparison(comparison) => Event::default()
+                .event("comparison")
+                .data(comparison.to_string()),
+            SearchEvent::Done => Event::default().event("done").data("Search complete"),
+            SearchEvent::Error(error) => Event::default().event("error").data(error.to_string()),
+        }
+    }
+}
+
+// Handler for GET /api/search/stream, reporting progress while the search runs
+#[utoipa::path(
+    get,
+    path = "/api/search/stream",
+    params(StreamQuery),
+    responses(
+        (status = 200, description = "Server-sent events: searching and status while the agent \
+            works, a paper per result, then papers or comparison, and finally done or error",
+            body = String, content_type = "text/event-stream"),
+    )
+)]
+async fn search_stream(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<StreamQuery>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(
+        async move {
+            let last = match stream_search(&state, query, &sender).await {
+                Ok(()) => SearchEvent::Done,
+                Err(err) => {
+                    tracing::warn!(error = %err.detail(), "streamed search failed");
+                    SearchEvent::Error(json!(err.body()))
+                }
+            };
+            // The client may have gone away already, there's no one left to tell
+            let _ = sender.send(last);
+        }
+        .in_current_span(),
+    );
+
+    let events = futures::stream::unfold(receiver, |mut receiver| async move {
+        let event = receiver.recv().await?;
+        Some((Ok(event.into_event()), receiver))
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+// The search pipeline, sending an event as each stage completes
+async fn stream_search(
+    state: &AppState,
+    query: StreamQuery,
+    sender: &mpsc::UnboundedSender<SearchEvent>,
+) -> Result<(), AppError> {
+    let request = SearchRequest {
+        query: query.query,
+        max_results: query.max_results,
+        sort_by: query.sort_by,
+        sort: query.sort,
+        group_by: None,
+        format: None,
+    }
+    .validate(state.max_query_chars)?;
+
+    let _ = sender.send(SearchEvent::Searching(request.query.clone()));
+    let event = match answer_search(state, &request, query.no_cache, Some(sender)).await? {
+        AgentAnswer::Results(mut result) => {
+            if let Some(sort) = request.sort {
+                tools::sort_papers(&mut result.papers, sort);
+            }
+            let _ = sender.send(SearchEvent::Status("Formatting results".to_string()));
+            for paper in &result.papers {
+                let _ = sender.send(SearchEvent::Paper(serde_json::to_value(paper)?));
+            }
+            SearchEvent::Papers(serde_json::to_value(SearchResponse {
+                query: &request.query,
+                result: &result,
+            })?)
+        }
+        AgentAnswer::Comparison(comparison) => {
+            SearchEvent::Comparison(serde_json::to_value(comparison)?)
+        }
+        AgentAnswer::Prose(text) => {
+            return Err(tools::ArxivError::AgentResponseNotJson(text).into());
+        }
+    };
+    let _ = sender.send(event);
+    Ok(())
+}
+
+// Handler writing a plain-text literature summary of papers the client already has
+#[utoipa::path(
+    post,
+    path = "/api/summarize",
+    request_body = Vec<Paper>,
+    responses(
+        (status = 200, description = "Summary of the papers", body = String,
+            content_type = "text/plain"),
+        (status = 400, description = "No papers to summarize", body = ErrorBody),
+        (status = 503, description = "The LLM failed", body = ErrorBody),
+    )
+)]
+async fn summarize(
+    State(state): State<Arc<AppState>>,
+    Json(papers): Json<Vec<Paper>>,
+) -> Result<Response, AppError> {
+    if papers.is_empty() {
+        return Err(AppError::BadRequest("No papers to summarize".to_string()));
+    }
+
+    let summarizer = state.client.build_summarizer(&state.model, Some(&state.fetches));
+    let summary = agent::summarize_papers(&summarizer, &papers)
+        .instrument(tracing::info_span!("agent_summarize", model = %state.model))
         .await?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        summary,
+    )
+        .into_response())
+}
+
+// Handler serving a query's newest papers as an Atom feed
+#[utoipa::path(
+    get,
+    path = "/api/feed",
+    params(FeedQuery),
+    responses(
+        (status = 200, description = "Atom feed", body = String,
+            content_type = "application/atom+xml"),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    )
+)]
+async fn feed(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<FeedQuery>,
+) -> Result<Response, AppError> {
+    let request = SearchRequest {
+        query: query.query,
+        max_results: query.max_results,
+        sort_by: Some(SortBy::SubmittedDate),
+        sort: None,
+        group_by: None,
+        format: None,
+    }
+    .validate(state.max_query_chars)?;
 
-    let papers: Vec<Paper> = serde_json::from_str(&response)?;
+    // Readers poll every few minutes, so polls within the cache TTL don't reach arXiv
+    let source = cache::arxiv_source(Some(&state.fetches));
+    let papers = tools::search_latest(
+        source.as_ref(),
+        &request.query,
+        &[],
+        tools::clamp_max_results(request.max_results),
+    )
+    .await?;
 
-    // Format the papers into HTML table
-    let html = tools::format_papers_as_html(&papers)?;
-    Ok(Html(html))
+    Ok((
+        [(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+        tools::format_papers_as_atom(&request.query, &papers),
+    )
+        .into_response())
 }
 
-#[shuttle_runtime::main]
-async fn axum(
-    #[shuttle_runtime::Secrets] secrets: SecretStore,
-) -> shuttle_axum::ShuttleAxum {
-    // Initialize OpenAI client from secrets
-    let openai_key = secrets
-        .get("OPENAI_API_KEY")
-        .context("OPENAI_API_KEY secret not found")?;
-    
-    let openai_client = openai::Client::new(&openai_key);
-    
-    // Create shared state
-    let state = Arc::new(AppState {
-        openai_client,
+// Handler exporting papers the client already has as an RIS file
+#[utoipa::path(
+    post,
+    path = "/api/export/ris",
+    request_body = Vec<Paper>,
+    responses(
+        (status = 200, description = "RIS file", body = String,
+            content_type = "application/x-research-info-systems"),
+    )
+)]
+async fn export_ris(Json(papers): Json<Vec<Paper>>) -> Response {
+    ris_attachment(tools::format_papers_as_ris(&papers))
+}
+
+fn ris_attachment(ris: String) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, "application/x-research-info-systems"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"papers.ris\""),
+        ],
+        ris,
+    )
+        .into_response()
+}
+
+// Query parameters for the history endpoint
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct HistoryParams {
+    #[serde(default = "default_history_limit")]
+    limit: u32,
+}
+
+fn default_history_limit() -> u32 {
+    20
+}
+
+// Handler listing the most recent searches
+#[utoipa::path(
+    get,
+    path = "/api/history",
+    params(HistoryParams),
+    responses(
+        (status = 200, description = "Most recent searches first", body = Vec<HistoryEntry>),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    )
+)]
+async fn search_history(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HistoryParams>,
+) -> Result<Response, AppError> {
+    let entries = state
+        .history
+        .recent(params.limit.min(MAX_HISTORY_LIMIT))
+        .await?;
+    Ok(Json(entries).into_response())
+}
+
+// Handler saving a search to be re-run in the background
+#[utoipa::path(
+    post,
+    path = "/api/watches",
+    request_body = NewWatch,
+    responses(
+        (status = 201, description = "Saved search", body = Watch),
+        (status = 400, description = "Invalid search", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    )
+)]
+async fn create_watch(
+    State(state): State<Arc<AppState>>,
+    Json(mut watch): Json<NewWatch>,
+) -> Result<Response, AppError> {
+    watch.query = SearchRequest {
+        query: watch.query,
+        max_results: watch.max_results,
+        sort_by: None,
+        sort: None,
+        group_by: None,
+        format: None,
+    }
+    .validate(state.max_query_chars)?
+    .query;
+    let watch = state.watches.create(watch).await?;
+
+    // The first run sets the baseline later runs are compared against
+    let watches = state.watches.clone();
+    let id = watch.id;
+    tokio::spawn(async move {
+        let Ok(Some(watch)) = watches.get(id).await else {
+            return;
+        };
+        if let Err(e) = watches.refresh(&watch).await {
+            tracing::warn!(watch = id, error = %e, "could not run new watch");
+        }
     });
 
+    Ok((StatusCode::CREATED, Json(watch)).into_response())
+}
+
+// Handler listing saved searches
+#[utoipa::path(
+    get,
+    path = "/api/watches",
+    responses(
+        (status = 200, description = "Saved searches", body = Vec<Watch>),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    )
+)]
+async fn list_watches(State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    Ok(Json(state.watches.list().await?).into_response())
+}
+
+// A watch and the papers its latest refresh found for the first time
+#[derive(Serialize, ToSchema)]
+struct WatchNewPapers {
+    watch: Watch,
+    papers: Vec<Paper>,
+}
+
+// Handler for the papers a watch's latest refresh found for the first time
+#[utoipa::path(
+    get,
+    path = "/api/watches/{id}/new",
+    params(("id" = i64, Path, description = "Watch id")),
+    responses(
+        (status = 200, description = "Newly found papers", body = WatchNewPapers),
+        (status = 404, description = "No such watch", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    )
+)]
+async fn watch_new_papers(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Response, AppError> {
+    let watch = state
+        .watches
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No watch with id {}", id)))?;
+    let papers = state.watches.new_papers(&watch).await?;
+    Ok(Json(WatchNewPapers { watch, papers }).into_response())
+}
+
+// Liveness check for load balancers and uptime monitors
+#[utoipa::path(
+    get,
+    path = "/health",
+    responses((status = 200, description = "The service is up")),
+    security(())
+)]
+async fn health() -> impl IntoResponse {
+    Json(json!({ "status": "ok" }))
+}
+
+// One dependency's part of the readiness report
+#[derive(Clone, Serialize)]
+struct DependencyStatus {
+    name: &'static str,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// Readiness check confirming arXiv answers and the LLM provider has credentials
+#[utoipa::path(
+    get,
+    path = "/ready",
+    responses(
+        (status = 200, description = "arXiv and the LLM provider are usable"),
+        (status = 503, description = "A dependency isn't, the body says which"),
+    ),
+    security(())
+)]
+async fn ready(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    // Probes are cached so frequent monitoring doesn't turn into load on arXiv
+    let arxiv = match state.readiness.get("arxiv") {
+        Some(status) => status,
+        None => {
+            let started = Instant::now();
+            let result = tools::check_arxiv_reachable().await;
+            let status = DependencyStatus {
+                name: "arxiv",
+                ok: result.is_ok(),
+                latency_ms: Some(started.elapsed().as_millis() as u64),
+                error: result.err().map(|e| e.to_string()),
+            };
+            state.readiness.insert("arxiv".to_string(), status.clone());
+            status
+        }
+    };
+    let llm = DependencyStatus {
+        name: "llm",
+        ok: state.llm_key_configured,
+        latency_ms: None,
+        error: (!state.llm_key_configured)
+            .then(|| "No API key is configured for the LLM provider".to_string()),
+    };
+
+    let dependencies = [arxiv, llm];
+    let ready = dependencies.iter().all(|dependency| dependency.ok);
+    let (status, label) = if ready {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    };
+    (status, Json(json!({ "status": label, "dependencies": dependencies })))
+}
+
+// Handler proxying a paper's PDF from arXiv
+#[utoipa::path(
+    get,
+    path = "/api/pdf/{id}",
+    params(("id" = String, Path, description = "arXiv id, e.g. 2310.06825")),
+    responses(
+        (status = 200, description = "The paper's PDF", content_type = "application/pdf"),
+        (status = 400, description = "Invalid arXiv id", body = ErrorBody),
+        (status = 404, description = "No such paper", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    )
+)]
+async fn download_pdf(Path(id): Path<String>) -> Result<Response, AppError> {
+    // Streamed straight through, so nothing touches the disk and big PDFs never sit in memory
+    let pdf = tools::open_pdf(&tools::pdf_url(&id), &id).await?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/pdf")],
+        Body::from_stream(pdf.bytes_stream()),
+    )
+        .into_response())
+}
+
+// Handler returning the abstract block of a paper's arXiv page
+#[utoipa::path(
+    get,
+    path = "/api/abstract/{id}",
+    params(("id" = String, Path, description = "arXiv id, e.g. 2310.06825")),
+    responses(
+        (status = 200, description = "Title, authors and abstract as an HTML fragment",
+            content_type = "text/html"),
+        (status = 400, description = "Invalid arXiv id", body = ErrorBody),
+        (status = 404, description = "No such paper", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    )
+)]
+async fn abstract_page(Path(id): Path<String>) -> Result<Response, AppError> {
+    let html = tools::fetch_abstract_html(&id).await?;
+    Ok(Html(html).into_response())
+}
+
+// Handler returning a paper's full text from its arXiv HTML rendering
+#[utoipa::path(
+    get,
+    path = "/api/fulltext/{id}",
+    params(("id" = String, Path, description = "arXiv id, e.g. 2310.06825")),
+    responses(
+        (status = 200, description = "The paper's body as plain text",
+            content_type = "text/plain"),
+        (status = 400, description = "Invalid arXiv id", body = ErrorBody),
+        (status = 404, description = "No HTML version of the paper", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    )
+)]
+async fn full_text(Path(id): Path<String>) -> Result<Response, AppError> {
+    let text = tools::fetch_full_text(&id).await?;
+    Ok(([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], text).into_response())
+}
+
+// One span per request, tagged with its id so concurrent searches can be told apart
+fn request_span(request: &axum::http::Request<axum::body::Body>) -> tracing::Span {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or("-");
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id,
+    )
+}
+
+// Model used by the agent: the MODEL_NAME secret, OPENAI_MODEL for OpenAI, or the provider default
+fn resolve_model(provider: Provider, secrets: &SecretStore) -> String {
+    let configured = match provider {
+        Provider::Openai => secrets
+            .get("MODEL_NAME")
+            .or_else(|| std::env::var("OPENAI_MODEL").ok()),
+        _ => secrets.get("MODEL_NAME"),
+    };
+    configured
+        .map(|model| model.trim().to_string())
+        .filter(|model| !model.is_empty())
+        .unwrap_or_else(|| provider.default_model().to_string())
+}
+
+// Whether the selected provider has a non-empty API key, Ollama doesn't need one
+fn llm_key_configured(provider: Provider, secrets: &SecretStore) -> bool {
+    let key = match provider {
+        Provider::Openai => secrets.get("OPENAI_API_KEY"),
+        Provider::Anthropic => secrets.get("ANTHROPIC_API_KEY"),
+        Provider::Ollama => return true,
+    };
+    key.is_some_and(|key| !key.trim().is_empty())
+}
+
+// Every route of the app, with the middleware shared by all of them
+fn router(state: Arc<AppState>) -> Router {
     // Set up CORS
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods([axum::http::Method::GET, axum::http::Method::POST])
         .allow_headers(Any);
 
-    // Create router
-    let router = Router::new()
+    // Everything that can spend LLM or arXiv quota sits behind the API keys
+    let api = Router::new()
+        .route("/api/search", get(search_papers_get).post(search_papers))
+        .route("/api/search.json", post(search_papers_json))
+        .route("/api/search/stream", get(search_stream))
+        .route("/api/pdf/:id", get(download_pdf))
+        .route("/api/abstract/:id", get(abstract_page))
+        .route("/api/fulltext/:id", get(full_text))
+        .route("/api/export/ris", post(export_ris))
+        .route("/api/feed", get(feed))
+        .route("/api/history", get(search_history))
+        .route("/api/summarize", post(summarize))
+        .route("/api/watches", get(list_watches).post(create_watch))
+        .route("/api/watches/:id/new", get(watch_new_papers))
+        .route("/ws/chat", get(chat::chat_socket))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_api_key))
+        // Outside the key check, so guessing keys counts against the limit too
+        .route_layer(middleware::from_fn_with_state(state.clone(), rate_limit));
+
+    Router::new()
         .route("/", get(serve_index))
-        .route("/api/search", post(search_papers))
+        .route("/health", get(health))
+        .route("/healthz", get(health))
+        .route("/ready", get(ready))
+        .route("/readyz", get(ready))
+        // Shuttle.toml ships static/ with the deploy
+        .nest_service("/static", ServeDir::new("static"))
+        .merge(openapi::docs())
+        .merge(api)
         .layer(cors)
-        .with_state(state);
+        .layer(PropagateRequestIdLayer::x_request_id())
+        // Responses logged at info, with their status and latency, so they show in deployed logs;
+        // outside CORS, so preflights and the other responses CORS gives itself are logged too
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(request_span)
+                .on_response(
+                    DefaultOnResponse::new()
+                        .level(tracing::Level::INFO)
+                        .latency_unit(LatencyUnit::Millis),
+                ),
+        )
+        // Outermost: every layer added later wraps the ones before it, so the id is set before
+        // the trace layer creates the request span from it
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .with_state(state)
+}
+
+#[shuttle_runtime::main]
+async fn axum(
+    #[shuttle_runtime::Secrets] secrets: SecretStore,
+    #[shuttle_shared_db::Postgres] pool: sqlx::PgPool,
+) -> shuttle_axum::ShuttleAxum {
+    // Initialize the LLM provider client from secrets
+    let provider: Provider = secrets
+        .get("PROVIDER")
+        .as_deref()
+        .unwrap_or("openai")
+        .parse()?;
+    let client = ProviderClient::from_secrets(provider, &secrets)?;
+
+    // Cache settings, in seconds and entries per cache
+    let ttl = secrets
+        .get("CACHE_TTL_SECS")
+        .and_then(|secs| secs.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(cache::DEFAULT_TTL);
+    let max_entries = secrets
+        .get("CACHE_MAX_ENTRIES")
+        .and_then(|entries| entries.parse().ok())
+        .unwrap_or(cache::DEFAULT_MAX_ENTRIES);
+
+    // History and watches live in the Postgres database Shuttle provisions for us
+    sqlx::migrate!()
+        .run(&pool)
+        .await
+        .context("could not migrate the database")?;
+    let history = History::new(pool.clone());
+    let watches = Arc::new(Watches::new(pool));
+
+    // Hours between background refreshes of saved searches
+    let refresh_hours = match secrets.get("WATCH_REFRESH_HOURS") {
+        Some(hours) => hours
+            .trim()
+            .parse()
+            .with_context(|| format!("WATCH_REFRESH_HOURS is not a number: {}", hours))?,
+        None => watches::DEFAULT_REFRESH_HOURS,
+    };
+    tokio::spawn(watches::refresh_periodically(
+        watches.clone(),
+        Duration::from_secs(refresh_hours.max(1) * 60 * 60),
+    ));
+
+    // Requests per client IP per minute, 0 turns the limit off
+    let requests_per_minute = match secrets.get("RATE_LIMIT_PER_MINUTE") {
+        Some(limit) => limit
+            .trim()
+            .parse()
+            .with_context(|| format!("RATE_LIMIT_PER_MINUTE is not a number: {}", limit))?,
+        None => rate_limit::DEFAULT_REQUESTS_PER_MINUTE,
+    };
+    let rate_limiter =
+        (requests_per_minute > 0).then(|| RateLimiter::per_minute(requests_per_minute));
+
+    // Searches the agent works on at once, whoever sent them
+    let max_concurrent_searches = match secrets.get("MAX_CONCURRENT_SEARCHES") {
+        Some(limit) => limit
+            .trim()
+            .parse()
+            .with_context(|| format!("MAX_CONCURRENT_SEARCHES is not a number: {}", limit))?,
+        None => DEFAULT_MAX_CONCURRENT_SEARCHES,
+    };
+
+    // A preamble file that can't be read should stop the deploy too
+    let preamble = agent::resolve_preamble(secrets.get("ARXIV_AGENT_PREAMBLE"))?;
+    // Rendered for each agent so {today} stays current, but a bad placeholder fails now
+    agent::render_preamble(&preamble, tools::clamp_max_results(None))?;
+
+    // A broken template should stop the deploy, not fail every search
+    let templates = tools::templates().context("could not compile the HTML templates")?;
+
+    // Create shared state
+    let state = Arc::new(AppState {
+        client,
+        model: resolve_model(provider, &secrets),
+        preamble,
+        results: TtlCache::new(ttl, max_entries),
+        fetches: Arc::new(TtlCache::new(ttl, max_entries)),
+        max_query_chars: secrets
+            .get("MAX_QUERY_CHARS")
+            .and_then(|chars| chars.parse().ok())
+            .unwrap_or(DEFAULT_MAX_QUERY_CHARS),
+        history,
+        watches,
+        readiness: TtlCache::new(READINESS_CACHE_TTL, 1),
+        llm_key_configured: llm_key_configured(provider, &secrets),
+        api_keys: secrets
+            .get("API_KEYS")
+            .map(|keys| {
+                keys.split(',')
+                    .map(|key| key.trim().to_string())
+                    .filter(|key| !key.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        rate_limiter,
+        search_slots: Semaphore::new(max_concurrent_searches.max(1)),
+        templates,
+    });
+
+    // Shuttle serves this with connect info, so the rate limiter can fall back on the peer
+    Ok(router(state).into())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forwarded_for(ip: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_str(ip).unwrap());
+        headers
+    }
+
+    #[test]
+    fn rate_limits_each_client_address_on_its_own() {
+        let limiter = RateLimiter::per_minute(2);
+        let proxy = Some(SocketAddr::from(([10, 0, 0, 1], 443)));
+        let first = forwarded_for("198.51.100.7, 203.0.113.1");
+        assert!(check_rate_limit(&limiter, &first, proxy).is_ok());
+        assert!(check_rate_limit(&limiter, &first, proxy).is_ok());
+        let refused = check_rate_limit(&limiter, &first, proxy).unwrap_err();
+        assert_eq!(refused.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // Spoofing an earlier hop doesn't get the client a fresh bucket
+        let spoofed = forwarded_for("192.0.2.99, 203.0.113.1");
+        assert!(check_rate_limit(&limiter, &spoofed, proxy).is_err());
+        assert!(check_rate_limit(&limiter, &forwarded_for("203.0.113.2"), proxy).is_ok());
+
+        // Without the header the peer is the client, and the proxy's address isn't used up
+        let direct = Some(SocketAddr::from(([203, 0, 113, 1], 50000)));
+        assert!(check_rate_limit(&limiter, &HeaderMap::new(), direct).is_err());
+        assert!(check_rate_limit(&limiter, &HeaderMap::new(), proxy).is_ok());
+
+        let anonymous = check_rate_limit(&limiter, &HeaderMap::new(), None).unwrap_err();
+        assert_eq!(anonymous.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn lets_through_only_requests_with_a_configured_key() {
+        let keys = ["first-key".to_string(), "second-key".to_string()];
+        let bearer = |token: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, HeaderValue::from_str(token).unwrap());
+            headers
+        };
+
+        assert!(check_api_key(&keys, &bearer("Bearer second-key")).is_ok());
+        for headers in [
+            HeaderMap::new(),
+            bearer("second-key"),
+            bearer("Bearer wrong-key"),
+            bearer("Bearer first-ke"),
+        ] {
+            let refused = check_api_key(&keys, &headers).unwrap_err();
+            assert_eq!(refused.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        // A browser's WebSocket handshake offers the key as a subprotocol instead
+        let offered = |protocols: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_str(protocols).unwrap(),
+            );
+            headers
+        };
+        assert!(check_api_key(&keys, &offered("bearer, first-key")).is_ok());
+        for headers in [
+            offered("first-key"),
+            offered("bearer"),
+            offered("bearer, wrong-key"),
+        ] {
+            assert!(check_api_key(&keys, &headers).is_err());
+        }
+
+        // Without any keys configured the API is open
+        assert!(check_api_key(&[], &HeaderMap::new()).is_ok());
+    }
+
+    fn status_of(err: impl Into<AppError>) -> StatusCode {
+        err.into().into_response().status()
+    }
+
+    #[test]
+    fn maps_arxiv_errors_to_statuses_by_whose_fault_they_are() {
+        use tools::ArxivError;
+
+        assert_eq!(status_of(ArxivError::NoResults), StatusCode::NOT_FOUND);
+        assert_eq!(
+            status_of(ArxivError::PaperNotFound("2401.99999".to_string())),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status_of(ArxivError::InvalidId("../etc".to_string())),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status_of(ArxivError::InvalidSince("soon".to_string())),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status_of(ArxivError::AgentResponseNotJson("Sure!".to_string())),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            status_of(ArxivError::RateLimited {
+                retry_after: Duration::from_secs(3)
+            }),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            status_of(ArxivError::MalformedEntry("title")),
+            StatusCode::BAD_GATEWAY
+        );
+        assert_eq!(
+            status_of(ArxivError::UnexpectedContentType("text/html".to_string())),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        // The LLM's bad JSON is the upstream's fault, not ours
+        let bad_json = serde_json::from_str::<Vec<tools::Paper>>("not json").unwrap_err();
+        assert_eq!(status_of(bad_json), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn maps_network_errors_to_gateway_statuses() {
+        use wiremock::matchers::path;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/slow"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+            .mount(&server)
+            .await;
+        Mock::given(path("/down"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+        Mock::given(path("/broken"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let network_error = |path: &str| {
+            let request = client.get(format!("{}{}", server.uri(), path));
+            async move {
+                let err = match request.send().await {
+                    Ok(response) => response.error_for_status().unwrap_err(),
+                    Err(err) => err,
+                };
+                tools::ArxivError::Network(err)
+            }
+        };
+
+        assert_eq!(
+            status_of(network_error("/slow").await),
+            StatusCode::GATEWAY_TIMEOUT
+        );
+        assert_eq!(
+            status_of(network_error("/down").await),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            status_of(network_error("/broken").await),
+            StatusCode::BAD_GATEWAY
+        );
+    }
+
+    async fn error_response(err: AppError) -> (StatusCode, serde_json::Value) {
+        let response = err.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn answers_each_class_of_failure_with_its_status_and_a_json_body() {
+        let bad_json =
+            serde_json::from_str::<Vec<tools::Paper>>("Here are the papers").unwrap_err();
+        let cases = [
+            (
+                AppError::BadRequest("The search query is empty".to_string()),
+                StatusCode::BAD_REQUEST,
+                "bad_request",
+            ),
+            (
+                tools::ArxivError::NoResults.into(),
+                StatusCode::NOT_FOUND,
+                "no_results",
+            ),
+            (
+                AppError::RateLimited(Duration::from_millis(1500)),
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limited",
+            ),
+            (
+                tools::ArxivError::MalformedEntry("id").into(),
+                StatusCode::BAD_GATEWAY,
+                "malformed_entry",
+            ),
+            (
+                bad_json.into(),
+                StatusCode::SERVICE_UNAVAILABLE,
+                "llm_error",
+            ),
+            (
+                AppError::Overloaded,
+                StatusCode::SERVICE_UNAVAILABLE,
+                "overloaded",
+            ),
+            (
+                anyhow::anyhow!("disk full").into(),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal",
+            ),
+        ];
+
+        for (err, status, kind) in cases {
+            let detail = err.detail();
+            let (actual, body) = error_response(err).await;
+            assert_eq!(actual, status, "{}", kind);
+            assert_eq!(
+                body,
+                json!({ "error": { "kind": kind, "message": detail } })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn turns_searches_away_while_every_slot_is_taken() {
+        let slots = Semaphore::new(2);
+        let first = take_search_slot(&slots).unwrap();
+        let _second = take_search_slot(&slots).unwrap();
+
+        let (status, body) = error_response(take_search_slot(&slots).unwrap_err()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["error"]["kind"], "overloaded");
+
+        // A search finishing frees its slot for the next one
+        drop(first);
+        assert!(take_search_slot(&slots).is_ok());
+    }
+
+    #[test]
+    fn tells_clients_how_to_recover() {
+        let response = AppError::Unauthorized("Missing API key".to_string()).into_response();
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        // Rounded up, so retrying on time isn't refused again
+        let response = AppError::RateLimited(Duration::from_millis(1500)).into_response();
+        assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+    }
+
+    #[tokio::test]
+    async fn indents_json_only_when_asked_to() {
+        let value = &json!({ "query": "attention", "papers": [{ "title": "Attention" }] });
+        let body = |pretty| async move {
+            let response = json_response(value, pretty).unwrap();
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        let compact = body(false).await;
+        let pretty = body(true).await;
+        assert!(!compact.contains('\n'), "{}", compact);
+        assert!(pretty.contains("\n  \"papers\": [\n"), "{}", pretty);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&compact).unwrap(),
+            serde_json::from_str::<serde_json::Value>(&pretty).unwrap()
+        );
+    }
+
+    // State for router tests: the agent is answered by `llm` and no database is reachable, so
+    // recording history fails fast and is only logged
+    fn test_state(llm: &wiremock::MockServer) -> AppState {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(100))
+            .connect_lazy("postgres://arxiv@127.0.0.1:1/arxiv")
+            .unwrap();
+        AppState {
+            client: ProviderClient::OpenAi(
+                rig::providers::openai::Client::from_url("test-key", &llm.uri()),
+                agent::ModelParams::default(),
+            ),
+            model: "gpt-4".to_string(),
+            preamble: agent::resolve_preamble(None).unwrap(),
+            results: TtlCache::new(cache::DEFAULT_TTL, cache::DEFAULT_MAX_ENTRIES),
+            fetches: Arc::new(TtlCache::new(
+                cache::DEFAULT_TTL,
+                cache::DEFAULT_MAX_ENTRIES,
+            )),
+            max_query_chars: DEFAULT_MAX_QUERY_CHARS,
+            history: History::new(pool.clone()),
+            watches: Arc::new(Watches::new(pool)),
+            readiness: TtlCache::new(READINESS_CACHE_TTL, 1),
+            llm_key_configured: true,
+            api_keys: Vec::new(),
+            rate_limiter: None,
+            search_slots: Semaphore::new(DEFAULT_MAX_CONCURRENT_SEARCHES),
+            templates: tools::templates().unwrap(),
+        }
+    }
+
+    // Bind the app to a free port and serve it the way Shuttle does, peer addresses included
+    async fn serve(state: AppState) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = router(Arc::new(state)).into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn rate_limits_clients_without_a_forwarded_address_by_their_peer_address() {
+        let llm = wiremock::MockServer::start().await;
+        let base_url = serve(AppState {
+            rate_limiter: Some(RateLimiter::per_minute(2)),
+            ..test_state(&llm)
+        })
+        .await;
+        let client = reqwest::Client::new();
+        let export = || {
+            client
+                .post(format!("{}/api/export/ris", base_url))
+                .json(&json!([]))
+                .send()
+        };
+
+        for _ in 0..2 {
+            assert_eq!(export().await.unwrap().status(), StatusCode::OK);
+        }
+        let refused = export().await.unwrap();
+        assert_eq!(refused.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = refused.headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        // One request comes back every 30 seconds
+        assert!((1..=30).contains(&retry_after), "{}", retry_after);
+        let body: serde_json::Value = refused.json().await.unwrap();
+        assert_eq!(body["error"]["kind"], "rate_limited");
+        assert_eq!(
+            body["error"]["message"],
+            format!("Too many requests, try again in {} seconds", retry_after)
+        );
+
+        // A client behind the proxy has a bucket of its own
+        let forwarded = client
+            .post(format!("{}/api/export/ris", base_url))
+            .header("x-forwarded-for", "203.0.113.9")
+            .json(&json!([]))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(forwarded.status(), StatusCode::OK);
+    }
+
+    // Tests pointing ARXIV_BASE_URL somewhere hold this, the environment is the whole process's
+    static ARXIV_ENV: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    // Send the agent's tools and the readiness probe to `arxiv` for as long as the guard lives
+    async fn arxiv_at(arxiv: &wiremock::MockServer) -> tokio::sync::MutexGuard<'static, ()> {
+        let guard = ARXIV_ENV.lock().await;
+        std::env::set_var("ARXIV_BASE_URL", format!("{}/api/query", arxiv.uri()));
+        guard
+    }
+
+    // An OpenAI chat completion carrying `message`
+    fn completion(message: serde_json::Value, finish_reason: &str) -> wiremock::ResponseTemplate {
+        wiremock::ResponseTemplate::new(200).set_body_json(json!({
+            "id": "chatcmpl-test",
+            "object": "chat.completion",
+            "created": 1_700_000_000,
+            "model": "gpt-4",
+            "choices": [{
+                "index": 0,
+                "message": message,
+                "logprobs": null,
+                "finish_reason": finish_reason,
+            }],
+            "usage": { "prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2 },
+        }))
+    }
+
+    // The model answering in words
+    fn answer(content: &str) -> wiremock::ResponseTemplate {
+        completion(json!({ "role": "assistant", "content": content }), "stop")
+    }
+
+    // The model calling one of the agent's tools
+    fn tool_call(name: &str, arguments: serde_json::Value) -> wiremock::ResponseTemplate {
+        completion(
+            json!({
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [{
+                    "id": "call_0",
+                    "type": "function",
+                    "function": { "name": name, "arguments": arguments.to_string() },
+                }],
+            }),
+            "tool_calls",
+        )
+    }
+
+    // The model answering with search results, as the preamble asks it to
+    fn results(papers: &[Paper]) -> wiremock::ResponseTemplate {
+        let result = SearchResult {
+            papers: papers.to_vec(),
+            total_results: papers.len(),
+            start: 0,
+            related_to: None,
+        };
+        answer(&serde_json::to_string(&result).unwrap())
+    }
+
+    // Have the mock LLM give every completion request the same response
+    async fn llm_responds(llm: &wiremock::MockServer, response: wiremock::ResponseTemplate) {
+        wiremock::Mock::given(wiremock::matchers::path("/chat/completions"))
+            .respond_with(response)
+            .mount(llm)
+            .await;
+    }
+
+    async fn search(base_url: &str, body: serde_json::Value, accept: &str) -> (StatusCode, String) {
+        let response = reqwest::Client::new()
+            .post(format!("{}/api/search", base_url))
+            .header(header::ACCEPT, accept)
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        (response.status(), response.text().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn tags_every_response_with_its_request_id() {
+        let llm = wiremock::MockServer::start().await;
+        let base_url = serve(test_state(&llm)).await;
+        let client = reqwest::Client::new();
+        let request_id = |response: &reqwest::Response| {
+            response.headers()["x-request-id"]
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let response = client
+            .get(format!("{}/health", base_url))
+            .send()
+            .await
+            .unwrap();
+        // A fresh UUID when the client sent none
+        assert_eq!(request_id(&response).len(), 36);
 
-    Ok(router.into())
-}
\ No newline at end of file
+        let response = client
+            .get(format!("{}/health", base_url))
+            .header("x-request-id", "trace-me")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(request_id(&response), "trace-me");
+
+        // CORS answers preflights without reaching the routes, they still get one
+        let response = client
+            .request(reqwest::Method::OPTIONS, format!("{}/api/search", base_url))
+            .header("origin", "https://example.org")
+            .header("access-control-request-method", "POST")
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success(), "{}", response.status());
+        assert_eq!(request_id(&response).len(), 36);
+    }
+
+    #[tokio::test]
+    async fn rejects_an_empty_search_without_asking_the_agent() {
+        let llm = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::any())
+            .respond_with(answer("unused"))
+            .expect(0)
+            .mount(&llm)
+            .await;
+        let base_url = serve(test_state(&llm)).await;
+
+        let (status, body) =
+            search(&base_url, json!({ "query": " \n\t " }), "application/json").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({ "error": { "kind": "bad_request", "message": "The search query is empty" } })
+        );
+    }
+
+    #[tokio::test]
+    async fn blames_arxiv_when_it_is_down_during_a_search() {
+        let arxiv = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/api/query"))
+            .respond_with(wiremock::ResponseTemplate::new(503))
+            .mount(&arxiv)
+            .await;
+        let _arxiv = arxiv_at(&arxiv).await;
+        let llm = wiremock::MockServer::start().await;
+        llm_responds(
+            &llm,
+            tool_call("search_arxiv", json!({ "query": "transformers" })),
+        )
+        .await;
+        let base_url = serve(test_state(&llm)).await;
+
+        let (status, body) = search(
+            &base_url,
+            json!({ "query": "transformers" }),
+            "application/json",
+        )
+        .await;
+        // arXiv's own 503 is passed on, not reported as the LLM failing
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["error"]["kind"], "network");
+        assert!(!arxiv.received_requests().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn falls_back_on_the_agents_prose_when_it_returns_no_results() {
+        let llm = wiremock::MockServer::start().await;
+        llm_responds(&llm, answer("I couldn't find any papers on that, sorry.")).await;
+        let base_url = serve(test_state(&llm)).await;
+
+        let (status, body) = search(
+            &base_url,
+            json!({ "query": "transformers" }),
+            "application/json",
+        )
+        .await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["error"]["kind"], "agent_response_not_json");
+
+        // The search page shows the prose under a warning instead
+        let (status, body) =
+            search(&base_url, json!({ "query": "transformers" }), "text/html").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("class=\"agent-warning\""), "{}", body);
+        assert!(body.contains("I couldn&#x27;t find any papers"), "{}", body);
+    }
+
+    // A fresh app probing a mock arXiv answering with `arxiv_status`, so no earlier probe is
+    // reused from the readiness cache
+    async fn serve_with_arxiv(
+        arxiv_status: u16,
+    ) -> (
+        String,
+        tokio::sync::MutexGuard<'static, ()>,
+        wiremock::MockServer,
+    ) {
+        let arxiv = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/api/query"))
+            .and(wiremock::matchers::query_param("max_results", "1"))
+            .respond_with(wiremock::ResponseTemplate::new(arxiv_status))
+            .mount(&arxiv)
+            .await;
+        let guard = arxiv_at(&arxiv).await;
+        let llm = wiremock::MockServer::start().await;
+        (serve(test_state(&llm)).await, guard, arxiv)
+    }
+
+    async fn get_json(url: String) -> (StatusCode, serde_json::Value) {
+        let response = reqwest::get(url).await.unwrap();
+        (response.status(), response.json().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn is_live_and_ready_only_while_arxiv_answers() {
+        let (base_url, arxiv_env, _arxiv) = serve_with_arxiv(200).await;
+        let (status, body) = get_json(format!("{}/health", base_url)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "status": "ok" }));
+        let (status, body) = get_json(format!("{}/ready", base_url)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ok");
+        // The next app needs ARXIV_BASE_URL pointed at its own mock
+        drop(arxiv_env);
+
+        let (base_url, _arxiv_env, _arxiv) = serve_with_arxiv(500).await;
+        // Still alive, just not ready to take searches
+        let (status, _) = get_json(format!("{}/health", base_url)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = get_json(format!("{}/ready", base_url)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "unavailable");
+    }
+
+    #[tokio::test]
+    async fn reports_each_dependency_on_the_probe_aliases() {
+        let (base_url, _arxiv_env, arxiv) = serve_with_arxiv(200).await;
+        let (status, body) = get_json(format!("{}/healthz", base_url)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "status": "ok" }));
+
+        let (status, body) = get_json(format!("{}/readyz", base_url)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ok");
+        let dependencies = body["dependencies"].as_array().unwrap();
+        assert_eq!(dependencies.len(), 2);
+        assert_eq!(dependencies[0]["name"], "arxiv");
+        assert_eq!(dependencies[0]["ok"], true);
+        assert!(dependencies[0]["latency_ms"].is_u64());
+        assert!(dependencies[0].get("error").is_none());
+        assert_eq!(dependencies[1], json!({ "name": "llm", "ok": true }));
+
+        // A second probe within the TTL is answered from the cache
+        let (status, _) = get_json(format!("{}/readyz", base_url)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(arxiv.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn says_which_dependencies_are_unusable() {
+        let arxiv = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/api/query"))
+            .respond_with(wiremock::ResponseTemplate::new(503))
+            .mount(&arxiv)
+            .await;
+        let _arxiv_env = arxiv_at(&arxiv).await;
+        let llm = wiremock::MockServer::start().await;
+        let base_url = serve(AppState {
+            llm_key_configured: false,
+            ..test_state(&llm)
+        })
+        .await;
+
+        let (status, body) = get_json(format!("{}/readyz", base_url)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "unavailable");
+        let arxiv_status = &body["dependencies"][0];
+        assert_eq!(arxiv_status["name"], "arxiv");
+        assert_eq!(arxiv_status["ok"], false);
+        assert!(
+            arxiv_status["error"].as_str().unwrap().contains("503"),
+            "{}",
+            arxiv_status
+        );
+        assert_eq!(
+            body["dependencies"][1],
+            json!({
+                "name": "llm",
+                "ok": false,
+                "error": "No API key is configured for the LLM provider",
+            })
+        );
+        // Liveness doesn't depend on either of them
+        let (status, _) = get_json(format!("{}/healthz", base_url)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn turns_away_searches_beyond_the_concurrency_limit() {
+        let llm = wiremock::MockServer::start().await;
+        let slow = results(&[Paper::fixture("Attention Is All You Need")])
+            .set_delay(Duration::from_millis(500));
+        llm_responds(&llm, slow).await;
+        let base_url = serve(AppState {
+            search_slots: Semaphore::new(2),
+            ..test_state(&llm)
+        })
+        .await;
+
+        // Distinct queries, so none of them is answered from another's cached results
+        let searches = (0..5).map(|i| {
+            search(
+                &base_url,
+                json!({ "query": format!("transformers {}", i) }),
+                "application/json",
+            )
+        });
+        let responses = futures::future::join_all(searches).await;
+
+        let served = responses
+            .iter()
+            .filter(|(status, _)| *status == StatusCode::OK)
+            .count();
+        assert_eq!(served, 2);
+        for (status, body) in responses
+            .iter()
+            .filter(|(status, _)| *status != StatusCode::OK)
+        {
+            assert_eq!(*status, StatusCode::SERVICE_UNAVAILABLE);
+            let body: serde_json::Value = serde_json::from_str(body).unwrap();
+            assert_eq!(body["error"]["kind"], "overloaded");
+        }
+
+        // The slots are free again once those searches are done
+        let (status, _) = search(
+            &base_url,
+            json!({ "query": "attention" }),
+            "application/json",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn searches_from_a_shareable_link() {
+        let llm = wiremock::MockServer::start().await;
+        llm_responds(
+            &llm,
+            results(&[Paper::fixture("Attention Is All You Need")]),
+        )
+        .await;
+        let base_url = serve(test_state(&llm)).await;
+
+        let response = reqwest::Client::new()
+            .get(format!("{}/api/search", base_url))
+            .query(&[
+                ("q", "attention is all"),
+                ("max_results", "5"),
+                ("format", "json"),
+            ])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::VARY], "accept");
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["query"], "attention is all");
+        assert_eq!(body["papers"][0]["title"], "Attention Is All You Need");
+
+        // The query parameters reach the agent like a POSTed search's fields
+        let requests = llm.received_requests().await.unwrap();
+        let prompt: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        let prompt = prompt["messages"].as_array().unwrap().last().unwrap()["content"].clone();
+        assert_eq!(prompt, "attention is all (return at most 5 papers)");
+
+        let response = reqwest::get(format!("{}/api/search?max_results=5", base_url))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn summarizes_the_papers_it_is_given() {
+        let llm = wiremock::MockServer::start().await;
+        llm_responds(
+            &llm,
+            answer("Both papers replace recurrence with attention."),
+        )
+        .await;
+        let base_url = serve(test_state(&llm)).await;
+        let summarize = |papers: Vec<Paper>| {
+            reqwest::Client::new()
+                .post(format!("{}/api/summarize", base_url))
+                .json(&papers)
+                .send()
+        };
+
+        let papers = vec![
+            Paper::fixture("Attention Is All You Need"),
+            Paper::fixture("Transformer-XL"),
+        ];
+        let response = summarize(papers).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            response.text().await.unwrap(),
+            "Both papers replace recurrence with attention."
+        );
+        // The papers are what the summarizer is prompted with
+        let requests = llm.received_requests().await.unwrap();
+        let request: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        let prompt = request["messages"].as_array().unwrap().last().unwrap()["content"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let prompted: Vec<Paper> = serde_json::from_str(&prompt).unwrap();
+        assert_eq!(prompted.len(), 2);
+        assert_eq!(prompted[1].title, "Transformer-XL");
+
+        let response = summarize(Vec::new()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(llm.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn answers_in_the_format_the_accept_header_prefers() {
+        let llm = wiremock::MockServer::start().await;
+        llm_responds(
+            &llm,
+            results(&[Paper::fixture("Attention Is All You Need")]),
+        )
+        .await;
+        let base_url = serve(test_state(&llm)).await;
+
+        let cases = [
+            (
+                "application/json",
+                "application/json",
+                "\"title\":\"Attention Is All You Need\"",
+            ),
+            (
+                "text/html",
+                "text/html; charset=utf-8",
+                "<h3>Attention Is All You Need</h3>",
+            ),
+            (
+                "text/markdown",
+                "text/markdown; charset=utf-8",
+                "| Attention Is All You Need |  |  | http://arxiv.org/abs/2401.00001v1 |",
+            ),
+            ("text/csv", "text/csv; charset=utf-8", "title,authors,"),
+            (
+                "application/x-research-info-systems",
+                "application/x-research-info-systems",
+                "TI  - Attention Is All You Need\r\n",
+            ),
+            (
+                "application/x-bibtex",
+                "application/x-bibtex; charset=utf-8",
+                "title = {Attention Is All You Need},",
+            ),
+            // The most preferred type we have wins, whatever its position
+            (
+                "text/markdown;q=0.5, application/json",
+                "application/json",
+                "\"papers\":[",
+            ),
+            // Browsers and anything asking for a type we don't have get the page fragment
+            (
+                "image/png",
+                "text/html; charset=utf-8",
+                "<h3>Attention Is All You Need</h3>",
+            ),
+            (
+                "*/*",
+                "text/html; charset=utf-8",
+                "<h3>Attention Is All You Need</h3>",
+            ),
+        ];
+        for (accept, content_type, excerpt) in cases {
+            let response = reqwest::Client::new()
+                .post(format!("{}/api/search", base_url))
+                .header(header::ACCEPT, accept)
+                .json(&json!({ "query": "attention" }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", accept);
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                content_type,
+                "{}",
+                accept
+            );
+            let body = response.text().await.unwrap();
+            assert!(body.contains(excerpt), "{}: {}", accept, body);
+        }
+    }
+
+    #[tokio::test]
+    async fn validates_searches_before_they_reach_the_agent() {
+        let llm = wiremock::MockServer::start().await;
+        llm_responds(
+            &llm,
+            results(&[Paper::fixture("Attention Is All You Need")]),
+        )
+        .await;
+        let base_url = serve(AppState {
+            max_query_chars: 20,
+            ..test_state(&llm)
+        })
+        .await;
+        let search_json = |body: serde_json::Value| {
+            let request = reqwest::Client::new()
+                .post(format!("{}/api/search.json", base_url))
+                .json(&body);
+            async move {
+                let response = request.send().await.unwrap();
+                let status = response.status();
+                (status, response.json::<serde_json::Value>().await.unwrap())
+            }
+        };
+
+        for (body, message) in [
+            (
+                json!({ "query": "" }),
+                "The search query is empty".to_string(),
+            ),
+            (
+                json!({ "query": " \u{7}\t " }),
+                "The search query is empty".to_string(),
+            ),
+            (
+                json!({ "query": "a".repeat(21) }),
+                "The search query is 21 characters long, the limit is 20".to_string(),
+            ),
+            (
+                json!({ "query": "attention", "max_results": 0 }),
+                format!(
+                    "max_results must be between 1 and {}, got 0",
+                    MAX_RESULTS_LIMIT
+                ),
+            ),
+        ] {
+            let (status, response) = search_json(body.clone()).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+            assert_eq!(response["error"]["message"], message, "{}", body);
+        }
+        assert!(llm.received_requests().await.unwrap().is_empty());
+
+        // Control characters are dropped and the rest trimmed, which fits in the limit again
+        let (status, response) =
+            search_json(json!({ "query": "  attention\u{0}\u{1b} is all  ", "max_results": 3 }))
+                .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response["query"], "attention is all");
+        assert_eq!(response["papers"][0]["title"], "Attention Is All You Need");
+    }
+
+    #[tokio::test]
+    async fn serves_json_to_scripts_and_html_to_the_page() {
+        let llm = wiremock::MockServer::start().await;
+        llm_responds(
+            &llm,
+            results(&[Paper::fixture("Attention Is All You Need")]),
+        )
+        .await;
+        let base_url = serve(test_state(&llm)).await;
+        let post = |path: &str, body: serde_json::Value| {
+            reqwest::Client::new()
+                .post(format!("{}{}", base_url, path))
+                .header(header::ACCEPT, "text/html")
+                .json(&body)
+                .send()
+        };
+
+        // The JSON route ignores Accept, for its results and its errors alike
+        let response = post("/api/search.json", json!({ "query": "attention" }))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["query"], "attention");
+        assert_eq!(body["total_results"], 1);
+        assert_eq!(body["papers"][0]["title"], "Attention Is All You Need");
+
+        let response = post("/api/search.json", json!({ "query": "" }))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["kind"], "bad_request");
+
+        // The page's route keeps answering in HTML, errors included
+        let response = post("/api/search", json!({ "query": "attention" }))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+        assert!(response
+            .text()
+            .await
+            .unwrap()
+            .contains("<h3>Attention Is All You Need</h3>"));
+
+        let response = post("/api/search", json!({ "query": "" })).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.text().await.unwrap(),
+            "<p class=\"error\">The search query is empty</p>"
+        );
+    }
+
+    // Every event of a streamed search, as (name, data) pairs in the order they came
+    async fn stream(base_url: &str, query: &str) -> Vec<(String, String)> {
+        let response = reqwest::Client::new()
+            .get(format!("{}/api/search/stream", base_url))
+            .query(&[("query", query)])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+        // The stream closes once the terminal event is sent
+        let body = response.text().await.unwrap();
+        body.split("\n\n")
+            .filter_map(|block| {
+                let field = |name: &str| {
+                    block
+                        .lines()
+                        .find_map(|line| line.strip_prefix(name))
+                        .map(|value| value.trim_start().to_string())
+                };
+                Some((field("event:")?, field("data:").unwrap_or_default()))
+            })
+            .collect()
+    }
+
+    fn names(events: &[(String, String)]) -> Vec<&str> {
+        events.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn streams_progress_then_the_papers_then_done() {
+        let llm = wiremock::MockServer::start().await;
+        llm_responds(
+            &llm,
+            results(&[Paper::fixture("Attention Is All You Need")]),
+        )
+        .await;
+        let base_url = serve(test_state(&llm)).await;
+
+        let events = stream(&base_url, "attention").await;
+        let papers = events
+            .iter()
+            .position(|(name, _)| name == "papers")
+            .unwrap();
+        assert!(names(&events[..papers]).contains(&"status"));
+        assert_eq!(names(&events[papers..]), ["papers", "done"]);
+        let payload: serde_json::Value = serde_json::from_str(&events[papers].1).unwrap();
+        assert_eq!(payload["query"], "attention");
+        assert_eq!(payload["papers"][0]["title"], "Attention Is All You Need");
+    }
+
+    #[tokio::test]
+    async fn ends_the_stream_with_an_error_when_the_search_fails() {
+        let llm = wiremock::MockServer::start().await;
+        llm_responds(&llm, answer("Sorry, I couldn't find anything.")).await;
+        let base_url = serve(test_state(&llm)).await;
+
+        let events = stream(&base_url, "attention").await;
+        assert!(names(&events).contains(&"status"));
+        assert!(!names(&events).contains(&"papers"));
+        let (last, data) = events.last().unwrap();
+        assert_eq!(last, "error");
+        let error: serde_json::Value = serde_json::from_str(data).unwrap();
+        assert_eq!(error["error"]["kind"], "agent_response_not_json");
+
+        // Validation fails before anything else gets sent
+        let events = stream(&base_url, " ").await;
+        assert_eq!(names(&events), ["error"]);
+    }
+
+    #[tokio::test]
+    async fn streams_each_paper_before_the_full_results() {
+        let llm = wiremock::MockServer::start().await;
+        llm_responds(
+            &llm,
+            results(&[
+                Paper::fixture("Attention Is All You Need"),
+                Paper::fixture("BERT"),
+            ]),
+        )
+        .await;
+        let base_url = serve(test_state(&llm)).await;
+
+        let events = stream(&base_url, "attention").await;
+        assert_eq!(events[0].1, "Searching arXiv for \"attention\"");
+        let stages: Vec<&str> = names(&events)
+            .into_iter()
+            .filter(|name| *name != "status")
+            .collect();
+        assert_eq!(
+            stages,
+            ["searching", "parsing", "paper", "paper", "papers", "done"]
+        );
+        let titles: Vec<String> = events
+            .iter()
+            .filter(|(name, _)| name == "paper")
+            .map(|(_, data)| {
+                let paper: serde_json::Value = serde_json::from_str(data).unwrap();
+                paper["title"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(titles, ["Attention Is All You Need", "BERT"]);
+    }
+
+    #[tokio::test]
+    async fn chats_over_a_websocket_opened_with_the_key_as_subprotocol() {
+        use futures::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Error, Message};
+
+        let llm = wiremock::MockServer::start().await;
+        llm_responds(
+            &llm,
+            results(&[Paper::fixture("Attention Is All You Need")]),
+        )
+        .await;
+        let mut state = test_state(&llm);
+        state.api_keys = vec!["chat-key".to_string()];
+        let url = serve(state).await.replace("http://", "ws://") + "/ws/chat";
+
+        let refused = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .unwrap_err();
+        let Error::Http(response) = refused else {
+            panic!("expected the handshake to be refused, got {:?}", refused);
+        };
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let mut request = url.as_str().into_client_request().unwrap();
+        request.headers_mut().insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static("bearer, chat-key"),
+        );
+        let (mut socket, response) = tokio_tungstenite::connect_async(request).await.unwrap();
+        assert_eq!(response.headers()[header::SEC_WEBSOCKET_PROTOCOL], "bearer");
+
+        // Each frame gets exactly one reply, and a bad one doesn't end the session
+        let mut replies = Vec::new();
+        for content in ["attention", "  ", "and transformers?"] {
+            let frame = json!({ "type": "user", "content": content });
+            socket.send(Message::Text(frame.to_string())).await.unwrap();
+            let reply = socket.next().await.unwrap().unwrap();
+            replies
+                .push(serde_json::from_str::<serde_json::Value>(reply.to_text().unwrap()).unwrap());
+        }
+        assert_eq!(replies[0]["type"], "papers");
+        assert_eq!(
+            replies[0]["papers"][0]["title"],
+            "Attention Is All You Need"
+        );
+        assert_eq!(
+            replies[1],
+            json!({ "type": "error", "error": "bad_request", "detail": "The message is empty" })
+        );
+        assert_eq!(replies[2]["type"], "papers");
+    }
+
+    #[tokio::test]
+    async fn answers_in_the_format_the_request_names() {
+        let llm = wiremock::MockServer::start().await;
+        llm_responds(
+            &llm,
+            results(&[Paper::fixture("Attention Is All You Need")]),
+        )
+        .await;
+        let base_url = serve(test_state(&llm)).await;
+
+        let cases = [
+            (
+                "html",
+                "text/html; charset=utf-8",
+                None,
+                "<h3>Attention Is All You Need</h3>",
+            ),
+            (
+                "json",
+                "application/json",
+                None,
+                "\"title\":\"Attention Is All You Need\"",
+            ),
+            (
+                "markdown",
+                "text/markdown; charset=utf-8",
+                None,
+                "| Attention Is All You Need |",
+            ),
+            (
+                "csv",
+                "text/csv; charset=utf-8",
+                Some("attachment; filename=\"papers.csv\""),
+                "Attention Is All You Need",
+            ),
+            (
+                "ris",
+                "application/x-research-info-systems",
+                Some("attachment; filename=\"papers.ris\""),
+                "TI  - Attention Is All You Need\r\n",
+            ),
+            (
+                "BibTeX",
+                "application/x-bibtex; charset=utf-8",
+                Some("attachment; filename=\"papers.bib\""),
+                "title = {Attention Is All You Need},",
+            ),
+        ];
+        for (format, content_type, disposition, excerpt) in cases {
+            // The named format wins over whatever Accept asks for
+            let response = reqwest::Client::new()
+                .post(format!("{}/api/search", base_url))
+                .header(header::ACCEPT, "image/png")
+                .json(&json!({ "query": "attention", "format": format }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", format);
+            let headers = response.headers();
+            assert_eq!(headers[header::CONTENT_TYPE], content_type, "{}", format);
+            assert_eq!(
+                headers
+                    .get(header::CONTENT_DISPOSITION)
+                    .map(|value| value.to_str().unwrap()),
+                disposition,
+                "{}",
+                format
+            );
+            let body = response.text().await.unwrap();
+            assert!(body.contains(excerpt), "{}: {}", format, body);
+        }
+
+        let (status, body) = search(
+            &base_url,
+            json!({ "query": "attention", "format": "pdf" }),
+            "application/json",
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["error"]["kind"], "bad_request");
+        assert_eq!(
+            body["error"]["message"],
+            format!(
+                "Unknown format \"pdf\", supported formats are {}",
+                SUPPORTED_FORMATS
+            )
+        );
+    }
+}
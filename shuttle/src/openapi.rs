@@ -0,0 +1,142 @@
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::history::HistoryEntry;
+use crate::tools::{Author, GroupBy, Paper, SearchResult, SortBy, SortKey};
+use crate::watches::{NewWatch, Watch};
+use crate::{ErrorBody, ErrorDetail, OutputFormat, SearchRequest, SearchResponse, WatchNewPapers};
+
+// The API contract, served at /api/openapi.json and browsable at /api/docs
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "arXiv research agent", description = "Search arXiv through an AI agent"),
+    paths(
+        crate::search_papers,
+        crate::search_papers_get,
+        crate::search_papers_json,
+        crate::search_stream,
+        crate::summarize,
+        crate::feed,
+        crate::export_ris,
+        crate::search_history,
+        crate::create_watch,
+        crate::list_watches,
+        crate::watch_new_papers,
+        crate::download_pdf,
+        crate::abstract_page,
+        crate::full_text,
+        crate::chat::chat_socket,
+        crate::health,
+        crate::ready,
+    ),
+    components(schemas(
+        Author,
+        ErrorBody,
+        ErrorDetail,
+        GroupBy,
+        HistoryEntry,
+        NewWatch,
+        OutputFormat,
+        Paper,
+        SearchRequest,
+        SearchResponse,
+        SearchResult,
+        SortBy,
+        SortKey,
+        Watch,
+        WatchNewPapers,
+    )),
+    modifiers(&BearerAuth, &ProbeAliases),
+    security(("bearer" = []))
+)]
+pub struct ApiDoc;
+
+pub fn docs() -> SwaggerUi {
+    SwaggerUi::new("/api/docs").url("/api/openapi.json", ApiDoc::openapi())
+}
+
+// API_KEYS turns on bearer tokens for every /api route
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+// /healthz and /readyz answer exactly like /health and /ready
+struct ProbeAliases;
+
+impl Modify for ProbeAliases {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for (path, alias) in [("/health", "/healthz"), ("/ready", "/readyz")] {
+            let Some(mut item) = openapi.paths.paths.get(path).cloned() else {
+                continue;
+            };
+            // Operation ids have to be unique across the spec
+            for operation in item.operations.values_mut() {
+                operation.operation_id = operation.operation_id.take().map(|id| id + "z");
+            }
+            openapi.paths.paths.insert(alias.to_string(), item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_a_spec_listing_every_route() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = axum::Router::new().merge(docs());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let spec = reqwest::get(format!("http://{}/api/openapi.json", address))
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let spec: serde_json::Value = serde_json::from_str(&spec).unwrap();
+        let mut paths: Vec<&str> = spec["paths"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        paths.sort_unstable();
+        // Every route of the app but the index page, static files and these docs themselves
+        assert_eq!(
+            paths,
+            [
+                "/api/abstract/{id}",
+                "/api/export/ris",
+                "/api/feed",
+                "/api/fulltext/{id}",
+                "/api/history",
+                "/api/pdf/{id}",
+                "/api/search",
+                "/api/search.json",
+                "/api/search/stream",
+                "/api/summarize",
+                "/api/watches",
+                "/api/watches/{id}/new",
+                "/health",
+                "/healthz",
+                "/ready",
+                "/readyz",
+                "/ws/chat",
+            ]
+        );
+        assert_eq!(spec["paths"]["/readyz"]["get"]["operationId"], "readyz");
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_REQUESTS_PER_MINUTE: u32 = 30;
+
+// Token bucket per client: bursts up to the per-minute limit, refilled continuously
+pub struct RateLimiter {
+    state: Mutex<Buckets>,
+    capacity: f64,
+    refill_per_sec: f64,
+}
+
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    last_sweep: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn per_minute(requests: u32) -> Self {
+        let capacity = f64::from(requests.max(1));
+        Self {
+            state: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+            capacity,
+            refill_per_sec: capacity / 60.0,
+        }
+    }
+
+    // Take a token for `client`, or say how long until one is available
+    pub fn check(&self, client: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        // A bucket idle for this long has refilled completely, the same as having none,
+        // so dropping it keeps memory bounded without letting anyone through early
+        let refill_time = Duration::from_secs_f64(self.capacity / self.refill_per_sec);
+        if now.duration_since(state.last_sweep) >= refill_time {
+            state
+                .buckets
+                .retain(|_, bucket| now.duration_since(bucket.updated) < refill_time);
+            state.last_sweep = now;
+        }
+
+        let bucket = state.buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / self.refill_per_sec;
+            Err(Duration::from_secs_f64(wait))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_a_client_over_its_limit_but_not_the_others() {
+        let limiter = RateLimiter::per_minute(3);
+        for _ in 0..3 {
+            assert!(limiter.check("203.0.113.1").is_ok());
+        }
+        let wait = limiter.check("203.0.113.1").unwrap_err();
+        // One token comes back every 20 seconds
+        assert!(wait > Duration::from_secs(19) && wait <= Duration::from_secs(20));
+
+        assert!(limiter.check("203.0.113.2").is_ok());
+    }
+}
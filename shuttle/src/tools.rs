@@ -3,121 +3,2588 @@ use quick_xml::{
     events::{BytesEnd, BytesStart, BytesText, Event},
     Reader,
 };
-use rig::{completion::ToolDefinition, tool::Tool};
+use rig::{completion::ToolDefinition, extractor::ExtractionError, tool::Tool};
 use serde_json::json;
+use futures::{Stream, StreamExt};
+use std::{
+    borrow::Cow,
+    cmp::{Ordering, Reverse},
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    future::Future,
+    ops::RangeInclusive,
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::agent::ProviderClient;
+use crate::categories;
+
+const ARXIV_URL: &str = "http://export.arxiv.org/api/query";
+const ARXIV_PDF_URL: &str = "https://arxiv.org/pdf";
+const ARXIV_ABS_URL: &str = "https://arxiv.org/abs";
+const ARXIV_HTML_URL: &str = "https://arxiv.org/html";
+const PDF_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+const READINESS_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_USER_AGENT: &str = concat!(
+    "rig-arxiv-agent-example/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/0xPlaygrounds/rig-arxiv-agent-example)"
+);
+
+const COMPARE_PREAMBLE: &str =
+    "Compare the two papers from their abstracts: the topic they share, \
+                                how their methods differ, and which datasets or benchmarks each \
+                                one mentions that the other does not.";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArxivError {
+    #[error("Network error: {0}")]
+    Network(#[from] reqwest::Error),
+    #[error("XML parsing error: {0}")]
+    XmlParsing(#[from] quick_xml::Error),
+    #[error("No results found")]
+    NoResults,
+    #[error("UTF-8 decoding error: {0}")]
+    Utf8Error(#[from] std::str::Utf8Error),
+    #[error("Invalid arXiv id: {0}")]
+    InvalidId(String),
+    #[error("Expected a PDF but got content type {0:?}")]
+    UnexpectedContentType(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("No paper found for arXiv id {0:?}")]
+    PaperNotFound(String),
+    #[error("The agent did not return a JSON array of papers: {0:?}")]
+    AgentResponseNotJson(String),
+    #[error("arXiv is rate limiting requests, retry after {} seconds", retry_after.as_secs())]
+    RateLimited { retry_after: Duration },
+    #[error("arXiv returned an entry without a {0}")]
+    MalformedEntry(&'static str),
+    #[error("arXiv has no HTML full text for {0}, usually only recent papers do")]
+    FullTextUnavailable(String),
+    #[error("Invalid since {0:?}, expected a number and d, w, m or y, e.g. 7d")]
+    InvalidSince(String),
+}
+
+impl ArxivError {
+    // The variant's name, for API clients that branch on the kind of failure
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ArxivError::Network(_) => "network",
+            ArxivError::XmlParsing(_) => "xml_parsing",
+            ArxivError::NoResults => "no_results",
+            ArxivError::Utf8Error(_) => "utf8_error",
+            ArxivError::InvalidId(_) => "invalid_id",
+            ArxivError::UnexpectedContentType(_) => "unexpected_content_type",
+            ArxivError::Io(_) => "io",
+            ArxivError::PaperNotFound(_) => "paper_not_found",
+            ArxivError::AgentResponseNotJson(_) => "agent_response_not_json",
+            ArxivError::RateLimited { .. } => "rate_limited",
+            ArxivError::MalformedEntry(_) => "malformed_entry",
+            ArxivError::FullTextUnavailable(_) => "full_text_unavailable",
+            ArxivError::InvalidSince(_) => "invalid_since",
+        }
+    }
+}
+
+// Struct to hold paper metadata
+#[derive(
+    Debug, serde::Deserialize, serde::Serialize, Clone, schemars::JsonSchema, utoipa::ToSchema,
+)]
+pub struct Paper {
+    pub title: String,
+    pub authors: Vec<Author>,
+    pub abstract_text: String,
+    pub url: String,
+    pub categories: Vec<String>,
+    #[serde(default)]
+    pub primary_category: Option<String>,
+    #[serde(default)]
+    pub published: Option<String>,
+    // Only present once the preprint has a published version
+    #[serde(default)]
+    pub doi: Option<String>,
+    // The queries that found this paper, set by multi_search
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub found_by: Vec<String>,
+    // Canonical arXiv id without its version, e.g. 2310.06825 or hep-th/9901001
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arxiv_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+    // Older versions that came back in the same results and were collapsed into this one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub superseded_versions: Vec<u32>,
+}
+
+// Reference-list styles a paper can be cited in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CitationStyle {
+    Apa,
+    Mla,
+}
+
+// APA 7 lists up to 20 authors, past that the first 19, an ellipsis and the last
+const APA_MAX_AUTHORS: usize = 20;
+
+// Words that make an author a group, cited as written rather than as surname and initials
+const GROUP_AUTHOR_WORDS: &[&str] = &["collaboration", "consortium", "team", "group", "project"];
+
+// MLA abbreviates every month but May, June and July
+const MLA_MONTHS: [&str; 12] = [
+    "Jan.", "Feb.", "Mar.", "Apr.", "May", "June", "July", "Aug.", "Sept.", "Oct.", "Nov.", "Dec.",
+];
+
+impl Paper {
+    fn new() -> Self {
+        Self {
+            title: String::new(),
+            authors: Vec::new(),
+            abstract_text: String::new(),
+            url: String::new(),
+            categories: Vec::new(),
+            primary_category: None,
+            published: None,
+            doi: None,
+            found_by: Vec::new(),
+            arxiv_id: None,
+            version: None,
+            superseded_versions: Vec::new(),
+        }
+    }
+
+    // A reference-list entry citing the arXiv preprint, e.g. for pasting into a bibliography
+    pub fn to_citation(&self, style: CitationStyle) -> String {
+        let title = collapse_whitespace(&normalize_latex(&self.title));
+        let id = match &self.arxiv_id {
+            Some(id) => id.clone(),
+            None => base_arxiv_id(&self.url).to_string(),
+        };
+        let authors: Vec<&str> = self
+            .authors
+            .iter()
+            .map(|author| author.name.as_str())
+            .collect();
+        match style {
+            CitationStyle::Apa => apa_citation(&authors, self.published.as_deref(), &title, &id),
+            CitationStyle::Mla => mla_citation(&authors, self.published.as_deref(), &title, &id),
+        }
+    }
+}
+
+// The paper tests start from, filling in only the fields they look at
+#[cfg(test)]
+impl Paper {
+    pub fn fixture(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            url: "http://arxiv.org/abs/2401.00001v1".to_string(),
+            ..Self::new()
+        }
+    }
+}
+
+// Author. (Year). Title (arXiv:id). arXiv. https://doi.org/10.48550/arXiv.id
+fn apa_citation(authors: &[&str], published: Option<&str>, title: &str, id: &str) -> String {
+    let year = published.and_then(|date| date.get(..4)).unwrap_or("n.d.");
+    let source = format!("arXiv. https://doi.org/10.48550/arXiv.{}", id);
+    let names: Vec<String> = authors.iter().map(|name| apa_name(name)).collect();
+    let names = match names.len() {
+        // Without authors the title takes their place
+        0 => return format!("{} (arXiv:{}). ({}). {}", title, id, year, source),
+        1 => names[0].clone(),
+        n if n <= APA_MAX_AUTHORS => format!("{}, & {}", names[..n - 1].join(", "), names[n - 1]),
+        n => format!(
+            "{}, . . . {}",
+            names[..APA_MAX_AUTHORS - 1].join(", "),
+            names[n - 1]
+        ),
+    };
+    format!(
+        "{} ({}). {} (arXiv:{}). {}",
+        with_period(&names),
+        year,
+        title,
+        id,
+        source
+    )
+}
+
+// Surname, I. I., keeping hyphenated given names hyphenated (J.-P.)
+fn apa_name(name: &str) -> String {
+    let Some((given, surname)) = split_name(name) else {
+        return collapse_whitespace(name);
+    };
+    let initials: Vec<String> = given
+        .split_whitespace()
+        .map(|word| {
+            word.split('-')
+                .filter_map(|part| part.chars().next())
+                .map(|initial| format!("{}.", initial))
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect();
+    format!("{}, {}", surname, initials.join(" "))
+}
+
+// Surname, Given, et al. "Title." arXiv, 12 June 2017, arXiv:id.
+fn mla_citation(authors: &[&str], published: Option<&str>, title: &str, id: &str) -> String {
+    let mut citation = match authors {
+        [] => String::new(),
+        [only] => format!("{} ", with_period(&mla_name(only))),
+        [first, second] => format!(
+            "{}, and {} ",
+            mla_name(first),
+            with_period(&collapse_whitespace(second))
+        ),
+        [first, ..] => format!("{}, et al. ", mla_name(first)),
+    };
+    citation.push_str(&format!("\"{}\" arXiv, ", with_period(title)));
+    if let Some(date) = published.and_then(mla_date) {
+        citation.push_str(&format!("{}, ", date));
+    }
+    citation.push_str(&format!("arXiv:{}.", id));
+    citation
+}
+
+// Surname, Given
+fn mla_name(name: &str) -> String {
+    match split_name(name) {
+        Some((given, surname)) => format!("{}, {}", surname, given),
+        None => collapse_whitespace(name),
+    }
+}
+
+// 2017-06-12T17:57:34Z as 12 June 2017
+fn mla_date(published: &str) -> Option<String> {
+    let mut parts = published.get(..10)?.split('-');
+    let year = parts.next()?;
+    let month: usize = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    let month = MLA_MONTHS.get(month.checked_sub(1)?)?;
+    Some(format!("{} {} {}", day, month, year))
+}
+
+// A person's given names and surname, with particles like "van der" kept in the surname.
+// None for one-word names and groups such as "The ATLAS Collaboration"
+fn split_name(name: &str) -> Option<(String, String)> {
+    let words: Vec<&str> = name.split_whitespace().collect();
+    let is_group = words
+        .iter()
+        .any(|word| GROUP_AUTHOR_WORDS.contains(&word.to_lowercase().as_str()));
+    if words.len() < 2 || is_group {
+        return None;
+    }
+    let mut start = words.len() - 1;
+    while start > 1 && words[start - 1].starts_with(char::is_lowercase) {
+        start -= 1;
+    }
+    Some((words[..start].join(" "), words[start..].join(" ")))
+}
+
+fn with_period(text: &str) -> String {
+    if text.ends_with(['.', '?', '!']) {
+        text.to_string()
+    } else {
+        format!("{}.", text)
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// A paper's author, with their affiliation when arXiv lists one
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(from = "AuthorJson", into = "AuthorJson")]
+pub struct Author {
+    pub name: String,
+    pub affiliation: Option<String>,
+}
+
+impl Author {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            affiliation: None,
+        }
+    }
+}
+
+// Just the name, which is what every existing output shows
+impl fmt::Display for Author {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+// Authors without an affiliation stay plain strings in JSON, so existing consumers still
+// read names; only authors with one become objects
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, utoipa::ToSchema)]
+#[serde(untagged)]
+enum AuthorJson {
+    Name(String),
+    Full {
+        name: String,
+        #[serde(default)]
+        affiliation: Option<String>,
+    },
+}
+
+impl From<AuthorJson> for Author {
+    fn from(json: AuthorJson) -> Self {
+        match json {
+            AuthorJson::Name(name) => Author::new(name),
+            AuthorJson::Full { name, affiliation } => Author { name, affiliation },
+        }
+    }
+}
+
+impl From<Author> for AuthorJson {
+    fn from(author: Author) -> Self {
+        match author.affiliation {
+            None => AuthorJson::Name(author.name),
+            affiliation => AuthorJson::Full {
+                name: author.name,
+                affiliation,
+            },
+        }
+    }
+}
+
+impl schemars::JsonSchema for Author {
+    fn schema_name() -> String {
+        "Author".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        AuthorJson::json_schema(gen)
+    }
+}
+
+impl<'s> utoipa::ToSchema<'s> for Author {
+    fn schema() -> (
+        &'s str,
+        utoipa::openapi::RefOr<utoipa::openapi::schema::Schema>,
+    ) {
+        ("Author", AuthorJson::schema().1)
+    }
+}
+
+// Author names joined for display
+fn join_authors(authors: &[Author], separator: &str) -> String {
+    authors
+        .iter()
+        .map(|author| author.name.as_str())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+// Orders for results already fetched, applied without another arXiv call
+#[derive(Debug, serde::Deserialize, Clone, Copy, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    PublishedDesc,
+    PublishedAsc,
+    Title,
+    FirstAuthor,
+}
+
+// Stable, so ties keep the order arXiv returned them in. Papers without a published date
+// go last whichever way dates are sorted
+pub fn sort_papers(papers: &mut [Paper], key: SortKey) {
+    match key {
+        SortKey::PublishedDesc => papers.sort_by(|a, b| by_published(a, b, true)),
+        SortKey::PublishedAsc => papers.sort_by(|a, b| by_published(a, b, false)),
+        SortKey::Title => papers.sort_by_cached_key(|paper| paper.title.trim().to_lowercase()),
+        // By surname, papers without authors last
+        SortKey::FirstAuthor => papers.sort_by_cached_key(|paper| {
+            let surname = paper
+                .authors
+                .first()
+                .and_then(|author| author.name.split_whitespace().last())
+                .map(fold_name);
+            (surname.is_none(), surname)
+        }),
+    }
+}
+
+fn by_published(a: &Paper, b: &Paper, newest_first: bool) -> Ordering {
+    // ISO 8601 dates compare correctly as strings
+    match (&a.published, &b.published) {
+        (Some(a), Some(b)) if newest_first => b.cmp(a),
+        (Some(a), Some(b)) => a.cmp(b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+// Ways to split the HTML results into sections
+#[derive(Debug, serde::Deserialize, Clone, Copy, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupBy {
+    // Primary category, biggest first
+    Category,
+}
+
+// Where papers without a primary category are grouped
+pub const OTHER_CATEGORY: &str = "Other";
+
+// Papers sharing a primary category, in the order they were found
+#[derive(Debug, serde::Serialize)]
+pub struct CategoryGroup {
+    pub category: String,
+    pub papers: Vec<Paper>,
+}
+
+// Papers grouped by primary category, biggest group first and "Other" last. Groups of the
+// same size stay in the order their first paper was found
+pub fn group_by_primary_category(papers: &[Paper]) -> Vec<CategoryGroup> {
+    let mut groups: Vec<CategoryGroup> = Vec::new();
+    for paper in papers {
+        let category = paper.primary_category.as_deref().unwrap_or(OTHER_CATEGORY);
+        match groups.iter_mut().find(|group| group.category == category) {
+            Some(group) => group.papers.push(paper.clone()),
+            None => groups.push(CategoryGroup {
+                category: category.to_string(),
+                papers: vec![paper.clone()],
+            }),
+        }
+    }
+    groups.sort_by_key(|group| {
+        (
+            group.category == OTHER_CATEGORY,
+            Reverse(group.papers.len()),
+        )
+    });
+    groups
+}
+
+// Papers from a single page of results, plus the overall match count
+#[derive(
+    Debug, serde::Deserialize, serde::Serialize, Clone, schemars::JsonSchema, utoipa::ToSchema,
+)]
+pub struct SearchResult {
+    pub papers: Vec<Paper>,
+    pub total_results: usize,
+    pub start: usize,
+    // Title of the paper these results were found to be related to
+    #[serde(default)]
+    pub related_to: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SearchArgs {
+    query: String,
+    max_results: Option<i32>,
+    #[serde(default)]
+    sort_by: SortBy,
+    // Codes, names or aliases of arXiv categories to restrict the search to
+    #[serde(default)]
+    categories: Vec<String>,
+    // Applied to the parsed results rather than the arXiv query, see passes_filters
+    #[serde(default)]
+    filter_categories: Vec<String>,
+    #[serde(default)]
+    filter_authors: Vec<String>,
+    // Re-sorts the parsed results, independent of arXiv's own order
+    #[serde(default)]
+    sort: Option<SortKey>,
+    // Only papers submitted this recently, e.g. 7d, 2w, 1m or 1y, see parse_since
+    #[serde(default)]
+    since: Option<String>,
+}
+
+// Orderings the arXiv API supports, newest first for the date-based ones
+#[derive(Debug, serde::Deserialize, Clone, Copy, Default, utoipa::ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum SortBy {
+    #[default]
+    Relevance,
+    LastUpdatedDate,
+    SubmittedDate,
+}
+
+impl SortBy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SortBy::Relevance => "relevance",
+            SortBy::LastUpdatedDate => "lastUpdatedDate",
+            SortBy::SubmittedDate => "submittedDate",
+        }
+    }
+}
+
+// Raw bytes rather than text, the parser decides what to do with invalid UTF-8
+pub type FetchFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<u8>, ArxivError>> + Send + 'a>>;
+
+// Where raw arXiv Atom feeds come from
+pub trait ArxivSource: Send + Sync {
+    // `search_query` uses the arXiv query syntax, e.g. "all:transformers" or "cat:cs.LG"
+    fn fetch_page<'a>(
+        &'a self,
+        search_query: &'a str,
+        start: usize,
+        max_results: i32,
+        sort_by: SortBy,
+    ) -> FetchFuture<'a>;
+    fn fetch_by_id<'a>(&'a self, id: &'a str) -> FetchFuture<'a>;
+}
+
+// The arXiv HTTP API, or a mirror of it
+pub struct LiveSource {
+    base_url: String,
+}
+
+impl LiveSource {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+        }
+    }
+}
+
+impl Default for LiveSource {
+    fn default() -> Self {
+        Self::new(arxiv_base_url())
+    }
+}
+
+impl ArxivSource for LiveSource {
+    fn fetch_page<'a>(
+        &'a self,
+        search_query: &'a str,
+        start: usize,
+        max_results: i32,
+        sort_by: SortBy,
+    ) -> FetchFuture<'a> {
+        Box::pin(async move {
+            // Asks for gzip or deflate and inflates the body itself, big feeds shrink a lot
+            let request = arxiv_client()?.get(&self.base_url).query(&[
+                ("search_query", search_query.to_string()),
+                ("start", start.to_string()),
+                ("max_results", max_results.to_string()),
+                ("sortBy", sort_by.as_str().to_string()),
+                ("sortOrder", "descending".to_string()),
+            ]);
+            let response = send_with_retry(request).await?;
+            tracing::debug!(status = %response.status(), url = %response.url(), "arXiv responded");
+            // Client errors still carry an Atom feed explaining them, server errors don't
+            if response.status().is_server_error() {
+                response.error_for_status_ref()?;
+            }
+            Ok(response.bytes().await?.to_vec())
+        })
+    }
+
+    fn fetch_by_id<'a>(&'a self, id: &'a str) -> FetchFuture<'a> {
+        Box::pin(async move {
+            let request = arxiv_client()?
+                .get(&self.base_url)
+                .query(&[("id_list", id.trim())]);
+            let response = send_with_retry(request).await?;
+            tracing::debug!(status = %response.status(), url = %response.url(), "arXiv responded");
+            // Client errors still carry an Atom feed explaining them, server errors don't
+            if response.status().is_server_error() {
+                response.error_for_status_ref()?;
+            }
+            Ok(response.bytes().await?.to_vec())
+        })
+    }
+}
+
+// A canned feed on disk, for demos and runs without network access
+pub struct FixtureSource {
+    path: PathBuf,
+}
+
+impl FixtureSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl ArxivSource for FixtureSource {
+    fn fetch_page<'a>(
+        &'a self,
+        _search_query: &'a str,
+        _start: usize,
+        _max_results: i32,
+        _sort_by: SortBy,
+    ) -> FetchFuture<'a> {
+        Box::pin(async move { Ok(tokio::fs::read(&self.path).await?) })
+    }
+
+    fn fetch_by_id<'a>(&'a self, _id: &'a str) -> FetchFuture<'a> {
+        Box::pin(async move { Ok(tokio::fs::read(&self.path).await?) })
+    }
+}
+
+// Attempts per arXiv request while it keeps answering 429
+const MAX_ATTEMPTS: u32 = 3;
+
+// Wait after a 429 without Retry-After, doubled on each further attempt
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(3);
+
+// Longest Retry-After we wait out rather than failing the request
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+// Send an arXiv request, waiting as long as arXiv asks whenever it rate limits us
+async fn send_with_retry(
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::Response, ArxivError> {
+    let mut attempt = 1;
+    loop {
+        let response = request
+            .try_clone()
+            .expect("arXiv requests have no streaming body")
+            .send()
+            .await?;
+        if response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Ok(response);
+        }
+
+        let retry_after =
+            retry_after(response.headers()).unwrap_or(RATE_LIMIT_BACKOFF * 2u32.pow(attempt - 1));
+        if attempt >= MAX_ATTEMPTS || retry_after > MAX_RETRY_AFTER {
+            return Err(ArxivError::RateLimited { retry_after });
+        }
+        tracing::warn!(
+            ?retry_after,
+            attempt,
+            "arXiv rate limited the request, waiting"
+        );
+        tokio::time::sleep(retry_after).await;
+        attempt += 1;
+    }
+}
+
+// Retry-After in either of its forms, delay-seconds or an HTTP-date
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    // A date in the past means we may retry right away
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+// ARXIV_BASE_URL if set, e.g. to point at a mirror or a local mock server
+pub fn arxiv_base_url() -> String {
+    std::env::var("ARXIV_BASE_URL").unwrap_or_else(|_| ARXIV_URL.to_string())
+}
+
+// Use the fixture named by ARXIV_FIXTURE_PATH if set, the live API otherwise
+pub fn source_from_env() -> Box<dyn ArxivSource> {
+    match std::env::var("ARXIV_FIXTURE_PATH") {
+        Ok(path) => Box::new(FixtureSource::new(path)),
+        Err(_) => Box::new(LiveSource::default()),
+    }
+}
+
+// arXiv asks clients to say who they are, ARXIV_USER_AGENT should carry a contact address
+pub fn user_agent() -> String {
+    std::env::var("ARXIV_USER_AGENT")
+        .ok()
+        .filter(|agent| !agent.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_USER_AGENT.to_string())
+}
+
+fn arxiv_client() -> Result<reqwest::Client, reqwest::Error> {
+    reqwest::Client::builder().user_agent(user_agent()).build()
+}
+
+// Tool to search for papers
+pub struct ArxivSearchTool {
+    // Fail on invalid UTF-8 instead of replacing it, to detect corrupted feeds
+    pub strict_utf8: bool,
+    source: Box<dyn ArxivSource>,
+    progress: Option<Progress>,
+}
+
+// Callback told what a tool is doing, for clients watching a search as it runs
+pub type Progress = Arc<dyn Fn(String) + Send + Sync>;
+
+impl ArxivSearchTool {
+    pub fn new(source: Box<dyn ArxivSource>) -> Self {
+        Self {
+            strict_utf8: strict_utf8_enabled(),
+            source,
+            progress: None,
+        }
+    }
+
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    fn report(&self, message: String) {
+        if let Some(progress) = &self.progress {
+            progress(message);
+        }
+    }
+
+    async fn search(
+        &self,
+        args: &SearchArgs,
+        search_query: &str,
+        max_results: i32,
+    ) -> Result<SearchResult, ArxivError> {
+        let fetch = |max_results| {
+            fetch_results(
+                self.source.as_ref(),
+                search_query,
+                max_results,
+                args.sort_by,
+                self.strict_utf8,
+            )
+        };
+        let mut result = fetch(max_results).await?;
+        if args.filter_categories.is_empty() && args.filter_authors.is_empty() {
+            return Ok(result);
+        }
+
+        let filter = |result: &mut SearchResult| {
+            result.papers.retain(|paper| {
+                passes_filters(paper, &args.filter_categories, &args.filter_authors)
+            })
+        };
+        filter(&mut result);
+        if result.papers.is_empty() {
+            // Nothing on the first page passed, so look deeper once before giving up
+            self.report("No papers passed the filters, looking further".to_string());
+            result = fetch((max_results * FILTER_OVERFETCH).min(ARXIV_MAX_RESULTS)).await?;
+            filter(&mut result);
+            if result.papers.is_empty() {
+                return Err(ArxivError::NoResults);
+            }
+            result.papers.truncate(max_results.max(0) as usize);
+        }
+        Ok(result)
+    }
+}
+
+impl Default for ArxivSearchTool {
+    fn default() -> Self {
+        Self::new(source_from_env())
+    }
+}
+
+impl Tool for ArxivSearchTool {
+    const NAME: &'static str = "search_arxiv";
+    type Error = ArxivError;
+    type Args = SearchArgs;
+    type Output = SearchResult;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: "search_arxiv".to_string(),
+            description: "Search for academic papers on arXiv".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": "Search query for papers"
+                    },
+                    "max_results": {
+                        "type": "integer",
+                        "description": "Maximum number of results to return, between 1 and 2000 (default: 5)"
+                    },
+                    "sort_by": {
+                        "type": "string",
+                        "enum": ["relevance", "lastUpdatedDate", "submittedDate"],
+                        "description": "Result order, newest first for the dates (default: relevance)"
+                    },
+                    "categories": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "arXiv categories to search in, as codes (cs.LG) or names (machine learning)"
+                    },
+                    "since": {
+                        "type": "string",
+                        "description": "Only papers submitted this recently: a number and a unit, d for days, w for weeks, m for months or y for years (e.g. 7d for the last week)"
+                    },
+                    "filter_categories": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Keep only papers listed in one of these categories, as codes (cs.LG), names, or prefixes like cs.* for a whole archive. Applied to the results, not the query"
+                    },
+                    "filter_authors": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Keep only papers with an author whose name contains one of these, ignoring case and accents (\"erdos\" matches Erdős). Applied to the results, not the query"
+                    },
+                    "sort": {
+                        "type": "string",
+                        "enum": ["published_desc", "published_asc", "title", "first_author"],
+                        "description": "Re-sort the results: by published date (undated papers last), title, or first author's surname"
+                    }
+                },
+                "required": ["query"]
+            }),
+        }
+    }
+
+    #[tracing::instrument(
+        name = "search_arxiv",
+        skip_all,
+        fields(query = %args.query, max_results = ?args.max_results, sort_by = ?args.sort_by)
+    )]
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let started = Instant::now();
+        let max_results = clamp_max_results(args.max_results);
+        let search_query = with_since(
+            with_categories(format!("all:{}", args.query), &args.categories),
+            args.since.as_deref(),
+        )?;
+        self.report(format!("Querying arXiv for \"{}\"", args.query));
+        let mut result = self.search(&args, &search_query, max_results).await;
+        if let (Ok(result), Some(sort)) = (&mut result, args.sort) {
+            sort_papers(&mut result.papers, sort);
+        }
+
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        match &result {
+            Ok(result) => {
+                tracing::info!(
+                    results = result.papers.len(),
+                    total_results = result.total_results,
+                    elapsed_ms,
+                    "search finished"
+                );
+                self.report(format!("Found {} papers", result.papers.len()));
+            }
+            Err(e) => tracing::warn!(error = %e, elapsed_ms, "search failed"),
+        }
+        result
+    }
+}
+
+// Tool listing the newest submissions in a category, for "what's new in cs.CL"
+pub struct LatestPapersTool {
+    source: Box<dyn ArxivSource>,
+}
+
+impl LatestPapersTool {
+    pub fn new(source: Box<dyn ArxivSource>) -> Self {
+        Self { source }
+    }
+}
+
+impl Default for LatestPapersTool {
+    fn default() -> Self {
+        Self::new(source_from_env())
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct LatestArgs {
+    category: String,
+    count: Option<i32>,
+}
+
+impl Tool for LatestPapersTool {
+    const NAME: &'static str = "latest_arxiv";
+    type Error = ArxivError;
+    type Args = LatestArgs;
+    type Output = SearchResult;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: "latest_arxiv".to_string(),
+            description: "List the most recently submitted papers in an arXiv category".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "category": {
+                        "type": "string",
+                        "description": "arXiv category, as a code (cs.CL) or name (computation and language)"
+                    },
+                    "count": {
+                        "type": "integer",
+                        "description": "Number of papers to return, between 1 and 2000 (default: 5)"
+                    }
+                },
+                "required": ["category"]
+            }),
+        }
+    }
+
+    #[tracing::instrument(name = "latest_arxiv", skip_all, fields(category = %args.category))]
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        fetch_results(
+            self.source.as_ref(),
+            &latest_query(&args.category),
+            clamp_max_results(args.count),
+            SortBy::SubmittedDate,
+            false,
+        )
+        .await
+    }
+}
+
+// Query matching everything in one category, left to the sort to pick the newest
+fn latest_query(category: &str) -> String {
+    format!("cat:{}", categories::category_code(category))
+}
+
+// Restrict an arXiv query to any of the given categories
+fn with_categories(search_query: String, categories: &[String]) -> String {
+    if categories.is_empty() {
+        return search_query;
+    }
+    let filters: Vec<String> = categories
+        .iter()
+        .map(|category| format!("cat:{}", categories::category_code(category)))
+        .collect();
+    format!("({}) AND ({})", search_query, filters.join(" OR "))
+}
+
+// Units a `since` value may use, and how many days each is. Months and years are
+// approximate, which is close enough for "papers from the last month"
+const SINCE_UNITS: &[(char, u64)] = &[('d', 1), ('w', 7), ('m', 30), ('y', 365)];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// How far back a relative age like 7d, 2w, 1m or 1y reaches
+pub fn parse_since(since: &str) -> Result<Duration, ArxivError> {
+    let invalid = || ArxivError::InvalidSince(since.to_string());
+    let since = since.trim();
+    let unit = since.chars().last().ok_or_else(invalid)?;
+    let days = SINCE_UNITS
+        .iter()
+        .find(|(known, _)| *known == unit.to_ascii_lowercase())
+        .map(|(_, days)| *days)
+        .ok_or_else(invalid)?;
+    let count: u64 = since[..since.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid())?;
+    if count == 0 {
+        return Err(invalid());
+    }
+    // Far enough back to overflow is a typo rather than a date arXiv could have papers from
+    let secs = count.checked_mul(days * 24 * 60 * 60).ok_or_else(invalid)?;
+    Ok(Duration::from_secs(secs))
+}
+
+// Restrict an arXiv query to papers submitted within `since` of now
+fn with_since(search_query: String, since: Option<&str>) -> Result<String, ArxivError> {
+    let Some(since) = since else {
+        return Ok(search_query);
+    };
+    let now = SystemTime::now();
+    // SystemTime reaches back before 1970, but submittedDate ranges and HTTP dates don't
+    let start = now
+        .checked_sub(parse_since(since)?)
+        .map_or(UNIX_EPOCH, |start| start.max(UNIX_EPOCH));
+    Ok(format!(
+        "({}) AND submittedDate:[{} TO {}]",
+        search_query,
+        arxiv_timestamp(start),
+        arxiv_timestamp(now)
+    ))
+}
+
+// YYYYMMDDHHMM in UTC, the form submittedDate ranges take
+fn arxiv_timestamp(time: SystemTime) -> String {
+    // e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+    let date = httpdate::fmt_http_date(time);
+    let month = MONTHS
+        .iter()
+        .position(|month| *month == &date[8..11])
+        .map_or(1, |i| i + 1);
+    format!(
+        "{}{:02}{}{}{}",
+        &date[12..16],
+        month,
+        &date[5..7],
+        &date[17..19],
+        &date[20..22]
+    )
+}
+
+// Looked further down the results once when post-filters leave nothing, this many times deeper
+const FILTER_OVERFETCH: i32 = 5;
+
+// Latin letters with diacritics and the plain letter they fold to, so "erdos" finds Erdős
+const DIACRITICS: &[(&str, char)] = &[
+    ("àáâãäåāăą", 'a'),
+    ("çćĉċč", 'c'),
+    ("ďđ", 'd'),
+    ("èéêëēĕėęě", 'e'),
+    ("ĝğġģ", 'g'),
+    ("ĥħ", 'h'),
+    ("ìíîïĩīĭįı", 'i'),
+    ("ĵ", 'j'),
+    ("ķ", 'k'),
+    ("ĺļľŀł", 'l'),
+    ("ñńņňŉ", 'n'),
+    ("òóôõöøōŏő", 'o'),
+    ("ŕŗř", 'r'),
+    ("śŝşšș", 's'),
+    ("ţťŧț", 't'),
+    ("ùúûüũūŭůűų", 'u'),
+    ("ŵ", 'w'),
+    ("ýÿŷ", 'y'),
+    ("źżž", 'z'),
+];
+
+// Lowercase with diacritics dropped, for matching names however they were typed
+fn fold_name(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| {
+            DIACRITICS
+                .iter()
+                .find(|(accented, _)| accented.contains(c))
+                .map_or(c, |&(_, plain)| plain)
+        })
+        .collect()
+}
+
+// A category filter is a code, name or alias, or a prefix ending in '*' like cs.*
+fn category_matches(filter: &str, category: &str) -> bool {
+    match filter.trim().strip_suffix('*') {
+        Some(prefix) => category.starts_with(prefix),
+        None => category == categories::category_code(filter),
+    }
+}
+
+// Post-filters applied to parsed results: a paper needs a category matching one of
+// `categories` and an author whose name contains one of `authors`, ignoring case and
+// diacritics. An empty filter lets every paper through
+pub fn passes_filters(paper: &Paper, categories: &[String], authors: &[String]) -> bool {
+    let category_ok = categories.is_empty()
+        || paper.categories.iter().any(|category| {
+            categories
+                .iter()
+                .any(|filter| category_matches(filter, category))
+        });
+    let authors: Vec<String> = authors
+        .iter()
+        .map(|author| fold_name(author.trim()))
+        .collect();
+    let author_ok = authors.is_empty()
+        || paper.authors.iter().any(|author| {
+            let name = fold_name(&author.name);
+            authors.iter().any(|filter| name.contains(filter.as_str()))
+        });
+    category_ok && author_ok
+}
+
+// arXiv rejects requests for more than this many results at once
+const ARXIV_MAX_RESULTS: i32 = 2000;
+const DEFAULT_MAX_RESULTS: i32 = 5;
+
+// Missing or non-positive counts mean the default; anything above arXiv's cap is capped
+pub fn clamp_max_results(max_results: Option<i32>) -> i32 {
+    match max_results {
+        None => DEFAULT_MAX_RESULTS,
+        Some(n) if n <= 0 => {
+            tracing::warn!(
+                "max_results {} is not positive, using {}",
+                n,
+                DEFAULT_MAX_RESULTS
+            );
+            DEFAULT_MAX_RESULTS
+        }
+        Some(n) if n > ARXIV_MAX_RESULTS => {
+            tracing::warn!(
+                "max_results {} exceeds arXiv's limit, using {}",
+                n,
+                ARXIV_MAX_RESULTS
+            );
+            ARXIV_MAX_RESULTS
+        }
+        Some(n) => n,
+    }
+}
+
+// Run a raw arXiv query against any source, shared by all the search tools
+pub async fn search_with(
+    source: &dyn ArxivSource,
+    search_query: &str,
+    max_results: i32,
+) -> Result<SearchResult, ArxivError> {
+    fetch_results(source, search_query, max_results, SortBy::Relevance, false).await
+}
+
+// Newest submissions matching a query, without going through an agent
+pub async fn search_latest(
+    source: &dyn ArxivSource,
+    query: &str,
+    categories: &[String],
+    max_results: i32,
+) -> Result<Vec<Paper>, ArxivError> {
+    let search_query = with_categories(format!("all:{}", query), categories);
+    let result = fetch_results(
+        source,
+        &search_query,
+        max_results,
+        SortBy::SubmittedDate,
+        false,
+    );
+    match result.await {
+        Ok(result) => Ok(result.papers),
+        Err(ArxivError::NoResults) => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+// One request for small result sets, successive pages for large ones
+async fn fetch_results(
+    source: &dyn ArxivSource,
+    search_query: &str,
+    max_results: i32,
+    sort_by: SortBy,
+    strict_utf8: bool,
+) -> Result<SearchResult, ArxivError> {
+    if max_results <= ARXIV_PAGE_SIZE as i32 {
+        let response = source
+            .fetch_page(search_query, 0, max_results, sort_by)
+            .await?;
+        return ArxivParser::new()
+            .with_strict_utf8(strict_utf8)
+            .parse_response(&response);
+    }
+
+    let mut pages = ArxivPaginator::new(source, search_query)
+        .with_max_papers(max_results.max(0) as usize)
+        .with_sort_by(sort_by)
+        .with_strict_utf8(strict_utf8);
+    let mut papers = Vec::new();
+    while let Some(paper) = pages.next().await {
+        papers.push(paper?);
+    }
+    Ok(SearchResult {
+        total_results: pages.total_results().unwrap_or(papers.len()),
+        start: 0,
+        papers,
+        related_to: None,
+    })
+}
+
+// Results per request when paginating; smaller pages come back faster from arXiv
+const ARXIV_PAGE_SIZE: usize = 500;
+
+// arXiv asks API clients to wait this long between consecutive requests
+pub const ARXIV_PAGE_DELAY: Duration = Duration::from_secs(3);
+
+type PageFuture<'a> = Pin<Box<dyn Future<Output = Result<SearchResult, ArxivError>> + Send + 'a>>;
+
+// Stream of papers across successive result pages, until arXiv runs out or the cap is hit
+pub struct ArxivPaginator<'a> {
+    source: &'a dyn ArxivSource,
+    search_query: String,
+    max_papers: usize,
+    sort_by: SortBy,
+    strict_utf8: bool,
+    next_start: usize,
+    yielded: usize,
+    total_results: Option<usize>,
+    buffer: VecDeque<Paper>,
+    pending: Option<PageFuture<'a>>,
+    done: bool,
+}
+
+impl<'a> ArxivPaginator<'a> {
+    pub fn new(source: &'a dyn ArxivSource, search_query: &str) -> Self {
+        Self {
+            source,
+            search_query: search_query.to_string(),
+            max_papers: usize::MAX,
+            sort_by: SortBy::Relevance,
+            strict_utf8: strict_utf8_enabled(),
+            next_start: 0,
+            yielded: 0,
+            total_results: None,
+            buffer: VecDeque::new(),
+            pending: None,
+            done: false,
+        }
+    }
+
+    pub fn with_max_papers(mut self, max_papers: usize) -> Self {
+        self.max_papers = max_papers;
+        self
+    }
+
+    pub fn with_sort_by(mut self, sort_by: SortBy) -> Self {
+        self.sort_by = sort_by;
+        self
+    }
+
+    pub fn with_strict_utf8(mut self, strict_utf8: bool) -> Self {
+        self.strict_utf8 = strict_utf8;
+        self
+    }
+
+    // Overall match count reported by arXiv, known once the first page has arrived
+    pub fn total_results(&self) -> Option<usize> {
+        self.total_results
+    }
+
+    fn fetch_next_page(&mut self) -> PageFuture<'a> {
+        let source = self.source;
+        let search_query = self.search_query.clone();
+        let start = self.next_start;
+        let page_size = ARXIV_PAGE_SIZE.min(self.max_papers - self.yielded) as i32;
+        let delay = if start > 0 { ARXIV_PAGE_DELAY } else { Duration::ZERO };
+        let sort_by = self.sort_by;
+        let strict_utf8 = self.strict_utf8;
+        Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            let response = source
+                .fetch_page(&search_query, start, page_size, sort_by)
+                .await?;
+            ArxivParser::new()
+                .with_strict_utf8(strict_utf8)
+                .parse_response(&response)
+        })
+    }
+}
+
+impl Stream for ArxivPaginator<'_> {
+    type Item = Result<Paper, ArxivError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if this.yielded >= this.max_papers {
+                return Poll::Ready(None);
+            }
+            if let Some(paper) = this.buffer.pop_front() {
+                this.yielded += 1;
+                return Poll::Ready(Some(Ok(paper)));
+            }
+            if this.done || this.total_results.is_some_and(|total| this.next_start >= total) {
+                return Poll::Ready(None);
+            }
+
+            if this.pending.is_none() {
+                this.pending = Some(this.fetch_next_page());
+            }
+            let pending = this.pending.as_mut().expect("a page request was just started");
+            let page = ready!(pending.as_mut().poll(cx));
+            this.pending = None;
+            match page {
+                Ok(page) => {
+                    this.total_results = Some(page.total_results);
+                    this.next_start += page.papers.len();
+                    this.done = page.papers.is_empty();
+                    this.buffer.extend(page.papers);
+                }
+                // Paged past the last result
+                Err(ArxivError::NoResults) if this.next_start > 0 => this.done = true,
+                Err(e) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+        }
+    }
+}
+
+// Fetch a single paper through the id_list API
+pub async fn fetch_paper_by_id(source: &dyn ArxivSource, id: &str) -> Result<Paper, ArxivError> {
+    let response = source.fetch_by_id(id).await?;
+
+    // Unknown or malformed ids come back as an "Error" entry rather than an HTTP error
+    match ArxivParser::new().parse_response(&response) {
+        Ok(result) => result
+            .papers
+            .into_iter()
+            .find(|paper| paper.title != "Error" && !paper.url.contains("/api/errors"))
+            .ok_or_else(|| ArxivError::PaperNotFound(id.to_owned())),
+        Err(ArxivError::NoResults) => Err(ArxivError::PaperNotFound(id.to_owned())),
+        Err(e) => Err(e),
+    }
+}
+
+// Papers similar to a seed paper: same primary category, overlapping title terms
+pub async fn find_related(
+    source: &dyn ArxivSource,
+    id: &str,
+    max_results: i32,
+) -> Result<SearchResult, ArxivError> {
+    let seed = fetch_paper_by_id(source, id).await?;
+
+    // Ask for one extra result since the seed usually matches its own query
+    let mut result = search_with(source, &related_query(&seed), max_results + 1).await?;
+    let mut seen = HashSet::from([base_arxiv_id(&seed.url).to_owned()]);
+    result
+        .papers
+        .retain(|paper| seen.insert(base_arxiv_id(&paper.url).to_owned()));
+    result.papers.truncate(max_results.max(0) as usize);
+    result.related_to = Some(seed.title);
+
+    Ok(result)
+}
+
+// Words too common in titles to say anything about the topic
+const TITLE_STOPWORDS: &[&str] = &[
+    "about", "based", "from", "into", "over", "that", "their", "this", "through", "toward",
+    "towards", "under", "using", "with", "without",
+];
+
+fn related_query(seed: &Paper) -> String {
+    let mut terms: Vec<String> = Vec::new();
+    for word in seed.title.split(|c: char| !c.is_alphanumeric()) {
+        let word = word.to_lowercase();
+        if word.len() > 3 && !TITLE_STOPWORDS.contains(&word.as_str()) && !terms.contains(&word) {
+            terms.push(word);
+        }
+    }
+    let terms = terms
+        .iter()
+        .take(5)
+        .map(|term| format!("ti:{}", term))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+
+    let category = seed
+        .primary_category
+        .as_ref()
+        .or_else(|| seed.categories.first());
+    match (category, terms.is_empty()) {
+        (Some(category), true) => format!("cat:{}", category),
+        (Some(category), false) => format!("cat:{} AND ({})", category, terms),
+        (None, _) => terms,
+    }
+}
+
+// Searches run at once by multi_search, few enough to stay friendly to arXiv
+const MULTI_SEARCH_CONCURRENCY: usize = 3;
+const MAX_MULTI_QUERIES: usize = 8;
+
+// Tool running several searches at once, for questions that span more than one topic
+pub struct MultiSearchTool {
+    source: Box<dyn ArxivSource>,
+}
+
+impl MultiSearchTool {
+    pub fn new(source: Box<dyn ArxivSource>) -> Self {
+        Self { source }
+    }
+}
+
+impl Default for MultiSearchTool {
+    fn default() -> Self {
+        Self::new(source_from_env())
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct MultiSearchArgs {
+    queries: Vec<String>,
+    max_results: Option<i32>,
+    // How many papers each query fetches, max_results if unset
+    #[serde(default)]
+    max_results_per_query: Option<i32>,
+}
+
+impl Tool for MultiSearchTool {
+    const NAME: &'static str = "multi_search";
+    type Error = ArxivError;
+    type Args = MultiSearchArgs;
+    type Output = SearchResult;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: "multi_search".to_string(),
+            description: "Run several arXiv searches at once and merge their results, for \
+                          questions connecting more than one topic"
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "queries": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Targeted search queries, one per topic or angle (at most 8)"
+                    },
+                    "max_results": {
+                        "type": "integer",
+                        "description": "Maximum number of merged results to return, between 1 and 2000 (default: 5, or everything found when only max_results_per_query is given)"
+                    },
+                    "max_results_per_query": {
+                        "type": "integer",
+                        "description": "Results fetched for each query before merging, between 1 and 2000 (default: max_results)"
+                    }
+                },
+                "required": ["queries"]
+            }),
+        }
+    }
+
+    #[tracing::instrument(name = "multi_search", skip_all, fields(queries = args.queries.len()))]
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let max_results = clamp_max_results(args.max_results);
+        let per_query = args
+            .max_results_per_query
+            .map_or(max_results, |n| clamp_max_results(Some(n)));
+        // Asked only for a per-query count, the caller gets every paper the queries found
+        let limit = match (args.max_results, args.max_results_per_query) {
+            (None, Some(_)) => usize::MAX,
+            _ => max_results.max(0) as usize,
+        };
+        let queries: Vec<String> = args
+            .queries
+            .iter()
+            .map(|query| query.trim().to_string())
+            .filter(|query| !query.is_empty())
+            .take(MAX_MULTI_QUERIES)
+            .collect();
+
+        let turns = tokio::sync::Semaphore::new(MULTI_SEARCH_CONCURRENCY);
+        let searches = queries.iter().map(|query| {
+            let turns = &turns;
+            async move {
+                let _permit = turns.acquire().await;
+                let search_query = format!("all:{}", query);
+                let result = search_with(self.source.as_ref(), &search_query, per_query).await;
+                (query, result)
+            }
+        });
+
+        // A query that fails or finds nothing shouldn't sink the others
+        let mut results = Vec::new();
+        let mut last_error = None;
+        for (query, result) in futures::future::join_all(searches).await {
+            match result {
+                Ok(result) => results.push((query.clone(), result.papers)),
+                Err(ArxivError::NoResults) => (),
+                Err(e) => {
+                    tracing::warn!(query = %query, error = %e, "one of the searches failed");
+                    last_error = Some(e);
+                }
+            }
+        }
+        if results.is_empty() {
+            return Err(last_error.unwrap_or(ArxivError::NoResults));
+        }
+
+        let mut papers = merge_results(results);
+        let total_results = papers.len();
+        papers.truncate(limit);
+        Ok(SearchResult {
+            papers,
+            total_results,
+            start: 0,
+            related_to: None,
+        })
+    }
+}
+
+// Papers from several searches, each once, best combined rank first. Every search a paper
+// appears in adds the reciprocal of its position there, so papers found by several queries,
+// or near the top of one, come first
+pub fn merge_results(results: Vec<(String, Vec<Paper>)>) -> Vec<Paper> {
+    let mut merged: Vec<(Paper, f32)> = Vec::new();
+    for (query, papers) in results {
+        for (position, paper) in dedup_papers(papers).into_iter().enumerate() {
+            let id = base_arxiv_id(&paper.url).to_string();
+            let score = rank_score(position);
+            match merged
+                .iter_mut()
+                .find(|(seen, _)| base_arxiv_id(&seen.url) == id)
+            {
+                Some((seen, total)) => {
+                    seen.found_by.push(query.clone());
+                    *total += score;
+                }
+                None => {
+                    let mut paper = paper;
+                    paper.found_by = vec![query.clone()];
+                    merged.push((paper, score));
+                }
+            }
+        }
+    }
+    // Stable, so ties keep the order the queries were given in
+    merged.sort_by(|a, b| b.1.total_cmp(&a.1));
+    merged.into_iter().map(|(paper, _)| paper).collect()
+}
+
+// The first of each paper by unversioned arXiv id, in order
+pub fn dedup_papers(papers: Vec<Paper>) -> Vec<Paper> {
+    let mut seen = HashSet::new();
+    papers
+        .into_iter()
+        .filter(|paper| seen.insert(base_arxiv_id(&paper.url).to_string()))
+        .collect()
+}
+
+// What a result at this position contributes to a paper's combined rank
+pub fn rank_score(position: usize) -> f32 {
+    1.0 / (position as f32 + 1.0)
+}
+
+// Tool to find papers related to one the user already has
+pub struct FindRelatedTool {
+    source: Box<dyn ArxivSource>,
+}
+
+impl FindRelatedTool {
+    pub fn new(source: Box<dyn ArxivSource>) -> Self {
+        Self { source }
+    }
+}
+
+impl Default for FindRelatedTool {
+    fn default() -> Self {
+        Self::new(source_from_env())
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct RelatedArgs {
+    id: String,
+    max_results: Option<i32>,
+}
+
+impl Tool for FindRelatedTool {
+    const NAME: &'static str = "find_related";
+    type Error = ArxivError;
+    type Args = RelatedArgs;
+    type Output = SearchResult;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: "find_related".to_string(),
+            description: "Find papers related to a given arXiv paper".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "id": {
+                        "type": "string",
+                        "description": "arXiv id of the paper to find related work for"
+                    },
+                    "max_results": {
+                        "type": "integer",
+                        "description": "Maximum number of results to return, between 1 and 2000 (default: 5)"
+                    }
+                },
+                "required": ["id"]
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        find_related(
+            self.source.as_ref(),
+            &args.id,
+            clamp_max_results(args.max_results),
+        )
+        .await
+    }
+}
+
+// Tool to compare two papers by arXiv id
+pub struct ComparePapersTool {
+    client: ProviderClient,
+    model: String,
+    source: Box<dyn ArxivSource>,
+}
+
+impl ComparePapersTool {
+    pub fn new(client: ProviderClient, model: &str) -> Self {
+        Self {
+            client,
+            model: model.to_string(),
+            source: source_from_env(),
+        }
+    }
+
+    pub fn with_source(mut self, source: Box<dyn ArxivSource>) -> Self {
+        self.source = source;
+        self
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct CompareArgs {
+    pub first_id: String,
+    pub second_id: String,
+}
+
+// What the model contributes to a comparison
+#[derive(Debug, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+struct ComparisonAnalysis {
+    shared_topic: String,
+    differing_methods: Vec<String>,
+    differing_datasets: Vec<String>,
+}
+
+// Structured comparison of two papers
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct PaperComparison {
+    pub first: Paper,
+    pub second: Paper,
+    pub shared_topic: String,
+    pub differing_methods: Vec<String>,
+    pub differing_datasets: Vec<String>,
+    // arXiv id of the more recent paper, when both have a published date
+    pub newer: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CompareError {
+    #[error(transparent)]
+    Arxiv(#[from] ArxivError),
+    #[error("Comparison failed: {0}")]
+    Extraction(#[from] ExtractionError),
+}
+
+impl Tool for ComparePapersTool {
+    const NAME: &'static str = "compare_papers";
+    type Error = CompareError;
+    type Args = CompareArgs;
+    type Output = PaperComparison;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: "compare_papers".to_string(),
+            description: "Compare two arXiv papers: shared topic, differing methods and datasets, and which is newer".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "first_id": {
+                        "type": "string",
+                        "description": "arXiv id of the first paper (e.g. 2310.06825)"
+                    },
+                    "second_id": {
+                        "type": "string",
+                        "description": "arXiv id of the second paper"
+                    }
+                },
+                "required": ["first_id", "second_id"]
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let first = fetch_paper_by_id(self.source.as_ref(), &args.first_id).await?;
+        let second = fetch_paper_by_id(self.source.as_ref(), &args.second_id).await?;
+
+        let text = format!(
+            "Paper A: {}\nAbstract: {}\n\nPaper B: {}\nAbstract: {}",
+            first.title, first.abstract_text, second.title, second.abstract_text
+        );
+        let analysis: ComparisonAnalysis = self
+            .client
+            .extract(&self.model, COMPARE_PREAMBLE, &text)
+            .await?;
+
+        // ISO 8601 dates compare correctly as strings
+        let newer = match (&first.published, &second.published) {
+            (Some(a), Some(b)) if a >= b => Some(args.first_id),
+            (Some(_), Some(_)) => Some(args.second_id),
+            _ => None,
+        };
+
+        Ok(PaperComparison {
+            first,
+            second,
+            shared_topic: analysis.shared_topic,
+            differing_methods: analysis.differing_methods,
+            differing_datasets: analysis.differing_datasets,
+            newer,
+        })
+    }
+}
+
+// Smallest possible search, confirming the arXiv API is reachable and answering queries
+pub async fn check_arxiv_reachable() -> Result<(), ArxivError> {
+    let client = reqwest::Client::builder()
+        .timeout(READINESS_TIMEOUT)
+        .user_agent(user_agent())
+        .build()?;
+    client
+        .get(arxiv_base_url())
+        .query(&[("search_query", "all:electron"), ("max_results", "1")])
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+// ARXIV_PDF_BASE_URL if set, e.g. to point at a mirror or a local mock server
+pub fn pdf_url(id: &str) -> String {
+    let base_url =
+        std::env::var("ARXIV_PDF_BASE_URL").unwrap_or_else(|_| ARXIV_PDF_URL.to_string());
+    format!("{}/{}", base_url, id)
+}
+
+// ARXIV_ABS_BASE_URL if set, like ARXIV_PDF_BASE_URL for the abstract pages
+fn abs_url(id: &str) -> String {
+    let base_url =
+        std::env::var("ARXIV_ABS_BASE_URL").unwrap_or_else(|_| ARXIV_ABS_URL.to_string());
+    format!("{}/{}", base_url, id)
+}
+
+// The response for a paper's PDF once it is known to be one, for streaming its body on
+pub async fn open_pdf(url: &str, id: &str) -> Result<reqwest::Response, ArxivError> {
+    // The id ends up in a file path, so never let one escape the download directory
+    if !is_valid_arxiv_id(id) {
+        return Err(ArxivError::InvalidId(id.to_string()));
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(PDF_DOWNLOAD_TIMEOUT)
+        .user_agent(user_agent())
+        .build()?;
+    let response = client.get(url).send().await?.error_for_status()?;
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    if !content_type.starts_with("application/pdf") {
+        return Err(ArxivError::UnexpectedContentType(content_type));
+    }
+    Ok(response)
+}
+
+// The abstract block of a paper's arXiv page, as a standalone HTML fragment
+pub async fn fetch_abstract_html(id: &str) -> Result<String, ArxivError> {
+    if !is_valid_arxiv_id(id) {
+        return Err(ArxivError::InvalidId(id.to_string()));
+    }
+
+    let response = arxiv_client()?.get(abs_url(id)).send().await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(ArxivError::PaperNotFound(id.to_string()));
+    }
+    let page = response.error_for_status()?.text().await?;
+    // A page without an abstract (e.g. a listing arXiv redirected to) has nothing to show
+    extract_abstract_html(&page).ok_or_else(|| ArxivError::PaperNotFound(id.to_string()))
+}
+
+// Title, authors and abstract from an abstract page, rebuilt from their text so none of the
+// page's scripts, styles or links come along
+pub fn extract_abstract_html(page: &str) -> Option<String> {
+    let document = scraper::Html::parse_document(page);
+    let block = |selector: &str, label: &str| {
+        let selector = scraper::Selector::parse(selector).expect("valid selector");
+        let element = document.select(&selector).next()?;
+        // Scripts can sit inside the blocks, e.g. MathJax hooks in the abstract
+        let text = element
+            .descendants()
+            .filter(|node| {
+                !node
+                    .ancestors()
+                    .filter_map(scraper::ElementRef::wrap)
+                    .any(|element| matches!(element.value().name(), "script" | "style"))
+            })
+            .filter_map(|node| node.value().as_text().map(|text| &**text))
+            .collect::<String>();
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        // arXiv labels each block, e.g. "Abstract: ..."
+        let text = text.strip_prefix(label).unwrap_or(&text).trim();
+        Some(tera::escape_html(text))
+    };
+
+    let abstract_text = block("#abs blockquote.abstract", "Abstract:")?;
+    let mut html = String::from("<div class=\"arxiv-abstract\">\n");
+    if let Some(title) = block("#abs h1.title", "Title:") {
+        html.push_str(&format!("  <h1>{}</h1>\n", title));
+    }
+    if let Some(authors) = block("#abs div.authors", "Authors:") {
+        html.push_str(&format!("  <p class=\"authors\">{}</p>\n", authors));
+    }
+    if let Some(dateline) = block("#abs div.dateline", "") {
+        html.push_str(&format!("  <p class=\"dateline\">{}</p>\n", dateline));
+    }
+    html.push_str(&format!("  <blockquote>{}</blockquote>\n", abstract_text));
+    html.push_str("</div>\n");
+    Some(html)
+}
+
+// Elements whose text is page furniture rather than the paper
+const FULL_TEXT_SKIPPED: &[&str] = &["script", "style", "nav", "header", "footer", "noscript"];
+
+// Elements that start a new paragraph of the extracted text
+const FULL_TEXT_BLOCKS: &[&str] = &[
+    "p", "h1", "h2", "h3", "h4", "h5", "h6", "li", "figcaption", "blockquote", "td", "div",
+];
+
+// The body text of a paper's HTML rendering, for handing to the LLM. arXiv only has these
+// for papers it could convert, which is most of those submitted since late 2023
+pub async fn fetch_full_text(id: &str) -> Result<String, ArxivError> {
+    if !is_valid_arxiv_id(id) {
+        return Err(ArxivError::InvalidId(id.to_string()));
+    }
+
+    let response = arxiv_client()?.get(html_url(id)).send().await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(ArxivError::FullTextUnavailable(id.to_string()));
+    }
+    let page = response.error_for_status()?.text().await?;
+    extract_full_text(&page).ok_or_else(|| ArxivError::FullTextUnavailable(id.to_string()))
+}
+
+// ARXIV_HTML_BASE_URL if set, like ARXIV_ABS_BASE_URL for the HTML renderings
+fn html_url(id: &str) -> String {
+    let base_url =
+        std::env::var("ARXIV_HTML_BASE_URL").unwrap_or_else(|_| ARXIV_HTML_URL.to_string());
+    format!("{}/{}", base_url, id)
+}
+
+// Plain text of the page's article, one paragraph per block element, without the text of
+// scripts, styles or navigation
+pub fn extract_full_text(page: &str) -> Option<String> {
+    let document = scraper::Html::parse_document(page);
+    let root = ["article", "main", "body"].iter().find_map(|selector| {
+        let selector = scraper::Selector::parse(selector).expect("valid selector");
+        document.select(&selector).next()
+    })?;
+
+    let mut text = String::new();
+    let mut last_block = None;
+    for node in root.descendants() {
+        let Some(fragment) = node.value().as_text() else {
+            continue;
+        };
+        let mut ancestors = node.ancestors().filter_map(scraper::ElementRef::wrap);
+        if ancestors
+            .clone()
+            .any(|element| FULL_TEXT_SKIPPED.contains(&element.value().name()))
+        {
+            continue;
+        }
+        let block = ancestors
+            .find(|element| FULL_TEXT_BLOCKS.contains(&element.value().name()))
+            .map(|element| element.id());
+        if block != last_block {
+            text.push('\n');
+            last_block = block;
+        }
+        // Newlines in the page's source are only wrapping, paragraphs come from the blocks
+        text.push_str(&fragment.replace('\n', " "));
+        text.push(' ');
+    }
+
+    let paragraphs: Vec<String> = text
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect();
+    (!paragraphs.is_empty()).then(|| paragraphs.join("\n\n"))
+}
+
+// Extract the arXiv identifier (e.g. 2310.06825v1) from an abstract or PDF URL
+fn arxiv_id_from_url(url: &str) -> &str {
+    let id = url
+        .split_once("/abs/")
+        .or_else(|| url.split_once("/pdf/"))
+        .map_or(url, |(_, id)| id);
+    id.trim_end_matches(".pdf")
+}
+
+// The identifier without its version suffix, so v1 and v2 count as the same paper
+pub fn base_arxiv_id(url: &str) -> &str {
+    let id = arxiv_id_from_url(url);
+    match id.rsplit_once('v') {
+        Some((base, version))
+            if !version.is_empty() && version.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            base
+        }
+        _ => id,
+    }
+}
+
+// The version a URL points at, e.g. 2 for 2310.06825v2
+pub fn arxiv_version(url: &str) -> Option<u32> {
+    let (_, version) = arxiv_id_from_url(url).rsplit_once('v')?;
+    version.parse().ok()
+}
+
+// New-style ids are 2310.06825 (four digits after the dot before 2015), old-style ones
+// hep-th/9901001, with the archive before the slash
+fn is_arxiv_identifier(id: &str) -> bool {
+    let digits = |s: &str, lengths: RangeInclusive<usize>| {
+        lengths.contains(&s.len()) && s.chars().all(|c| c.is_ascii_digit())
+    };
+    match id.split_once('/') {
+        Some((archive, number)) => {
+            !archive.is_empty()
+                && archive
+                    .chars()
+                    .all(|c| c.is_ascii_alphabetic() || matches!(c, '-' | '.'))
+                && digits(number, 7..=7)
+        }
+        None => id
+            .split_once('.')
+            .is_some_and(|(month, number)| digits(month, 4..=4) && digits(number, 4..=5)),
+    }
+}
+
+// Set each paper's arXiv id and version, and collapse versions of the same paper into the
+// newest one, at the rank the first of them had. Papers whose URL isn't an arXiv one are
+// kept as they are
+pub fn collapse_versions(papers: Vec<Paper>) -> Vec<Paper> {
+    let mut collapsed: Vec<Paper> = Vec::with_capacity(papers.len());
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for mut paper in papers {
+        let id = base_arxiv_id(&paper.url).to_string();
+        if !is_arxiv_identifier(&id) {
+            collapsed.push(paper);
+            continue;
+        }
+        paper.version = arxiv_version(&paper.url);
+        paper.arxiv_id = Some(id.clone());
+
+        let Some(&position) = positions.get(&id) else {
+            positions.insert(id, collapsed.len());
+            collapsed.push(paper);
+            continue;
+        };
+        let kept = &mut collapsed[position];
+        if paper.version > kept.version {
+            paper.superseded_versions = std::mem::take(&mut kept.superseded_versions);
+            paper.superseded_versions.extend(kept.version);
+            *kept = paper;
+        } else {
+            kept.superseded_versions.extend(paper.version);
+        }
+        // The same version found through two different links isn't an older one
+        let version = kept.version;
+        kept.superseded_versions.retain(|&v| Some(v) != version);
+        kept.superseded_versions.sort_unstable();
+        kept.superseded_versions.dedup();
+    }
+    collapsed
+}
+
+// arXiv ids look like 2310.06825v1 or hep-th/9901001
+fn is_valid_arxiv_id(id: &str) -> bool {
+    !id.is_empty()
+        && !id.starts_with('/')
+        && !id.contains("..")
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '/'))
+}
+
+// LaTeX commands with a direct Unicode equivalent
+const LATEX_SYMBOLS: &[(&str, &str)] = &[
+    ("alpha", "α"),
+    ("beta", "β"),
+    ("gamma", "γ"),
+    ("delta", "δ"),
+    ("epsilon", "ε"),
+    ("varepsilon", "ε"),
+    ("theta", "θ"),
+    ("lambda", "λ"),
+    ("mu", "μ"),
+    ("pi", "π"),
+    ("rho", "ρ"),
+    ("sigma", "σ"),
+    ("tau", "τ"),
+    ("phi", "φ"),
+    ("psi", "ψ"),
+    ("omega", "ω"),
+    ("Gamma", "Γ"),
+    ("Delta", "Δ"),
+    ("Theta", "Θ"),
+    ("Lambda", "Λ"),
+    ("Sigma", "Σ"),
+    ("Phi", "Φ"),
+    ("Omega", "Ω"),
+    ("to", "→"),
+    ("rightarrow", "→"),
+    ("leftarrow", "←"),
+    ("leq", "≤"),
+    ("geq", "≥"),
+    ("neq", "≠"),
+    ("approx", "≈"),
+    ("sim", "∼"),
+    ("times", "×"),
+    ("cdot", "·"),
+    ("pm", "±"),
+    ("infty", "∞"),
+    ("partial", "∂"),
+    ("nabla", "∇"),
+    ("sum", "∑"),
+    ("in", "∈"),
+];
+
+// Formatting commands whose argument is kept and the command dropped
+const LATEX_FORMATTING: &[&str] = &[
+    "textbf", "textit", "texttt", "emph", "text", "mathrm", "mathbf", "mathit", "mathsf",
+];
+
+const SUPERSCRIPTS: &[(char, char)] = &[
+    ('0', '⁰'),
+    ('1', '¹'),
+    ('2', '²'),
+    ('3', '³'),
+    ('4', '⁴'),
+    ('5', '⁵'),
+    ('6', '⁶'),
+    ('7', '⁷'),
+    ('8', '⁸'),
+    ('9', '⁹'),
+    ('+', '⁺'),
+    ('-', '⁻'),
+    ('n', 'ⁿ'),
+    ('i', 'ⁱ'),
+];
+
+const SUBSCRIPTS: &[(char, char)] = &[
+    ('0', '₀'),
+    ('1', '₁'),
+    ('2', '₂'),
+    ('3', '₃'),
+    ('4', '₄'),
+    ('5', '₅'),
+    ('6', '₆'),
+    ('7', '₇'),
+    ('8', '₈'),
+    ('9', '₉'),
+    ('+', '₊'),
+    ('-', '₋'),
+];
+
+// Render common LaTeX in titles and abstracts as Unicode for display, leaving anything
+// unrecognized as written
+pub fn normalize_latex(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut output = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '$' => i += 1,
+            '\\' => {
+                let start = i + 1;
+                let mut end = start;
+                while end < chars.len() && chars[end].is_ascii_alphabetic() {
+                    end += 1;
+                }
+                let command: String = chars[start..end].iter().collect();
+
+                if command.is_empty() {
+                    // Escaped punctuation such as \% or \&
+                    match chars.get(start) {
+                        Some(&c) if "%&_#$".contains(c) => output.push(c),
+                        Some(&c) => {
+                            output.push('\\');
+                            output.push(c);
+                        }
+                        None => output.push('\\'),
+                    }
+                    i = start + 1;
+                } else if let Some((_, symbol)) =
+                    LATEX_SYMBOLS.iter().find(|(name, _)| *name == command)
+                {
+                    output.push_str(symbol);
+                    i = end;
+                } else if LATEX_FORMATTING.contains(&command.as_str())
+                    && chars.get(end) == Some(&'{')
+                {
+                    match braced_group(&chars, end) {
+                        Some((inner, next)) => {
+                            output.push_str(&normalize_latex(&inner));
+                            i = next;
+                        }
+                        None => {
+                            output.push('\\');
+                            output.push_str(&command);
+                            i = end;
+                        }
+                    }
+                } else {
+                    output.push('\\');
+                    output.push_str(&command);
+                    i = end;
+                }
+            }
+            marker @ ('^' | '_') => {
+                let table = if marker == '^' {
+                    SUPERSCRIPTS
+                } else {
+                    SUBSCRIPTS
+                };
+                let (script, next) = match chars.get(i + 1) {
+                    Some('{') => braced_group(&chars, i + 1).unwrap_or_default(),
+                    Some(&c) => (c.to_string(), i + 2),
+                    None => (String::new(), i + 1),
+                };
+                let mapped: Option<String> = script
+                    .chars()
+                    .map(|c| table.iter().find(|(from, _)| *from == c).map(|(_, to)| *to))
+                    .collect();
+                match mapped {
+                    Some(mapped) if !script.is_empty() => {
+                        output.push_str(&mapped);
+                        i = next;
+                    }
+                    // No Unicode form for every character, keep the original
+                    _ => {
+                        output.push(marker);
+                        i += 1;
+                    }
+                }
+            }
+            c => {
+                output.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    output
+}
+
+// Contents of the `{...}` group opening at `open`, and the index just past it
+fn braced_group(chars: &[char], open: usize) -> Option<(String, usize)> {
+    let mut depth = 0;
+    for (offset, &c) in chars[open..].iter().enumerate() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    let inner = chars[open + 1..open + offset].iter().collect();
+                    return Some((inner, open + offset + 1));
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+// Parse JSON out of an agent response, tolerating markdown fences and surrounding prose
+pub fn parse_agent_json<T: serde::de::DeserializeOwned>(
+    response: &str,
+) -> Result<T, serde_json::Error> {
+    let response = response.trim();
+    if let Ok(value) = serde_json::from_str(response) {
+        return Ok(value);
+    }
+
+    let unfenced = strip_code_fence(response);
+    if let Ok(value) = serde_json::from_str(unfenced) {
+        return Ok(value);
+    }
+
+    if let Ok(array) = extract_json_array(unfenced) {
+        if let Ok(value) = serde_json::from_str(array) {
+            return Ok(value);
+        }
+    }
+
+    // Fall back to the outermost JSON object or array in the text
+    match (unfenced.find(['{', '[']), unfenced.rfind(['}', ']'])) {
+        (Some(start), Some(end)) if start < end => serde_json::from_str(&unfenced[start..=end]),
+        _ => serde_json::from_str(unfenced),
+    }
+}
+
+fn strip_code_fence(text: &str) -> &str {
+    let Some(start) = text.find("```") else {
+        return text;
+    };
+    let fenced = &text[start + 3..];
+    // Skip the language tag on the opening fence line
+    let fenced = fenced.split_once('\n').map_or(fenced, |(_, rest)| rest);
+    fenced
+        .find("```")
+        .map_or(fenced, |end| &fenced[..end])
+        .trim()
+}
+
+// The first JSON array in an agent response, skipping markdown fences and any prose around it
+pub fn extract_json_array(response: &str) -> Result<&str, ArxivError> {
+    let unfenced = strip_code_fence(response.trim());
+    for (start, _) in unfenced.match_indices('[') {
+        let Some(len) = bracketed_len(&unfenced[start..]) else {
+            continue;
+        };
+        let candidate = &unfenced[start..start + len];
+        if serde_json::from_str::<serde_json::Value>(candidate).is_ok() {
+            return Ok(candidate);
+        }
+    }
+    Err(ArxivError::AgentResponseNotJson(response.chars().take(200).collect()))
+}
+
+// Length of the bracketed block opening the text, ignoring brackets inside JSON strings
+fn bracketed_len(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+// HTML templates, compiled into the binary so rendering doesn't depend on the working directory
+pub fn templates() -> Result<tera::Tera, tera::Error> {
+    let mut tera = tera::Tera::default();
+    tera.add_raw_template("table.html", include_str!("../static/table.html"))?;
+    Ok(tera)
+}
+
+// HTML formatting function for papers
+pub fn format_papers_as_html(
+    templates: &tera::Tera,
+    papers: &[Paper],
+    group_by: Option<GroupBy>,
+) -> Result<String, anyhow::Error> {
+    // Alongside each paper's abstract, for copying into a bibliography
+    let citations: Vec<serde_json::Value> = papers
+        .iter()
+        .map(|paper| {
+            json!({
+                "apa": paper.to_citation(CitationStyle::Apa),
+                "mla": paper.to_citation(CitationStyle::Mla),
+            })
+        })
+        .collect();
+    let papers: Vec<Paper> = papers.iter().map(normalize_paper_latex).collect();
+    // Ungrouped results are one group without a heading
+    let groups = match group_by {
+        Some(GroupBy::Category) => group_by_primary_category(&papers),
+        None => vec![CategoryGroup {
+            category: String::new(),
+            papers: papers.clone(),
+        }],
+    };
+    let mut context = tera::Context::new();
+    context.insert("papers", &papers);
+    context.insert("citations", &citations);
+    context.insert("groups", &groups);
+    context.insert("grouped", &group_by.is_some());
+
+    let result = templates.render("table.html", &context)?;
+
+    Ok(result)
+}
+
+// Display copy of a paper with LaTeX in the title and abstract rendered as Unicode
+fn normalize_paper_latex(paper: &Paper) -> Paper {
+    Paper {
+        title: normalize_latex(&paper.title),
+        abstract_text: normalize_latex(&paper.abstract_text),
+        ..paper.clone()
+    }
+}
+
+// HTML formatting for a two-paper comparison
+pub fn format_comparison_as_html(comparison: &PaperComparison) -> String {
+    let list = |items: &[String]| {
+        items
+            .iter()
+            .map(|item| format!("<li>{}</li>", tera::escape_html(item)))
+            .collect::<String>()
+    };
+    let newer = comparison
+        .newer
+        .as_deref()
+        .map(|id| format!("<p><strong>Newer:</strong> {}</p>", tera::escape_html(id)))
+        .unwrap_or_default();
+
+    format!(
+        "<div class=\"comparison\">\
+         <h2><a href=\"{}\">{}</a> vs <a href=\"{}\">{}</a></h2>\
+         <p><strong>Shared topic:</strong> {}</p>\
+         <h3>Differing methods</h3><ul>{}</ul>\
+         <h3>Differing datasets and benchmarks</h3><ul>{}</ul>\
+         {}</div>",
+        tera::escape_html(&comparison.first.url),
+        tera::escape_html(&comparison.first.title),
+        tera::escape_html(&comparison.second.url),
+        tera::escape_html(&comparison.second.title),
+        tera::escape_html(&comparison.shared_topic),
+        list(&comparison.differing_methods),
+        list(&comparison.differing_datasets),
+        newer
+    )
+}
+
+// Markdown formatting function for papers (GitHub-flavored)
+pub fn format_papers_as_markdown(papers: &[Paper]) -> String {
+    let mut output = String::new();
 
-const ARXIV_URL: &str = "http://export.arxiv.org/api/query";
+    output.push_str("| Title | Authors | Categories | URL |\n");
+    output.push_str("| --- | --- | --- | --- |\n");
+    for paper in papers {
+        let authors = if paper.authors.len() > 2 {
+            format!("{} et al.", paper.authors[0])
+        } else {
+            join_authors(&paper.authors, ", ")
+        };
+        output.push_str(&format!(
+            "| {} | {} | {} | {} |\n",
+            escape_markdown_cell(&normalize_latex(&paper.title)),
+            escape_markdown_cell(&authors),
+            escape_markdown_cell(&paper.categories.join(", ")),
+            paper.url
+        ));
+    }
 
-#[derive(Debug, thiserror::Error)]
-pub enum ArxivError {
-    #[error("Network error: {0}")]
-    Network(#[from] reqwest::Error),
-    #[error("XML parsing error: {0}")]
-    XmlParsing(#[from] quick_xml::Error),
-    #[error("No results found")]
-    NoResults,
-    #[error("UTF-8 decoding error: {0}")]
-    Utf8Error(#[from] std::str::Utf8Error),
+    output.push_str("\n## Abstracts\n");
+    for paper in papers {
+        output.push_str(&format!("\n### {}\n\n", normalize_latex(&paper.title)));
+        output.push_str(&format!(
+            "**Authors:** {}\n\n",
+            join_authors(&paper.authors, ", ")
+        ));
+        output.push_str(&format!(
+            "**Categories:** {}\n\n",
+            paper.categories.join(", ")
+        ));
+        output.push_str(&format!("**URL:** {}\n\n", paper.url));
+        output.push_str(&format!("{}\n", normalize_latex(&paper.abstract_text)));
+    }
+
+    output
 }
 
-// Struct to hold paper metadata
-#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
-pub struct Paper {
-    pub title: String,
-    pub authors: Vec<String>,
-    pub abstract_text: String,
-    pub url: String,
-    pub categories: Vec<String>,
+// CSV formatting function for spreadsheets
+pub fn format_papers_as_csv(papers: &[Paper]) -> Result<String, anyhow::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record([
+        "title",
+        "authors",
+        "primary_category",
+        "categories",
+        "published",
+        "url",
+        "doi",
+        "abstract",
+    ])?;
+
+    for paper in papers {
+        let authors = join_authors(&paper.authors, ";");
+        let categories = paper.categories.join(";");
+        writer.write_record([
+            paper.title.as_str(),
+            authors.as_str(),
+            paper.primary_category.as_deref().unwrap_or_default(),
+            categories.as_str(),
+            paper.published.as_deref().unwrap_or_default(),
+            paper.url.as_str(),
+            paper.doi.as_deref().unwrap_or_default(),
+            paper.abstract_text.as_str(),
+        ])?;
+    }
+
+    let bytes = writer.into_inner().map_err(|e| e.into_error())?;
+    Ok(String::from_utf8(bytes)?)
 }
 
-impl Paper {
-    fn new() -> Self {
-        Self {
-            title: String::new(),
-            authors: Vec::new(),
-            abstract_text: String::new(),
-            url: String::new(),
-            categories: Vec::new(),
+// RIS formatting function for reference managers (Zotero, EndNote)
+pub fn format_papers_as_ris(papers: &[Paper]) -> String {
+    let mut output = String::new();
+
+    for paper in papers {
+        // Only papers with a DOI are known to be published, preprints are generic
+        let kind = if paper.doi.is_some() { "JOUR" } else { "GEN" };
+        push_ris_tag(&mut output, "TY", kind);
+        push_ris_tag(&mut output, "TI", &paper.title);
+        for author in &paper.authors {
+            push_ris_tag(&mut output, "AU", &author.name);
+        }
+        push_ris_tag(&mut output, "AB", &paper.abstract_text);
+        push_ris_tag(&mut output, "UR", &paper.url);
+        if let Some(doi) = &paper.doi {
+            push_ris_tag(&mut output, "DO", doi);
+        }
+        if let Some(year) = paper.published.as_deref().and_then(|date| date.get(..4)) {
+            push_ris_tag(&mut output, "PY", year);
+        }
+        for category in &paper.categories {
+            push_ris_tag(&mut output, "KW", category);
         }
+        output.push_str("ER  - \r\n");
     }
-}
 
-#[derive(serde::Deserialize)]
-pub struct SearchArgs {
-    query: String,
-    max_results: Option<i32>,
+    output
 }
 
-// Tool to search for papers
-#[derive(serde::Deserialize, serde::Serialize)]
-pub struct ArxivSearchTool;
+// Atom feed of papers, newest first, for feed readers following a query
+pub fn format_papers_as_atom(query: &str, papers: &[Paper]) -> String {
+    let xml = |text: &str| quick_xml::escape::escape(text).into_owned();
+    let feed_id = reqwest::Url::parse_with_params(
+        ARXIV_URL,
+        &[
+            ("search_query", format!("all:{}", query)),
+            ("sortBy", SortBy::SubmittedDate.as_str().to_string()),
+            ("sortOrder", "descending".to_string()),
+        ],
+    )
+    .map_or_else(|_| ARXIV_URL.to_string(), String::from);
+    // A feed must say when it last changed, which is when its newest paper appeared
+    let updated = papers
+        .iter()
+        .filter_map(|paper| paper.published.as_deref())
+        .max()
+        .unwrap_or("1970-01-01T00:00:00Z");
 
-impl Tool for ArxivSearchTool {
-    const NAME: &'static str = "search_arxiv";
-    type Error = ArxivError;
-    type Args = SearchArgs;
-    type Output = Vec<Paper>;
+    let mut output = String::new();
+    output.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    output.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    output.push_str(&format!("  <id>{}</id>\n", xml(&feed_id)));
+    output.push_str(&format!("  <title>arXiv: {}</title>\n", xml(query)));
+    output.push_str(&format!("  <updated>{}</updated>\n", xml(updated)));
+    // Entries without authors fall back to the feed's
+    output.push_str("  <author><name>arXiv</name></author>\n");
 
-    async fn definition(&self, _prompt: String) -> ToolDefinition {
-        ToolDefinition {
-            name: "search_arxiv".to_string(),
-            description: "Search for academic papers on arXiv".to_string(),
-            parameters: json!({
-                "type": "object",
-                "properties": {
-                    "query": {
-                        "type": "string",
-                        "description": "Search query for papers"
-                    },
-                    "max_results": {
-                        "type": "integer",
-                        "description": "Maximum number of results to return (default: 5)"
-                    }
-                },
-                "required": ["query"]
-            }),
+    for paper in papers {
+        let published = paper.published.as_deref().unwrap_or(updated);
+        output.push_str("  <entry>\n");
+        // Unversioned ids, so a new version doesn't show up as another entry
+        output.push_str(&format!(
+            "    <id>oai:arXiv.org:{}</id>\n",
+            xml(base_arxiv_id(&paper.url))
+        ));
+        output.push_str(&format!(
+            "    <title>{}</title>\n",
+            xml(&normalize_latex(&paper.title))
+        ));
+        output.push_str(&format!(
+            "    <link rel=\"alternate\" href=\"{}\"/>\n",
+            xml(&paper.url)
+        ));
+        output.push_str(&format!("    <published>{}</published>\n", xml(published)));
+        output.push_str(&format!("    <updated>{}</updated>\n", xml(published)));
+        for author in &paper.authors {
+            output.push_str(&format!(
+                "    <author><name>{}</name></author>\n",
+                xml(&author.name)
+            ));
+        }
+        for category in &paper.categories {
+            output.push_str(&format!("    <category term=\"{}\"/>\n", xml(category)));
         }
+        output.push_str(&format!(
+            "    <summary>{}</summary>\n",
+            xml(&normalize_latex(&paper.abstract_text))
+        ));
+        output.push_str("  </entry>\n");
     }
 
-    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
-        let max_results = args.max_results.unwrap_or(5);
-        let client = reqwest::Client::new();
-
-        let response = client
-            .get(ARXIV_URL)
-            .query(&[
-                ("search_query", format!("all:{}", args.query)),
-                ("start", 0.to_string()),
-                ("max_results", max_results.to_string()),
-            ])
-            .send()
-            .await?
-            .text()
-            .await?;
+    output.push_str("</feed>\n");
+    output
+}
 
-        Ok(ArxivParser::new().parse_response(&response)?)
+// BibTeX formatting for LaTeX bibliographies
+pub fn format_papers_as_bibtex(papers: &[Paper]) -> String {
+    let mut output = String::new();
+
+    for paper in papers {
+        let id = arxiv_id_from_url(&paper.url);
+        output.push_str(&format!("@misc{{{},\n", id));
+        output.push_str(&format!("  title = {{{}}},\n", paper.title));
+        output.push_str(&format!(
+            "  author = {{{}}},\n",
+            join_authors(&paper.authors, " and ")
+        ));
+        if let Some(year) = paper.published.as_deref().and_then(|date| date.get(..4)) {
+            output.push_str(&format!("  year = {{{}}},\n", year));
+        }
+        output.push_str(&format!("  eprint = {{{}}},\n", id));
+        output.push_str("  archivePrefix = {arXiv},\n");
+        if let Some(category) = paper.primary_category.as_ref().or(paper.categories.first()) {
+            output.push_str(&format!("  primaryClass = {{{}}},\n", category));
+        }
+        if let Some(doi) = &paper.doi {
+            output.push_str(&format!("  doi = {{{}}},\n", doi));
+        }
+        output.push_str(&format!("  url = {{{}}},\n", paper.url));
+        output.push_str("}\n\n");
     }
+
+    output
 }
 
-// HTML formatting function for papers
-pub fn format_papers_as_html(papers: &[Paper]) -> Result<String, anyhow::Error> {
-    let tpl = std::fs::read_to_string("static/table.html")?;
-    let mut context = tera::Context::new();
-    context.insert("papers", papers);
+// RIS values must stay on a single line, and some importers require CRLF endings
+fn push_ris_tag(output: &mut String, tag: &str, value: &str) {
+    let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+    output.push_str(&format!("{}  - {}\r\n", tag, value));
+}
 
-    let result = tera::Tera::one_off(&tpl, &context, false)?;
+// Table cells can't contain raw pipes or line breaks
+fn escape_markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
 
-    Ok(result)
+// ARXIV_STRICT_ENTRIES makes an incomplete entry fail the whole parse instead of being skipped
+fn strict_entries_enabled() -> bool {
+    std::env::var("ARXIV_STRICT_ENTRIES")
+        .is_ok_and(|value| matches!(value.trim(), "1" | "true" | "yes"))
+}
+
+// ARXIV_STRICT_UTF8 makes invalid UTF-8 fail the parse instead of being replaced, to detect
+// corrupted feeds
+fn strict_utf8_enabled() -> bool {
+    std::env::var("ARXIV_STRICT_UTF8")
+        .is_ok_and(|value| matches!(value.trim(), "1" | "true" | "yes"))
 }
 
 #[derive(Default)]
 struct ArxivParser<'a> {
     papers: Vec<Paper>,
     current_paper: Option<Paper>,
-    current_authors: Vec<String>,
+    current_authors: Vec<Author>,
     current_categories: Vec<String>,
     in_entry: bool,
     current_field: Option<&'a str>,
+    // The entry's <id>, only checked for being there
+    current_id: String,
+    strict_utf8: bool,
+    // Fail on entries without a title or id instead of skipping them
+    strict_entries: bool,
+    lossy_replacements: usize,
+    total_results: usize,
+    start: usize,
 }
 
 impl<'a> ArxivParser<'a> {
@@ -129,35 +2596,101 @@ impl<'a> ArxivParser<'a> {
             current_categories: Vec::new(),
             in_entry: false,
             current_field: None,
+            current_id: String::new(),
+            strict_utf8: strict_utf8_enabled(),
+            strict_entries: strict_entries_enabled(),
+            lossy_replacements: 0,
+            total_results: 0,
+            start: 0,
         }
     }
 
+    fn with_strict_utf8(mut self, strict_utf8: bool) -> Self {
+        self.strict_utf8 = strict_utf8;
+        self
+    }
+
     fn parse_start_event(&mut self, event: &BytesStart) {
-        match event.name().as_ref() {
+        // Without its prefix, so `arxiv:doi` is `doi` whichever prefix the feed declared
+        match event.local_name().into_inner() {
             b"entry" => {
                 self.in_entry = true;
+                self.current_field = None;
+                self.current_id.clear();
                 self.current_paper = Some(Paper::new());
                 self.current_authors.clear();
                 self.current_categories.clear();
             }
+            // The feed's own title (the query echo), id and updated date aren't paper data
+            b"title" | b"id" | b"updated" if !self.in_entry => self.current_field = None,
             b"title" if self.in_entry => self.current_field = Some("title"),
-            b"author" if self.in_entry => self.current_field = Some("author"),
+            b"id" if self.in_entry => self.current_field = Some("id"),
+            b"author" if self.in_entry => self.current_authors.push(Author::new(String::new())),
+            b"name" if self.in_entry => self.current_field = Some("author"),
+            b"affiliation" if self.in_entry => self.current_field = Some("affiliation"),
             b"summary" if self.in_entry => self.current_field = Some("abstract"),
             b"link" if self.in_entry => self.current_field = Some("link"),
             b"category" if self.in_entry => self.current_field = Some("category"),
+            b"published" if self.in_entry => self.current_field = Some("published"),
+            b"doi" if self.in_entry => self.current_field = Some("doi"),
+            b"totalResults" => self.current_field = Some("total_results"),
+            b"startIndex" => self.current_field = Some("start_index"),
             _ => (),
         };
     }
 
     fn parse_text_event(&mut self, event: &BytesText) -> Result<(), ArxivError> {
+        // Feed-level OpenSearch metadata lives outside of any entry
+        match self.current_field {
+            Some("total_results") => {
+                self.total_results = parse_count(event)?;
+                return Ok(());
+            }
+            Some("start_index") => {
+                self.start = parse_count(event)?;
+                return Ok(());
+            }
+            _ => (),
+        }
+
+        // Anything else only counts inside an entry, whatever state an earlier event left
+        if !self.in_entry {
+            return Ok(());
+        }
         let Some(paper) = self.current_paper.as_mut() else {
             return Ok(());
         };
-        let text = str::from_utf8(event.as_ref())?.to_owned();
+        let text = if self.strict_utf8 {
+            str::from_utf8(event.as_ref())?.to_owned()
+        } else {
+            // Keep as much of the text as possible rather than dropping the whole feed
+            let text = String::from_utf8_lossy(event.as_ref());
+            if let Cow::Owned(_) = text {
+                self.lossy_replacements += 1;
+            }
+            text.into_owned()
+        };
         match self.current_field {
             Some("title") => paper.title = text,
-            Some("author") => self.current_authors.push(text),
+            Some("id") => self.current_id = text,
+            Some("author") => {
+                if let Some(author) = self.current_authors.last_mut() {
+                    author.name = text;
+                }
+            }
+            // Authors can list several affiliations
+            Some("affiliation") => {
+                if let Some(author) = self.current_authors.last_mut() {
+                    let affiliation = text.trim();
+                    author.affiliation = Some(match author.affiliation.take() {
+                        Some(earlier) => format!("{}; {}", earlier, affiliation),
+                        None => affiliation.to_owned(),
+                    });
+                }
+            }
             Some("abstract") => paper.abstract_text = text,
+            Some("published") => paper.published = Some(text),
+            Some("doi") => paper.doi = Some(text.trim().to_owned()),
             _ => (),
         }
         Ok(())
@@ -168,7 +2701,7 @@ impl<'a> ArxivParser<'a> {
             return Ok(());
         }
 
-        if event.name().as_ref() == b"link" {
+        if event.local_name().into_inner() == b"link" {
             if let Some(paper) = self.current_paper.as_mut() {
                 for attr in event.attributes().flatten() {
                     if attr.key.as_ref() == b"href" {
@@ -181,7 +2714,17 @@ impl<'a> ArxivParser<'a> {
             }
         }
 
-        if event.name().as_ref() == b"category" {
+        if event.local_name().into_inner() == b"primary_category" {
+            if let Some(paper) = self.current_paper.as_mut() {
+                for attr in event.attributes().flatten() {
+                    if attr.key.as_ref() == b"term" {
+                        paper.primary_category = Some(str::from_utf8(&attr.value)?.to_owned());
+                    }
+                }
+            }
+        }
+
+        if event.local_name().into_inner() == b"category" {
             for attr in event.attributes().flatten() {
                 if attr.key.as_ref() == b"term" {
                     self.current_categories
@@ -194,16 +2737,37 @@ impl<'a> ArxivParser<'a> {
     }
 
     fn parse_end_event(&mut self, event: &BytesEnd) -> Result<(), ArxivError> {
-        match event.name().as_ref() {
+        match event.local_name().into_inner() {
             b"entry" => {
-                if let Some(mut paper) = self.current_paper.take() {
-                    paper.authors.clone_from(&self.current_authors);
-                    paper.categories.clone_from(&self.current_categories);
-                    self.papers.push(paper);
-                }
                 self.in_entry = false;
+                self.current_field = None;
+                let Some(mut paper) = self.current_paper.take() else {
+                    return Ok(());
+                };
+                // An entry without a title or id is junk rather than a paper
+                let missing = if paper.title.trim().is_empty() {
+                    Some("title")
+                } else if self.current_id.trim().is_empty() {
+                    Some("id")
+                } else {
+                    None
+                };
+                if let Some(field) = missing {
+                    if self.strict_entries {
+                        return Err(ArxivError::MalformedEntry(field));
+                    }
+                    tracing::warn!(field, "skipping an arXiv entry that has no {}", field);
+                    return Ok(());
+                }
+                paper.authors = dedup_authors(&self.current_authors);
+                paper.categories.clone_from(&self.current_categories);
+                self.papers.push(paper);
+            }
+            b"title" | b"id" | b"author" | b"name" | b"affiliation" | b"summary"
+            | b"link" | b"category" | b"published" | b"doi" => {
+                self.current_field = None;
             }
-            b"title" | b"author" | b"summary" | b"link" | b"category" => {
+            b"totalResults" | b"startIndex" => {
                 self.current_field = None;
             }
             _ => (),
@@ -211,8 +2775,10 @@ impl<'a> ArxivParser<'a> {
         Ok(())
     }
 
-    fn parse_response(&mut self, input: &str) -> Result<Vec<Paper>, ArxivError> {
-        let mut reader = Reader::from_str(input);
+    #[tracing::instrument(skip_all, fields(bytes = input.len()))]
+    fn parse_response(&mut self, input: &[u8]) -> Result<SearchResult, ArxivError> {
+        // From the bytes, so invalid UTF-8 reaches parse_text_event instead of failing here
+        let mut reader = Reader::from_reader(input);
         reader.trim_text(true);
 
         let mut buf = Vec::new();
@@ -228,12 +2794,59 @@ impl<'a> ArxivParser<'a> {
             }
         }
 
+        if self.lossy_replacements > 0 {
+            tracing::warn!(
+                "Replaced invalid UTF-8 in {} text fields of the arXiv response",
+                self.lossy_replacements
+            );
+        }
+
+        tracing::debug!(entries = self.papers.len(), "parsed arXiv feed");
         if self.papers.is_empty() {
             return Err(ArxivError::NoResults);
         }
 
-        Ok(self.papers.clone())
+        Ok(SearchResult {
+            papers: collapse_versions(self.papers.clone()),
+            total_results: self.total_results,
+            start: self.start,
+            related_to: None,
+        })
+    }
+}
+
+// Authors with whitespace collapsed and repeats dropped, keeping the first spelling seen
+fn dedup_authors(authors: &[Author]) -> Vec<Author> {
+    let mut deduped: Vec<Author> = Vec::with_capacity(authors.len());
+    let mut seen = HashSet::new();
+    for author in authors {
+        let name = author.name.split_whitespace().collect::<Vec<_>>().join(" ");
+        if name.is_empty() {
+            continue;
+        }
+        if seen.insert(name.to_lowercase()) {
+            deduped.push(Author {
+                name,
+                affiliation: author.affiliation.clone(),
+            });
+        } else if let Some(affiliation) = &author.affiliation {
+            // A repeat may be the only listing that carries the affiliation
+            let first = deduped
+                .iter_mut()
+                .find(|first| first.name.to_lowercase() == name.to_lowercase());
+            if let Some(first) = first.filter(|first| first.affiliation.is_none()) {
+                first.affiliation = Some(affiliation.clone());
+            }
+        }
     }
+    deduped
+}
+
+fn parse_count(event: &BytesText) -> Result<usize, ArxivError> {
+    Ok(str::from_utf8(event.as_ref())?
+        .trim()
+        .parse()
+        .unwrap_or_default())
 }
 
 fn convert_pdf_url(url: &str) -> String {
@@ -250,3 +2863,855 @@ fn convert_pdf_url(url: &str) -> String {
         url.replace("http://", "https://")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An Atom feed around the given entries, as raw bytes so tests can break its encoding
+    fn feed(entries: &[u8]) -> Vec<u8> {
+        let mut feed = b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <feed xmlns=\"http://www.w3.org/2005/Atom\">"
+            .to_vec();
+        feed.extend_from_slice(entries);
+        feed.extend_from_slice(b"</feed>");
+        feed
+    }
+
+    #[test]
+    fn replaces_invalid_utf8_instead_of_dropping_the_feed() {
+        let feed = feed(
+            b"<entry><id>http://arxiv.org/abs/2401.00001v1</id>\
+              <title>Caf\xe9 Transformers</title>\
+              <summary>One stray \xff byte</summary>\
+              <link href=\"http://arxiv.org/abs/2401.00001v1\" rel=\"alternate\"/></entry>",
+        );
+
+        let mut parser = ArxivParser::new().with_strict_utf8(false);
+        let result = parser.parse_response(&feed).unwrap();
+        assert_eq!(result.papers.len(), 1);
+        assert_eq!(result.papers[0].title, "Caf\u{FFFD} Transformers");
+        assert_eq!(result.papers[0].abstract_text, "One stray \u{FFFD} byte");
+        assert_eq!(parser.lossy_replacements, 2);
+
+        let strict = ArxivParser::new()
+            .with_strict_utf8(true)
+            .parse_response(&feed);
+        assert!(matches!(strict, Err(ArxivError::Utf8Error(_))));
+    }
+
+    const FAKE_PDF: &[u8] = b"%PDF-1.4\n1 0 obj << /Type /Catalog >> endobj\n%%EOF\n";
+
+    #[tokio::test]
+    async fn downloads_a_pdf_only_when_it_is_one() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/pdf/2401.00001v1"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(FAKE_PDF, "application/pdf"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/pdf/2401.00002v1"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("<html></html>", "text/html"))
+            .mount(&server)
+            .await;
+        let url = |id: &str| format!("{}/pdf/{}", server.uri(), id);
+
+        let response = open_pdf(&url("2401.00001v1"), "2401.00001v1")
+            .await
+            .unwrap();
+        assert_eq!(response.bytes().await.unwrap(), FAKE_PDF);
+
+        assert!(matches!(
+            open_pdf(&url("2401.00002v1"), "2401.00002v1").await,
+            Err(ArxivError::UnexpectedContentType(content_type)) if content_type == "text/html"
+        ));
+        assert!(matches!(
+            open_pdf(&url("../../etc/passwd"), "../../etc/passwd").await,
+            Err(ArxivError::InvalidId(_))
+        ));
+    }
+
+    #[test]
+    fn clamps_max_results_to_what_arxiv_accepts() {
+        assert_eq!(clamp_max_results(None), 5);
+        assert_eq!(clamp_max_results(Some(0)), 5);
+        assert_eq!(clamp_max_results(Some(-3)), 5);
+        assert_eq!(clamp_max_results(Some(5)), 5);
+        assert_eq!(clamp_max_results(Some(2000)), 2000);
+        assert_eq!(clamp_max_results(Some(3000)), 2000);
+    }
+
+    #[test]
+    fn reads_the_opensearch_totals_of_a_feed() {
+        let feed = feed(
+            b"<opensearch:totalResults xmlns:opensearch=\"http://a9.com/-/spec/opensearch/1.1/\">\
+              1234</opensearch:totalResults>\
+              <opensearch:startIndex xmlns:opensearch=\"http://a9.com/-/spec/opensearch/1.1/\">\
+              10</opensearch:startIndex>\
+              <entry><id>http://arxiv.org/abs/2401.00001v1</id><title>Only one</title>\
+              <link href=\"http://arxiv.org/abs/2401.00001v1\" rel=\"alternate\"/></entry>",
+        );
+
+        let result = ArxivParser::new().parse_response(&feed).unwrap();
+        assert_eq!(result.total_results, 1234);
+        assert_eq!(result.start, 10);
+        assert_eq!(result.papers.len(), 1);
+    }
+
+    #[test]
+    fn reads_the_doi_of_entries_that_have_one() {
+        let feed = feed(
+            b"<entry><id>http://arxiv.org/abs/1207.7214v2</id><title>Published</title>\
+              <link href=\"http://arxiv.org/abs/1207.7214v2\" rel=\"alternate\"/>\
+              <arxiv:doi xmlns:arxiv=\"http://arxiv.org/schemas/atom\">\
+              10.1016/j.physletb.2012.08.020</arxiv:doi></entry>\
+              <entry><id>http://arxiv.org/abs/2401.00001v1</id><title>Preprint</title>\
+              <link href=\"http://arxiv.org/abs/2401.00001v1\" rel=\"alternate\"/></entry>",
+        );
+
+        let papers = ArxivParser::new().parse_response(&feed).unwrap().papers;
+        assert_eq!(
+            papers[0].doi.as_deref(),
+            Some("10.1016/j.physletb.2012.08.020")
+        );
+        assert_eq!(papers[1].doi, None);
+    }
+
+    #[test]
+    fn renders_common_latex_as_unicode() {
+        assert_eq!(
+            normalize_latex(r"$\alpha$-Divergence Minimization"),
+            "α-Divergence Minimization"
+        );
+        assert_eq!(
+            normalize_latex(r"From $A \to B$ with $\infty$ data"),
+            "From A → B with ∞ data"
+        );
+        assert_eq!(
+            normalize_latex(r"Scaling $x^2$ and $x^{10}$, CO$_2$ and H$_{2}$O"),
+            "Scaling x² and x¹⁰, CO₂ and H₂O"
+        );
+        assert_eq!(
+            normalize_latex(r"\textbf{Bold} \emph{claims}, 50\% faster"),
+            "Bold claims, 50% faster"
+        );
+    }
+
+    #[test]
+    fn leaves_latex_it_cannot_render_as_written() {
+        assert_eq!(
+            normalize_latex(r"$\mathcal{A}$ in $O(n^{1/2})$"),
+            r"\mathcal{A} in O(n^{1/2})"
+        );
+        assert_eq!(normalize_latex(r"\unknown{x}"), r"\unknown{x}");
+        assert_eq!(normalize_latex("No LaTeX at all"), "No LaTeX at all");
+    }
+
+    #[test]
+    fn finds_the_json_array_in_an_agent_response() {
+        let array = r#"[{"title": "A [short] survey", "authors": ["Ada Lovelace"]}]"#;
+        let clean = array.to_string();
+        let fenced = format!("```json\n{}\n```", array);
+        let prefixed = format!(
+            "Here are the papers I found [2 of them]:\n\n{}\n\nLet me know if you need more.",
+            array
+        );
+        for response in [&clean, &fenced, &prefixed] {
+            assert_eq!(extract_json_array(response).unwrap(), array, "{}", response);
+            let papers: Vec<serde_json::Value> = parse_agent_json(response).unwrap();
+            assert_eq!(papers[0]["title"], "A [short] survey");
+        }
+
+        assert!(matches!(
+            extract_json_array("I could not find any papers [sorry]."),
+            Err(ArxivError::AgentResponseNotJson(_))
+        ));
+    }
+
+    #[test]
+    fn drops_repeated_and_blank_author_names() {
+        let author = |name: &str, affiliation: Option<&str>| Author {
+            name: name.to_string(),
+            affiliation: affiliation.map(str::to_string),
+        };
+        let authors = [
+            author("  Ada   Lovelace ", None),
+            author("Charles Babbage", Some("Cambridge")),
+            author("ada lovelace", Some("London")),
+            author(" ", None),
+            author("Charles\nBabbage", Some("Trinity")),
+        ];
+        // A repeat lends its affiliation to the first listing only when that has none
+        assert_eq!(
+            dedup_authors(&authors),
+            [
+                author("Ada Lovelace", Some("London")),
+                author("Charles Babbage", Some("Cambridge"))
+            ]
+        );
+    }
+
+    #[test]
+    fn gives_each_author_their_own_affiliations() {
+        let feed = feed(
+            b"<entry xmlns:arxiv=\"http://arxiv.org/schemas/atom\">\
+              <id>http://arxiv.org/abs/2401.00001v1</id><title>Affiliated</title>\
+              <link href=\"http://arxiv.org/abs/2401.00001v1\" rel=\"alternate\"/>\
+              <author><name>Ada Lovelace</name>\
+              <arxiv:affiliation>University of London</arxiv:affiliation></author>\
+              <author><name>Charles Babbage</name>\
+              <arxiv:affiliation>Cambridge</arxiv:affiliation>\
+              <arxiv:affiliation> Royal Society </arxiv:affiliation></author>\
+              <author><name>Mary Somerville</name></author></entry>",
+        );
+
+        let papers = ArxivParser::new().parse_response(&feed).unwrap().papers;
+        let authors: Vec<_> = papers[0]
+            .authors
+            .iter()
+            .map(|author| (author.name.as_str(), author.affiliation.as_deref()))
+            .collect();
+        assert_eq!(
+            authors,
+            [
+                ("Ada Lovelace", Some("University of London")),
+                ("Charles Babbage", Some("Cambridge; Royal Society")),
+                ("Mary Somerville", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn serializes_authors_without_an_affiliation_as_plain_names() {
+        let authors = vec![
+            Author::new("Mary Somerville".to_string()),
+            Author {
+                name: "Ada Lovelace".to_string(),
+                affiliation: Some("University of London".to_string()),
+            },
+        ];
+        let json = serde_json::to_value(&authors).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                "Mary Somerville",
+                {"name": "Ada Lovelace", "affiliation": "University of London"}
+            ])
+        );
+        // Both shapes read back, so papers saved before affiliations still load
+        assert_eq!(
+            serde_json::from_value::<Vec<Author>>(json).unwrap(),
+            authors
+        );
+    }
+
+    #[test]
+    fn renders_papers_as_html_escaping_what_arxiv_sent() {
+        let papers = [
+            Paper {
+                authors: vec![
+                    Author {
+                        name: "Ada Lovelace".to_string(),
+                        affiliation: Some("University of London".to_string()),
+                    },
+                    Author::new("Charles Babbage".to_string()),
+                ],
+                abstract_text: "Why <script>alert(1)</script> & friends fail.".to_string(),
+                categories: vec!["cs.CR".to_string()],
+                ..Paper::fixture("Escaping <b>markup</b> in titles")
+            },
+            Paper {
+                url: "http://arxiv.org/abs/1706.03762v7".to_string(),
+                ..Paper::fixture("Attention Is All You Need")
+            },
+        ];
+
+        let html = format_papers_as_html(&templates().unwrap(), &papers, None).unwrap();
+        assert!(
+            html.contains("<h3>Escaping &lt;b&gt;markup&lt;&#x2F;b&gt; in titles</h3>"),
+            "{}",
+            html
+        );
+        assert!(html.contains("Why &lt;script&gt;alert(1)&lt;&#x2F;script&gt; &amp; friends"));
+        assert!(!html.contains("<script>"));
+        assert!(html.contains(
+            "Ada Lovelace <span class=\"affiliation\">(University of London)</span>, \
+             Charles Babbage\n"
+        ));
+        assert!(html.contains("<h3>Attention Is All You Need</h3>"));
+        assert!(html.contains("href=\"http:&#x2F;&#x2F;arxiv.org&#x2F;abs&#x2F;1706.03762v7\""));
+    }
+
+    #[test]
+    fn reads_retry_after_as_seconds_or_a_date() {
+        use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+
+        let with = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+            headers
+        };
+        assert_eq!(retry_after(&with("120")), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(&with(" 5 ")), Some(Duration::from_secs(5)));
+
+        let later = SystemTime::now() + Duration::from_secs(3600);
+        let wait = retry_after(&with(&httpdate::fmt_http_date(later))).unwrap();
+        assert!(wait > Duration::from_secs(3500) && wait <= Duration::from_secs(3600));
+        assert_eq!(
+            retry_after(&with("Thu, 01 Jan 1970 00:00:00 GMT")),
+            Some(Duration::ZERO)
+        );
+
+        assert_eq!(retry_after(&with("soon")), None);
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+
+    fn titles(papers: &[Paper]) -> Vec<&str> {
+        papers.iter().map(|paper| paper.title.as_str()).collect()
+    }
+
+    #[test]
+    fn merges_searches_by_combined_rank() {
+        let results = vec![
+            (
+                "attention".to_string(),
+                vec![
+                    Paper {
+                        url: "http://arxiv.org/abs/2401.00001v1".to_string(),
+                        ..Paper::fixture("X")
+                    },
+                    Paper {
+                        url: "http://arxiv.org/abs/2401.00002v1".to_string(),
+                        ..Paper::fixture("Y")
+                    },
+                    Paper {
+                        url: "http://arxiv.org/abs/2401.00001v2".to_string(),
+                        ..Paper::fixture("X again")
+                    },
+                ],
+            ),
+            (
+                "memory".to_string(),
+                vec![
+                    Paper {
+                        url: "http://arxiv.org/abs/2401.00002v1".to_string(),
+                        ..Paper::fixture("Y")
+                    },
+                    Paper {
+                        url: "http://arxiv.org/abs/2401.00003v1".to_string(),
+                        ..Paper::fixture("Z")
+                    },
+                ],
+            ),
+        ];
+        // Y scores 1/2 + 1, X 1 and Z 1/2, and X's second version counts once
+        let merged = merge_results(results);
+        assert_eq!(titles(&merged), ["Y", "X", "Z"]);
+        assert_eq!(merged[0].found_by, ["attention", "memory"]);
+        assert_eq!(merged[1].found_by, ["attention"]);
+        assert_eq!(merged[2].found_by, ["memory"]);
+    }
+
+    #[test]
+    fn collapses_versions_into_the_newest_at_the_first_rank() {
+        let collapsed = collapse_versions(vec![
+            Paper {
+                url: "http://arxiv.org/abs/2401.00001v1".to_string(),
+                ..Paper::fixture("v1")
+            },
+            Paper {
+                url: "http://arxiv.org/abs/hep-th/9901001v3".to_string(),
+                ..Paper::fixture("old-style")
+            },
+            Paper {
+                url: "http://arxiv.org/abs/2401.00001v3".to_string(),
+                ..Paper::fixture("v3")
+            },
+            Paper {
+                url: "http://arxiv.org/abs/2401.00001v2".to_string(),
+                ..Paper::fixture("v2")
+            },
+            Paper {
+                url: "https://example.org/paper".to_string(),
+                ..Paper::fixture("elsewhere")
+            },
+            Paper {
+                url: "http://arxiv.org/pdf/hep-th/9901001v3.pdf".to_string(),
+                ..Paper::fixture("old-style pdf")
+            },
+        ]);
+        assert_eq!(titles(&collapsed), ["v3", "old-style", "elsewhere"]);
+
+        assert_eq!(collapsed[0].arxiv_id.as_deref(), Some("2401.00001"));
+        assert_eq!(collapsed[0].version, Some(3));
+        assert_eq!(collapsed[0].superseded_versions, [1, 2]);
+        // The same version through another link isn't an older one
+        assert_eq!(collapsed[1].arxiv_id.as_deref(), Some("hep-th/9901001"));
+        assert_eq!(collapsed[1].version, Some(3));
+        assert!(collapsed[1].superseded_versions.is_empty());
+        assert_eq!(collapsed[2].arxiv_id, None);
+        assert_eq!(collapsed[2].version, None);
+    }
+
+    #[test]
+    fn filters_by_category_and_by_author_ignoring_diacritics() {
+        let paper = Paper {
+            authors: vec![
+                Author::new("Kurt Gödel".to_string()),
+                Author::new("Paul Erdős".to_string()),
+            ],
+            categories: vec!["math.LO".to_string(), "cs.LO".to_string()],
+            ..Paper::fixture("On formally undecidable propositions")
+        };
+        let passes = |categories: &[&str], authors: &[&str]| {
+            let to_strings =
+                |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            passes_filters(&paper, &to_strings(categories), &to_strings(authors))
+        };
+
+        assert!(passes(&[], &[]));
+        assert!(passes(&["math.*"], &[]));
+        assert!(passes(&["cs.LG", "cs.LO"], &[]));
+        assert!(!passes(&["cs.LG"], &[]));
+        assert!(passes(&[], &["godel"]));
+        assert!(passes(&[], &[" GÖDEL "]));
+        assert!(passes(&[], &["Erdos"]));
+        assert!(!passes(&[], &["Noether"]));
+        // Both filters have to pass
+        assert!(passes(&["math.LO"], &["erdős"]));
+        assert!(!passes(&["cs.LG"], &["godel"]));
+    }
+
+    fn sortable() -> Vec<Paper> {
+        let by = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| Author::new(name.to_string()))
+                .collect()
+        };
+        vec![
+            Paper {
+                authors: by(&["Zoë Ångström"]),
+                published: Some("2024-03-01".to_string()),
+                ..Paper::fixture("beta")
+            },
+            Paper {
+                authors: by(&["Ada Lovelace"]),
+                ..Paper::fixture("Alpha")
+            },
+            Paper {
+                published: Some("2023-01-01".to_string()),
+                ..Paper::fixture(" gamma")
+            },
+            Paper {
+                authors: by(&["Charles Babbage"]),
+                published: Some("2024-03-01".to_string()),
+                ..Paper::fixture("delta")
+            },
+        ]
+    }
+
+    #[test]
+    fn sorts_papers_stably_with_missing_values_last() {
+        let sorted = |key| {
+            let mut papers = sortable();
+            sort_papers(&mut papers, key);
+            papers
+        };
+        assert_eq!(
+            titles(&sorted(SortKey::PublishedDesc)),
+            ["beta", "delta", " gamma", "Alpha"]
+        );
+        assert_eq!(
+            titles(&sorted(SortKey::PublishedAsc)),
+            [" gamma", "beta", "delta", "Alpha"]
+        );
+        assert_eq!(
+            titles(&sorted(SortKey::Title)),
+            ["Alpha", "beta", "delta", " gamma"]
+        );
+        // By surname with diacritics folded, so Ångström sorts with the A's
+        assert_eq!(
+            titles(&sorted(SortKey::FirstAuthor)),
+            ["beta", "delta", "Alpha", " gamma"]
+        );
+    }
+
+    #[test]
+    fn matches_elements_whatever_their_namespace_prefix() {
+        let feed = feed(
+            b"<os:totalResults xmlns:os=\"http://a9.com/-/spec/opensearch/1.1/\">\
+              42</os:totalResults>\
+              <entry xmlns:ax=\"http://arxiv.org/schemas/atom\">\
+              <id>http://arxiv.org/abs/1207.7214v2</id><title>Prefixed</title>\
+              <link href=\"http://arxiv.org/abs/1207.7214v2\" rel=\"alternate\"/>\
+              <author><name>Ada Lovelace</name><ax:affiliation>Cambridge</ax:affiliation></author>\
+              <ax:primary_category term=\"hep-ex\"/><category term=\"hep-ex\"/>\
+              <ax:doi>10.1016/j.physletb.2012.08.020</ax:doi></entry>",
+        );
+        let result = ArxivParser::new().parse_response(&feed).unwrap();
+        assert_eq!(result.total_results, 42);
+        let paper = &result.papers[0];
+        assert_eq!(paper.primary_category.as_deref(), Some("hep-ex"));
+        assert_eq!(paper.authors[0].affiliation.as_deref(), Some("Cambridge"));
+        assert_eq!(paper.doi.as_deref(), Some("10.1016/j.physletb.2012.08.020"));
+    }
+
+    #[test]
+    fn writes_an_atom_feed_the_parser_reads_back() {
+        let papers = [
+            Paper {
+                url: "http://arxiv.org/abs/1706.03762v7".to_string(),
+                published: Some("2017-06-12T17:57:34Z".to_string()),
+                authors: vec![Author::new("Ashish Vaswani".to_string())],
+                categories: vec!["cs.CL".to_string(), "cs.LG".to_string()],
+                ..Paper::fixture("Attention Is All You Need")
+            },
+            Paper {
+                url: "http://arxiv.org/abs/1810.04805v2".to_string(),
+                published: Some("2018-10-11T00:50:01Z".to_string()),
+                ..Paper::fixture("BERT")
+            },
+        ];
+
+        let atom = format_papers_as_atom("attention", &papers);
+        let parsed = ArxivParser::new().parse_response(atom.as_bytes()).unwrap();
+
+        // The feed's own title, id and author don't turn into papers
+        assert_eq!(
+            titles(&parsed.papers),
+            ["Attention Is All You Need", "BERT"]
+        );
+        let ids: Vec<&str> = parsed
+            .papers
+            .iter()
+            .map(|paper| base_arxiv_id(&paper.url))
+            .collect();
+        assert_eq!(ids, ["1706.03762", "1810.04805"]);
+        let first = &parsed.papers[0];
+        assert_eq!(first.authors[0].name, "Ashish Vaswani");
+        assert_eq!(first.categories, ["cs.CL", "cs.LG"]);
+        assert_eq!(first.published.as_deref(), Some("2017-06-12T17:57:34Z"));
+    }
+
+    const ABSTRACT_PAGE: &str = include_str!("../tests/fixtures/abs_1706.03762.html");
+
+    #[test]
+    fn keeps_only_the_text_of_an_abstract_page() {
+        let html = extract_abstract_html(ABSTRACT_PAGE).unwrap();
+        assert_eq!(
+            html,
+            "<div class=\"arxiv-abstract\">\n\
+             \x20 <h1>Attention Is All You Need</h1>\n\
+             \x20 <p class=\"authors\">Ashish Vaswani, Noam Shazeer, Niki Parmar</p>\n\
+             \x20 <p class=\"dateline\">[Submitted on 12 Jun 2017 (v1), last revised 2 Aug 2023 \
+             (this version, v7)]</p>\n\
+             \x20 <blockquote>The dominant sequence transduction models are based on complex \
+             recurrent or convolutional neural networks &amp; include an encoder and a decoder. \
+             Our model achieves 28.4 BLEU, where previous results were &lt;28.</blockquote>\n\
+             </div>\n"
+        );
+
+        // A page without an abstract block isn't an abstract page
+        assert_eq!(
+            extract_abstract_html("<html><body><div id=\"abs\"></div></body></html>"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn names_the_client_to_arxiv() {
+        use wiremock::matchers::{header, method};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        for agent in [DEFAULT_USER_AGENT, "survey-bot/1.0 (mailto:me@example.org)"] {
+            Mock::given(method("GET"))
+                .and(header("user-agent", agent))
+                .respond_with(ResponseTemplate::new(200).set_body_string(agent))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        let source = LiveSource::new(format!("{}/api/query", server.uri()));
+
+        // No other test reads ARXIV_USER_AGENT
+        std::env::remove_var("ARXIV_USER_AGENT");
+        let default = source.fetch_by_id("1706.03762").await;
+        std::env::set_var("ARXIV_USER_AGENT", "survey-bot/1.0 (mailto:me@example.org)");
+        let overridden = source.fetch_by_id("1706.03762").await;
+        std::env::remove_var("ARXIV_USER_AGENT");
+
+        // Each mock echoes the agent it matched, any other agent gets an empty 404
+        assert_eq!(default.unwrap(), DEFAULT_USER_AGENT.as_bytes());
+        assert_eq!(
+            overridden.unwrap(),
+            b"survey-bot/1.0 (mailto:me@example.org)"
+        );
+    }
+
+    #[tokio::test]
+    async fn fetches_abstract_pages_from_the_configured_base_url() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/abs/1706.03762v7"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(ABSTRACT_PAGE, "text/html"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/abs/2401.99999"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        std::env::set_var("ARXIV_ABS_BASE_URL", format!("{}/abs", server.uri()));
+
+        let html = fetch_abstract_html("1706.03762v7").await.unwrap();
+        assert!(
+            html.contains("<h1>Attention Is All You Need</h1>"),
+            "{}",
+            html
+        );
+        assert!(matches!(
+            fetch_abstract_html("2401.99999").await,
+            Err(ArxivError::PaperNotFound(id)) if id == "2401.99999"
+        ));
+        // Ids are checked before anything is fetched
+        assert!(matches!(
+            fetch_abstract_html("../etc/passwd").await,
+            Err(ArxivError::InvalidId(_))
+        ));
+        std::env::remove_var("ARXIV_ABS_BASE_URL");
+    }
+
+    #[tokio::test]
+    async fn asks_arxiv_for_the_newest_submissions_first() {
+        use wiremock::matchers::{method, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let page = feed(
+            b"<entry><id>http://arxiv.org/abs/2401.00001v1</id><title>Newest</title>\
+              <link href=\"http://arxiv.org/abs/2401.00001v1\" rel=\"alternate\"/></entry>",
+        );
+        let server = MockServer::start().await;
+        for search_query in ["cat:cs.CL", "(all:diffusion) AND (cat:cs.CV)"] {
+            Mock::given(method("GET"))
+                .and(query_param("search_query", search_query))
+                .and(query_param("sortBy", "submittedDate"))
+                .and(query_param("sortOrder", "descending"))
+                .and(query_param("max_results", "3"))
+                .respond_with(
+                    ResponseTemplate::new(200).set_body_raw(page.clone(), "application/atom+xml"),
+                )
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        let source = || Box::new(LiveSource::new(format!("{}/api/query", server.uri())));
+
+        // Category names are turned into their codes
+        let latest = LatestPapersTool::new(source())
+            .call(LatestArgs {
+                category: "computation and language".to_string(),
+                count: Some(3),
+            })
+            .await
+            .unwrap();
+        assert_eq!(latest.papers[0].title, "Newest");
+
+        let papers = search_latest(source().as_ref(), "diffusion", &["cs.CV".to_string()], 3)
+            .await
+            .unwrap();
+        assert_eq!(papers.len(), 1);
+    }
+
+    const FULL_TEXT_PAGE: &str = "<html><head><style>p { color: red; }</style>\
+        <script>var tracking = true;</script></head><body>\
+        <nav>Back to arXiv</nav>\
+        <article><h1>Attention Is All You Need</h1>\
+        <p>The dominant <em>sequence transduction</em> models\n are recurrent.</p>\
+        <script>renderMath();</script>\
+        <ul><li>Encoder</li><li>Decoder</li></ul></article>\
+        <footer>arXiv is supported by its members</footer></body></html>";
+
+    #[test]
+    fn keeps_only_the_paper_of_an_html_rendering() {
+        assert_eq!(
+            extract_full_text(FULL_TEXT_PAGE).as_deref(),
+            Some(
+                "Attention Is All You Need\n\n\
+                 The dominant sequence transduction models are recurrent.\n\n\
+                 Encoder\n\nDecoder"
+            )
+        );
+        // Without an article the whole body is read, still leaving out scripts and styles
+        let text = extract_full_text(
+            "<html><body><style>.x {}</style><p>Body text</p><script>go()</script></body></html>",
+        );
+        assert_eq!(text.as_deref(), Some("Body text"));
+        assert_eq!(
+            extract_full_text("<html><body><script>go()</script></body></html>"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn fetches_html_renderings_from_the_configured_base_url() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/html/2401.00001v1"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(FULL_TEXT_PAGE, "text/html"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/html/1207.7214"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        std::env::set_var("ARXIV_HTML_BASE_URL", format!("{}/html", server.uri()));
+
+        let text = fetch_full_text("2401.00001v1").await.unwrap();
+        assert!(
+            text.starts_with("Attention Is All You Need\n\n"),
+            "{}",
+            text
+        );
+        assert!(
+            !text.contains("tracking") && !text.contains("color"),
+            "{}",
+            text
+        );
+        // Older papers have no HTML rendering
+        assert!(matches!(
+            fetch_full_text("1207.7214").await,
+            Err(ArxivError::FullTextUnavailable(id)) if id == "1207.7214"
+        ));
+        std::env::remove_var("ARXIV_HTML_BASE_URL");
+    }
+
+    // A search with `total` matches, of which arXiv serves two per page whatever is asked
+    struct TwoPerPage {
+        total: usize,
+        requests: std::sync::Mutex<Vec<(usize, i32)>>,
+    }
+
+    impl TwoPerPage {
+        fn new(total: usize) -> Self {
+            Self {
+                total,
+                requests: std::sync::Mutex::new(Vec::new()),
+            }
+        }
+
+        fn requests(&self) -> Vec<(usize, i32)> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    impl ArxivSource for TwoPerPage {
+        fn fetch_page<'a>(
+            &'a self,
+            _search_query: &'a str,
+            start: usize,
+            max_results: i32,
+            _sort_by: SortBy,
+        ) -> FetchFuture<'a> {
+            self.requests.lock().unwrap().push((start, max_results));
+            let mut entries = format!(
+                "<opensearch:totalResults xmlns:opensearch=\"http://a9.com/-/spec/opensearch/1.1/\">\
+                 {}</opensearch:totalResults>",
+                self.total
+            );
+            for n in start..(start + 2).min(self.total) {
+                entries.push_str(&format!(
+                    "<entry><id>http://arxiv.org/abs/2401.{n:05}v1</id><title>Paper {n}</title>\
+                     <link href=\"http://arxiv.org/abs/2401.{n:05}v1\" rel=\"alternate\"/></entry>"
+                ));
+            }
+            let page = feed(entries.as_bytes());
+            Box::pin(async move { Ok(page) })
+        }
+
+        fn fetch_by_id<'a>(&'a self, id: &'a str) -> FetchFuture<'a> {
+            Box::pin(async move { Err(ArxivError::PaperNotFound(id.to_string())) })
+        }
+    }
+
+    async fn paginate(pages: ArxivPaginator<'_>) -> Vec<String> {
+        pages.map(|paper| paper.unwrap().title).collect().await
+    }
+
+    // Paused, so the pauses between pages pass instantly
+    #[tokio::test(start_paused = true)]
+    async fn pages_through_results_until_arxiv_has_no_more() {
+        let source = TwoPerPage::new(5);
+        let started = tokio::time::Instant::now();
+
+        let mut pages = ArxivPaginator::new(&source, "all:attention");
+        assert_eq!(pages.total_results(), None);
+        let mut titles = Vec::new();
+        while let Some(paper) = pages.next().await {
+            titles.push(paper.unwrap().title);
+        }
+        assert_eq!(pages.total_results(), Some(5));
+        assert_eq!(
+            titles,
+            ["Paper 0", "Paper 1", "Paper 2", "Paper 3", "Paper 4"]
+        );
+        // No request past the total, and arXiv's pause before each page after the first
+        let starts: Vec<usize> = source.requests().iter().map(|(start, _)| *start).collect();
+        assert_eq!(starts, [0, 2, 4]);
+        assert_eq!(started.elapsed(), ARXIV_PAGE_DELAY * 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_paging_at_the_most_papers_asked_for() {
+        let source = TwoPerPage::new(100);
+        let titles =
+            paginate(ArxivPaginator::new(&source, "all:attention").with_max_papers(3)).await;
+        assert_eq!(titles, ["Paper 0", "Paper 1", "Paper 2"]);
+        // Each page asks only for what is still missing
+        assert_eq!(source.requests(), [(0, 3), (2, 1)]);
+
+        let source = TwoPerPage::new(100);
+        assert!(
+            paginate(ArxivPaginator::new(&source, "all:attention").with_max_papers(0))
+                .await
+                .is_empty()
+        );
+        assert!(source.requests().is_empty());
+    }
+
+    #[test]
+    fn skips_entries_without_a_title_unless_strict() {
+        let feed = feed(
+            b"<entry><id>http://arxiv.org/abs/1706.03762v7</id>\
+              <title>Attention Is All You Need</title>\
+              <link href=\"http://arxiv.org/abs/1706.03762v7\" rel=\"alternate\"/></entry>\
+              <entry><id>http://arxiv.org/abs/1810.04805v2</id>\
+              <summary>No title here</summary>\
+              <link href=\"http://arxiv.org/abs/1810.04805v2\" rel=\"alternate\"/></entry>",
+        );
+
+        let parser = |strict_entries| ArxivParser {
+            strict_entries,
+            ..ArxivParser::new()
+        };
+        let papers = parser(false).parse_response(&feed).unwrap().papers;
+        assert_eq!(titles(&papers), ["Attention Is All You Need"]);
+
+        assert!(matches!(
+            parser(true).parse_response(&feed),
+            Err(ArxivError::MalformedEntry("title"))
+        ));
+    }
+}
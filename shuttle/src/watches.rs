@@ -0,0 +1,243 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json, PgPool};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use crate::categories;
+use crate::tools::{self, ArxivSource, Paper};
+
+pub const DEFAULT_REFRESH_HOURS: u64 = 24;
+
+// A saved search, re-run in the background
+#[derive(Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct Watch {
+    pub id: i64,
+    pub name: String,
+    pub query: String,
+    pub categories: Vec<String>,
+    pub max_results: i32,
+    // Unix timestamps in seconds
+    pub created_at: i64,
+    pub last_run_at: Option<i64>,
+    // Number of completed refreshes, papers found by the latest one are the new ones
+    pub runs: i32,
+}
+
+// Body of POST /api/watches
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct NewWatch {
+    pub name: Option<String>,
+    pub query: String,
+    #[serde(default)]
+    pub categories: Vec<String>,
+    pub max_results: Option<i32>,
+}
+
+#[derive(sqlx::FromRow)]
+struct WatchPaperRow {
+    metadata: Json<Paper>,
+}
+
+const WATCH_COLUMNS: &str = "id, name, query, categories, max_results,
+     EXTRACT(EPOCH FROM created_at)::BIGINT AS created_at,
+     EXTRACT(EPOCH FROM last_run_at)::BIGINT AS last_run_at, runs";
+
+// Saved searches and every paper each of them has found, kept in Postgres
+pub struct Watches {
+    pool: PgPool,
+    source: Box<dyn ArxivSource>,
+    // Held for a whole refresh, so watches never query arXiv side by side
+    arxiv_turn: Mutex<()>,
+}
+
+impl Watches {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            source: tools::source_from_env(),
+            arxiv_turn: Mutex::new(()),
+        }
+    }
+
+    pub async fn create(&self, watch: NewWatch) -> Result<Watch, sqlx::Error> {
+        let categories: Vec<String> = watch
+            .categories
+            .iter()
+            .map(|category| categories::category_code(category))
+            .collect();
+        sqlx::query_as(&format!(
+            "INSERT INTO watches (name, query, categories, max_results)
+             VALUES ($1, $2, $3, $4) RETURNING {}",
+            WATCH_COLUMNS
+        ))
+        .bind(watch.name.unwrap_or_else(|| watch.query.clone()))
+        .bind(&watch.query)
+        .bind(&categories)
+        .bind(tools::clamp_max_results(watch.max_results))
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    pub async fn list(&self) -> Result<Vec<Watch>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM watches ORDER BY id",
+            WATCH_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn get(&self, id: i64) -> Result<Option<Watch>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM watches WHERE id = $1",
+            WATCH_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    // Papers the latest refresh found that no earlier refresh had
+    pub async fn new_papers(&self, watch: &Watch) -> Result<Vec<Paper>, sqlx::Error> {
+        let rows: Vec<WatchPaperRow> = sqlx::query_as(
+            "SELECT metadata FROM watch_papers
+             WHERE watch_id = $1 AND first_seen_run = $2
+             ORDER BY metadata->>'published' DESC",
+        )
+        .bind(watch.id)
+        .bind(watch.runs)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|row| row.metadata.0).collect())
+    }
+
+    // Re-run a watch's search and store what it found, returning how many papers were new
+    pub async fn refresh(&self, watch: &Watch) -> anyhow::Result<usize> {
+        let papers = {
+            let _turn = self.arxiv_turn.lock().await;
+            let papers = tools::search_latest(
+                self.source.as_ref(),
+                &watch.query,
+                &watch.categories,
+                watch.max_results,
+            )
+            .await;
+            // arXiv asks for a pause between requests, whichever watch makes the next one
+            tokio::time::sleep(tools::ARXIV_PAGE_DELAY).await;
+            papers?
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let seen: Vec<String> =
+            sqlx::query_scalar("SELECT arxiv_id FROM watch_papers WHERE watch_id = $1")
+                .bind(watch.id)
+                .fetch_all(&mut *tx)
+                .await?;
+        let seen: HashSet<String> = seen.into_iter().collect();
+        let (run,): (i32,) = sqlx::query_as(
+            "UPDATE watches SET runs = runs + 1, last_run_at = now() WHERE id = $1
+             RETURNING runs",
+        )
+        .bind(watch.id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let fresh = unseen(&seen, &papers);
+        for paper in &papers {
+            // Papers seen before keep the run that first found them, but get fresh metadata
+            sqlx::query(
+                "INSERT INTO watch_papers (watch_id, arxiv_id, metadata, first_seen_run)
+                 VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (watch_id, arxiv_id) DO UPDATE SET metadata = EXCLUDED.metadata",
+            )
+            .bind(watch.id)
+            .bind(tools::base_arxiv_id(&paper.url))
+            .bind(Json(paper))
+            .bind(run)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(fresh.len())
+    }
+
+    // Refresh every watch, one after another
+    pub async fn refresh_all(&self) -> Result<(), sqlx::Error> {
+        for watch in self.list().await? {
+            match self.refresh(&watch).await {
+                Ok(new) => tracing::info!(watch = watch.id, new, "refreshed watch"),
+                // One failing search shouldn't hold back the rest
+                Err(e) => tracing::warn!(watch = watch.id, error = %e, "could not refresh watch"),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Papers whose arXiv id isn't in `seen`, ignoring versions. A paper that dropped out of
+// the results and came back was seen before, so it doesn't count as new
+pub fn unseen<'a>(seen: &HashSet<String>, papers: &'a [Paper]) -> Vec<&'a Paper> {
+    papers
+        .iter()
+        .filter(|paper| !seen.contains(tools::base_arxiv_id(&paper.url)))
+        .collect()
+}
+
+// Re-run every watch every `interval`, for as long as the app is up
+pub async fn refresh_periodically(watches: Arc<Watches>, interval: Duration) {
+    // The first tick waits a full interval, so redeploying doesn't refresh everything
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    // A refresh that overran shouldn't be followed by a burst of catch-up runs
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = watches.refresh_all().await {
+            tracing::warn!(error = %e, "could not list watches to refresh");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paper(id: &str) -> Paper {
+        Paper {
+            url: format!("http://arxiv.org/abs/{}", id),
+            ..Paper::fixture(id)
+        }
+    }
+
+    // One refresh, remembering every id it found the way watch_papers does
+    fn refresh(seen: &mut HashSet<String>, ids: &[&str]) -> Vec<String> {
+        let papers: Vec<Paper> = ids.iter().map(|id| paper(id)).collect();
+        let fresh = unseen(seen, &papers)
+            .into_iter()
+            .map(|paper| paper.title.clone())
+            .collect();
+        seen.extend(
+            papers
+                .iter()
+                .map(|paper| tools::base_arxiv_id(&paper.url).to_string()),
+        );
+        fresh
+    }
+
+    #[test]
+    fn counts_only_papers_no_earlier_refresh_found() {
+        let mut seen = HashSet::new();
+        assert_eq!(
+            refresh(&mut seen, &["2401.00001v1", "2401.00002v1"]),
+            ["2401.00001v1", "2401.00002v1"]
+        );
+        // A new version of a paper is the same paper, a new id is new
+        assert_eq!(
+            refresh(&mut seen, &["2401.00001v2", "2401.00003v1"]),
+            ["2401.00003v1"]
+        );
+        // 2401.00002 dropped out of the last results, coming back doesn't make it new
+        assert!(refresh(&mut seen, &["2401.00002v1", "2401.00003v1"]).is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+const chatContainer = document.getElementById('chat-container');
+const userInput = document.getElementById('user-input');
+const loading = document.getElementById('loading');
+let currentZoom = 100;
+
+userInput.addEventListener('keypress', (e) => {
+    if (e.key === 'Enter') {
+        sendMessage();
+    }
+});
+
+function zoomIn() {
+    currentZoom += 10;
+    updateZoom();
+}
+
+function zoomOut() {
+    currentZoom = Math.max(50, currentZoom - 10);
+    updateZoom();
+}
+
+function resetZoom() {
+    currentZoom = 100;
+    updateZoom();
+}
+
+function updateZoom() {
+    const viewer = document.getElementById('pdf-viewer');
+    viewer.style.transform = `scale(${currentZoom / 100})`;
+    viewer.style.transformOrigin = 'top left';
+}
+
+async function sendMessage() {
+    const message = userInput.value.trim();
+    if (!message) return;
+
+    appendMessage(message, 'user');
+    userInput.value = '';
+    loading.style.display = 'block';
+
+    try {
+        const response = await fetch('/api/search', {
+            method: 'POST',
+            headers: {
+                'Content-Type': 'application/json',
+                'Accept': 'text/html',
+            },
+            body: JSON.stringify({
+                query: message
+            })
+        });
+
+        // Errors come back as an HTML fragment explaining what went wrong
+        const isHtml = (response.headers.get('Content-Type') || '').startsWith('text/html');
+        if (!response.ok && !isHtml) {
+            throw new Error('Network response was not ok');
+        }
+
+        const text = await response.text();
+        appendMessage(text, 'assistant');
+    } catch (error) {
+        appendMessage('Sorry, there was an error processing your request.', 'assistant');
+        console.error('Error:', error);
+    } finally {
+        loading.style.display = 'none';
+    }
+}
+
+function loadPaper(url) {
+    const pdfViewer = document.getElementById('pdf-viewer');
+    const placeholder = document.getElementById('pdf-placeholder');
+    const toolbar = document.getElementById('pdf-toolbar');
+
+    pdfViewer.style.display = 'block';
+    placeholder.style.display = 'none';
+    toolbar.style.display = 'flex';
+
+    currentZoom = 100;
+    updateZoom();
+
+    pdfViewer.src = url;
+}
+
+function copyCitation(button) {
+    navigator.clipboard.writeText(button.dataset.citation).then(() => {
+        button.textContent = 'Copied';
+        setTimeout(() => {
+            button.textContent = 'Copy';
+        }, 1500);
+    }).catch((error) => {
+        console.error('Error:', error);
+    });
+}
+
+function appendMessage(message, sender) {
+    const messageDiv = document.createElement('div');
+    messageDiv.className = `message ${sender}-message`;
+
+    if (sender === 'assistant') {
+        messageDiv.innerHTML = message;
+        setTimeout(() => {
+            messageDiv.querySelectorAll('.paper-link').forEach(link => {
+                link.addEventListener('click', (e) => {
+                    e.preventDefault();
+                    loadPaper(link.href);
+                });
+            });
+            messageDiv.querySelectorAll('.copy-citation').forEach(button => {
+                button.addEventListener('click', () => copyCitation(button));
+            });
+        }, 0);
+    } else {
+        messageDiv.textContent = message;
+    }
+
+    chatContainer.appendChild(messageDiv);
+    chatContainer.scrollTop = chatContainer.scrollHeight;
+}
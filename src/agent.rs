@@ -0,0 +1,601 @@
+use anyhow::Context;
+use clap::ValueEnum;
+use rig::{
+    agent::{Agent, AgentBuilder},
+    completion::{Chat, CompletionModel, Message, Prompt, PromptError},
+    extractor::ExtractionError,
+    providers::{anthropic, openai},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use serde::Deserialize;
+
+use crate::ask::AskPaperTool;
+use crate::citations::EnrichCitationsTool;
+use crate::crossref::CrossrefLookupTool;
+use crate::fulltext::FetchFullTextTool;
+use crate::library::{Library, QueryLibraryTool};
+use crate::tools::{
+    self, ArxivSearchTool, ComparePapersTool, DownloadPdfTool, FindRelatedTool, MultiSearchTool,
+    Paper, PaperComparison, SavedPapersTool, SearchSummary,
+};
+
+// Shared with the web app, so the two can't drift apart
+const PREAMBLE: &str = include_str!("../shuttle/src/preamble.txt");
+
+// Guidance for the tools only the CLI registers
+const PREAMBLE_TOOLS: &str = "Questions about papers from earlier sessions can be answered with \
+                              the query_library tool, and questions about the details of a \
+                              specific paper with the ask_paper tool. To read a paper or one of \
+                              its sections, such as its experiments or conclusion, use the \
+                              fetch_fulltext tool. To judge how influential papers are, look up \
+                              their citation counts with the enrich_citations tool, and where \
+                              they were published with the crossref_lookup tool.";
+
+// Appended to the preamble when the agent works from a saved result set
+const OFFLINE_PREAMBLE: &str = "There is no arXiv access in this session. Instead of search_arxiv \
+                                or any other arXiv tool, use the lookup_saved_papers tool to find \
+                                papers among the ones the user saved, and return only its raw JSON \
+                                response.";
+
+const REPROMPT: &str = "Respond with only the raw JSON array returned by the search_arxiv tool, \
+                        without any other text.";
+
+const EXTRACT_PREAMBLE: &str =
+    "Extract the arXiv papers and any commentary about them from the text.";
+
+const SYNTHESIS_PREAMBLE: &str = "You are a research assistant writing a literature synthesis. \
+                                  From the papers provided, identify the common themes, the \
+                                  methodological differences between them, and the open questions \
+                                  they leave.";
+
+// Keep the synthesis prompt well inside the context window
+const MAX_SYNTHESIS_PAPERS: usize = 10;
+const MAX_SYNTHESIS_ABSTRACT_CHARS: usize = 1500;
+
+const DEFAULT_OLLAMA_URL: &str = "http://localhost:11434/v1";
+
+// Anthropic requires an explicit completion budget
+const ANTHROPIC_MAX_TOKENS: u64 = 4096;
+
+// The agent's instructions: ARXIV_AGENT_PREAMBLE if set, as text or the path of a file
+// holding it, otherwise the built-in preamble. A template, see render_preamble
+pub fn preamble() -> anyhow::Result<String> {
+    let Some(configured) = std::env::var("ARXIV_AGENT_PREAMBLE")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+    else {
+        return Ok(format!("{} {}", PREAMBLE.trim(), PREAMBLE_TOOLS));
+    };
+    let path = std::path::Path::new(configured.trim());
+    if path.is_file() {
+        return std::fs::read_to_string(path)
+            .with_context(|| format!("could not read the preamble from {}", path.display()));
+    }
+    Ok(configured)
+}
+
+// Fill in a preamble's {today} and {max_results}, with {{ and }} for literal braces. Any
+// other placeholder is an error, so a typo fails at startup instead of reaching the model
+pub fn render_preamble(template: &str, max_results: i32) -> anyhow::Result<String> {
+    let mut preamble = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        preamble.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        if let Some(after) = tail.strip_prefix("{{").or_else(|| tail.strip_prefix("}}")) {
+            preamble.push_str(&tail[..1]);
+            rest = after;
+            continue;
+        }
+        if tail.starts_with('}') {
+            anyhow::bail!("the preamble has a '}}' without a '{{', write '}}}}' for a literal one");
+        }
+        let Some(end) = tail.find('}') else {
+            anyhow::bail!("the preamble has a '{{' without a '}}', write '{{{{' for a literal one");
+        };
+        match &tail[1..end] {
+            "today" => preamble.push_str(&today()),
+            "max_results" => preamble.push_str(&max_results.to_string()),
+            other => anyhow::bail!(
+                "unknown placeholder {{{}}} in the preamble, expected {{today}} or {{max_results}}",
+                other
+            ),
+        }
+        rest = &tail[end + 1..];
+    }
+    preamble.push_str(rest);
+    Ok(preamble)
+}
+
+// Today's date in UTC, e.g. "16 Oct 2026", cut out of an HTTP date
+fn today() -> String {
+    httpdate::fmt_http_date(std::time::SystemTime::now())[5..16].to_string()
+}
+
+// LLM provider backing the research agent
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    Openai,
+    Anthropic,
+    Ollama,
+}
+
+impl Provider {
+    // Model used when none is configured
+    pub fn default_model(self) -> &'static str {
+        match self {
+            Provider::Openai => openai::GPT_4,
+            Provider::Anthropic => anthropic::CLAUDE_3_5_SONNET,
+            Provider::Ollama => "llama3.1",
+        }
+    }
+}
+
+// Sampling temperature unless configured otherwise, low so the assistant reports what it
+// found instead of improvising
+pub const DEFAULT_TEMPERATURE: f64 = 0.2;
+
+// Sampling settings applied to every agent
+#[derive(Clone, Copy, Debug)]
+pub struct ModelParams {
+    pub temperature: f64,
+    // The provider's own limit when unset, except Anthropic's which needs one
+    pub max_tokens: Option<u64>,
+    pub top_p: Option<f64>,
+}
+
+impl Default for ModelParams {
+    fn default() -> Self {
+        Self {
+            temperature: DEFAULT_TEMPERATURE,
+            max_tokens: None,
+            top_p: None,
+        }
+    }
+}
+
+impl ModelParams {
+    // Out of range values are refused here, before any request could be rejected for them
+    pub fn validate(self) -> anyhow::Result<Self> {
+        if !(0.0..=2.0).contains(&self.temperature) {
+            anyhow::bail!(
+                "temperature must be between 0 and 2, got {}",
+                self.temperature
+            );
+        }
+        if let Some(top_p) = self.top_p.filter(|top_p| !(*top_p > 0.0 && *top_p <= 1.0)) {
+            anyhow::bail!("top_p must be above 0 and at most 1, got {}", top_p);
+        }
+        if self.max_tokens == Some(0) {
+            anyhow::bail!("max_tokens must be at least 1");
+        }
+        Ok(self)
+    }
+}
+
+// Lets the params go anywhere in an agent builder chain
+trait WithParams {
+    fn with_params(self, params: &ModelParams) -> Self;
+}
+
+impl<M: CompletionModel> WithParams for AgentBuilder<M> {
+    fn with_params(self, params: &ModelParams) -> Self {
+        let mut builder = self.temperature(params.temperature);
+        if let Some(max_tokens) = params.max_tokens {
+            builder = builder.max_tokens(max_tokens);
+        }
+        if let Some(top_p) = params.top_p {
+            builder = builder.additional_params(serde_json::json!({ "top_p": top_p }));
+        }
+        builder
+    }
+}
+
+// Client for whichever provider was selected, with the sampling settings for its agents
+#[derive(Clone)]
+pub enum ProviderClient {
+    OpenAi(openai::Client, ModelParams),
+    Anthropic(anthropic::Client, ModelParams),
+}
+
+impl ProviderClient {
+    pub fn from_env(provider: Provider, params: ModelParams) -> Result<Self, anyhow::Error> {
+        let client = match provider {
+            Provider::Openai => {
+                let api_key =
+                    std::env::var("OPENAI_API_KEY").context("OPENAI_API_KEY is not set")?;
+                match std::env::var("OPENAI_BASE_URL") {
+                    Ok(base_url) => ProviderClient::OpenAi(
+                        openai::Client::from_url(&api_key, &base_url),
+                        params,
+                    ),
+                    Err(_) => ProviderClient::OpenAi(openai::Client::new(&api_key), params),
+                }
+            }
+            Provider::Anthropic => {
+                let api_key =
+                    std::env::var("ANTHROPIC_API_KEY").context("ANTHROPIC_API_KEY is not set")?;
+                ProviderClient::Anthropic(anthropic::ClientBuilder::new(&api_key).build(), params)
+            }
+            Provider::Ollama => {
+                // Ollama serves an OpenAI-compatible API and ignores the key
+                let base_url = std::env::var("OLLAMA_BASE_URL")
+                    .unwrap_or_else(|_| DEFAULT_OLLAMA_URL.to_string());
+                ProviderClient::OpenAi(openai::Client::from_url("ollama", &base_url), params)
+            }
+        };
+
+        Ok(client)
+    }
+
+    // Embeddings are only available through the OpenAI-compatible API
+    pub fn embedding_model(&self) -> Option<openai::EmbeddingModel> {
+        match self {
+            ProviderClient::OpenAi(client, _) => {
+                Some(client.embedding_model(openai::TEXT_EMBEDDING_3_SMALL))
+            }
+            ProviderClient::Anthropic(..) => None,
+        }
+    }
+
+    // Create agent with arxiv search tool, registered identically for every provider
+    pub fn build_agent(&self, model: &str, preamble: &str) -> ResearchAgent {
+        match self {
+            ProviderClient::OpenAi(client, params) => {
+                let embedder = client.embedding_model(openai::TEXT_EMBEDDING_3_SMALL);
+                ResearchAgent::OpenAi(
+                    client
+                        .agent(model)
+                        .preamble(preamble)
+                        .with_params(params)
+                        .tool(ArxivSearchTool::default().with_embedder(embedder.clone()))
+                        .tool(MultiSearchTool::default())
+                        .tool(FindRelatedTool::default())
+                        .tool(ComparePapersTool::new(self.clone(), model))
+                        .tool(DownloadPdfTool::default())
+                        .tool(EnrichCitationsTool)
+                        .tool(CrossrefLookupTool::default())
+                        .tool(FetchFullTextTool::default())
+                        .tool(AskPaperTool::new(self.clone(), model, embedder.clone()))
+                        .tool(QueryLibraryTool::new(Library::default_path(), embedder))
+                        .build(),
+                )
+            }
+            ProviderClient::Anthropic(client, params) => ResearchAgent::Anthropic(
+                client
+                    .agent(model)
+                    .preamble(preamble)
+                    .max_tokens(ANTHROPIC_MAX_TOKENS)
+                    .with_params(params)
+                    .tool(ArxivSearchTool::default())
+                    .tool(MultiSearchTool::default())
+                    .tool(FindRelatedTool::default())
+                    .tool(ComparePapersTool::new(self.clone(), model))
+                    .tool(DownloadPdfTool::default())
+                    .tool(EnrichCitationsTool)
+                    .tool(CrossrefLookupTool::default())
+                    .tool(FetchFullTextTool::default())
+                    .build(),
+            ),
+        }
+    }
+
+    // An agent with only the saved papers to look through, for working without arXiv
+    pub fn build_offline_agent(
+        &self,
+        model: &str,
+        preamble: &str,
+        papers: Vec<Paper>,
+    ) -> ResearchAgent {
+        let preamble = format!("{} {}", preamble, OFFLINE_PREAMBLE);
+        match self {
+            ProviderClient::OpenAi(client, params) => ResearchAgent::OpenAi(
+                client
+                    .agent(model)
+                    .preamble(&preamble)
+                    .with_params(params)
+                    .tool(SavedPapersTool::new(papers))
+                    .build(),
+            ),
+            ProviderClient::Anthropic(client, params) => ResearchAgent::Anthropic(
+                client
+                    .agent(model)
+                    .preamble(&preamble)
+                    .max_tokens(ANTHROPIC_MAX_TOKENS)
+                    .with_params(params)
+                    .tool(SavedPapersTool::new(papers))
+                    .build(),
+            ),
+        }
+    }
+
+    // One-off completion without tools, for answering from context we supply
+    pub async fn complete(
+        &self,
+        model: &str,
+        preamble: &str,
+        prompt: &str,
+    ) -> Result<String, PromptError> {
+        match self {
+            ProviderClient::OpenAi(client, params) => {
+                client
+                    .agent(model)
+                    .preamble(preamble)
+                    .with_params(params)
+                    .build()
+                    .prompt(prompt)
+                    .await
+            }
+            ProviderClient::Anthropic(client, params) => {
+                client
+                    .agent(model)
+                    .preamble(preamble)
+                    .max_tokens(ANTHROPIC_MAX_TOKENS)
+                    .with_params(params)
+                    .build()
+                    .prompt(prompt)
+                    .await
+            }
+        }
+    }
+
+    pub async fn extract<T>(
+        &self,
+        model: &str,
+        preamble: &str,
+        text: &str,
+    ) -> Result<T, ExtractionError>
+    where
+        T: schemars::JsonSchema + DeserializeOwned + Serialize + Send + Sync,
+    {
+        match self {
+            ProviderClient::OpenAi(client, _) => {
+                client
+                    .extractor::<T>(model)
+                    .preamble(preamble)
+                    .build()
+                    .extract(text)
+                    .await
+            }
+            ProviderClient::Anthropic(client, _) => {
+                client
+                    .extractor::<T>(model)
+                    .preamble(preamble)
+                    .build()
+                    .extract(text)
+                    .await
+            }
+        }
+    }
+}
+
+// Research agent for whichever provider was selected
+pub enum ResearchAgent {
+    OpenAi(Agent<openai::CompletionModel>),
+    Anthropic(Agent<anthropic::completion::CompletionModel>),
+}
+
+impl ResearchAgent {
+    pub async fn prompt(&self, prompt: &str) -> Result<String, PromptError> {
+        match self {
+            ResearchAgent::OpenAi(agent) => agent.prompt(prompt).await,
+            ResearchAgent::Anthropic(agent) => agent.prompt(prompt).await,
+        }
+    }
+
+    pub async fn chat(&self, prompt: &str, history: Vec<Message>) -> Result<String, PromptError> {
+        match self {
+            ResearchAgent::OpenAi(agent) => agent.chat(prompt, history).await,
+            ResearchAgent::Anthropic(agent) => agent.chat(prompt, history).await,
+        }
+    }
+}
+
+// What the agent gave back, once we've tried our best to read it as search results
+pub enum AgentAnswer {
+    Papers(SearchSummary),
+    // Boxed, it is many times the size of the other variants
+    Comparison(Box<PaperComparison>),
+    // The agent answered conversationally and no results could be recovered
+    Prose(String),
+}
+
+// Recover typed results from the agent's response: lenient JSON parsing, structured
+// extraction, then a single re-prompt before giving up and keeping the prose
+pub async fn interpret_response(
+    client: &ProviderClient,
+    model: &str,
+    agent: &ResearchAgent,
+    prompt: &str,
+    response: String,
+) -> AgentAnswer {
+    if let Ok(papers) = tools::parse_agent_json::<Vec<Paper>>(&response) {
+        return AgentAnswer::Papers(SearchSummary {
+            papers,
+            commentary: None,
+            related_to: None,
+        });
+    }
+    if let Ok(summary) = tools::parse_agent_json::<SearchSummary>(&response) {
+        return AgentAnswer::Papers(summary);
+    }
+    if let Ok(comparison) = tools::parse_agent_json::<PaperComparison>(&response) {
+        return AgentAnswer::Comparison(Box::new(comparison));
+    }
+
+    match client
+        .extract::<SearchSummary>(model, EXTRACT_PREAMBLE, &response)
+        .await
+    {
+        Ok(summary) if !summary.papers.is_empty() => return AgentAnswer::Papers(summary),
+        Ok(_) => (),
+        Err(e) => eprintln!("Structured extraction failed: {}", e),
+    }
+
+    let history = vec![
+        Message {
+            role: "user".to_string(),
+            content: prompt.to_string(),
+        },
+        Message {
+            role: "assistant".to_string(),
+            content: response.clone(),
+        },
+    ];
+    match agent.chat(REPROMPT, history).await {
+        Ok(retry) => {
+            if let Ok(papers) = tools::parse_agent_json::<Vec<Paper>>(&retry) {
+                return AgentAnswer::Papers(SearchSummary {
+                    papers,
+                    commentary: Some(response),
+                    related_to: None,
+                });
+            }
+        }
+        Err(e) => eprintln!("Re-prompting the agent failed: {}", e),
+    }
+
+    AgentAnswer::Prose(response)
+}
+
+// Structured synthesis across a set of papers
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct Synthesis {
+    pub common_themes: Vec<String>,
+    pub methodological_differences: Vec<String>,
+    pub open_questions: Vec<String>,
+}
+
+// Ask the model for a synthesis of the papers rather than a list of abstracts
+pub async fn summarize_papers(
+    client: &ProviderClient,
+    model: &str,
+    papers: &[Paper],
+) -> Result<Synthesis, ExtractionError> {
+    let mut text = String::new();
+    for (i, paper) in papers.iter().take(MAX_SYNTHESIS_PAPERS).enumerate() {
+        text.push_str(&format!(
+            "Paper {}: {}\nAuthors: {}\nAbstract: {}\n\n",
+            i + 1,
+            paper.title,
+            paper.authors.join(", "),
+            truncate_chars(&paper.abstract_text, MAX_SYNTHESIS_ABSTRACT_CHARS)
+        ));
+    }
+
+    client
+        .extract::<Synthesis>(model, SYNTHESIS_PREAMBLE, &text)
+        .await
+}
+
+// Whether the user's request asks for more than a list of papers
+pub fn wants_synthesis(request: &str) -> bool {
+    let request = request.to_lowercase();
+    [
+        "summar",
+        "synthes",
+        "state of the art",
+        "overview",
+        "survey",
+    ]
+    .iter()
+    .any(|keyword| request.contains(keyword))
+}
+
+fn truncate_chars(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The only test touching ARXIV_AGENT_PREAMBLE
+    #[test]
+    fn replaces_the_default_preamble_with_the_configured_one() {
+        let path =
+            std::env::temp_dir().join(format!("arxiv-agent-preamble-{}.txt", std::process::id()));
+        std::fs::write(&path, "Only cite papers from {today}.").unwrap();
+
+        std::env::remove_var("ARXIV_AGENT_PREAMBLE");
+        let default = preamble().unwrap();
+        std::env::set_var("ARXIV_AGENT_PREAMBLE", "Answer in French.");
+        let text = preamble().unwrap();
+        std::env::set_var("ARXIV_AGENT_PREAMBLE", &path);
+        let file = preamble().unwrap();
+        std::env::set_var("ARXIV_AGENT_PREAMBLE", "  ");
+        let blank = preamble().unwrap();
+        std::env::remove_var("ARXIV_AGENT_PREAMBLE");
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(default.starts_with(PREAMBLE.trim()), "{}", default);
+        assert!(default.ends_with(PREAMBLE_TOOLS));
+        assert_eq!(text, "Answer in French.");
+        assert_eq!(file, "Only cite papers from {today}.");
+        assert_eq!(blank, default);
+    }
+
+    #[test]
+    fn fills_in_placeholders_and_unescapes_braces() {
+        let preamble = render_preamble(
+            "Find {max_results} papers published before {today}. Answer as {{\"papers\": []}}",
+            7,
+        )
+        .unwrap();
+        assert_eq!(
+            preamble,
+            format!(
+                "Find 7 papers published before {}. Answer as {{\"papers\": []}}",
+                today()
+            )
+        );
+        // The shipped preamble is a valid template
+        assert!(render_preamble(PREAMBLE, 5).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders_and_unmatched_braces() {
+        for (template, error) in [
+            ("Search for {topic}", "unknown placeholder {topic}"),
+            ("Answer as {\"papers\": []}", "unknown placeholder"),
+            ("Answer in {today", "a '{' without a '}'"),
+            ("Answer in }", "a '}' without a '{'"),
+        ] {
+            let err = render_preamble(template, 5).unwrap_err().to_string();
+            assert!(err.contains(error), "{}: {}", template, err);
+        }
+    }
+
+    #[test]
+    fn accepts_sampling_settings_only_within_their_bounds() {
+        let params = |temperature, max_tokens, top_p| ModelParams {
+            temperature,
+            max_tokens,
+            top_p,
+        };
+        for valid in [
+            ModelParams::default(),
+            params(0.0, Some(1), Some(1.0)),
+            params(2.0, None, Some(0.01)),
+        ] {
+            assert!(valid.validate().is_ok(), "{:?}", valid);
+        }
+        for (invalid, error) in [
+            (params(-0.1, None, None), "temperature"),
+            (params(2.1, None, None), "temperature"),
+            (params(f64::NAN, None, None), "temperature"),
+            (params(0.2, Some(0), None), "max_tokens"),
+            (params(0.2, None, Some(0.0)), "top_p"),
+            (params(0.2, None, Some(1.5)), "top_p"),
+            (params(0.2, None, Some(f64::NAN)), "top_p"),
+        ] {
+            let err = invalid.validate().unwrap_err().to_string();
+            assert!(err.starts_with(error), "{:?}: {}", invalid, err);
+        }
+    }
+}
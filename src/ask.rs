@@ -0,0 +1,205 @@
+use rig::{
+    completion::{PromptError, ToolDefinition},
+    embeddings::{EmbeddingError, EmbeddingModel},
+    providers::openai,
+    tool::Tool,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+use crate::agent::ProviderClient;
+use crate::tools::{self, DownloadError};
+
+const ANSWER_PREAMBLE: &str = "You answer questions about a research paper using only the \
+                               excerpts provided. Cite the page of every fact you use, like \
+                               (p. 3). If the excerpts don't contain the answer, say so instead \
+                               of guessing.";
+
+// Rough chunk size in characters, small enough for several to fit in one prompt
+const CHUNK_CHARS: usize = 1500;
+
+// Number of chunks handed to the model for each question
+const TOP_K_CHUNKS: usize = 4;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AskError {
+    #[error(transparent)]
+    Download(#[from] DownloadError),
+    #[error("The PDF for {0} has no extractable text (it may be a scanned document)")]
+    NoText(String),
+    #[error("Could not extract text from the PDF: {0}")]
+    Extraction(String),
+    #[error("Embedding error: {0}")]
+    Embedding(#[from] EmbeddingError),
+    #[error("Answering failed: {0}")]
+    Prompt(#[from] PromptError),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Text cache is corrupted: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+// An answer grounded in the full text of a paper
+#[derive(Debug, Serialize)]
+pub struct PaperAnswer {
+    pub id: String,
+    pub answer: String,
+    // Pages the supporting excerpts came from
+    pub pages: Vec<usize>,
+}
+
+struct Chunk {
+    page: usize,
+    text: String,
+}
+
+// Tool to answer questions from a paper's full text rather than its abstract
+pub struct AskPaperTool {
+    client: ProviderClient,
+    model: String,
+    embedder: openai::EmbeddingModel,
+    dir: PathBuf,
+}
+
+impl AskPaperTool {
+    pub fn new(client: ProviderClient, model: &str, embedder: openai::EmbeddingModel) -> Self {
+        Self {
+            client,
+            model: model.to_string(),
+            embedder,
+            dir: tools::download_dir(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AskArgs {
+    pub id: String,
+    pub question: String,
+}
+
+impl Tool for AskPaperTool {
+    const NAME: &'static str = "ask_paper";
+    type Error = AskError;
+    type Args = AskArgs;
+    type Output = PaperAnswer;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: "ask_paper".to_string(),
+            description: "Answer a question from the full text of an arXiv paper".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "id": {
+                        "type": "string",
+                        "description": "arXiv id of the paper (e.g. 2310.06825)"
+                    },
+                    "question": {
+                        "type": "string",
+                        "description": "Question about the paper's content"
+                    }
+                },
+                "required": ["id", "question"]
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let id = args.id.trim();
+        let pages = paper_pages(id, &self.dir).await?;
+        let chunks = chunk_pages(&pages);
+        if chunks.is_empty() {
+            return Err(AskError::NoText(id.to_string()));
+        }
+
+        // The question goes in the same batches as the chunks
+        let mut documents = vec![args.question.clone()];
+        documents.extend(chunks.iter().map(|chunk| chunk.text.clone()));
+        let mut embeddings = Vec::with_capacity(documents.len());
+        for batch in documents.chunks(openai::EmbeddingModel::MAX_DOCUMENTS) {
+            embeddings.extend(self.embedder.embed_documents(batch.to_vec()).await?);
+        }
+
+        let question = &embeddings[0].vec;
+        let mut ranked: Vec<(f64, &Chunk)> = chunks
+            .iter()
+            .zip(&embeddings[1..])
+            .map(|(chunk, embedding)| (tools::cosine_similarity(question, &embedding.vec), chunk))
+            .collect();
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+        ranked.truncate(TOP_K_CHUNKS);
+
+        let mut prompt = format!("Question: {}\n\nExcerpts:\n", args.question);
+        for (_, chunk) in &ranked {
+            prompt.push_str(&format!("\n[page {}]\n{}\n", chunk.page, chunk.text));
+        }
+        let answer = self
+            .client
+            .complete(&self.model, ANSWER_PREAMBLE, &prompt)
+            .await?;
+
+        let mut pages: Vec<usize> = ranked.iter().map(|(_, chunk)| chunk.page).collect();
+        pages.sort_unstable();
+        pages.dedup();
+
+        Ok(PaperAnswer {
+            id: id.to_string(),
+            answer,
+            pages,
+        })
+    }
+}
+
+// Text of each page, extracted once per paper and cached next to the PDF
+async fn paper_pages(id: &str, dir: &Path) -> Result<Vec<String>, AskError> {
+    let cache = dir.join(format!("{}.pages.json", id.replace('/', "_")));
+    if let Ok(cached) = tokio::fs::read_to_string(&cache).await {
+        return Ok(serde_json::from_str(&cached)?);
+    }
+
+    let url = format!("https://arxiv.org/pdf/{}", id);
+    let path = match tools::download_pdf(&url, dir, tools::DEFAULT_MAX_PDF_BYTES, false).await {
+        Ok(pdf) => pdf.path,
+        // Downloaded earlier, e.g. with --download
+        Err(DownloadError::AlreadyExists(path)) => path,
+        Err(e) => return Err(e.into()),
+    };
+
+    // Extraction is CPU bound, keep it off the async workers
+    let pages = tokio::task::spawn_blocking(move || pdf_extract::extract_text_by_pages(path))
+        .await
+        .map_err(|e| AskError::Extraction(e.to_string()))?
+        .map_err(|e| AskError::Extraction(e.to_string()))?;
+    if pages.iter().all(|page| page.trim().is_empty()) {
+        return Err(AskError::NoText(id.to_string()));
+    }
+
+    tokio::fs::write(&cache, serde_json::to_string(&pages)?).await?;
+    Ok(pages)
+}
+
+// Split pages into roughly CHUNK_CHARS-sized chunks on word boundaries
+fn chunk_pages(pages: &[String]) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    for (i, page) in pages.iter().enumerate() {
+        let mut text = String::new();
+        for word in page.split_whitespace() {
+            if !text.is_empty() && text.len() + word.len() + 1 > CHUNK_CHARS {
+                chunks.push(Chunk {
+                    page: i + 1,
+                    text: std::mem::take(&mut text),
+                });
+            }
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(word);
+        }
+        if !text.is_empty() {
+            chunks.push(Chunk { page: i + 1, text });
+        }
+    }
+    chunks
+}
@@ -0,0 +1,104 @@
+use rig::{completion::Message, providers::openai};
+use std::io::Write as _;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing::Instrument;
+
+use crate::{
+    agent::ResearchAgent,
+    format_papers_as_table, library,
+    TableConfig,
+    tools::{self, Paper},
+};
+
+// Interactive multi-turn session with the research agent
+pub async fn run(
+    agent: &ResearchAgent,
+    embedder: Option<openai::EmbeddingModel>,
+    table: &TableConfig,
+) -> Result<(), anyhow::Error> {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut history: Vec<Message> = Vec::new();
+    let mut last_papers: Vec<Paper> = Vec::new();
+
+    println!("Ask about a research topic. Commands: /clear, /save <file>, /quit");
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+
+        let Some(line) = lines.next_line().await? else {
+            break;
+        };
+        let line = line.trim();
+
+        match line {
+            "" => continue,
+            "/quit" => break,
+            "/clear" => {
+                history.clear();
+                last_papers.clear();
+                println!("Conversation history cleared");
+                continue;
+            }
+            _ => (),
+        }
+
+        if let Some(file) = line.strip_prefix("/save") {
+            let file = file.trim();
+            if file.is_empty() {
+                eprintln!("Usage: /save <file>");
+            } else if let Err(e) = save_papers(file, &last_papers) {
+                eprintln!("Error saving papers: {}", e);
+            } else {
+                println!("Saved {} papers to {}", last_papers.len(), file);
+            }
+            continue;
+        }
+
+        // Ctrl-C cancels the pending request and returns to the prompt
+        let request = agent
+            .chat(line, history.clone())
+            .instrument(tracing::info_span!("agent_chat", turn = history.len() / 2 + 1));
+        let response = tokio::select! {
+            response = request => response,
+            _ = tokio::signal::ctrl_c() => {
+                eprintln!("\nRequest cancelled");
+                continue;
+            }
+        };
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                continue;
+            }
+        };
+
+        history.push(Message {
+            role: "user".to_string(),
+            content: line.to_string(),
+        });
+        history.push(Message {
+            role: "assistant".to_string(),
+            content: response.clone(),
+        });
+
+        // Render tool results as a table, anything else as plain text
+        match tools::parse_agent_json::<Vec<Paper>>(&response) {
+            Ok(papers) => {
+                println!("{}", format_papers_as_table(&papers, table)?);
+                if let Some(embedder) = &embedder {
+                    library::remember(embedder, &papers).await;
+                }
+                last_papers = papers;
+            }
+            Err(_) => println!("{}", response),
+        }
+    }
+
+    Ok(())
+}
+
+fn save_papers(file: &str, papers: &[Paper]) -> Result<(), anyhow::Error> {
+    std::fs::write(file, serde_json::to_string_pretty(papers)?)?;
+    Ok(())
+}
@@ -0,0 +1,304 @@
+use rig::{completion::ToolDefinition, tool::Tool};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+
+use crate::tools::{self, Paper};
+
+const SEMANTIC_SCHOLAR_URL: &str = "https://api.semanticscholar.org/graph/v1";
+
+// The batch endpoint takes at most this many ids per request
+const MAX_BATCH_IDS: usize = 500;
+
+// Attempts per batch while Semantic Scholar keeps answering 429
+const MAX_ATTEMPTS: u32 = 4;
+
+// Wait after a 429 without Retry-After, doubled on each further attempt. Without a key the
+// API shares one rate limit between every anonymous client, so this is generous
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(2);
+
+// Longest Retry-After we wait out rather than failing
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, thiserror::Error)]
+pub enum CitationError {
+    #[error("Network error: {0}")]
+    Network(#[from] reqwest::Error),
+    #[error(
+        "Semantic Scholar is rate limiting requests, retry after {} seconds",
+        retry_after.as_secs()
+    )]
+    RateLimited { retry_after: Duration },
+    #[error("No arXiv ids were given")]
+    NoIds,
+}
+
+// What Semantic Scholar knows about one paper. Everything is None for papers it doesn't have
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Citations {
+    pub arxiv_id: String,
+    pub citation_count: Option<u64>,
+    pub influential_citation_count: Option<u64>,
+    pub semantic_scholar_url: Option<String>,
+}
+
+// One entry of the batch response, null for ids it doesn't know
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchPaper {
+    citation_count: Option<u64>,
+    influential_citation_count: Option<u64>,
+    url: Option<String>,
+}
+
+// SEMANTIC_SCHOLAR_BASE_URL if set, e.g. to point at a local mock server
+fn base_url() -> String {
+    std::env::var("SEMANTIC_SCHOLAR_BASE_URL").unwrap_or_else(|_| SEMANTIC_SCHOLAR_URL.to_string())
+}
+
+// Citation counts for arXiv ids, in the order given. Ids Semantic Scholar doesn't know come
+// back with every count unset
+pub async fn fetch_citations(ids: &[String]) -> Result<Vec<Citations>, CitationError> {
+    // Optional, but anonymous clients get a much smaller share of the rate limit
+    let api_key = std::env::var("SEMANTIC_SCHOLAR_API_KEY")
+        .ok()
+        .filter(|key| !key.trim().is_empty());
+    fetch_citations_from(&base_url(), api_key.as_deref(), ids).await
+}
+
+// fetch_citations against the API at `base_url`
+async fn fetch_citations_from(
+    base_url: &str,
+    api_key: Option<&str>,
+    ids: &[String],
+) -> Result<Vec<Citations>, CitationError> {
+    let client = reqwest::Client::builder()
+        .user_agent(tools::user_agent())
+        .build()?;
+    let url = format!("{}/paper/batch", base_url);
+
+    let mut citations = Vec::with_capacity(ids.len());
+    for batch in ids.chunks(MAX_BATCH_IDS) {
+        let mut request = client
+            .post(&url)
+            .query(&[("fields", "citationCount,influentialCitationCount,url")])
+            .json(&json!({
+                "ids": batch
+                    .iter()
+                    .map(|id| format!("ARXIV:{}", id))
+                    .collect::<Vec<_>>()
+            }));
+        if let Some(key) = api_key {
+            request = request.header("x-api-key", key);
+        }
+        let found: Vec<Option<BatchPaper>> = send_with_retry(request)
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        // Entries line up with the ids sent, missing ones are null
+        let mut found = found.into_iter();
+        for id in batch {
+            let paper = found.next().flatten();
+            citations.push(Citations {
+                arxiv_id: id.clone(),
+                citation_count: paper.as_ref().and_then(|paper| paper.citation_count),
+                influential_citation_count: paper
+                    .as_ref()
+                    .and_then(|paper| paper.influential_citation_count),
+                semantic_scholar_url: paper.and_then(|paper| paper.url),
+            });
+        }
+    }
+    Ok(citations)
+}
+
+// Fill in citation counts for the papers that don't have them yet
+pub async fn enrich_papers(papers: &mut [Paper]) -> Result<(), CitationError> {
+    let pending: Vec<usize> = papers
+        .iter()
+        .enumerate()
+        .filter(|(_, paper)| paper.citation_count.is_none())
+        .map(|(i, _)| i)
+        .collect();
+    if pending.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<String> = pending
+        .iter()
+        .map(|&i| match &papers[i].arxiv_id {
+            Some(id) => id.clone(),
+            None => tools::base_arxiv_id(&papers[i].url).to_string(),
+        })
+        .collect();
+    for (i, citations) in pending.into_iter().zip(fetch_citations(&ids).await?) {
+        let paper = &mut papers[i];
+        paper.citation_count = citations.citation_count;
+        paper.influential_citation_count = citations.influential_citation_count;
+        paper.semantic_scholar_url = citations.semantic_scholar_url;
+    }
+    Ok(())
+}
+
+// Send a batch request, backing off whenever Semantic Scholar rate limits us
+async fn send_with_retry(
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::Response, CitationError> {
+    let mut attempt = 1;
+    loop {
+        let response = request
+            .try_clone()
+            .expect("batch requests have a buffered body")
+            .send()
+            .await?;
+        if response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Ok(response);
+        }
+
+        let retry_after = tools::retry_after(response.headers())
+            .unwrap_or(RATE_LIMIT_BACKOFF * 2u32.pow(attempt - 1));
+        if attempt >= MAX_ATTEMPTS || retry_after > MAX_RETRY_AFTER {
+            return Err(CitationError::RateLimited { retry_after });
+        }
+        tracing::warn!(
+            ?retry_after,
+            attempt,
+            "Semantic Scholar rate limited the request, waiting"
+        );
+        tokio::time::sleep(retry_after).await;
+        attempt += 1;
+    }
+}
+
+// Tool to look up how often papers have been cited
+pub struct EnrichCitationsTool;
+
+#[derive(Deserialize)]
+pub struct EnrichArgs {
+    pub ids: Vec<String>,
+}
+
+impl Tool for EnrichCitationsTool {
+    const NAME: &'static str = "enrich_citations";
+    type Error = CitationError;
+    type Args = EnrichArgs;
+    type Output = Vec<Citations>;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: "enrich_citations".to_string(),
+            description: "Look up citation counts for arXiv papers on Semantic Scholar. Copy the counts and URL into the citation_count, influential_citation_count and semantic_scholar_url fields of the matching papers".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "ids": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "arXiv ids of the papers (e.g. 2310.06825)"
+                    }
+                },
+                "required": ["ids"]
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let ids: Vec<String> = args
+            .ids
+            .iter()
+            .map(|id| tools::base_arxiv_id(id.trim()).to_string())
+            .filter(|id| !id.is_empty())
+            .collect();
+        if ids.is_empty() {
+            return Err(CitationError::NoIds);
+        }
+        fetch_citations(&ids).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_json, header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn reads_counts_in_the_order_of_the_ids_sent() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/paper/batch"))
+            .and(query_param(
+                "fields",
+                "citationCount,influentialCitationCount,url",
+            ))
+            .and(header("x-api-key", "secret"))
+            .and(body_json(
+                json!({ "ids": ["ARXIV:1706.03762", "ARXIV:2401.99999"] }),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                {
+                    "citationCount": 120000,
+                    "influentialCitationCount": 15000,
+                    "url": "https://www.semanticscholar.org/paper/204e3073"
+                },
+                null
+            ])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let citations = fetch_citations_from(
+            &server.uri(),
+            Some("secret"),
+            &ids(&["1706.03762", "2401.99999"]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(citations[0].arxiv_id, "1706.03762");
+        assert_eq!(citations[0].citation_count, Some(120000));
+        assert_eq!(citations[0].influential_citation_count, Some(15000));
+        assert_eq!(
+            citations[0].semantic_scholar_url.as_deref(),
+            Some("https://www.semanticscholar.org/paper/204e3073")
+        );
+        // Semantic Scholar doesn't know the second one
+        assert_eq!(citations[1].arxiv_id, "2401.99999");
+        assert_eq!(citations[1].citation_count, None);
+        assert_eq!(citations[1].semantic_scholar_url, None);
+    }
+
+    #[tokio::test]
+    async fn retries_while_rate_limited_then_gives_up() {
+        let rate_limited = ResponseTemplate::new(429).insert_header("retry-after", "0");
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(rate_limited.clone())
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([{ "citationCount": 7 }])))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let citations = fetch_citations_from(&server.uri(), None, &ids(&["2401.00001"]))
+            .await
+            .unwrap();
+        assert_eq!(citations[0].citation_count, Some(7));
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(rate_limited)
+            .expect(u64::from(MAX_ATTEMPTS))
+            .mount(&server)
+            .await;
+        let refused = fetch_citations_from(&server.uri(), None, &ids(&["2401.00001"])).await;
+        assert!(matches!(refused, Err(CitationError::RateLimited { .. })));
+    }
+}
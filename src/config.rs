@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::agent::Provider;
+use crate::tools::SortKey;
+use crate::{ColorChoice, GroupBy, OutputFormat};
+
+const CONFIG_FILE: &str = "arxiv-agent/config.toml";
+
+// Every key Config reads, anything else in the file gets a warning
+const KEYS: &[&str] = &[
+    "provider",
+    "model",
+    "temperature",
+    "max_tokens",
+    "top_p",
+    "max_results",
+    "format",
+    "sort",
+    "group_by",
+    "color",
+    "ascii_only",
+    "download_dir",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Could not read {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("{0} is not a valid config file: {1}")]
+    Toml(PathBuf, #[source] toml::de::Error),
+}
+
+// Defaults from the config file. Flags and environment variables win over these, and
+// these win over the built-in defaults
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<Provider>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_results: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<SortKey>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_by: Option<GroupBy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<ColorChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ascii_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_dir: Option<PathBuf>,
+}
+
+impl Config {
+    // Under the XDG config dir, ~/.config by default
+    pub fn default_path() -> PathBuf {
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .unwrap_or_default();
+        config_dir.join(CONFIG_FILE)
+    }
+
+    // The file at the default path, which doesn't have to exist
+    pub fn load_default() -> Result<Self, ConfigError> {
+        let path = Self::default_path();
+        match Self::load(&path) {
+            Err(ConfigError::Io(_, e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Self::default())
+            }
+            result => result,
+        }
+    }
+
+    // Unknown keys are warned about rather than refused, so an older build can still read a
+    // file written for a newer one
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+        let table: toml::Table =
+            toml::from_str(&contents).map_err(|e| ConfigError::Toml(path.to_owned(), e))?;
+        for key in table.keys().filter(|key| !KEYS.contains(&key.as_str())) {
+            eprintln!(
+                "Warning: ignoring unknown key {:?} in {}",
+                key,
+                path.display()
+            );
+        }
+        toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| ConfigError::Toml(path.to_owned(), e))
+    }
+}
@@ -0,0 +1,346 @@
+use rig::{completion::ToolDefinition, tool::Tool};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::tools::{self, Paper};
+
+const CROSSREF_URL: &str = "https://api.crossref.org";
+
+// Lookups in flight at once, well inside what Crossref's polite pool allows
+const CROSSREF_CONCURRENCY: usize = 4;
+
+const CROSSREF_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Debug, thiserror::Error)]
+pub enum CrossrefError {
+    #[error("Network error: {0}")]
+    Network(#[from] reqwest::Error),
+    #[error(
+        "Crossref is rate limiting requests, retry after {} seconds",
+        retry_after.as_secs()
+    )]
+    RateLimited { retry_after: Duration },
+    #[error("No DOIs were given")]
+    NoDois,
+}
+
+// Where a paper was published, as Crossref records it. Everything is None when Crossref
+// doesn't know the DOI or couldn't be asked
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PublishedVersion {
+    pub doi: String,
+    pub journal: Option<String>,
+    pub publisher: Option<String>,
+    // As precise as Crossref has it: 2021, 2021-06 or 2021-06-15
+    pub published_print: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct WorkResponse {
+    message: Work,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Work {
+    #[serde(default)]
+    container_title: Vec<String>,
+    publisher: Option<String>,
+    published_print: Option<PartialDate>,
+}
+
+// Crossref dates are [[year, month, day]] with the month and day optional
+#[derive(Deserialize)]
+struct PartialDate {
+    #[serde(rename = "date-parts")]
+    date_parts: Vec<Vec<u32>>,
+}
+
+impl PartialDate {
+    fn format(&self) -> Option<String> {
+        let parts = self.date_parts.first().filter(|parts| !parts.is_empty())?;
+        let mut date = parts[0].to_string();
+        for part in &parts[1..] {
+            date.push_str(&format!("-{:02}", part));
+        }
+        Some(date)
+    }
+}
+
+// Looks DOIs up on Crossref, remembering every answer for as long as it lives
+pub struct CrossrefClient {
+    base_url: String,
+    // Crossref serves clients that give a contact address from its faster "polite" pool
+    mailto: Option<String>,
+    // Keyed by lowercased DOI; None for DOIs Crossref doesn't know
+    cache: Mutex<HashMap<String, Option<PublishedVersion>>>,
+}
+
+impl Default for CrossrefClient {
+    // CROSSREF_BASE_URL to point at a mirror or mock server, CROSSREF_MAILTO for the polite pool
+    fn default() -> Self {
+        Self {
+            base_url: std::env::var("CROSSREF_BASE_URL")
+                .unwrap_or_else(|_| CROSSREF_URL.to_string()),
+            mailto: std::env::var("CROSSREF_MAILTO")
+                .ok()
+                .filter(|mailto| !mailto.trim().is_empty()),
+            cache: Mutex::default(),
+        }
+    }
+}
+
+impl CrossrefClient {
+    // One entry per DOI, in the order given. A DOI that fails is logged and comes back with
+    // nothing filled in rather than failing the others
+    pub async fn lookup(&self, dois: &[String]) -> Result<Vec<PublishedVersion>, CrossrefError> {
+        let mut user_agent = tools::user_agent();
+        if let Some(mailto) = &self.mailto {
+            user_agent.push_str(&format!(" mailto:{}", mailto));
+        }
+        let client = reqwest::Client::builder()
+            .user_agent(user_agent)
+            .timeout(CROSSREF_TIMEOUT)
+            .build()?;
+
+        let turns = tokio::sync::Semaphore::new(CROSSREF_CONCURRENCY);
+        let lookups = dois.iter().map(|doi| {
+            let (client, turns) = (&client, &turns);
+            async move {
+                let _permit = turns.acquire().await;
+                let found = match self.lookup_one(client, doi).await {
+                    Ok(found) => found,
+                    Err(e) => {
+                        tracing::warn!(doi = %doi, error = %e, "Crossref lookup failed");
+                        None
+                    }
+                };
+                found.unwrap_or_else(|| PublishedVersion {
+                    doi: doi.clone(),
+                    ..Default::default()
+                })
+            }
+        });
+        Ok(futures::future::join_all(lookups).await)
+    }
+
+    async fn lookup_one(
+        &self,
+        client: &reqwest::Client,
+        doi: &str,
+    ) -> Result<Option<PublishedVersion>, CrossrefError> {
+        let key = doi.to_lowercase();
+        let cached = self.cache.lock().unwrap().get(&key).cloned();
+        if let Some(cached) = cached {
+            return Ok(cached);
+        }
+
+        let mut request = client.get(format!(
+            "{}/works/{}",
+            self.base_url,
+            urlencoding::encode(doi)
+        ));
+        if let Some(mailto) = &self.mailto {
+            request = request.query(&[("mailto", mailto)]);
+        }
+        let response = request.send().await?;
+        // Failures other than an unknown DOI aren't cached, so a later lookup tries again
+        let found = match response.status() {
+            reqwest::StatusCode::NOT_FOUND => None,
+            reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                let retry_after = tools::retry_after(response.headers()).unwrap_or_default();
+                return Err(CrossrefError::RateLimited { retry_after });
+            }
+            _ => {
+                let work = response
+                    .error_for_status()?
+                    .json::<WorkResponse>()
+                    .await?
+                    .message;
+                Some(PublishedVersion {
+                    doi: doi.to_string(),
+                    journal: work.container_title.into_iter().next(),
+                    publisher: work.publisher,
+                    published_print: work.published_print.and_then(|date| date.format()),
+                })
+            }
+        };
+        self.cache.lock().unwrap().insert(key, found.clone());
+        Ok(found)
+    }
+
+    // Fill in the published version of every paper that has a DOI and isn't filled in yet
+    pub async fn enrich_papers(&self, papers: &mut [Paper]) -> Result<(), CrossrefError> {
+        let pending: Vec<(usize, String)> = papers
+            .iter()
+            .enumerate()
+            .filter(|(_, paper)| paper.journal.is_none())
+            .filter_map(|(i, paper)| Some((i, normalize_doi(paper.doi.as_deref()?)?)))
+            .collect();
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let dois: Vec<String> = pending.iter().map(|(_, doi)| doi.clone()).collect();
+        for ((i, _), found) in pending.into_iter().zip(self.lookup(&dois).await?) {
+            let paper = &mut papers[i];
+            paper.journal = found.journal;
+            paper.publisher = found.publisher;
+            paper.published_print = found.published_print;
+        }
+        Ok(())
+    }
+}
+
+// The bare DOI from any of the forms it's written in: 10.1000/xyz, doi:10.1000/xyz or a
+// doi.org link
+pub fn normalize_doi(doi: &str) -> Option<String> {
+    let doi = doi.trim();
+    let doi = [
+        "https://doi.org/",
+        "http://doi.org/",
+        "https://dx.doi.org/",
+        "doi:",
+    ]
+    .iter()
+    .find_map(|prefix| doi.strip_prefix(prefix))
+    .unwrap_or(doi)
+    .trim();
+    doi.starts_with("10.").then(|| doi.to_string())
+}
+
+// Tool to find where arXiv papers were published
+#[derive(Default)]
+pub struct CrossrefLookupTool {
+    crossref: CrossrefClient,
+}
+
+#[derive(Deserialize)]
+pub struct CrossrefArgs {
+    pub dois: Vec<String>,
+}
+
+impl Tool for CrossrefLookupTool {
+    const NAME: &'static str = "crossref_lookup";
+    type Error = CrossrefError;
+    type Args = CrossrefArgs;
+    type Output = Vec<PublishedVersion>;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: "crossref_lookup".to_string(),
+            description: "Look up the journal, publisher and print publication date of papers by DOI on Crossref. Copy them into the journal, publisher and published_print fields of the matching papers".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "dois": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "DOIs from the papers' doi fields (e.g. 10.1038/s41586-021-03819-2)"
+                    }
+                },
+                "required": ["dois"]
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let dois: Vec<String> = args
+            .dois
+            .iter()
+            .filter_map(|doi| normalize_doi(doi))
+            .collect();
+        if dois.is_empty() {
+            return Err(CrossrefError::NoDois);
+        }
+        self.crossref.lookup(&dois).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path_regex, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn client(server: &MockServer) -> CrossrefClient {
+        CrossrefClient {
+            base_url: server.uri(),
+            mailto: Some("research@example.org".to_string()),
+            cache: Mutex::default(),
+        }
+    }
+
+    fn dois(dois: &[&str]) -> Vec<String> {
+        dois.iter().map(|doi| doi.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn looks_up_each_doi_once_and_remembers_unknown_ones() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path_regex("^/works/10.1016.*physletb"))
+            .and(query_param("mailto", "research@example.org"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "message": {
+                    "container-title": ["Physics Letters B"],
+                    "publisher": "Elsevier BV",
+                    "published-print": { "date-parts": [[2012, 9]] }
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex("^/works/10.9999"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let crossref = client(&server);
+        let dois = dois(&["10.1016/j.physletb.2012.08.020", "10.9999/unknown"]);
+        for _ in 0..2 {
+            let found = crossref.lookup(&dois).await.unwrap();
+            assert_eq!(found[0].doi, "10.1016/j.physletb.2012.08.020");
+            assert_eq!(found[0].journal.as_deref(), Some("Physics Letters B"));
+            assert_eq!(found[0].publisher.as_deref(), Some("Elsevier BV"));
+            assert_eq!(found[0].published_print.as_deref(), Some("2012-09"));
+            assert_eq!(found[1].doi, "10.9999/unknown");
+            assert_eq!(found[1].journal, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn leaves_failed_lookups_empty_and_tries_them_again() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "30"))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let crossref = client(&server);
+        let dois = dois(&["10.1000/rate-limited"]);
+        for _ in 0..2 {
+            let found = crossref.lookup(&dois).await.unwrap();
+            assert_eq!(found[0].doi, "10.1000/rate-limited");
+            assert_eq!(found[0].journal, None);
+        }
+    }
+
+    #[test]
+    fn normalizes_the_ways_dois_are_written() {
+        for doi in [
+            "10.1000/xyz",
+            " doi:10.1000/xyz ",
+            "https://doi.org/10.1000/xyz",
+            "https://dx.doi.org/10.1000/xyz",
+        ] {
+            assert_eq!(normalize_doi(doi).as_deref(), Some("10.1000/xyz"));
+        }
+        assert_eq!(normalize_doi("arXiv:2401.00001"), None);
+    }
+}
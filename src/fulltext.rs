@@ -0,0 +1,331 @@
+use rig::{completion::ToolDefinition, tool::Tool};
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::tools;
+
+const AR5IV_URL: &str = "https://ar5iv.labs.arxiv.org/html";
+
+// Characters of text handed to the model per call, roughly 6k tokens
+const MAX_FULLTEXT_CHARS: usize = 24_000;
+
+const AR5IV_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Page furniture around the paper, and MathML whose text is replaced by its LaTeX source
+const SKIPPED: &[&str] = &[
+    "script", "style", "nav", "header", "footer", "noscript", "math",
+];
+
+// Elements that start a new paragraph of text
+const BLOCKS: &[&str] = &[
+    "p",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "li",
+    "figcaption",
+    "blockquote",
+    "td",
+    "div",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum FullTextError {
+    #[error("Network error: {0}")]
+    Network(#[from] reqwest::Error),
+    #[error("Invalid arXiv id: {0}")]
+    InvalidId(String),
+    #[error("ar5iv has no HTML rendering of {0}, its PDF is the only full text")]
+    NotRendered(String),
+    #[error("{id} has no section named {section:?}, its sections are: {}", available.join(", "))]
+    UnknownSection {
+        id: String,
+        section: String,
+        available: Vec<String>,
+    },
+}
+
+// One titled part of a paper, numbered the way the paper numbers it ("5.1 Training Data")
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub heading: String,
+    // 1 for the paper's title, 2 for sections, 3 for subsections and so on
+    pub level: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FullText {
+    pub id: String,
+    // The section asked for, the whole paper when None
+    pub section: Option<String>,
+    pub text: String,
+    // Whether text was cut off at MAX_FULLTEXT_CHARS
+    pub truncated: bool,
+    // Every heading in the paper, for asking for one of them next
+    pub sections: Vec<String>,
+}
+
+// Fetches papers from ar5iv, remembering each one for as long as it lives
+pub struct Ar5ivClient {
+    base_url: String,
+    // Keyed by arXiv id without its version, ar5iv only renders the latest one
+    cache: Mutex<HashMap<String, Vec<Section>>>,
+}
+
+impl Default for Ar5ivClient {
+    // AR5IV_BASE_URL to point at a mirror or mock server
+    fn default() -> Self {
+        Self {
+            base_url: std::env::var("AR5IV_BASE_URL").unwrap_or_else(|_| AR5IV_URL.to_string()),
+            cache: Mutex::default(),
+        }
+    }
+}
+
+impl Ar5ivClient {
+    async fn sections(&self, id: &str) -> Result<Vec<Section>, FullTextError> {
+        let id = tools::base_arxiv_id(id.trim());
+        if !tools::is_valid_arxiv_id(id) {
+            return Err(FullTextError::InvalidId(id.to_string()));
+        }
+        let cached = self.cache.lock().unwrap().get(id).cloned();
+        if let Some(cached) = cached {
+            return Ok(cached);
+        }
+
+        let client = reqwest::Client::builder()
+            .user_agent(tools::user_agent())
+            .timeout(AR5IV_TIMEOUT)
+            .build()?;
+        let response = client
+            .get(format!("{}/{}", self.base_url, id))
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(FullTextError::NotRendered(id.to_string()));
+        }
+        // Papers ar5iv couldn't convert redirect to their arXiv abstract page, which has no
+        // LaTeXML document in it
+        let page = response.error_for_status()?.text().await?;
+        let sections =
+            parse_sections(&page).ok_or_else(|| FullTextError::NotRendered(id.to_string()))?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), sections.clone());
+        Ok(sections)
+    }
+
+    // The whole paper, or the named section with its subsections, at most max_chars long
+    pub async fn fetch(
+        &self,
+        id: &str,
+        section: Option<&str>,
+        max_chars: usize,
+    ) -> Result<FullText, FullTextError> {
+        let sections = self.sections(id).await?;
+        let chosen = match section {
+            Some(name) => {
+                find_section(&sections, name).ok_or_else(|| FullTextError::UnknownSection {
+                    id: id.trim().to_string(),
+                    section: name.to_string(),
+                    available: headings(&sections),
+                })?
+            }
+            None => &sections[..],
+        };
+
+        let mut text = chosen
+            .iter()
+            .map(
+                |section| match (section.heading.is_empty(), section.text.is_empty()) {
+                    (true, _) => section.text.clone(),
+                    (false, true) => section.heading.clone(),
+                    (false, false) => format!("{}\n\n{}", section.heading, section.text),
+                },
+            )
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let cut = text.char_indices().nth(max_chars).map(|(i, _)| i);
+        if let Some(cut) = cut {
+            text.truncate(cut);
+        }
+
+        Ok(FullText {
+            id: id.trim().to_string(),
+            section: section.map(str::to_string),
+            text,
+            truncated: cut.is_some(),
+            sections: headings(&sections),
+        })
+    }
+}
+
+fn headings(sections: &[Section]) -> Vec<String> {
+    sections
+        .iter()
+        .map(|section| section.heading.clone())
+        .filter(|heading| !heading.is_empty())
+        .collect()
+}
+
+// A heading without its number, for matching "Conclusion" against "7 Conclusion"
+fn heading_name(heading: &str) -> String {
+    heading
+        .trim_start_matches(|c: char| c.is_ascii_digit() || c == '.' || c.is_whitespace())
+        .to_lowercase()
+}
+
+// The named section and the subsections under it. An exact name wins over one that only
+// contains it, so "Results" doesn't pick "Preliminary Results" when both exist
+fn find_section<'a>(sections: &'a [Section], name: &str) -> Option<&'a [Section]> {
+    let name = name.trim().to_lowercase();
+    let start = sections
+        .iter()
+        .position(|section| heading_name(&section.heading) == name)
+        .or_else(|| {
+            sections.iter().position(|section| {
+                !section.heading.is_empty() && heading_name(&section.heading).contains(&name)
+            })
+        })?;
+    let level = sections[start].level;
+    let end = sections[start + 1..]
+        .iter()
+        .position(|section| section.level <= level)
+        .map_or(sections.len(), |i| start + 1 + i);
+    Some(&sections[start..end])
+}
+
+// The paper on an ar5iv page split at its headings, with equations as their LaTeX source.
+// None when the page isn't a LaTeXML rendering of a paper
+pub fn parse_sections(page: &str) -> Option<Vec<Section>> {
+    let document = Html::parse_document(page);
+    let selector = Selector::parse("article.ltx_document").expect("valid selector");
+    let root = document.select(&selector).next()?;
+
+    // Text of each block element in document order, with the level of those that are headings
+    let mut blocks: Vec<(Option<usize>, String)> = Vec::new();
+    let mut last_block = None;
+    for node in root.descendants() {
+        let fragment = match node.value() {
+            scraper::Node::Text(text) => &**text,
+            scraper::Node::Element(element) if element.name() == "math" => {
+                match element.attr("alttext") {
+                    Some(latex) => latex,
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+        let mut ancestors = node.ancestors().filter_map(ElementRef::wrap);
+        if ancestors
+            .clone()
+            .any(|element| SKIPPED.contains(&element.value().name()))
+        {
+            continue;
+        }
+        let block = ancestors.find(|element| BLOCKS.contains(&element.value().name()));
+        let id = block.map(|element| element.id());
+        if id != last_block || blocks.is_empty() {
+            let level = block.and_then(|element| heading_level(element.value().name()));
+            blocks.push((level, String::new()));
+            last_block = id;
+        }
+        let text = &mut blocks.last_mut().expect("pushed above").1;
+        text.push_str(fragment);
+        text.push(' ');
+    }
+
+    let mut sections = vec![Section {
+        heading: String::new(),
+        level: 0,
+        text: String::new(),
+    }];
+    for (level, text) in blocks {
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if text.is_empty() {
+            continue;
+        }
+        let Some(level) = level else {
+            let section = sections.last_mut().expect("starts with one");
+            if !section.text.is_empty() {
+                section.text.push_str("\n\n");
+            }
+            section.text.push_str(&text);
+            continue;
+        };
+        sections.push(Section {
+            heading: text,
+            level,
+            text: String::new(),
+        });
+    }
+    sections.retain(|section| !section.heading.is_empty() || !section.text.is_empty());
+    (!sections.is_empty()).then_some(sections)
+}
+
+fn heading_level(name: &str) -> Option<usize> {
+    match name.as_bytes() {
+        [b'h', level @ b'1'..=b'6'] => Some((level - b'0') as usize),
+        _ => None,
+    }
+}
+
+// Tool to read a paper's full text, or one section of it, without downloading the PDF
+#[derive(Default)]
+pub struct FetchFullTextTool {
+    ar5iv: Ar5ivClient,
+}
+
+#[derive(Deserialize)]
+pub struct FullTextArgs {
+    pub id: String,
+    pub section: Option<String>,
+}
+
+impl Tool for FetchFullTextTool {
+    const NAME: &'static str = "fetch_fulltext";
+    type Error = FullTextError;
+    type Args = FullTextArgs;
+    type Output = FullText;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: "fetch_fulltext".to_string(),
+            description: "Read the text of an arXiv paper from its ar5iv HTML rendering, either whole or one section of it. Long papers are cut off, so ask for the section you need; the response lists every section heading".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "id": {
+                        "type": "string",
+                        "description": "arXiv id of the paper (e.g. 1706.03762)"
+                    },
+                    "section": {
+                        "type": "string",
+                        "description": "Heading of the section to return, without its number (e.g. Experiments or Conclusion). Omit for the whole paper"
+                    }
+                },
+                "required": ["id"]
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let section = args
+            .section
+            .as_deref()
+            .map(str::trim)
+            .filter(|section| !section.is_empty());
+        self.ar5iv
+            .fetch(&args.id, section, MAX_FULLTEXT_CHARS)
+            .await
+    }
+}
@@ -0,0 +1,190 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::tools::{Paper, SortKey};
+
+const HISTORY_FILE: &str = "arxiv-agent/history.json";
+
+// Oldest searches are dropped beyond this many, unless ARXIV_HISTORY_LIMIT says otherwise
+const DEFAULT_HISTORY_LIMIT: usize = 100;
+
+#[derive(Debug, thiserror::Error)]
+pub enum HistoryError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("History file is corrupted: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+// Searches made from the CLI, most recent last, persisted between runs
+#[derive(Default, Serialize, Deserialize)]
+pub struct History {
+    entries: Vec<HistoryEntry>,
+}
+
+// One past search with what it was asked and the papers it found
+#[derive(Serialize, Deserialize)]
+pub struct HistoryEntry {
+    // Unix timestamp in seconds
+    pub searched_at: u64,
+    pub query: String,
+    pub max_results: Option<i32>,
+    pub sort: Option<SortKey>,
+    // Whether arXiv was searched directly rather than through the agent
+    pub direct: bool,
+    pub papers: Vec<Paper>,
+}
+
+impl HistoryEntry {
+    pub fn new(query: &str, max_results: Option<i32>, sort: Option<SortKey>, direct: bool) -> Self {
+        let searched_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        Self {
+            searched_at,
+            query: query.to_string(),
+            max_results,
+            sort,
+            direct,
+            papers: Vec::new(),
+        }
+    }
+
+    pub fn searched_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.searched_at)
+    }
+}
+
+impl History {
+    // ARXIV_HISTORY_PATH if set, otherwise under the XDG data dir (~/.local/share by default)
+    pub fn default_path() -> PathBuf {
+        if let Ok(path) = std::env::var("ARXIV_HISTORY_PATH") {
+            return PathBuf::from(path);
+        }
+        let data_dir = std::env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+            .unwrap_or_default();
+        data_dir.join(HISTORY_FILE)
+    }
+
+    pub fn limit() -> usize {
+        std::env::var("ARXIV_HISTORY_LIMIT")
+            .ok()
+            .and_then(|limit| limit.parse().ok())
+            .unwrap_or(DEFAULT_HISTORY_LIMIT)
+    }
+
+    // A missing file is an empty history
+    pub fn load(path: &Path) -> Result<Self, HistoryError> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Written next to the real file and renamed over it, so a crash mid-write leaves the
+    // old history in place instead of half a JSON document
+    pub fn save(&self, path: &Path) -> Result<(), HistoryError> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        std::fs::write(&temp, serde_json::to_string(self)?)?;
+        std::fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    pub fn push(&mut self, entry: HistoryEntry, limit: usize) {
+        self.entries.push(entry);
+        if self.entries.len() > limit {
+            self.entries.drain(..self.entries.len() - limit);
+        }
+    }
+
+    // Most recent first, so `recent().nth(n - 1)` is what `history show n` prints
+    pub fn recent(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter().rev()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+// Add a search to the history file; failures only cost the record, so just warn
+pub fn record(entry: HistoryEntry) {
+    let path = History::default_path();
+    let result = History::load(&path).and_then(|mut history| {
+        history.push(entry, History::limit());
+        history.save(&path)
+    });
+    if let Err(e) = result {
+        eprintln!("Warning: could not update the search history: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queries(history: &History) -> Vec<&str> {
+        history.recent().map(|entry| entry.query.as_str()).collect()
+    }
+
+    #[test]
+    fn keeps_the_most_recent_searches_up_to_the_limit() {
+        let mut history = History::default();
+        for query in ["first", "second", "third"] {
+            history.push(HistoryEntry::new(query, Some(5), None, false), 2);
+        }
+        assert_eq!(queries(&history), ["third", "second"]);
+
+        history.clear();
+        assert!(history.recent().next().is_none());
+    }
+
+    #[test]
+    fn saves_and_loads_the_history_file() {
+        let dir = std::env::temp_dir().join(format!("arxiv-agent-history-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("nested/history.json");
+
+        // A missing file is an empty history
+        assert!(History::load(&path).unwrap().recent().next().is_none());
+
+        let mut history = History::default();
+        let mut entry = HistoryEntry::new("attention", Some(3), Some(SortKey::Title), true);
+        entry.searched_at = 1_700_000_000;
+        history.push(entry, 10);
+        history.push(HistoryEntry::new("scaling laws", None, None, false), 10);
+        history.save(&path).unwrap();
+
+        let loaded = History::load(&path).unwrap();
+        assert_eq!(queries(&loaded), ["scaling laws", "attention"]);
+        let entry = loaded.recent().nth(1).unwrap();
+        assert_eq!(entry.max_results, Some(3));
+        assert!(matches!(entry.sort, Some(SortKey::Title)));
+        assert!(entry.direct);
+        assert_eq!(
+            entry.searched_at(),
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+        // Nothing is left behind from writing it
+        assert!(!dir.join("nested/history.json.tmp").exists());
+
+        std::fs::write(&path, "{\"entries\": [").unwrap();
+        assert!(matches!(History::load(&path), Err(HistoryError::Json(_))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,301 @@
+use rig::{
+    completion::ToolDefinition,
+    embeddings::{EmbeddingError, EmbeddingModel},
+    providers::openai,
+    tool::Tool,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+use crate::tools::{self, Paper};
+
+const DEFAULT_LIBRARY_PATH: &str = "arxiv-library.json";
+
+// Oldest-used papers are evicted beyond this size
+const MAX_LIBRARY_SIZE: usize = 500;
+
+#[derive(Debug, thiserror::Error)]
+pub enum LibraryError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Library file is corrupted: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Embedding error: {0}")]
+    Embedding(#[from] EmbeddingError),
+}
+
+// Papers from earlier searches with their embeddings, persisted between runs
+#[derive(Default, Serialize, Deserialize)]
+pub struct Library {
+    entries: Vec<LibraryEntry>,
+    // Logical clock used to find the least recently used entry
+    clock: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LibraryEntry {
+    id: String,
+    paper: Paper,
+    embedding: Vec<f64>,
+    last_used: u64,
+}
+
+impl Library {
+    // ARXIV_LIBRARY_PATH if set, otherwise a file in the working directory
+    pub fn default_path() -> PathBuf {
+        std::env::var("ARXIV_LIBRARY_PATH")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(DEFAULT_LIBRARY_PATH))
+    }
+
+    // A missing file is an empty library
+    pub fn load(path: &Path) -> Result<Self, LibraryError> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), LibraryError> {
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    // Embed and store papers not seen before; papers already stored just count as used
+    pub async fn ingest<M: EmbeddingModel>(
+        &mut self,
+        model: &M,
+        papers: &[Paper],
+    ) -> Result<(), LibraryError> {
+        let mut new_papers: Vec<&Paper> = Vec::new();
+        for paper in papers {
+            let id = library_id(paper);
+            self.clock += 1;
+            match self.entries.iter_mut().find(|entry| entry.id == id) {
+                Some(entry) => entry.last_used = self.clock,
+                None if !new_papers.iter().any(|p| library_id(p) == id) => new_papers.push(paper),
+                None => (),
+            }
+        }
+
+        let documents: Vec<String> = new_papers.iter().copied().map(document).collect();
+        let mut embeddings = Vec::with_capacity(documents.len());
+        for batch in documents.chunks(M::MAX_DOCUMENTS) {
+            embeddings.extend(model.embed_documents(batch.to_vec()).await?);
+        }
+
+        for (paper, embedding) in new_papers.into_iter().zip(embeddings) {
+            self.clock += 1;
+            self.entries.push(LibraryEntry {
+                id: library_id(paper),
+                paper: paper.clone(),
+                embedding: embedding.vec,
+                last_used: self.clock,
+            });
+        }
+
+        while self.entries.len() > MAX_LIBRARY_SIZE {
+            let oldest = self
+                .entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(i, _)| i);
+            if let Some(i) = oldest {
+                self.entries.swap_remove(i);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Closest stored papers to the query embedding, best first
+    pub fn search(&mut self, query_embedding: &[f64], limit: usize) -> Vec<Paper> {
+        let mut scored: Vec<(f64, usize)> = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                (
+                    tools::cosine_similarity(query_embedding, &entry.embedding),
+                    i,
+                )
+            })
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut papers = Vec::new();
+        for (similarity, i) in scored.into_iter().take(limit) {
+            self.clock += 1;
+            let entry = &mut self.entries[i];
+            entry.last_used = self.clock;
+            let mut paper = entry.paper.clone();
+            paper.similarity = Some(similarity as f32);
+            papers.push(paper);
+        }
+        papers
+    }
+}
+
+// Add papers to the on-disk library; failures only cost future recall, so just warn
+pub async fn remember<M: EmbeddingModel>(model: &M, papers: &[Paper]) {
+    let path = Library::default_path();
+    let result = async {
+        let mut library = Library::load(&path)?;
+        library.ingest(model, papers).await?;
+        library.save(&path)
+    }
+    .await;
+    if let Err(e) = result {
+        eprintln!("Warning: could not update the paper library: {}", e);
+    }
+}
+
+// Versions of the same paper share one entry
+fn library_id(paper: &Paper) -> String {
+    tools::base_arxiv_id(&paper.url).to_string()
+}
+
+fn document(paper: &Paper) -> String {
+    format!("{}\n{}", paper.title, paper.abstract_text)
+}
+
+// Tool to search papers from earlier sessions without going back to arXiv
+pub struct QueryLibraryTool {
+    path: PathBuf,
+    embedder: openai::EmbeddingModel,
+}
+
+impl QueryLibraryTool {
+    pub fn new(path: PathBuf, embedder: openai::EmbeddingModel) -> Self {
+        Self { path, embedder }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LibraryArgs {
+    pub query: String,
+    pub max_results: Option<usize>,
+}
+
+impl Tool for QueryLibraryTool {
+    const NAME: &'static str = "query_library";
+    type Error = LibraryError;
+    type Args = LibraryArgs;
+    type Output = Vec<Paper>;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: "query_library".to_string(),
+            description: "Search the papers found in previous sessions by meaning".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": "What to look for in the previously found papers"
+                    },
+                    "max_results": {
+                        "type": "integer",
+                        "description": "Maximum number of results to return (default: 5)"
+                    }
+                },
+                "required": ["query"]
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let mut library = Library::load(&self.path)?;
+        let query_embedding = self.embedder.embed_document(&args.query).await?;
+        let papers = library.search(&query_embedding.vec, args.max_results.unwrap_or(5));
+        // Persist the recency updates so frequently used papers survive eviction
+        library.save(&self.path)?;
+        Ok(papers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::WordCountEmbedder;
+
+    const EMBEDDER: WordCountEmbedder = WordCountEmbedder(&["attention", "vision", "speech"]);
+
+    fn paper(id: &str, title: &str, abstract_text: &str) -> Paper {
+        Paper {
+            url: format!("http://arxiv.org/abs/{}", id),
+            abstract_text: abstract_text.to_string(),
+            ..Paper::fixture(title)
+        }
+    }
+
+    fn titles(papers: &[Paper]) -> Vec<&str> {
+        papers.iter().map(|paper| paper.title.as_str()).collect()
+    }
+
+    async fn query(library: &mut Library, text: &str, limit: usize) -> Vec<Paper> {
+        let embedding = EMBEDDER.embed_document(text).await.unwrap();
+        library.search(&embedding.vec, limit)
+    }
+
+    #[tokio::test]
+    async fn finds_ingested_papers_by_meaning_after_a_reload() {
+        let dir = std::env::temp_dir().join(format!("arxiv-agent-library-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("library.json");
+
+        // A missing file is an empty library
+        let mut library = Library::load(&path).unwrap();
+        assert!(query(&mut library, "attention", 5).await.is_empty());
+
+        // More papers than one embedding request takes, with one paper twice
+        let papers = [
+            paper(
+                "1706.03762v1",
+                "Transformer",
+                "Attention is all you need, attention",
+            ),
+            paper(
+                "2010.11929v1",
+                "ViT",
+                "Vision transformers, attention for vision",
+            ),
+            paper("2212.04356v1", "Whisper", "Speech recognition"),
+            paper(
+                "1706.03762v5",
+                "Transformer",
+                "Attention is all you need, attention",
+            ),
+        ];
+        library.ingest(&EMBEDDER, &papers).await.unwrap();
+
+        let found = query(&mut library, "vision", 5).await;
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[0].title, "ViT");
+        assert!(found[0].similarity.unwrap() > found[1].similarity.unwrap());
+        assert_eq!(titles(&query(&mut library, "speech", 1).await), ["Whisper"]);
+        library.save(&path).unwrap();
+
+        // A new version of a stored paper doesn't add another entry
+        let mut reloaded = Library::load(&path).unwrap();
+        reloaded
+            .ingest(
+                &EMBEDDER,
+                &[paper("1706.03762v7", "Transformer", "Attention")],
+            )
+            .await
+            .unwrap();
+        let found = query(&mut reloaded, "attention", 5).await;
+        assert_eq!(titles(&found), ["Transformer", "ViT", "Whisper"]);
+        assert_eq!(found[0].url, "http://arxiv.org/abs/1706.03762v1");
+
+        std::fs::write(&path, "{\"entries\": [").unwrap();
+        assert!(matches!(Library::load(&path), Err(LibraryError::Json(_))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,294 +1,1700 @@
-use anyhow::Result;
-use quick_xml::events::Event;
-use quick_xml::Reader;
-use rig::{
-    completion::{Prompt, ToolDefinition},
-    providers::openai::{self, GPT_4},
-    tool::Tool,
-};
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use owo_colors::{OwoColorize, Style};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
-use std::str;
-use std::fmt::Write as _;
-
-// Struct to hold paper metadata
-#[derive(Debug, Deserialize, Serialize)]
-struct Paper {
-    title: String,
-    authors: Vec<String>,
-    abstract_text: String,
-    url: String,
-    categories: Vec<String>,
-}
-
-// Tool to search for papers
-#[derive(Deserialize, Serialize)]
-struct ArxivSearchTool;
-
-#[derive(Deserialize)]
-struct SearchArgs {
-    query: String,
-    max_results: Option<i32>,
+use std::fmt::{self, Write as _};
+use std::io::{self, IsTerminal, Write as _};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
+
+mod agent;
+mod ask;
+// Shared with the web app, which is deployed from its own directory
+#[path = "../shuttle/src/categories.rs"]
+mod categories;
+mod chat;
+mod citations;
+mod config;
+mod crossref;
+mod fulltext;
+mod history;
+mod library;
+mod tools;
+use agent::{AgentAnswer, ModelParams, Provider, ProviderClient, Synthesis};
+use config::Config;
+use history::{History, HistoryEntry};
+use tools::{normalize_latex, CitationStyle, Paper, PaperComparison, SearchArgs, SearchSummary};
+
+// Column layout for the ASCII table
+struct TableConfig {
+    title_width: usize,
+    authors_width: usize,
+    categories_width: usize,
+    url_width: usize,
+    total_width: usize,
+    // ANSI styling, only for terminals
+    color: bool,
+    // Nothing but ASCII, for consoles that garble UTF-8
+    ascii_only: bool,
+    // A section per group of papers instead of one flat table
+    group_by: Option<GroupBy>,
 }
 
-#[derive(Debug, thiserror::Error)]
-enum ArxivError {
-    #[error("Network error: {0}")]
-    Network(#[from] reqwest::Error),
-    #[error("XML parsing error: {0}")]
-    XmlParsing(#[from] quick_xml::Error),
-    #[error("No results found")]
-    NoResults,
-    #[error("UTF-8 decoding error: {0}")]
-    Utf8Error(#[from] std::str::Utf8Error),
-}
-
-impl Tool for ArxivSearchTool {
-    const NAME: &'static str = "search_arxiv";
-    type Error = ArxivError;
-    type Args = SearchArgs;
-    type Output = Vec<Paper>;
-
-    async fn definition(&self, _prompt: String) -> ToolDefinition {
-        ToolDefinition {
-            name: "search_arxiv".to_string(),
-            description: "Search for academic papers on arXiv".to_string(),
-            parameters: json!({
-                "type": "object",
-                "properties": {
-                    "query": {
-                        "type": "string",
-                        "description": "Search query for papers"
-                    },
-                    "max_results": {
-                        "type": "integer",
-                        "description": "Maximum number of results to return (default: 5)"
-                    }
-                },
-                "required": ["query"]
-            }),
-        }
-    }
-
-    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
-        let max_results = args.max_results.unwrap_or(5);
-        let client = reqwest::Client::new();
-        
-        // URL encode the query
-        let encoded_query = urlencoding::encode(&args.query);
-        
-        // Construct arXiv API URL
-        let url = format!(
-            "http://export.arxiv.org/api/query?search_query=all:{}&start=0&max_results={}", 
-            encoded_query,
-            max_results
-        );
+impl Default for TableConfig {
+    fn default() -> Self {
+        Self {
+            title_width: 50,
+            authors_width: 20,
+            categories_width: 15,
+            url_width: 30,
+            total_width: 120,
+            color: false,
+            ascii_only: false,
+            group_by: None,
+        }
+    }
+}
 
-        let response = client.get(&url)
-            .send()
-            .await?
-            .text()
-            .await?;
-
-        parse_arxiv_response(&response)
-    }
-}
-
-fn parse_arxiv_response(response: &str) -> Result<Vec<Paper>, ArxivError> {
-    let mut reader = Reader::from_str(response);
-    reader.trim_text(true);
-
-    let mut papers = Vec::new();
-    let mut current_paper: Option<Paper> = None;
-    let mut current_authors = Vec::new();
-    let mut current_categories = Vec::new();
-    let mut buf = Vec::new();
-    let mut in_entry = false;
-    let mut current_field = None;
-
-    loop {
-        match reader.read_event_into(&mut buf) {
-            Ok(Event::Start(ref e)) => {
-                match e.name().as_ref() {
-                    b"entry" => {
-                        in_entry = true;
-                        current_paper = Some(Paper {
-                            title: String::new(),
-                            authors: Vec::new(),
-                            abstract_text: String::new(),
-                            url: String::new(),
-                            categories: Vec::new(),
-                        });
-                        current_authors.clear();
-                        current_categories.clear();
-                    }
-                    b"title" if in_entry => current_field = Some("title"),
-                    b"author" if in_entry => current_field = Some("author"),
-                    b"summary" if in_entry => current_field = Some("abstract"),
-                    b"link" if in_entry => current_field = Some("link"),
-                    b"category" if in_entry => current_field = Some("category"),
-                    _ => (),
-                }
-            }
-            Ok(Event::Text(e)) => {
-                if let Some(paper) = current_paper.as_mut() {
-                    let text = str::from_utf8(e.as_ref())?.to_owned();
-                    match current_field {
-                        Some("title") => paper.title = text,
-                        Some("author") => current_authors.push(text),
-                        Some("abstract") => paper.abstract_text = text,
-                        _ => (),
-                    }
-                }
-            }
-            Ok(Event::Empty(ref e)) => {
-                if in_entry && e.name().as_ref() == b"link" {
-                    if let Some(paper) = current_paper.as_mut() {
-                        for attr in e.attributes() {
-                            if let Ok(attr) = attr {
-                                if attr.key.as_ref() == b"href" {
-                                    paper.url = str::from_utf8(&attr.value)?.to_owned();
-                                }
-                            }
-                        }
-                    }
-                }
-                if in_entry && e.name().as_ref() == b"category" {
-                    for attr in e.attributes() {
-                        if let Ok(attr) = attr {
-                            if attr.key.as_ref() == b"term" {
-                                current_categories.push(str::from_utf8(&attr.value)?.to_owned());
-                            }
-                        }
-                    }
-                }
-            }
-            Ok(Event::End(ref e)) => {
-                match e.name().as_ref() {
-                    b"entry" => {
-                        if let Some(mut paper) = current_paper.take() {
-                            paper.authors = current_authors.clone();
-                            paper.categories = current_categories.clone();
-                            papers.push(paper);
-                        }
-                        in_entry = false;
-                    }
-                    b"title" | b"author" | b"summary" | b"link" | b"category" => {
-                        current_field = None;
-                    }
-                    _ => (),
-                }
+// Width used when output is piped, with no terminal to fit
+const PIPED_WIDTH: usize = 120;
+
+// Narrower than this, papers are listed one under another instead of in columns
+const MIN_TABLE_WIDTH: usize = 72;
+
+impl TableConfig {
+    // Fit the terminal, or a fixed width when piped
+    fn for_terminal() -> Self {
+        let width = match terminal_size::terminal_size() {
+            Some((terminal_size::Width(width), _)) if std::io::stdout().is_terminal() => {
+                width as usize
             }
-            Ok(Event::Eof) => break,
-            Err(e) => return Err(ArxivError::XmlParsing(e)),
-            _ => (),
+            _ => PIPED_WIDTH,
+        };
+        Self::for_width(width)
+    }
+
+    // Columns get a share of the width, capped where their content is never longer, and the
+    // title gets whatever is left over
+    fn for_width(width: usize) -> Self {
+        // Three " | " separators between the four columns
+        let available = width.saturating_sub(9);
+        let authors_width = (available / 5).clamp(8, 30);
+        // No narrower than the "Categories" header
+        let categories_width = (available * 14 / 100).clamp(10, 24);
+        // A full abstract URL with its version fits in 34
+        let url_width = (available * 3 / 10).clamp(10, 34);
+        Self {
+            title_width: available
+                .saturating_sub(authors_width + categories_width + url_width)
+                .max(20),
+            authors_width,
+            categories_width,
+            url_width,
+            total_width: width,
+            ..Self::default()
+        }
+    }
+
+    // Too narrow for columns
+    fn stacked(&self) -> bool {
+        self.total_width < MIN_TABLE_WIDTH
+    }
+
+    fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    fn with_ascii_only(mut self, ascii_only: bool) -> Self {
+        self.ascii_only = ascii_only;
+        self
+    }
+
+    fn with_group_by(mut self, group_by: Option<GroupBy>) -> Self {
+        self.group_by = group_by;
+        self
+    }
+
+    // Paper text for display: LaTeX rendered as Unicode, or kept as source in ASCII-only mode
+    fn text(&self, text: &str) -> String {
+        if self.ascii_only {
+            to_ascii(text)
+        } else {
+            normalize_latex(text)
         }
     }
 
-    if papers.is_empty() {
-        return Err(ArxivError::NoResults);
+    fn paint<'a>(&self, text: &'a str, style: Style) -> Painted<'a> {
+        Painted(text, self.color.then_some(style))
+    }
+}
+
+// Text styled only when colors are on; widths and alignment apply to the text, not the escapes
+struct Painted<'a>(&'a str, Option<Style>);
+
+impl fmt::Display for Painted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.1 {
+            Some(style) => fmt::Display::fmt(&self.0.style(style), f),
+            None => fmt::Display::fmt(self.0, f),
+        }
     }
+}
 
-    Ok(papers)
+// Common punctuation gets its ASCII equivalent, anything else that isn't ASCII becomes '?'
+fn to_ascii(text: &str) -> String {
+    let mut ascii = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            c if c.is_ascii() => ascii.push(c),
+            '…' => ascii.push_str("..."),
+            '‘' | '’' => ascii.push('\''),
+            '“' | '”' => ascii.push('"'),
+            '–' | '—' | '−' => ascii.push('-'),
+            '\u{a0}' => ascii.push(' '),
+            _ => ascii.push('?'),
+        }
+    }
+    ascii
 }
 
-fn format_papers_as_table(papers_json: &str) -> Result<String, anyhow::Error> {
-    let papers: Vec<Paper> = serde_json::from_str(papers_json)?;
-    
+// Colors on a terminal unless NO_COLOR (https://no-color.org) is set, or as --color says
+fn use_color(choice: ColorChoice) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+            !no_color_env && std::io::stdout().is_terminal()
+        }
+    }
+}
+
+fn format_papers_as_table(papers: &[Paper], config: &TableConfig) -> Result<String, anyhow::Error> {
+    match config.group_by {
+        None => format_table_section(papers, config, " Research Papers "),
+        Some(GroupBy::Category) => {
+            let mut output = String::new();
+            for group in tools::group_by_primary_category(papers) {
+                let heading = format!(" {} ({}) ", group.category, group.papers.len());
+                output.push_str(&format_table_section(&group.papers, config, &heading)?);
+            }
+            Ok(output)
+        }
+    }
+}
+
+// One table of papers and their abstracts under `heading`
+fn format_table_section(
+    papers: &[Paper],
+    config: &TableConfig,
+    heading: &str,
+) -> Result<String, anyhow::Error> {
     let mut output = String::new();
-    
-    // Write table header
-    writeln!(&mut output, "\n{:-^120}", " Research Papers ")?;
+    let total = config.total_width;
+
+    let rule = Style::new().blue();
     writeln!(
         &mut output,
-        "{:<50} | {:<20} | {:<15} | {:<30}",
-        "Title", "Authors", "Categories", "URL"
+        "\n{}",
+        config.paint(&format!("{:-^total$}", heading), rule)
+    )?;
+    if !config.stacked() {
+        write_table_rows(&mut output, papers, config)?;
+        writeln!(
+            &mut output,
+            "\n{}",
+            config.paint(&format!("{:-^total$}", " Abstracts "), rule)
+        )?;
+    }
+
+    for (i, paper) in papers.iter().enumerate() {
+        let title = config.text(&paper.title);
+        writeln!(&mut output, "\n{}. {}", i + 1, config.paint(&title, Style::new().bold()))?;
+        writeln!(&mut output, "Authors: {}", config.text(&paper.authors.join(", ")))?;
+        writeln!(&mut output, "\nAbstract:\n{}\n", config.text(&paper.abstract_text))?;
+        let categories = paper.categories.join(", ");
+        writeln!(&mut output, "Categories: {}\n", config.paint(&categories, Style::new().dimmed()))?;
+        writeln!(&mut output, "URL: {}\n", config.paint(&paper.url, Style::new().underline()))?;
+        if let Some(similarity) = paper.similarity {
+            writeln!(&mut output, "Similarity: {:.3}\n", similarity)?;
+        }
+        if let Some(score) = paper.score {
+            writeln!(&mut output, "Score: {:.3}\n", score)?;
+        }
+        writeln!(&mut output, "{:-<total$}", "")?;
+    }
+
+    Ok(output)
+}
+
+// Column headers and a row per paper, with long titles wrapped within their column
+fn write_table_rows(
+    output: &mut String,
+    papers: &[Paper],
+    config: &TableConfig,
+) -> Result<(), anyhow::Error> {
+    let total = config.total_width;
+    let (tw, aw, cw, uw) = (
+        config.title_width,
+        config.authors_width,
+        config.categories_width,
+        config.url_width,
+    );
+
+    let header = Style::new().bold();
+    writeln!(
+        output,
+        "{:<tw$} | {:<aw$} | {:<cw$} | {:<uw$}",
+        config.paint("Title", header),
+        config.paint("Authors", header),
+        config.paint("Categories", header),
+        config.paint("URL", header),
     )?;
-    writeln!(&mut output, "{:-<120}", "")?;
+    writeln!(output, "{:-<total$}", "")?;
 
-    // Write each paper's information
-    for paper in papers.iter() {
-        // Truncate and format title
-        let title = if paper.title.len() > 47 {
-            format!("{}...", &paper.title[..47])
+    for paper in papers {
+        let authors = if paper.authors.len() > 2 {
+            format!("{} et al.", paper.authors[0])
         } else {
-            paper.title.clone()
+            paper.authors.join(", ")
         };
+        let title = wrap(&config.text(&paper.title), tw);
+
+        writeln!(
+            output,
+            "{:<tw$} | {:<aw$} | {:<cw$} | {:<uw$}",
+            config.paint(&title[0], Style::new().bold()),
+            truncate(&config.text(&authors), aw),
+            config.paint(
+                &truncate(&paper.categories.join(", "), cw),
+                Style::new().dimmed()
+            ),
+            config.paint(&truncate(&paper.url, uw), Style::new().underline()),
+        )?;
+        // The rest of the title, with the other columns left empty
+        for line in &title[1..] {
+            let line = config.paint(line, Style::new().bold());
+            writeln!(output, "{:<tw$} | {:<aw$} | {:<cw$} |", line, "", "")?;
+        }
+    }
+    Ok(())
+}
+
+// Word-wrap text into lines of at most `width` characters, breaking up words longer than
+// a whole line. Always at least one line, empty for empty text
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_chars = 0;
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        while word.len() > width {
+            if line_chars > 0 {
+                lines.push(std::mem::take(&mut line));
+                line_chars = 0;
+            }
+            let rest = word.split_off(width);
+            lines.push(word.into_iter().collect());
+            word = rest;
+        }
+        if line_chars > 0 && line_chars + 1 + word.len() > width {
+            lines.push(std::mem::take(&mut line));
+            line_chars = 0;
+        }
+        if line_chars > 0 {
+            line.push(' ');
+            line_chars += 1;
+        }
+        line.extend(&word);
+        line_chars += word.len();
+    }
+    if line_chars > 0 || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+// Fit text into a column, cutting on a character boundary and marking the cut with "..."
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(width.saturating_sub(3)).collect();
+    format!("{}...", kept)
+}
+
+// Markdown formatting for GitHub issues and notes
+fn format_papers_as_markdown(papers: &[Paper]) -> String {
+    let mut output = String::new();
 
-        // Format authors
+    output.push_str("| Title | Authors | Categories |\n");
+    output.push_str("| --- | --- | --- |\n");
+    for paper in papers {
         let authors = if paper.authors.len() > 2 {
             format!("{} et al.", paper.authors[0])
         } else {
             paper.authors.join(", ")
         };
-        let authors = if authors.len() > 17 {
-            format!("{}...", &authors[..17])
+        output.push_str(&format!(
+            "| [{}]({}) | {} | {} |\n",
+            escape_markdown(&normalize_latex(&paper.title)),
+            paper.url,
+            escape_markdown(&authors),
+            paper.categories.join(", ")
+        ));
+    }
+
+    output.push_str("\n## Abstracts\n");
+    for paper in papers {
+        output.push_str(&format!(
+            "\n### {}\n\n",
+            escape_markdown(&normalize_latex(&paper.title))
+        ));
+        output.push_str(&format!(
+            "**Authors:** {}\n\n",
+            escape_markdown(&paper.authors.join(", "))
+        ));
+        output.push_str(&format!(
+            "**Categories:** {}\n\n",
+            paper.categories.join(", ")
+        ));
+        output.push_str(&format!("**URL:** {}\n\n", paper.url));
+        for line in normalize_latex(&paper.abstract_text).lines() {
+            output.push_str(&format!("> {}\n", line.trim()));
+        }
+    }
+
+    output
+}
+
+// Pipes and backticks would otherwise break table cells and inline code
+fn escape_markdown(text: &str) -> String {
+    text.replace('|', "\\|")
+        .replace('`', "\\`")
+        .replace('\n', " ")
+}
+
+// CSV formatting for spreadsheets
+fn format_papers_as_csv(papers: &[Paper]) -> Result<String, anyhow::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record([
+        "title",
+        "authors",
+        "primary_category",
+        "categories",
+        "published",
+        "url",
+        "doi",
+        "abstract",
+    ])?;
+
+    for paper in papers {
+        let authors = paper.authors.join(";");
+        let categories = paper.categories.join(";");
+        writer.write_record([
+            paper.title.as_str(),
+            authors.as_str(),
+            paper.primary_category.as_deref().unwrap_or_default(),
+            categories.as_str(),
+            paper.published.as_deref().unwrap_or_default(),
+            paper.url.as_str(),
+            paper.doi.as_deref().unwrap_or_default(),
+            paper.abstract_text.as_str(),
+        ])?;
+    }
+
+    let bytes = writer.into_inner().map_err(|e| e.into_error())?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+// RIS formatting for reference managers (Zotero, EndNote)
+fn to_ris(papers: &[Paper]) -> String {
+    let mut output = String::new();
+
+    for paper in papers {
+        // Only papers known to be published are journal articles, preprints are generic
+        let kind = if paper.journal.is_some() || paper.doi.is_some() {
+            "JOUR"
         } else {
-            authors
+            "GEN"
         };
+        push_ris_tag(&mut output, "TY", kind);
+        push_ris_tag(&mut output, "TI", &paper.title);
+        for author in &paper.authors {
+            push_ris_tag(&mut output, "AU", author);
+        }
+        push_ris_tag(&mut output, "AB", &paper.abstract_text);
+        push_ris_tag(&mut output, "UR", &paper.url);
+        if let Some(doi) = &paper.doi {
+            push_ris_tag(&mut output, "DO", doi);
+        }
+        if let Some(year) = paper.published.as_deref().and_then(|date| date.get(..4)) {
+            push_ris_tag(&mut output, "PY", year);
+        }
+        for category in &paper.categories {
+            push_ris_tag(&mut output, "KW", category);
+        }
+        output.push_str("ER  - \r\n");
+    }
 
-        // Format categories
-        let categories = paper.categories.join(", ");
-        let categories = if categories.len() > 12 {
-            format!("{}...", &categories[..12])
-        } else {
-            categories
+    output
+}
+
+// RIS values must stay on a single line, and some importers require CRLF endings
+fn push_ris_tag(output: &mut String, tag: &str, value: &str) {
+    let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+    output.push_str(&format!("{}  - {}\r\n", tag, value));
+}
+
+// BibTeX formatting for LaTeX bibliographies
+fn format_papers_as_bibtex(papers: &[Paper]) -> String {
+    let mut output = String::new();
+
+    for paper in papers {
+        let id = tools::arxiv_id_from_url(&paper.url);
+        output.push_str(&format!("@misc{{{},\n", id));
+        output.push_str(&format!("  title = {{{}}},\n", paper.title));
+        output.push_str(&format!("  author = {{{}}},\n", paper.authors.join(" and ")));
+        if let Some(year) = paper.published.as_deref().and_then(|date| date.get(..4)) {
+            output.push_str(&format!("  year = {{{}}},\n", year));
+        }
+        output.push_str(&format!("  eprint = {{{}}},\n", id));
+        output.push_str("  archivePrefix = {arXiv},\n");
+        if let Some(category) = &paper.primary_category {
+            output.push_str(&format!("  primaryClass = {{{}}},\n", category));
+        }
+        if let Some(doi) = &paper.doi {
+            output.push_str(&format!("  doi = {{{}}},\n", doi));
+        }
+        output.push_str(&format!("  url = {{{}}},\n", paper.url));
+        output.push_str("}\n\n");
+    }
+
+    output
+}
+
+// Reference lists in APA or MLA style, one paper per line
+fn format_citations(papers: &[Paper], style: CitationStyle) -> String {
+    papers
+        .iter()
+        .map(|paper| paper.to_citation(style))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Plain text formatting for a two-paper comparison
+fn format_comparison(comparison: &PaperComparison) -> String {
+    let mut output = format!("\n{:-^120}\n", " Comparison ");
+    output.push_str(&format!("A: {} ({})\n", comparison.first.title, comparison.first.url));
+    output.push_str(&format!("B: {} ({})\n", comparison.second.title, comparison.second.url));
+    output.push_str(&format!("\nShared topic: {}\n", comparison.shared_topic));
+    output.push_str("\nDiffering methods:\n");
+    for method in &comparison.differing_methods {
+        output.push_str(&format!("- {}\n", method));
+    }
+    output.push_str("\nDiffering datasets and benchmarks:\n");
+    for dataset in &comparison.differing_datasets {
+        output.push_str(&format!("- {}\n", dataset));
+    }
+    if let Some(newer) = &comparison.newer {
+        output.push_str(&format!("\nNewer: {}\n", newer));
+    }
+
+    output
+}
+
+// Synthesis formatting, printed below the papers
+fn format_synthesis(synthesis: &Synthesis, format: OutputFormat) -> String {
+    let sections = [
+        ("Common themes", &synthesis.common_themes),
+        ("Methodological differences", &synthesis.methodological_differences),
+        ("Open questions", &synthesis.open_questions),
+    ];
+
+    let mut output = String::new();
+    match format {
+        OutputFormat::Markdown => output.push_str("\n# Synthesis\n"),
+        _ => output.push_str(&format!("\n{:-^120}\n", " Synthesis ")),
+    }
+    for (heading, items) in sections {
+        match format {
+            OutputFormat::Markdown => output.push_str(&format!("\n## {}\n\n", heading)),
+            _ => output.push_str(&format!("\n{}:\n", heading)),
+        }
+        for item in items {
+            output.push_str(&format!("- {}\n", item));
+        }
+    }
+
+    output
+}
+
+// When to style the table with ANSI escapes
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+// Ways to split the table into sections
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum GroupBy {
+    // Primary category, biggest first
+    Category,
+}
+
+// Services that can fill in what arXiv doesn't know about a paper
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Enrichment {
+    // Citation counts from Semantic Scholar
+    Citations,
+    // Journal, publisher and print date from Crossref, for papers with a DOI
+    Crossref,
+}
+
+impl Enrichment {
+    fn service(self) -> &'static str {
+        match self {
+            Enrichment::Citations => "Semantic Scholar",
+            Enrichment::Crossref => "Crossref",
+        }
+    }
+}
+
+// Output formats supported by the CLI
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum OutputFormat {
+    Table,
+    Markdown,
+    Json,
+    Bibtex,
+    Ris,
+    Csv,
+    Apa,
+    Mla,
+}
+
+impl OutputFormat {
+    // The format a file name asks for, if its extension is one we write
+    fn from_extension(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "txt" => Some(Self::Table),
+            "md" | "markdown" => Some(Self::Markdown),
+            "json" => Some(Self::Json),
+            "bib" => Some(Self::Bibtex),
+            "ris" => Some(Self::Ris),
+            "csv" => Some(Self::Csv),
+            _ => None,
+        }
+    }
+}
+
+// Subcommands, for everything that isn't a search
+#[derive(Subcommand)]
+enum Command {
+    /// Past searches (kept in ARXIV_HISTORY_PATH, capped at ARXIV_HISTORY_LIMIT)
+    History {
+        #[command(subcommand)]
+        action: HistoryAction,
+    },
+    /// Read a paper's text from its ar5iv HTML rendering, whole or one section of it
+    Fulltext {
+        /// arXiv id of the paper (e.g. 1706.03762)
+        id: String,
+        /// Only this section and its subsections (e.g. Conclusion)
+        #[arg(long)]
+        section: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum HistoryAction {
+    /// Recent searches, newest first, with how many papers each found
+    List {
+        /// How many searches to list
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Print the papers of a past search again without asking arXiv (1 is the latest)
+    Show { n: usize },
+    /// Forget every past search
+    Clear,
+}
+
+// Command line arguments
+#[derive(Parser)]
+#[command(
+    about = "Search and analyze arXiv papers with an AI research assistant",
+    subcommand_negates_reqs = true
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Research topic to search for
+    #[arg(
+        required_unless_present_any = ["prompt", "chat", "input", "show_config"],
+        conflicts_with = "prompt"
+    )]
+    query: Option<String>,
+    /// Full prompt to send to the agent instead of a research topic
+    #[arg(long)]
+    prompt: Option<String>,
+    /// Maximum number of papers to return (default: 5)
+    #[arg(long)]
+    max_results: Option<i32>,
+    /// Output format for the papers (default: table)
+    #[arg(long, value_enum, global = true)]
+    format: Option<OutputFormat>,
+    /// JSON on a single line instead of indented, for piping into other programs
+    #[arg(long, global = true)]
+    compact: bool,
+    /// Re-sort the papers found, undated ones last when sorting by date
+    #[arg(long, value_enum)]
+    sort: Option<tools::SortKey>,
+    /// Split the table into a section per primary category, with its paper count
+    #[arg(long, value_enum)]
+    group_by: Option<GroupBy>,
+    /// Fill in the papers from other services: citation counts from Semantic Scholar, or
+    /// where papers with a DOI were published from Crossref
+    #[arg(long, value_enum, value_delimiter = ',')]
+    enrich: Vec<Enrichment>,
+    /// LLM provider backing the agent (default: openai)
+    #[arg(long, value_enum, env = "PROVIDER")]
+    provider: Option<Provider>,
+    /// Model used by the agent (default depends on the provider)
+    #[arg(long, env = "MODEL_NAME")]
+    model: Option<String>,
+    /// Sampling temperature for the agent, from 0 to 2 (default: 0.2)
+    #[arg(long)]
+    temperature: Option<f64>,
+    /// Longest answer the agent may give, in tokens (default: the provider's limit)
+    #[arg(long)]
+    max_tokens: Option<u64>,
+    /// Nucleus sampling cutoff for the agent, above 0 and at most 1
+    #[arg(long)]
+    top_p: Option<f64>,
+    /// Search arXiv directly without going through the LLM agent
+    #[arg(long, visible_alias = "direct")]
+    no_agent: bool,
+    /// Start an interactive multi-turn chat with the agent
+    #[arg(long, conflicts_with = "no_agent")]
+    chat: bool,
+    /// Follow the papers with a synthesis of their themes, methods and open questions
+    /// (implied when the request asks for a summary)
+    #[arg(long, conflicts_with = "no_agent")]
+    summarize: bool,
+    /// Download the PDFs of the results (into ARXIV_DOWNLOAD_DIR, the config file's
+    /// download_dir, or ./papers)
+    #[arg(long)]
+    download: bool,
+    /// When to style the table: on terminals without NO_COLOR set (the default), always,
+    /// or never
+    #[arg(long, value_enum)]
+    color: Option<ColorChoice>,
+    /// Same as --color never
+    #[arg(long, conflicts_with = "color")]
+    no_color: bool,
+    /// Only ASCII in the table, for consoles that garble UTF-8
+    #[arg(long, env = "ARXIV_ASCII_ONLY")]
+    ascii_only: bool,
+    /// Also write the papers to this file, in the format its extension names
+    /// (.json, .md, .csv, .bib, .ris or .txt for the table)
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Format of the --output file, whatever its extension
+    #[arg(long, value_enum, requires = "output")]
+    output_format: Option<OutputFormat>,
+    /// Overwrite the --output file if it already exists
+    #[arg(long, requires = "output")]
+    force: bool,
+    /// Work on papers saved with --output as JSON instead of searching arXiv. Without a query
+    /// they are all shown, with one they are narrowed to the papers mentioning it
+    #[arg(long)]
+    input: Option<PathBuf>,
+    /// Config file with defaults for these flags (default: ~/.config/arxiv-agent/config.toml)
+    #[arg(long, env = "ARXIV_AGENT_CONFIG")]
+    config: Option<PathBuf>,
+    /// Print the settings in effect, after the config file and flags, and exit
+    #[arg(long)]
+    show_config: bool,
+    // Loaded from the config file after parsing
+    #[arg(skip)]
+    defaults: Config,
+}
+
+impl Args {
+    fn provider(&self) -> Provider {
+        self.provider
+            .or(self.defaults.provider)
+            .unwrap_or(Provider::Openai)
+    }
+
+    fn model(&self) -> String {
+        let provider = self.provider();
+        let configured = match provider {
+            // OPENAI_MODEL is still honored for OpenAI
+            Provider::Openai => self
+                .model
+                .clone()
+                .or_else(|| std::env::var("OPENAI_MODEL").ok()),
+            _ => self.model.clone(),
         };
+        configured
+            .or_else(|| self.defaults.model.clone())
+            .unwrap_or_else(|| provider.default_model().to_string())
+    }
+
+    // Not validated yet, main checks them before anything runs
+    fn model_params(&self) -> ModelParams {
+        let defaults = ModelParams::default();
+        ModelParams {
+            temperature: self
+                .temperature
+                .or(self.defaults.temperature)
+                .unwrap_or(defaults.temperature),
+            max_tokens: self.max_tokens.or(self.defaults.max_tokens),
+            top_p: self.top_p.or(self.defaults.top_p),
+        }
+    }
+
+    fn max_results(&self) -> Option<i32> {
+        self.max_results.or(self.defaults.max_results)
+    }
 
-        // Format URL
-        let url = if paper.url.len() > 27 {
-            format!("{}...", &paper.url[..27])
+    fn format(&self) -> OutputFormat {
+        self.format
+            .or(self.defaults.format)
+            .unwrap_or(OutputFormat::Table)
+    }
+
+    fn sort(&self) -> Option<tools::SortKey> {
+        self.sort.or(self.defaults.sort)
+    }
+
+    fn group_by(&self) -> Option<GroupBy> {
+        self.group_by.or(self.defaults.group_by)
+    }
+
+    fn color(&self) -> ColorChoice {
+        if self.no_color {
+            ColorChoice::Never
         } else {
-            paper.url.clone()
-        };
+            self.color
+                .or(self.defaults.color)
+                .unwrap_or(ColorChoice::Auto)
+        }
+    }
 
-        writeln!(
-            &mut output,
-            "{:<50} | {:<20} | {:<15} | {:<30}",
-            title, authors, categories, url
-        )?;
+    fn ascii_only(&self) -> bool {
+        self.ascii_only || self.defaults.ascii_only.unwrap_or_default()
     }
 
-    // Add abstract section
-    writeln!(&mut output, "\n{:-^120}", " Abstracts ")?;
-    for (i, paper) in papers.iter().enumerate() {
-        writeln!(&mut output, "\n{}. {}", i + 1, paper.title)?;
-        writeln!(&mut output, "Authors: {}", paper.authors.join(", "))?;
-        writeln!(&mut output, "\nAbstract:\n{}\n", paper.abstract_text)?;
-        writeln!(&mut output, "Categories: {}\n", paper.categories.join(", "))?;
-        writeln!(&mut output, "URL: {}\n", paper.url)?;
-        writeln!(&mut output, "{:-<120}", "")?;
+    fn download_dir(&self) -> PathBuf {
+        match &self.defaults.download_dir {
+            Some(dir) if std::env::var_os("ARXIV_DOWNLOAD_DIR").is_none() => dir.clone(),
+            _ => tools::download_dir(),
+        }
     }
 
-    Ok(output)
+    // Every setting as it will be used, in the config file's shape
+    fn effective_config(&self) -> Config {
+        let params = self.model_params();
+        Config {
+            provider: Some(self.provider()),
+            model: Some(self.model()),
+            temperature: Some(params.temperature),
+            max_tokens: params.max_tokens,
+            top_p: params.top_p,
+            max_results: Some(tools::clamp_max_results(self.max_results())),
+            format: Some(self.format()),
+            sort: self.sort(),
+            group_by: self.group_by(),
+            color: Some(self.color()),
+            ascii_only: Some(self.ascii_only()),
+            download_dir: Some(self.download_dir()),
+        }
+    }
+
+    // Only a saved set with nothing asked about it skips the agent without --no-agent
+    fn direct(&self) -> bool {
+        self.no_agent
+            || (self.input.is_some() && self.query.is_none() && self.prompt.is_none() && !self.chat)
+    }
+
+    // Only the human-readable formats have room for a synthesis
+    fn wants_synthesis(&self) -> bool {
+        matches!(self.format(), OutputFormat::Table | OutputFormat::Markdown)
+            && (self.summarize || agent::wants_synthesis(self.query()))
+    }
+
+    fn query(&self) -> &str {
+        self.query
+            .as_deref()
+            .or(self.prompt.as_deref())
+            .unwrap_or_default()
+    }
+
+    fn prompt(&self) -> String {
+        if let Some(prompt) = &self.prompt {
+            return prompt.clone();
+        }
+
+        let mut prompt = format!(
+            "Find recent papers about {} and summarize them",
+            self.query()
+        );
+        if let Some(max_results) = self.max_results() {
+            prompt.push_str(&format!(" (return at most {} papers)", max_results));
+        }
+        prompt
+    }
+}
+
+// Indented unless `compact`, which keeps it on one line for other programs
+fn to_json<T: Serialize + ?Sized>(value: &T, compact: bool) -> serde_json::Result<String> {
+    if compact {
+        serde_json::to_string(value)
+    } else {
+        serde_json::to_string_pretty(value)
+    }
+}
+
+fn format_papers(
+    papers: &[Paper],
+    format: OutputFormat,
+    table: &TableConfig,
+    compact: bool,
+) -> Result<String, anyhow::Error> {
+    match format {
+        OutputFormat::Table => format_papers_as_table(papers, table),
+        OutputFormat::Markdown => Ok(format_papers_as_markdown(papers)),
+        OutputFormat::Json => Ok(to_json(papers, compact)?),
+        OutputFormat::Bibtex => Ok(format_papers_as_bibtex(papers)),
+        OutputFormat::Ris => Ok(to_ris(papers)),
+        OutputFormat::Csv => format_papers_as_csv(papers),
+        OutputFormat::Apa => Ok(format_citations(papers, CitationStyle::Apa)),
+        OutputFormat::Mla => Ok(format_citations(papers, CitationStyle::Mla)),
+    }
+}
+
+// Write the papers to `path` with the same formatter as stdout, returning the bytes written
+fn export_papers(
+    papers: &[Paper],
+    path: &Path,
+    format: Option<OutputFormat>,
+    table: &TableConfig,
+    compact: bool,
+    force: bool,
+) -> Result<usize, anyhow::Error> {
+    let Some(format) = format.or_else(|| OutputFormat::from_extension(path)) else {
+        anyhow::bail!(
+            "Cannot tell the format of {} from its extension, use .json, .md, .csv, .bib, \
+             .ris or .txt, or pass --output-format",
+            path.display()
+        );
+    };
+    let mut contents = format_papers(papers, format, table, compact)?;
+    contents.push('\n');
+
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Could not create {}", parent.display()))?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        // Fails if the file is there, without a window for another process to create it
+        options.create_new(true);
+    }
+    let mut file = options.open(path).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => anyhow::anyhow!(
+            "{} already exists, pass --force to overwrite it",
+            path.display()
+        ),
+        _ => anyhow::Error::new(e).context(format!("Could not write {}", path.display())),
+    })?;
+    file.write_all(contents.as_bytes())
+        .with_context(|| format!("Could not write {}", path.display()))?;
+    Ok(contents.len())
+}
+
+fn run_history(
+    action: &HistoryAction,
+    format: OutputFormat,
+    table: &TableConfig,
+    compact: bool,
+) -> Result<()> {
+    let path = History::default_path();
+    let mut history = History::load(&path)?;
+    match action {
+        HistoryAction::List { limit } => {
+            for (i, entry) in history.recent().take(*limit).enumerate() {
+                println!(
+                    "{:>3}. {}  {} ({} papers)",
+                    i + 1,
+                    httpdate::fmt_http_date(entry.searched_at()),
+                    entry.query,
+                    entry.papers.len()
+                );
+            }
+        }
+        HistoryAction::Show { n } => {
+            let Some(entry) = n.checked_sub(1).and_then(|i| history.recent().nth(i)) else {
+                anyhow::bail!("There is no search number {} in the history", n);
+            };
+            if matches!(format, OutputFormat::Table | OutputFormat::Markdown) {
+                println!("Searched for: {}", entry.query);
+            }
+            println!("{}", format_papers(&entry.papers, format, table, compact)?);
+        }
+        HistoryAction::Clear => {
+            history.clear();
+            history.save(&path)?;
+            eprintln!("Cleared the search history in {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+// Print a paper's text, or with --format json the sections it has as well
+async fn run_fulltext(id: &str, section: Option<&str>, format: OutputFormat) -> Result<()> {
+    let ar5iv = fulltext::Ar5ivClient::default();
+    let Some(text) = or_interrupted(ar5iv.fetch(id, section, usize::MAX)).await else {
+        exit_interrupted("ar5iv had not answered yet, no text to show");
+    };
+    let text = text?;
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&text)?),
+        _ => println!("{}", text.text),
+    }
+    Ok(())
+}
+
+// Papers written by --output as JSON, for working without arXiv
+fn load_papers(path: &Path) -> Result<Vec<Paper>, anyhow::Error> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read {}", path.display()))?;
+    // serde names the missing or mistyped field, along with where it is in the file
+    serde_json::from_str(&contents).with_context(|| {
+        format!(
+            "{} is not a list of papers as written by --output with a .json file",
+            path.display()
+        )
+    })
+}
+
+// Fill in what another service knows about the papers
+async fn enrich_papers(papers: &mut [Paper], enrichment: Enrichment) -> Result<()> {
+    match enrichment {
+        Enrichment::Citations => citations::enrich_papers(papers).await?,
+        Enrichment::Crossref => {
+            crossref::CrossrefClient::default()
+                .enrich_papers(papers)
+                .await?
+        }
+    }
+    Ok(())
+}
+
+// Download failures are reported per paper without aborting the rest
+async fn download_pdfs(papers: &[Paper], dir: &Path) {
+    for paper in papers {
+        let url = paper.url.replacen("/abs/", "/pdf/", 1);
+        match tools::download_pdf(&url, dir, tools::DEFAULT_MAX_PDF_BYTES, false).await {
+            Ok(pdf) => eprintln!("Downloaded {} ({} bytes)", pdf.path.display(), pdf.bytes),
+            Err(e) => eprintln!("Could not download {}: {}", paper.url, e),
+        }
+    }
+}
+
+// Await `future` unless Ctrl-C comes first, in which case it is dropped along with any
+// request still in flight
+async fn or_interrupted<F: Future>(future: F) -> Option<F::Output> {
+    or_cancelled(future, tokio::signal::ctrl_c()).await
+}
+
+async fn or_cancelled<F: Future, C: Future>(future: F, cancel: C) -> Option<F::Output> {
+    tokio::select! {
+        output = future => Some(output),
+        _ = cancel => None,
+    }
+}
+
+fn exit_interrupted(message: &str) -> ! {
+    eprintln!("Interrupted: {}", message);
+    // The conventional exit status for SIGINT
+    std::process::exit(130)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    // Initialize OpenAI client
-    let openai_client = openai::Client::from_env();
-
-    // Create agent with arxiv search tool
-    let paper_agent = openai_client
-        .agent(GPT_4)
-        .preamble(
-            "You are a helpful research assistant that can search and analyze academic papers from arXiv. \
-             When asked about a research topic, use the search_arxiv tool to find relevant papers and \
-             return only the raw JSON response from the tool."
+    // Logs go to stderr so they never mix with the results; RUST_LOG=debug traces arXiv calls
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")),
         )
-        .tool(ArxivSearchTool)
-        .build();
+        .with_writer(std::io::stderr)
+        .init();
+
+    let mut args = Args::parse();
+    args.defaults = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::load_default()?,
+    };
+    if args.show_config {
+        print!("{}", toml::to_string(&args.effective_config())?);
+        return Ok(());
+    }
+    let params = args.model_params().validate()?;
+
+    let table = TableConfig::for_terminal()
+        .with_color(use_color(args.color()))
+        .with_ascii_only(args.ascii_only())
+        .with_group_by(args.group_by());
+
+    if let Some(Command::History { action }) = &args.command {
+        return run_history(action, args.format(), &table, args.compact);
+    }
+    if let Some(Command::Fulltext { id, section }) = &args.command {
+        return run_fulltext(id, section.as_deref(), args.format()).await;
+    }
+
+    let saved = args.input.as_deref().map(load_papers).transpose()?;
+
+    let mut synthesis = None;
+    let mut summary = if let (true, Some(saved)) = (args.direct(), &saved) {
+        let mut papers = tools::match_saved_papers(saved, args.query());
+        if let Some(max_results) = args.max_results() {
+            papers.truncate(tools::clamp_max_results(Some(max_results)) as usize);
+        }
+        SearchSummary {
+            papers,
+            commentary: None,
+            related_to: None,
+        }
+    } else if args.direct() {
+        // Search arXiv directly, no LLM or API key involved
+        let search = tools::search_arxiv(SearchArgs {
+            query: args.query().to_string(),
+            max_results: args.max_results(),
+            ..Default::default()
+        });
+        let Some(papers) = or_interrupted(search).await else {
+            exit_interrupted("arXiv had not answered yet, no results to show");
+        };
+        let papers = papers?;
+        SearchSummary {
+            papers,
+            commentary: None,
+            related_to: None,
+        }
+    } else {
+        let client = ProviderClient::from_env(args.provider(), params)?;
+        let model = args.model();
+        let preamble = agent::render_preamble(
+            &agent::preamble()?,
+            tools::clamp_max_results(args.max_results()),
+        )?;
+        let paper_agent = match saved {
+            Some(papers) => client.build_offline_agent(&model, &preamble, papers),
+            None => client.build_agent(&model, &preamble),
+        };
+
+        if args.chat {
+            return chat::run(&paper_agent, client.embedding_model(), &table).await;
+        }
+
+        let prompt = args.prompt();
+        let answer = or_interrupted(async {
+            let response = paper_agent
+                .prompt(&prompt)
+                .instrument(tracing::info_span!("agent_prompt", %model))
+                .await?;
+            Ok::<_, anyhow::Error>(
+                agent::interpret_response(&client, &model, &paper_agent, &prompt, response).await,
+            )
+        })
+        .await;
+        let Some(answer) = answer else {
+            exit_interrupted("the agent had not finished, no results to show");
+        };
+        let answer = answer?;
+
+        let summary = match answer {
+            AgentAnswer::Papers(summary) => summary,
+            AgentAnswer::Comparison(comparison) => {
+                match args.format() {
+                    OutputFormat::Json => {
+                        println!("{}", to_json(&comparison, args.compact)?)
+                    }
+                    _ => println!("{}", format_comparison(&comparison)),
+                }
+                return Ok(());
+            }
+            AgentAnswer::Prose(text) => {
+                eprintln!("Warning: the agent did not return search results, showing its answer");
+                println!("{}", text);
+                return Ok(());
+            }
+        };
+
+        if let Some(embedder) = client.embedding_model() {
+            library::remember(&embedder, &summary.papers).await;
+        }
+
+        if args.wants_synthesis() && !summary.papers.is_empty() {
+            match or_interrupted(agent::summarize_papers(&client, &model, &summary.papers)).await {
+                Some(Ok(result)) => synthesis = Some(result),
+                Some(Err(e)) => eprintln!("Warning: could not synthesize the papers: {}", e),
+                // The papers are already found, show them without the synthesis
+                None => eprintln!("Interrupted: skipping the synthesis"),
+            }
+        }
+
+        summary
+    };
+
+    // Sorting by citations needs the counts, which the agent's papers only have if it looked
+    // them up itself
+    let mut enrichments = args.enrich.clone();
+    if matches!(args.sort(), Some(tools::SortKey::Citations))
+        && !enrichments.contains(&Enrichment::Citations)
+    {
+        enrichments.push(Enrichment::Citations);
+    }
+    for enrichment in enrichments {
+        match or_interrupted(enrich_papers(&mut summary.papers, enrichment)).await {
+            Some(Ok(())) => (),
+            Some(Err(e)) => eprintln!(
+                "Warning: could not enrich the papers from {}: {}",
+                enrichment.service(),
+                e
+            ),
+            None => {
+                eprintln!("Interrupted: showing the papers without the rest of the enrichment");
+                break;
+            }
+        }
+    }
 
-    // Example usage
-    let response = paper_agent
-        .prompt("Find recent papers about large language models and summarize them")
-        .await?;
+    if let Some(sort) = args.sort() {
+        tools::sort_papers(&mut summary.papers, sort);
+    }
+
+    // Going over a saved set again isn't a search worth remembering
+    if args.input.is_none() {
+        let mut entry =
+            HistoryEntry::new(args.query(), args.max_results(), args.sort(), args.no_agent);
+        entry.papers = summary.papers.clone();
+        history::record(entry);
+    }
+
+    // Format and print the papers
+    if let (OutputFormat::Table | OutputFormat::Markdown, Some(seed)) =
+        (args.format(), &summary.related_to)
+    {
+        println!("Related to: {}", seed);
+    }
+    println!(
+        "{}",
+        format_papers(&summary.papers, args.format(), &table, args.compact)?
+    );
+    if let (OutputFormat::Table, Some(commentary)) = (args.format(), &summary.commentary) {
+        println!("{}", commentary);
+    }
+    if let Some(synthesis) = &synthesis {
+        println!("{}", format_synthesis(synthesis, args.format()));
+    }
+
+    if let Some(path) = &args.output {
+        // Files never get ANSI escapes, and aren't sized to whatever terminal wrote them
+        let file_table = TableConfig::for_width(PIPED_WIDTH)
+            .with_ascii_only(args.ascii_only())
+            .with_group_by(args.group_by());
+        let bytes = export_papers(
+            &summary.papers,
+            path,
+            args.output_format,
+            &file_table,
+            args.compact,
+            args.force,
+        )?;
+        eprintln!("Wrote {} ({} bytes)", path.display(), bytes);
+    }
 
-    // Format and print the table
-    match format_papers_as_table(&response) {
-        Ok(formatted_table) => println!("{}", formatted_table),
-        Err(e) => println!("Error formatting table: {}", e),
+    if args.download {
+        let dir = args.download_dir();
+        if or_interrupted(download_pdfs(&summary.papers, &dir)).await.is_none() {
+            exit_interrupted("stopped downloading PDFs");
+        }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown_escapes_pipes_and_lists_abstracts_under_their_own_section() {
+        let paper = Paper {
+            authors: vec!["Ada Lovelace".to_string(), "Charles Babbage".to_string()],
+            abstract_text: "An abstract.".to_string(),
+            categories: vec!["cs.LG".to_string(), "stat.ML".to_string()],
+            ..Paper::fixture("Either | Or")
+        };
+        let markdown = format_papers_as_markdown(&[paper]);
+        assert!(
+            markdown.contains(
+                "| [Either \\| Or](http://arxiv.org/abs/2401.00001v1) \
+                 | Ada Lovelace, Charles Babbage | cs.LG, stat.ML |\n"
+            ),
+            "{}",
+            markdown
+        );
+        assert!(markdown.contains("\n## Abstracts\n\n### Either \\| Or\n\n**Authors:** "));
+        assert!(markdown.ends_with("> An abstract.\n"));
+    }
+
+    #[test]
+    fn markdown_lists_every_paper_in_the_table_then_under_abstracts() {
+        let papers = [
+            Paper {
+                authors: vec![
+                    "Ashish Vaswani".to_string(),
+                    "Noam Shazeer".to_string(),
+                    "Niki Parmar".to_string(),
+                ],
+                abstract_text: "The dominant sequence transduction models\n  are recurrent."
+                    .to_string(),
+                url: "http://arxiv.org/abs/1706.03762v7".to_string(),
+                categories: vec!["cs.CL".to_string(), "cs.LG".to_string()],
+                ..Paper::fixture("Attention Is All You Need")
+            },
+            Paper {
+                authors: vec!["Ada Lovelace".to_string()],
+                abstract_text: "Tables | code.".to_string(),
+                categories: vec!["cs.SE".to_string()],
+                ..Paper::fixture("Escaping `code` | pipes")
+            },
+        ];
+        assert_eq!(
+            format_papers_as_markdown(&papers),
+            "| Title | Authors | Categories |\n\
+             | --- | --- | --- |\n\
+             | [Attention Is All You Need](http://arxiv.org/abs/1706.03762v7) \
+             | Ashish Vaswani et al. | cs.CL, cs.LG |\n\
+             | [Escaping \\`code\\` \\| pipes](http://arxiv.org/abs/2401.00001v1) \
+             | Ada Lovelace | cs.SE |\n\
+             \n\
+             ## Abstracts\n\
+             \n\
+             ### Attention Is All You Need\n\
+             \n\
+             **Authors:** Ashish Vaswani, Noam Shazeer, Niki Parmar\n\
+             \n\
+             **Categories:** cs.CL, cs.LG\n\
+             \n\
+             **URL:** http://arxiv.org/abs/1706.03762v7\n\
+             \n\
+             > The dominant sequence transduction models\n\
+             > are recurrent.\n\
+             \n\
+             ### Escaping \\`code\\` \\| pipes\n\
+             \n\
+             **Authors:** Ada Lovelace\n\
+             \n\
+             **Categories:** cs.SE\n\
+             \n\
+             **URL:** http://arxiv.org/abs/2401.00001v1\n\
+             \n\
+             > Tables | code.\n"
+        );
+    }
+
+    #[test]
+    fn csv_round_trips_abstracts_with_commas_quotes_and_newlines() {
+        let abstract_text = "We show that \"attention\", alone,\nsuffices.\r\nMostly.";
+        let paper = Paper {
+            authors: vec!["Ada Lovelace".to_string(), "Charles Babbage".to_string()],
+            abstract_text: abstract_text.to_string(),
+            categories: vec!["cs.LG".to_string(), "stat.ML".to_string()],
+            primary_category: Some("cs.LG".to_string()),
+            published: Some("2024-01-02T00:00:00Z".to_string()),
+            doi: Some("10.1000/182".to_string()),
+            ..Paper::fixture("Commas, \"quotes\"")
+        };
+
+        let csv = format_papers_as_csv(&[paper]).unwrap();
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        assert_eq!(
+            reader.headers().unwrap(),
+            vec![
+                "title",
+                "authors",
+                "primary_category",
+                "categories",
+                "published",
+                "url",
+                "doi",
+                "abstract"
+            ]
+        );
+        let records: Vec<_> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0],
+            vec![
+                "Commas, \"quotes\"",
+                "Ada Lovelace;Charles Babbage",
+                "cs.LG",
+                "cs.LG;stat.ML",
+                "2024-01-02T00:00:00Z",
+                "http://arxiv.org/abs/2401.00001v1",
+                "10.1000/182",
+                abstract_text
+            ]
+        );
+    }
+
+    #[test]
+    fn ris_types_published_papers_as_journal_articles() {
+        let preprint = Paper {
+            authors: vec!["Ada Lovelace".to_string(), "Charles Babbage".to_string()],
+            abstract_text: "Line one\nline two.".to_string(),
+            categories: vec!["cs.LG".to_string(), "stat.ML".to_string()],
+            published: Some("2024-01-02T00:00:00Z".to_string()),
+            ..Paper::fixture("A  preprint")
+        };
+        let published = Paper {
+            doi: Some("10.1000/182".to_string()),
+            ..Paper::fixture("Published")
+        };
+        let crossref = Paper {
+            journal: Some("Physics Letters B".to_string()),
+            ..Paper::fixture("In a journal")
+        };
+
+        let ris = to_ris(&[preprint, published, crossref]);
+        let records: Vec<_> = ris.split_inclusive("ER  - \r\n").collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[0],
+            "TY  - GEN\r\n\
+             TI  - A preprint\r\n\
+             AU  - Ada Lovelace\r\n\
+             AU  - Charles Babbage\r\n\
+             AB  - Line one line two.\r\n\
+             UR  - http://arxiv.org/abs/2401.00001v1\r\n\
+             PY  - 2024\r\n\
+             KW  - cs.LG\r\n\
+             KW  - stat.ML\r\n\
+             ER  - \r\n"
+        );
+        assert!(records[1].starts_with("TY  - JOUR\r\n"));
+        assert!(records[1].contains("DO  - 10.1000/182\r\n"));
+        assert!(records[2].starts_with("TY  - JOUR\r\n"));
+    }
+
+    #[test]
+    fn ascii_only_tables_keep_to_ascii() {
+        assert_eq!(
+            to_ascii("Gödel’s “Incompleteness” — revisited…\u{a0}again"),
+            "G?del's \"Incompleteness\" - revisited... again"
+        );
+
+        let papers = [Paper {
+            abstract_text: "Proofs about ∞ and the “halting” problem.".to_string(),
+            ..Paper::fixture(r"Gödel’s theorems for $\alpha$-models")
+        }];
+        let config = TableConfig::default().with_ascii_only(true);
+        let table = format_papers_as_table(&papers, &config).unwrap();
+        assert!(table.is_ascii(), "{}", table);
+        // LaTeX is left as its source rather than rendered to symbols ASCII can't show
+        assert!(table.contains(r"G?del's theorems for $\alpha$-models"));
+        assert!(table.contains("Proofs about ? and the \"halting\" problem."));
+
+        let table = format_papers_as_table(&papers, &TableConfig::default()).unwrap();
+        assert!(table.contains("Gödel’s theorems for α-models"));
+    }
+
+    #[test]
+    fn wraps_on_words_and_splits_words_longer_than_a_line() {
+        assert_eq!(
+            wrap("Attention is all you need", 10),
+            ["Attention", "is all you", "need"]
+        );
+        assert_eq!(
+            wrap("Supercalifragilistic x", 8),
+            ["Supercal", "ifragili", "stic x"]
+        );
+        // Widths count characters, not bytes
+        assert_eq!(wrap("αβγδε ζ", 3), ["αβγ", "δε", "ζ"]);
+        assert_eq!(wrap("", 5), [""]);
+    }
+
+    #[test]
+    fn tables_fit_the_width_they_are_given() {
+        let papers = [Paper {
+            authors: vec!["Ada Lovelace".to_string(), "Charles Babbage".to_string()],
+            abstract_text: "An abstract.".to_string(),
+            categories: vec!["cs.LG".to_string(), "stat.ML".to_string()],
+            ..Paper::fixture(
+                "A remarkably long title about scaling laws for neural language models, \
+                 trained on ever larger datasets",
+            )
+        }];
+        for width in [72, 80, 120, 200] {
+            let table = format_papers_as_table(&papers, &TableConfig::for_width(width)).unwrap();
+            // Abstracts are printed whole, below the table
+            let rows = table.split(" Abstracts ").next().unwrap();
+            for line in rows.lines() {
+                assert!(line.chars().count() <= width, "{} wide: {:?}", width, line);
+            }
+            assert!(rows.contains("Title"), "{}", table);
+            assert!(rows.contains("datasets"), "{}", table);
+        }
+
+        // Too narrow for columns, so papers are listed one under another
+        let stacked = format_papers_as_table(&papers, &TableConfig::for_width(60)).unwrap();
+        assert!(!stacked.contains(" | "), "{}", stacked);
+        assert!(stacked.contains("\n1. A remarkably long title"));
+    }
+
+    // The text without its ANSI escapes
+    fn strip_ansi(styled: &str) -> String {
+        let mut plain = String::new();
+        let mut rest = styled;
+        while let Some(start) = rest.find("\x1b[") {
+            plain.push_str(&rest[..start]);
+            let end = rest[start..].find('m').expect("escape ends in m");
+            rest = &rest[start + end + 1..];
+        }
+        plain.push_str(rest);
+        plain
+    }
+
+    #[test]
+    fn colors_change_nothing_but_the_escapes() {
+        assert!(!use_color(ColorChoice::Never));
+        assert!(use_color(ColorChoice::Always));
+
+        let mut papers = vec![
+            Paper::fixture("Attention is all you need"),
+            Paper::fixture("Scaling laws for neural language models"),
+        ];
+        papers[1].similarity = Some(0.5);
+        for width in [60, 120] {
+            let config = TableConfig::for_width(width);
+            let plain = format_papers_as_table(&papers, &config).unwrap();
+            assert!(!plain.contains('\x1b'), "{}", plain);
+
+            let styled = format_papers_as_table(&papers, &config.with_color(true)).unwrap();
+            assert!(styled.contains('\x1b'));
+            assert_eq!(strip_ansi(&styled), plain);
+        }
+    }
+
+    #[test]
+    fn exports_in_the_format_the_extension_names_without_clobbering() {
+        let dir = std::env::temp_dir().join(format!("arxiv-agent-export-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let papers = [Paper::fixture("Attention is all you need")];
+        let table = TableConfig::default();
+        let path = dir.join("nested/papers.json");
+
+        let written = export_papers(&papers, &path, None, &table, false, false).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written, contents.len());
+        let exported: Vec<Paper> = serde_json::from_str(&contents).unwrap();
+        assert_eq!(exported[0].title, "Attention is all you need");
+
+        let refused = export_papers(&papers, &path, None, &table, false, false).unwrap_err();
+        assert!(
+            refused.to_string().contains("already exists"),
+            "{}",
+            refused
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), contents);
+
+        // --output-format wins over the extension, and --force overwrites
+        export_papers(&papers, &path, Some(OutputFormat::Csv), &table, false, true).unwrap();
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("title,"));
+
+        let unknown = dir.join("papers.xyz");
+        let refused = export_papers(&papers, &unknown, None, &table, false, false).unwrap_err();
+        assert!(
+            refused.to_string().contains("Cannot tell the format"),
+            "{}",
+            refused
+        );
+        assert!(!unknown.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parses_the_query_result_count_and_format() {
+        use clap::error::ErrorKind;
+
+        let args = Args::try_parse_from([
+            "arxiv-agent",
+            "diffusion models",
+            "--max-results",
+            "10",
+            "--format",
+            "bibtex",
+        ])
+        .unwrap();
+        assert_eq!(args.query(), "diffusion models");
+        assert_eq!(args.max_results(), Some(10));
+        assert!(matches!(args.format(), OutputFormat::Bibtex));
+        assert_eq!(
+            args.prompt(),
+            "Find recent papers about diffusion models and summarize them \
+             (return at most 10 papers)"
+        );
+
+        let args =
+            Args::try_parse_from(["arxiv-agent", "--prompt", "What is new in RLHF?"]).unwrap();
+        assert_eq!(args.prompt(), "What is new in RLHF?");
+        assert_eq!(args.max_results(), None);
+        assert!(matches!(args.format(), OutputFormat::Table));
+
+        let rejected = |argv: &[&str]| {
+            Args::try_parse_from(std::iter::once("arxiv-agent").chain(argv.iter().copied()))
+                .err()
+                .map(|e| e.kind())
+        };
+        assert_eq!(
+            rejected(&["attention", "--max-results", "ten"]),
+            Some(ErrorKind::ValueValidation)
+        );
+        assert_eq!(
+            rejected(&["attention", "--format", "yaml"]),
+            Some(ErrorKind::InvalidValue)
+        );
+        assert_eq!(rejected(&[]), Some(ErrorKind::MissingRequiredArgument));
+        assert_eq!(
+            rejected(&["attention", "--prompt", "What is new?"]),
+            Some(ErrorKind::ArgumentConflict)
+        );
+    }
+
+    // The only test touching MODEL_NAME and OPENAI_MODEL, the others never ask for the model
+    #[test]
+    fn picks_the_model_from_the_flag_then_the_environment_then_the_default() {
+        let parse = |argv: &[&str]| {
+            Args::try_parse_from(std::iter::once("arxiv-agent").chain(argv.iter().copied()))
+                .unwrap()
+                .model()
+        };
+        std::env::remove_var("MODEL_NAME");
+        std::env::remove_var("OPENAI_MODEL");
+        assert_eq!(parse(&["attention"]), Provider::Openai.default_model());
+        assert_eq!(
+            parse(&["attention", "--provider", "anthropic"]),
+            Provider::Anthropic.default_model()
+        );
+
+        std::env::set_var("OPENAI_MODEL", "gpt-4o-mini");
+        assert_eq!(parse(&["attention"]), "gpt-4o-mini");
+        assert_eq!(parse(&["attention", "--model", "gpt-4o"]), "gpt-4o");
+        // OPENAI_MODEL names an OpenAI model, other providers keep their own default
+        assert_eq!(
+            parse(&["attention", "--provider", "ollama"]),
+            Provider::Ollama.default_model()
+        );
+
+        std::env::set_var("MODEL_NAME", "o1-mini");
+        let model = parse(&["attention"]);
+        std::env::remove_var("MODEL_NAME");
+        std::env::remove_var("OPENAI_MODEL");
+        assert_eq!(model, "o1-mini");
+    }
+
+    #[test]
+    fn flags_win_over_the_config_file_which_wins_over_defaults() {
+        let path =
+            std::env::temp_dir().join(format!("arxiv-agent-config-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "format = \"markdown\"\nmax_results = 10\ntemperature = 0.5\ncolor = \"never\"\n",
+        )
+        .unwrap();
+        let defaults = Config::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut args = Args::try_parse_from([
+            "arxiv-agent",
+            "attention",
+            "--format",
+            "json",
+            "--top-p",
+            "0.9",
+        ])
+        .unwrap();
+        args.defaults = defaults;
+        assert!(matches!(args.format(), OutputFormat::Json));
+        assert_eq!(args.max_results(), Some(10));
+        assert!(matches!(args.color(), ColorChoice::Never));
+        let params = args.model_params();
+        assert_eq!(params.temperature, 0.5);
+        assert_eq!(params.top_p, Some(0.9));
+        assert_eq!(params.max_tokens, None);
+
+        // Without a config file the built-in defaults apply
+        args.defaults = Config::default();
+        assert_eq!(args.max_results(), None);
+        assert!(matches!(args.color(), ColorChoice::Auto));
+        assert_eq!(
+            args.model_params().temperature,
+            ModelParams::default().temperature
+        );
+    }
+
+    #[test]
+    fn json_is_indented_unless_compact() {
+        let papers = [Paper::fixture("Attention is all you need")];
+        let table = TableConfig::default();
+        let pretty = format_papers(&papers, OutputFormat::Json, &table, false).unwrap();
+        let compact = format_papers(&papers, OutputFormat::Json, &table, true).unwrap();
+
+        assert!(pretty.starts_with("[\n  {\n    \"title\": "), "{}", pretty);
+        assert!(!compact.contains('\n'), "{}", compact);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&compact).unwrap(),
+            serde_json::from_str::<serde_json::Value>(&pretty).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_a_future_as_soon_as_it_is_cancelled() {
+        let cancelled = tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            or_cancelled(std::future::pending::<()>(), async {}),
+        )
+        .await;
+        assert_eq!(cancelled, Ok(None));
+
+        let finished = or_cancelled(async { 3 }, std::future::pending::<()>()).await;
+        assert_eq!(finished, Some(3));
+    }
+}
@@ -0,0 +1,3171 @@
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use rig::{
+    completion::ToolDefinition,
+    embeddings::{EmbeddingError, EmbeddingModel},
+    extractor::ExtractionError,
+    providers::openai,
+    tool::Tool,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    borrow::Cow,
+    cmp::{Ordering, Reverse},
+    collections::{HashMap, HashSet},
+    future::Future,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    pin::Pin,
+    str,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::io::AsyncWriteExt;
+
+use crate::agent::ProviderClient;
+use crate::categories;
+use crate::citations;
+
+const ARXIV_URL: &str = "http://export.arxiv.org/api/query";
+const ARXIV_PDF_URL: &str = "https://arxiv.org/pdf";
+const PDF_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_DOWNLOAD_DIR: &str = "papers";
+pub const DEFAULT_MAX_PDF_BYTES: u64 = 30 * 1024 * 1024;
+const DEFAULT_USER_AGENT: &str = concat!(
+    "rig-arxiv-agent-example/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/0xPlaygrounds/rig-arxiv-agent-example)"
+);
+
+const COMPARE_PREAMBLE: &str = "Compare the two papers from their abstracts: the topic they share, \
+                                how their methods differ, and which datasets or benchmarks each \
+                                one mentions that the other does not.";
+
+// Struct to hold paper metadata
+#[derive(Debug, Clone, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct Paper {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub abstract_text: String,
+    pub url: String,
+    pub categories: Vec<String>,
+    // arXiv's <arxiv:primary_category>, which need not be the first of `categories`
+    #[serde(default)]
+    pub primary_category: Option<String>,
+    #[serde(default)]
+    pub published: Option<String>,
+    // Only present once the preprint has a published version
+    #[serde(default)]
+    pub doi: Option<String>,
+    // Cosine similarity to the query, set when results were re-ranked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub similarity: Option<f32>,
+    // How well the query terms match the title and abstract, set when results were ranked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f32>,
+    // The queries that found this paper, set by multi_search
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub found_by: Vec<String>,
+    // Canonical arXiv id without its version, e.g. 2310.06825 or hep-th/9901001
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arxiv_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+    // Older versions that came back in the same results and were collapsed into this one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub superseded_versions: Vec<u32>,
+    // From Semantic Scholar, set once the paper was enriched and it knew the paper
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub citation_count: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub influential_citation_count: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub semantic_scholar_url: Option<String>,
+    // From Crossref, set once a paper with a DOI was enriched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub journal: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publisher: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published_print: Option<String>,
+}
+
+// Reference-list styles a paper can be cited in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CitationStyle {
+    Apa,
+    Mla,
+}
+
+// APA 7 lists up to 20 authors, past that the first 19, an ellipsis and the last
+const APA_MAX_AUTHORS: usize = 20;
+
+// Words that make an author a group, cited as written rather than as surname and initials
+const GROUP_AUTHOR_WORDS: &[&str] = &["collaboration", "consortium", "team", "group", "project"];
+
+// MLA abbreviates every month but May, June and July
+const MLA_MONTHS: [&str; 12] = [
+    "Jan.", "Feb.", "Mar.", "Apr.", "May", "June", "July", "Aug.", "Sept.", "Oct.", "Nov.", "Dec.",
+];
+
+impl Paper {
+    // A reference-list entry citing the arXiv preprint, e.g. for pasting into a bibliography
+    pub fn to_citation(&self, style: CitationStyle) -> String {
+        let title = collapse_whitespace(&normalize_latex(&self.title));
+        let id = match &self.arxiv_id {
+            Some(id) => id.clone(),
+            None => base_arxiv_id(&self.url).to_string(),
+        };
+        let authors: Vec<&str> = self.authors.iter().map(String::as_str).collect();
+        match style {
+            CitationStyle::Apa => apa_citation(&authors, self.published.as_deref(), &title, &id),
+            CitationStyle::Mla => mla_citation(&authors, self.published.as_deref(), &title, &id),
+        }
+    }
+}
+
+// The paper tests start from, filling in only the fields they look at
+#[cfg(test)]
+impl Paper {
+    pub fn fixture(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            authors: Vec::new(),
+            abstract_text: String::new(),
+            url: "http://arxiv.org/abs/2401.00001v1".to_string(),
+            categories: Vec::new(),
+            primary_category: None,
+            published: None,
+            doi: None,
+            similarity: None,
+            score: None,
+            found_by: Vec::new(),
+            arxiv_id: None,
+            version: None,
+            superseded_versions: Vec::new(),
+            citation_count: None,
+            influential_citation_count: None,
+            semantic_scholar_url: None,
+            journal: None,
+            publisher: None,
+            published_print: None,
+        }
+    }
+}
+
+// Embeds text as how often each of its words appears, so tests can tell which papers are
+// close to a query without an embedding API
+#[cfg(test)]
+#[derive(Clone)]
+pub struct WordCountEmbedder(pub &'static [&'static str]);
+
+#[cfg(test)]
+impl EmbeddingModel for WordCountEmbedder {
+    const MAX_DOCUMENTS: usize = 2;
+
+    fn ndims(&self) -> usize {
+        self.0.len()
+    }
+
+    async fn embed_documents(
+        &self,
+        documents: Vec<String>,
+    ) -> Result<Vec<rig::embeddings::Embedding>, EmbeddingError> {
+        Ok(documents
+            .into_iter()
+            .map(|document| {
+                let lowercase = document.to_lowercase();
+                let words: Vec<&str> = lowercase.split(|c: char| !c.is_alphanumeric()).collect();
+                let vec = self
+                    .0
+                    .iter()
+                    .map(|word| words.iter().filter(|w| *w == word).count() as f64)
+                    .collect();
+                rig::embeddings::Embedding { document, vec }
+            })
+            .collect())
+    }
+}
+
+// Author. (Year). Title (arXiv:id). arXiv. https://doi.org/10.48550/arXiv.id
+fn apa_citation(authors: &[&str], published: Option<&str>, title: &str, id: &str) -> String {
+    let year = published.and_then(|date| date.get(..4)).unwrap_or("n.d.");
+    let source = format!("arXiv. https://doi.org/10.48550/arXiv.{}", id);
+    let names: Vec<String> = authors.iter().map(|name| apa_name(name)).collect();
+    let names = match names.len() {
+        // Without authors the title takes their place
+        0 => return format!("{} (arXiv:{}). ({}). {}", title, id, year, source),
+        1 => names[0].clone(),
+        n if n <= APA_MAX_AUTHORS => format!("{}, & {}", names[..n - 1].join(", "), names[n - 1]),
+        n => format!(
+            "{}, . . . {}",
+            names[..APA_MAX_AUTHORS - 1].join(", "),
+            names[n - 1]
+        ),
+    };
+    format!(
+        "{} ({}). {} (arXiv:{}). {}",
+        with_period(&names),
+        year,
+        title,
+        id,
+        source
+    )
+}
+
+// Surname, I. I., keeping hyphenated given names hyphenated (J.-P.)
+fn apa_name(name: &str) -> String {
+    let Some((given, surname)) = split_name(name) else {
+        return collapse_whitespace(name);
+    };
+    let initials: Vec<String> = given
+        .split_whitespace()
+        .map(|word| {
+            word.split('-')
+                .filter_map(|part| part.chars().next())
+                .map(|initial| format!("{}.", initial))
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect();
+    format!("{}, {}", surname, initials.join(" "))
+}
+
+// Surname, Given, et al. "Title." arXiv, 12 June 2017, arXiv:id.
+fn mla_citation(authors: &[&str], published: Option<&str>, title: &str, id: &str) -> String {
+    let mut citation = match authors {
+        [] => String::new(),
+        [only] => format!("{} ", with_period(&mla_name(only))),
+        [first, second] => format!(
+            "{}, and {} ",
+            mla_name(first),
+            with_period(&collapse_whitespace(second))
+        ),
+        [first, ..] => format!("{}, et al. ", mla_name(first)),
+    };
+    citation.push_str(&format!("\"{}\" arXiv, ", with_period(title)));
+    if let Some(date) = published.and_then(mla_date) {
+        citation.push_str(&format!("{}, ", date));
+    }
+    citation.push_str(&format!("arXiv:{}.", id));
+    citation
+}
+
+// Surname, Given
+fn mla_name(name: &str) -> String {
+    match split_name(name) {
+        Some((given, surname)) => format!("{}, {}", surname, given),
+        None => collapse_whitespace(name),
+    }
+}
+
+// 2017-06-12T17:57:34Z as 12 June 2017
+fn mla_date(published: &str) -> Option<String> {
+    let mut parts = published.get(..10)?.split('-');
+    let year = parts.next()?;
+    let month: usize = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    let month = MLA_MONTHS.get(month.checked_sub(1)?)?;
+    Some(format!("{} {} {}", day, month, year))
+}
+
+// A person's given names and surname, with particles like "van der" kept in the surname.
+// None for one-word names and groups such as "The ATLAS Collaboration"
+fn split_name(name: &str) -> Option<(String, String)> {
+    let words: Vec<&str> = name.split_whitespace().collect();
+    let is_group = words
+        .iter()
+        .any(|word| GROUP_AUTHOR_WORDS.contains(&word.to_lowercase().as_str()));
+    if words.len() < 2 || is_group {
+        return None;
+    }
+    let mut start = words.len() - 1;
+    while start > 1 && words[start - 1].starts_with(char::is_lowercase) {
+        start -= 1;
+    }
+    Some((words[..start].join(" "), words[start..].join(" ")))
+}
+
+fn with_period(text: &str) -> String {
+    if text.ends_with(['.', '?', '!']) {
+        text.to_string()
+    } else {
+        format!("{}.", text)
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Typed answer extracted from the agent's response
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct SearchSummary {
+    pub papers: Vec<Paper>,
+    // Any prose the agent added around the results
+    pub commentary: Option<String>,
+    // Title of the paper these results were found to be related to
+    #[serde(default)]
+    pub related_to: Option<String>,
+}
+
+// Raw bytes rather than text, the parser decides what to do with invalid UTF-8
+type FetchFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<u8>, ArxivError>> + Send + 'a>>;
+
+// Where raw arXiv Atom feeds come from
+pub trait ArxivSource: Send + Sync {
+    // `search_query` uses the arXiv query syntax, e.g. "all:transformers" or "cat:cs.LG"
+    fn fetch<'a>(&'a self, search_query: &'a str, max_results: i32) -> FetchFuture<'a>;
+    fn fetch_by_id<'a>(&'a self, id: &'a str) -> FetchFuture<'a>;
+}
+
+// The arXiv HTTP API, or a mirror of it
+pub struct LiveSource {
+    base_url: String,
+}
+
+impl LiveSource {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+        }
+    }
+}
+
+impl Default for LiveSource {
+    fn default() -> Self {
+        Self::new(arxiv_base_url())
+    }
+}
+
+impl ArxivSource for LiveSource {
+    fn fetch<'a>(&'a self, search_query: &'a str, max_results: i32) -> FetchFuture<'a> {
+        Box::pin(async move {
+            // Asks for gzip or deflate and inflates the body itself, big feeds shrink a lot
+            let client = arxiv_client()?;
+
+            // URL encode the query
+            let encoded_query = urlencoding::encode(search_query);
+
+            // Construct arXiv API URL
+            let url = format!(
+                "{}?search_query={}&start=0&max_results={}",
+                self.base_url,
+                encoded_query,
+                max_results
+            );
+
+            let response = send_with_retry(client.get(&url)).await?;
+            tracing::debug!(status = %response.status(), %url, "arXiv responded");
+            // Client errors still carry an Atom feed explaining them, server errors don't
+            if response.status().is_server_error() {
+                response.error_for_status_ref()?;
+            }
+            Ok(response.bytes().await?.to_vec())
+        })
+    }
+
+    fn fetch_by_id<'a>(&'a self, id: &'a str) -> FetchFuture<'a> {
+        Box::pin(async move {
+            let url = format!(
+                "{}?id_list={}",
+                self.base_url,
+                urlencoding::encode(id.trim())
+            );
+            let response = send_with_retry(arxiv_client()?.get(&url)).await?;
+            tracing::debug!(status = %response.status(), %url, "arXiv responded");
+            // Client errors still carry an Atom feed explaining them, server errors don't
+            if response.status().is_server_error() {
+                response.error_for_status_ref()?;
+            }
+            Ok(response.bytes().await?.to_vec())
+        })
+    }
+}
+
+// A canned feed on disk, for demos and runs without network access
+pub struct FixtureSource {
+    path: PathBuf,
+}
+
+impl FixtureSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl ArxivSource for FixtureSource {
+    fn fetch<'a>(&'a self, _search_query: &'a str, _max_results: i32) -> FetchFuture<'a> {
+        Box::pin(async move { Ok(tokio::fs::read(&self.path).await?) })
+    }
+
+    fn fetch_by_id<'a>(&'a self, _id: &'a str) -> FetchFuture<'a> {
+        Box::pin(async move { Ok(tokio::fs::read(&self.path).await?) })
+    }
+}
+
+// Attempts per arXiv request while it keeps answering 429
+const MAX_ATTEMPTS: u32 = 3;
+
+// Wait after a 429 without Retry-After, doubled on each further attempt
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(3);
+
+// Longest Retry-After we wait out rather than failing the request
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+// Send an arXiv request, waiting as long as arXiv asks whenever it rate limits us
+async fn send_with_retry(
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::Response, ArxivError> {
+    let mut attempt = 1;
+    loop {
+        let response = request
+            .try_clone()
+            .expect("arXiv requests have no streaming body")
+            .send()
+            .await?;
+        if response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Ok(response);
+        }
+
+        let retry_after =
+            retry_after(response.headers()).unwrap_or(RATE_LIMIT_BACKOFF * 2u32.pow(attempt - 1));
+        if attempt >= MAX_ATTEMPTS || retry_after > MAX_RETRY_AFTER {
+            return Err(ArxivError::RateLimited { retry_after });
+        }
+        tracing::warn!(
+            ?retry_after,
+            attempt,
+            "arXiv rate limited the request, waiting"
+        );
+        tokio::time::sleep(retry_after).await;
+        attempt += 1;
+    }
+}
+
+// Retry-After in either of its forms, delay-seconds or an HTTP-date
+pub fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    // A date in the past means we may retry right away
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+// ARXIV_BASE_URL if set, e.g. to point at a mirror or a local mock server
+pub fn arxiv_base_url() -> String {
+    std::env::var("ARXIV_BASE_URL").unwrap_or_else(|_| ARXIV_URL.to_string())
+}
+
+// Use the fixture named by ARXIV_FIXTURE_PATH if set, the live API otherwise
+pub fn source_from_env() -> Box<dyn ArxivSource> {
+    match std::env::var("ARXIV_FIXTURE_PATH") {
+        Ok(path) => Box::new(FixtureSource::new(path)),
+        Err(_) => Box::new(LiveSource::default()),
+    }
+}
+
+// arXiv asks clients to say who they are, ARXIV_USER_AGENT should carry a contact address
+pub fn user_agent() -> String {
+    std::env::var("ARXIV_USER_AGENT")
+        .ok()
+        .filter(|agent| !agent.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_USER_AGENT.to_string())
+}
+
+fn arxiv_client() -> Result<reqwest::Client, reqwest::Error> {
+    reqwest::Client::builder().user_agent(user_agent()).build()
+}
+
+// How many candidates to fetch per returned paper when re-ranking
+const RERANK_OVERFETCH: i32 = 3;
+
+// Tool to search for papers
+pub struct ArxivSearchTool {
+    source: Box<dyn ArxivSource>,
+    // Only available for providers with an embeddings API
+    reranker: Option<SemanticReranker<openai::EmbeddingModel>>,
+}
+
+impl ArxivSearchTool {
+    pub fn new(source: Box<dyn ArxivSource>) -> Self {
+        Self {
+            source,
+            reranker: None,
+        }
+    }
+
+    pub fn with_embedder(mut self, embedder: openai::EmbeddingModel) -> Self {
+        self.reranker = Some(SemanticReranker::new(embedder));
+        self
+    }
+}
+
+impl Default for ArxivSearchTool {
+    fn default() -> Self {
+        Self::new(source_from_env())
+    }
+}
+
+#[derive(Default, Deserialize)]
+pub struct SearchArgs {
+    pub query: String,
+    pub max_results: Option<i32>,
+    // Re-rank by embedding similarity, at the cost of extra embedding calls
+    #[serde(default)]
+    pub rerank: bool,
+    // Sort by query term matches in the title and abstract, without any extra calls
+    #[serde(default)]
+    pub rank: bool,
+    // Codes, names or aliases of arXiv categories to restrict the search to
+    #[serde(default)]
+    pub categories: Vec<String>,
+    // Applied to the parsed results rather than the arXiv query, see passes_filters
+    #[serde(default)]
+    pub filter_categories: Vec<String>,
+    #[serde(default)]
+    pub filter_authors: Vec<String>,
+    // Re-sorts the parsed results, independent of arXiv's own order
+    #[serde(default)]
+    pub sort: Option<SortKey>,
+    // Only papers submitted this recently, e.g. 7d, 2w, 1m or 1y, see parse_since
+    #[serde(default)]
+    pub since: Option<String>,
+}
+
+// Orders for results already fetched, applied without another arXiv call
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    PublishedDesc,
+    PublishedAsc,
+    Title,
+    FirstAuthor,
+    Citations,
+}
+
+// Stable, so ties keep the order arXiv returned them in. Papers without a published date
+// go last whichever way dates are sorted
+pub fn sort_papers(papers: &mut [Paper], key: SortKey) {
+    match key {
+        SortKey::PublishedDesc => papers.sort_by(|a, b| by_published(a, b, true)),
+        SortKey::PublishedAsc => papers.sort_by(|a, b| by_published(a, b, false)),
+        SortKey::Title => papers.sort_by_cached_key(|paper| paper.title.trim().to_lowercase()),
+        // By surname, papers without authors last
+        SortKey::FirstAuthor => papers.sort_by_cached_key(|paper| {
+            let surname = paper
+                .authors
+                .first()
+                .and_then(|author| author.split_whitespace().last())
+                .map(fold_name);
+            (surname.is_none(), surname)
+        }),
+        // Most cited first, papers that were never enriched last
+        SortKey::Citations => papers.sort_by_key(|paper| {
+            (
+                paper.citation_count.is_none(),
+                Reverse(paper.citation_count),
+                Reverse(paper.influential_citation_count),
+            )
+        }),
+    }
+}
+
+fn by_published(a: &Paper, b: &Paper, newest_first: bool) -> Ordering {
+    // ISO 8601 dates compare correctly as strings
+    match (&a.published, &b.published) {
+        (Some(a), Some(b)) if newest_first => b.cmp(a),
+        (Some(a), Some(b)) => a.cmp(b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+// Where papers without a primary category are grouped
+pub const OTHER_CATEGORY: &str = "Other";
+
+// Papers sharing a primary category, in the order they were found
+#[derive(Debug)]
+pub struct CategoryGroup {
+    pub category: String,
+    pub papers: Vec<Paper>,
+}
+
+// Papers grouped by primary category, biggest group first and "Other" last. Groups of the
+// same size stay in the order their first paper was found
+pub fn group_by_primary_category(papers: &[Paper]) -> Vec<CategoryGroup> {
+    let mut groups: Vec<CategoryGroup> = Vec::new();
+    for paper in papers {
+        let category = paper.primary_category.as_deref().unwrap_or(OTHER_CATEGORY);
+        match groups.iter_mut().find(|group| group.category == category) {
+            Some(group) => group.papers.push(paper.clone()),
+            None => groups.push(CategoryGroup {
+                category: category.to_string(),
+                papers: vec![paper.clone()],
+            }),
+        }
+    }
+    groups.sort_by_key(|group| {
+        (
+            group.category == OTHER_CATEGORY,
+            Reverse(group.papers.len()),
+        )
+    });
+    groups
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArxivError {
+    #[error("Network error: {0}")]
+    Network(#[from] reqwest::Error),
+    #[error("XML parsing error: {0}")]
+    XmlParsing(#[from] quick_xml::Error),
+    #[error("No results found")]
+    NoResults,
+    #[error("UTF-8 decoding error: {0}")]
+    Utf8Error(#[from] std::str::Utf8Error),
+    #[error("No paper found for arXiv id {0:?}")]
+    PaperNotFound(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Embedding error: {0}")]
+    Embedding(#[from] EmbeddingError),
+    #[error("The agent did not return a JSON array of papers: {0:?}")]
+    AgentResponseNotJson(String),
+    #[error("arXiv is rate limiting requests, retry after {} seconds", retry_after.as_secs())]
+    RateLimited { retry_after: Duration },
+    #[error("arXiv returned an entry without a {0}")]
+    MalformedEntry(&'static str),
+    #[error("Invalid since {0:?}, expected a number and d, w, m or y, e.g. 7d")]
+    InvalidSince(String),
+}
+
+impl Tool for ArxivSearchTool {
+    const NAME: &'static str = "search_arxiv";
+    type Error = ArxivError;
+    type Args = SearchArgs;
+    type Output = Vec<Paper>;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: "search_arxiv".to_string(),
+            description: "Search for academic papers on arXiv".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": "Search query for papers"
+                    },
+                    "max_results": {
+                        "type": "integer",
+                        "description": "Maximum number of results to return, between 1 and 2000 (default: 5)"
+                    },
+                    "rerank": {
+                        "type": "boolean",
+                        "description": "Re-rank results by semantic similarity to the query (default: false)"
+                    },
+                    "rank": {
+                        "type": "boolean",
+                        "description": "Sort results by how often the query terms appear in their title and abstract (default: false)"
+                    },
+                    "categories": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "arXiv categories to search in, as codes (cs.LG) or names (machine learning)"
+                    },
+                    "since": {
+                        "type": "string",
+                        "description": "Only papers submitted this recently: a number and a unit, d for days, w for weeks, m for months or y for years (e.g. 7d for the last week)"
+                    },
+                    "filter_categories": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Keep only papers listed in one of these categories, as codes (cs.LG), names, or prefixes like cs.* for a whole archive. Applied to the results, not the query"
+                    },
+                    "filter_authors": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Keep only papers with an author whose name contains one of these, ignoring case and accents (\"erdos\" matches Erdős). Applied to the results, not the query"
+                    },
+                    "sort": {
+                        "type": "string",
+                        "enum": ["published_desc", "published_asc", "title", "first_author", "citations"],
+                        "description": "Re-sort the results: by published date (undated papers last), title, first author's surname, or citation count (most cited first)"
+                    }
+                },
+                "required": ["query"]
+            }),
+        }
+    }
+
+    #[tracing::instrument(
+        name = "search_arxiv",
+        skip_all,
+        fields(query = %args.query, max_results = ?args.max_results, rerank = args.rerank)
+    )]
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let started = Instant::now();
+        let result = search_papers(self.source.as_ref(), self.reranker.as_ref(), args).await;
+        match &result {
+            Ok(papers) => tracing::info!(
+                results = papers.len(),
+                elapsed_ms = started.elapsed().as_millis() as u64,
+                "search finished"
+            ),
+            Err(e) => tracing::warn!(
+                error = %e,
+                elapsed_ms = started.elapsed().as_millis() as u64,
+                "search failed"
+            ),
+        }
+        result
+    }
+}
+
+// Restrict an arXiv query to any of the given categories
+fn with_categories(search_query: String, categories: &[String]) -> String {
+    if categories.is_empty() {
+        return search_query;
+    }
+    let filters: Vec<String> = categories
+        .iter()
+        .map(|category| format!("cat:{}", categories::category_code(category)))
+        .collect();
+    format!("({}) AND ({})", search_query, filters.join(" OR "))
+}
+
+// Units a `since` value may use, and how many days each is. Months and years are
+// approximate, which is close enough for "papers from the last month"
+const SINCE_UNITS: &[(char, u64)] = &[('d', 1), ('w', 7), ('m', 30), ('y', 365)];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// How far back a relative age like 7d, 2w, 1m or 1y reaches
+pub fn parse_since(since: &str) -> Result<Duration, ArxivError> {
+    let invalid = || ArxivError::InvalidSince(since.to_string());
+    let since = since.trim();
+    let unit = since.chars().last().ok_or_else(invalid)?;
+    let days = SINCE_UNITS
+        .iter()
+        .find(|(known, _)| *known == unit.to_ascii_lowercase())
+        .map(|(_, days)| *days)
+        .ok_or_else(invalid)?;
+    let count: u64 = since[..since.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid())?;
+    if count == 0 {
+        return Err(invalid());
+    }
+    // Far enough back to overflow is a typo rather than a date arXiv could have papers from
+    let secs = count.checked_mul(days * 24 * 60 * 60).ok_or_else(invalid)?;
+    Ok(Duration::from_secs(secs))
+}
+
+// Restrict an arXiv query to papers submitted within `since` of now
+fn with_since(search_query: String, since: Option<&str>) -> Result<String, ArxivError> {
+    let Some(since) = since else {
+        return Ok(search_query);
+    };
+    let now = SystemTime::now();
+    // SystemTime reaches back before 1970, but submittedDate ranges and HTTP dates don't
+    let start = now
+        .checked_sub(parse_since(since)?)
+        .map_or(UNIX_EPOCH, |start| start.max(UNIX_EPOCH));
+    Ok(format!(
+        "({}) AND submittedDate:[{} TO {}]",
+        search_query,
+        arxiv_timestamp(start),
+        arxiv_timestamp(now)
+    ))
+}
+
+// YYYYMMDDHHMM in UTC, the form submittedDate ranges take
+fn arxiv_timestamp(time: SystemTime) -> String {
+    // e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+    let date = httpdate::fmt_http_date(time);
+    let month = MONTHS
+        .iter()
+        .position(|month| *month == &date[8..11])
+        .map_or(1, |i| i + 1);
+    format!(
+        "{}{:02}{}{}{}",
+        &date[12..16],
+        month,
+        &date[5..7],
+        &date[17..19],
+        &date[20..22]
+    )
+}
+
+// Looked further down the results once when post-filters leave nothing, this many times deeper
+const FILTER_OVERFETCH: i32 = 5;
+
+// Latin letters with diacritics and the plain letter they fold to, so "erdos" finds Erdős
+const DIACRITICS: &[(&str, char)] = &[
+    ("àáâãäåāăą", 'a'),
+    ("çćĉċč", 'c'),
+    ("ďđ", 'd'),
+    ("èéêëēĕėęě", 'e'),
+    ("ĝğġģ", 'g'),
+    ("ĥħ", 'h'),
+    ("ìíîïĩīĭįı", 'i'),
+    ("ĵ", 'j'),
+    ("ķ", 'k'),
+    ("ĺļľŀł", 'l'),
+    ("ñńņňŉ", 'n'),
+    ("òóôõöøōŏő", 'o'),
+    ("ŕŗř", 'r'),
+    ("śŝşšș", 's'),
+    ("ţťŧț", 't'),
+    ("ùúûüũūŭůűų", 'u'),
+    ("ŵ", 'w'),
+    ("ýÿŷ", 'y'),
+    ("źżž", 'z'),
+];
+
+// Lowercase with diacritics dropped, for matching names however they were typed
+fn fold_name(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| {
+            DIACRITICS
+                .iter()
+                .find(|(accented, _)| accented.contains(c))
+                .map_or(c, |&(_, plain)| plain)
+        })
+        .collect()
+}
+
+// A category filter is a code, name or alias, or a prefix ending in '*' like cs.*
+fn category_matches(filter: &str, category: &str) -> bool {
+    match filter.trim().strip_suffix('*') {
+        Some(prefix) => category.starts_with(prefix),
+        None => category == categories::category_code(filter),
+    }
+}
+
+// Post-filters applied to parsed results: a paper needs a category matching one of
+// `categories` and an author whose name contains one of `authors`, ignoring case and
+// diacritics. An empty filter lets every paper through
+pub fn passes_filters(paper: &Paper, categories: &[String], authors: &[String]) -> bool {
+    let category_ok = categories.is_empty()
+        || paper.categories.iter().any(|category| {
+            categories
+                .iter()
+                .any(|filter| category_matches(filter, category))
+        });
+    let authors: Vec<String> = authors
+        .iter()
+        .map(|author| fold_name(author.trim()))
+        .collect();
+    let author_ok = authors.is_empty()
+        || paper.authors.iter().any(|author| {
+            let name = fold_name(author);
+            authors.iter().any(|filter| name.contains(filter.as_str()))
+        });
+    category_ok && author_ok
+}
+
+// ARXIV_STRICT_ENTRIES makes an incomplete entry fail the whole parse instead of being skipped
+fn strict_entries_enabled() -> bool {
+    std::env::var("ARXIV_STRICT_ENTRIES")
+        .is_ok_and(|value| matches!(value.trim(), "1" | "true" | "yes"))
+}
+
+// ARXIV_STRICT_UTF8 makes invalid UTF-8 fail the parse instead of being replaced, to detect
+// corrupted feeds
+fn strict_utf8_enabled() -> bool {
+    std::env::var("ARXIV_STRICT_UTF8")
+        .is_ok_and(|value| matches!(value.trim(), "1" | "true" | "yes"))
+}
+
+// Every search is re-ranked when ENABLE_SEMANTIC_RERANK is set, not just those asking for it
+fn semantic_rerank_enabled() -> bool {
+    std::env::var("ENABLE_SEMANTIC_RERANK")
+        .is_ok_and(|value| matches!(value.trim(), "1" | "true" | "yes"))
+}
+
+// Re-ranks papers by meaning rather than keywords, remembering each paper's embedding
+pub struct SemanticReranker<M> {
+    model: M,
+    // Title + abstract embeddings keyed by unversioned arXiv id
+    embeddings: Mutex<HashMap<String, Vec<f64>>>,
+}
+
+impl<M: EmbeddingModel> SemanticReranker<M> {
+    pub fn new(model: M) -> Self {
+        Self {
+            model,
+            embeddings: Mutex::default(),
+        }
+    }
+
+    // Sort papers by cosine similarity between the query and each title + abstract
+    pub async fn semantic_rerank(
+        &self,
+        query: &str,
+        mut papers: Vec<Paper>,
+    ) -> Result<Vec<Paper>, ArxivError> {
+        let missing: Vec<(String, String)> = {
+            let cached = self.embeddings.lock().unwrap();
+            let mut missing = Vec::new();
+            for paper in &papers {
+                let id = base_arxiv_id(&paper.url);
+                if !cached.contains_key(id) && !missing.iter().any(|(seen, _)| seen == id) {
+                    let text = format!("{}\n{}", paper.title, paper.abstract_text);
+                    missing.push((id.to_string(), text));
+                }
+            }
+            missing
+        };
+
+        // The query always needs embedding, papers only the first time they're seen
+        let mut documents = vec![query.to_string()];
+        documents.extend(missing.iter().map(|(_, text)| text.clone()));
+        // One request per batch rather than one per paper
+        let mut embeddings = Vec::with_capacity(documents.len());
+        for batch in documents.chunks(M::MAX_DOCUMENTS) {
+            embeddings.extend(self.model.embed_documents(batch.to_vec()).await?);
+        }
+
+        let query_embedding = &embeddings[0].vec;
+        let mut cached = self.embeddings.lock().unwrap();
+        for ((id, _), embedding) in missing.into_iter().zip(&embeddings[1..]) {
+            cached.insert(id, embedding.vec.clone());
+        }
+        for paper in &mut papers {
+            paper.similarity = cached
+                .get(base_arxiv_id(&paper.url))
+                .map(|embedding| cosine_similarity(query_embedding, embedding) as f32);
+        }
+        drop(cached);
+        papers.sort_by(|a, b| {
+            b.similarity
+                .partial_cmp(&a.similarity)
+                .unwrap_or(Ordering::Equal)
+        });
+
+        Ok(papers)
+    }
+}
+
+// A title match counts for this many abstract matches
+const TITLE_TERM_WEIGHT: f32 = 2.0;
+
+// Score papers by query term frequency in their title and abstract, best matches first
+pub fn rank_papers(mut papers: Vec<Paper>, query: &str) -> Vec<Paper> {
+    let terms = tokenize(query);
+    for paper in &mut papers {
+        paper.score = Some(term_score(&terms, paper));
+    }
+    // Stable, so equally scored papers keep arXiv's order
+    papers.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+    papers
+}
+
+fn term_score(terms: &[String], paper: &Paper) -> f32 {
+    if terms.is_empty() {
+        return 0.0;
+    }
+    let title = tokenize(&paper.title);
+    let abstract_text = tokenize(&paper.abstract_text);
+    let count = |words: &[String], term: &String| words.iter().filter(|w| *w == term).count();
+    let total: f32 = terms
+        .iter()
+        .map(|term| {
+            let frequency = TITLE_TERM_WEIGHT * count(&title, term) as f32
+                + count(&abstract_text, term) as f32;
+            // Log scaling, so one term repeated many times doesn't drown out the others
+            frequency.ln_1p()
+        })
+        .sum();
+    total / terms.len() as f32
+}
+
+// Lowercase words with punctuation stripped
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+pub fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f64>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+// arXiv rejects requests for more than this many results at once
+const ARXIV_MAX_RESULTS: i32 = 2000;
+const DEFAULT_MAX_RESULTS: i32 = 5;
+
+// Missing or non-positive counts mean the default; anything above arXiv's cap is capped
+pub fn clamp_max_results(max_results: Option<i32>) -> i32 {
+    match max_results {
+        None => DEFAULT_MAX_RESULTS,
+        Some(n) if n <= 0 => {
+            tracing::warn!("max_results {} is not positive, using {}", n, DEFAULT_MAX_RESULTS);
+            DEFAULT_MAX_RESULTS
+        }
+        Some(n) if n > ARXIV_MAX_RESULTS => {
+            tracing::warn!(
+                "max_results {} exceeds arXiv's limit, using {}",
+                n,
+                ARXIV_MAX_RESULTS
+            );
+            ARXIV_MAX_RESULTS
+        }
+        Some(n) => n,
+    }
+}
+
+// Search arXiv directly, without an agent or API key: the same categories, post-filters and
+// sorting as the search_arxiv tool, only without embedding re-ranks
+pub async fn search_arxiv(args: SearchArgs) -> Result<Vec<Paper>, ArxivError> {
+    search_papers(source_from_env().as_ref(), None, args).await
+}
+
+// Everything a search does after the arguments are parsed, shared by search_arxiv and the tool
+pub async fn search_papers(
+    source: &dyn ArxivSource,
+    reranker: Option<&SemanticReranker<openai::EmbeddingModel>>,
+    args: SearchArgs,
+) -> Result<Vec<Paper>, ArxivError> {
+    let max_results = clamp_max_results(args.max_results);
+    let mut papers = filtered_search(source, reranker, &args, max_results).await?;
+    if let Some(sort) = args.sort {
+        // Without counts the papers just keep their order, so a failed lookup isn't fatal
+        if matches!(sort, SortKey::Citations) {
+            if let Err(e) = citations::enrich_papers(&mut papers).await {
+                tracing::warn!(error = %e, "could not look up citation counts");
+            }
+        }
+        sort_papers(&mut papers, sort);
+    }
+    Ok(papers)
+}
+
+async fn filtered_search(
+    source: &dyn ArxivSource,
+    reranker: Option<&SemanticReranker<openai::EmbeddingModel>>,
+    args: &SearchArgs,
+    max_results: i32,
+) -> Result<Vec<Paper>, ArxivError> {
+    let papers = ranked_search(source, reranker, args, max_results).await?;
+    if args.filter_categories.is_empty() && args.filter_authors.is_empty() {
+        return Ok(papers);
+    }
+
+    let filter = |papers: Vec<Paper>| -> Vec<Paper> {
+        papers
+            .into_iter()
+            .filter(|paper| passes_filters(paper, &args.filter_categories, &args.filter_authors))
+            .collect()
+    };
+    let papers = filter(papers);
+    if !papers.is_empty() {
+        return Ok(papers);
+    }
+    // Nothing on the first page passed, so look deeper once before giving up
+    tracing::debug!("post-filters removed every result, fetching more");
+    let deeper = (max_results * FILTER_OVERFETCH).min(ARXIV_MAX_RESULTS);
+    let mut papers = filter(ranked_search(source, reranker, args, deeper).await?);
+    if papers.is_empty() {
+        return Err(ArxivError::NoResults);
+    }
+    papers.truncate(max_results.max(0) as usize);
+    Ok(papers)
+}
+
+async fn ranked_search(
+    source: &dyn ArxivSource,
+    reranker: Option<&SemanticReranker<openai::EmbeddingModel>>,
+    args: &SearchArgs,
+    max_results: i32,
+) -> Result<Vec<Paper>, ArxivError> {
+    let search_query = with_since(
+        with_categories(format!("all:{}", args.query), &args.categories),
+        args.since.as_deref(),
+    )?;
+
+    match (reranker, args.rerank || semantic_rerank_enabled()) {
+        (Some(reranker), true) => {
+            let candidates = search_with(
+                source,
+                &search_query,
+                (max_results * RERANK_OVERFETCH).min(ARXIV_MAX_RESULTS),
+            )
+            .await?;
+            let mut papers = reranker.semantic_rerank(&args.query, candidates).await?;
+            papers.truncate(max_results.max(0) as usize);
+            Ok(papers)
+        }
+        _ => {
+            let papers = search_with(source, &search_query, max_results).await?;
+            Ok(if args.rank {
+                rank_papers(papers, &args.query)
+            } else {
+                papers
+            })
+        }
+    }
+}
+
+// Run a raw arXiv query against any source, shared by all the search tools
+pub async fn search_with(
+    source: &dyn ArxivSource,
+    search_query: &str,
+    max_results: i32,
+) -> Result<Vec<Paper>, ArxivError> {
+    let response = source.fetch(search_query, max_results).await?;
+    parse_arxiv_response(&response)
+}
+
+// Fetch a single paper through the id_list API
+pub async fn fetch_paper_by_id(source: &dyn ArxivSource, id: &str) -> Result<Paper, ArxivError> {
+    let response = source.fetch_by_id(id).await?;
+
+    // Unknown or malformed ids come back as an "Error" entry rather than an HTTP error
+    match parse_arxiv_response(&response) {
+        Ok(papers) => papers
+            .into_iter()
+            .find(|paper| paper.title != "Error" && !paper.url.contains("/api/errors"))
+            .ok_or_else(|| ArxivError::PaperNotFound(id.to_string())),
+        Err(ArxivError::NoResults) => Err(ArxivError::PaperNotFound(id.to_string())),
+        Err(e) => Err(e),
+    }
+}
+
+// Papers similar to a seed paper: same primary category, overlapping title terms
+pub async fn find_related(
+    source: &dyn ArxivSource,
+    id: &str,
+    max_results: i32,
+) -> Result<SearchSummary, ArxivError> {
+    let seed = fetch_paper_by_id(source, id).await?;
+
+    // Ask for one extra result since the seed usually matches its own query
+    let papers = search_with(source, &related_query(&seed), max_results + 1).await?;
+    let mut seen = HashSet::from([base_arxiv_id(&seed.url).to_string()]);
+    let papers = papers
+        .into_iter()
+        .filter(|paper| seen.insert(base_arxiv_id(&paper.url).to_string()))
+        .take(max_results.max(0) as usize)
+        .collect();
+
+    Ok(SearchSummary {
+        papers,
+        commentary: None,
+        related_to: Some(seed.title),
+    })
+}
+
+// Words too common in titles to say anything about the topic
+const TITLE_STOPWORDS: &[&str] = &[
+    "about", "based", "from", "into", "over", "that", "their", "this", "through", "toward",
+    "towards", "under", "using", "with", "without",
+];
+
+fn related_query(seed: &Paper) -> String {
+    let mut terms: Vec<String> = Vec::new();
+    for word in seed.title.split(|c: char| !c.is_alphanumeric()) {
+        let word = word.to_lowercase();
+        if word.len() > 3 && !TITLE_STOPWORDS.contains(&word.as_str()) && !terms.contains(&word) {
+            terms.push(word);
+        }
+    }
+    let terms = terms
+        .iter()
+        .take(5)
+        .map(|term| format!("ti:{}", term))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+
+    match (&seed.primary_category, terms.is_empty()) {
+        (Some(category), true) => format!("cat:{}", category),
+        (Some(category), false) => format!("cat:{} AND ({})", category, terms),
+        (None, _) => terms,
+    }
+}
+
+// Extract the arXiv identifier (e.g. 2310.06825v1) from an abstract or PDF URL
+pub fn arxiv_id_from_url(url: &str) -> &str {
+    let id = url
+        .split_once("/abs/")
+        .or_else(|| url.split_once("/pdf/"))
+        .map_or(url, |(_, id)| id);
+    id.trim_end_matches(".pdf")
+}
+
+// The identifier without its version suffix, so v1 and v2 count as the same paper
+pub fn base_arxiv_id(url: &str) -> &str {
+    let id = arxiv_id_from_url(url);
+    match id.rsplit_once('v') {
+        Some((base, version))
+            if !version.is_empty() && version.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            base
+        }
+        _ => id,
+    }
+}
+
+// The version a URL points at, e.g. 2 for 2310.06825v2
+pub fn arxiv_version(url: &str) -> Option<u32> {
+    let (_, version) = arxiv_id_from_url(url).rsplit_once('v')?;
+    version.parse().ok()
+}
+
+// New-style ids are 2310.06825 (four digits after the dot before 2015), old-style ones
+// hep-th/9901001, with the archive before the slash
+fn is_arxiv_identifier(id: &str) -> bool {
+    let digits = |s: &str, lengths: RangeInclusive<usize>| {
+        lengths.contains(&s.len()) && s.chars().all(|c| c.is_ascii_digit())
+    };
+    match id.split_once('/') {
+        Some((archive, number)) => {
+            !archive.is_empty()
+                && archive
+                    .chars()
+                    .all(|c| c.is_ascii_alphabetic() || matches!(c, '-' | '.'))
+                && digits(number, 7..=7)
+        }
+        None => id
+            .split_once('.')
+            .is_some_and(|(month, number)| digits(month, 4..=4) && digits(number, 4..=5)),
+    }
+}
+
+// Set each paper's arXiv id and version, and collapse versions of the same paper into the
+// newest one, at the rank the first of them had. Papers whose URL isn't an arXiv one are
+// kept as they are
+pub fn collapse_versions(papers: Vec<Paper>) -> Vec<Paper> {
+    let mut collapsed: Vec<Paper> = Vec::with_capacity(papers.len());
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for mut paper in papers {
+        let id = base_arxiv_id(&paper.url).to_string();
+        if !is_arxiv_identifier(&id) {
+            collapsed.push(paper);
+            continue;
+        }
+        paper.version = arxiv_version(&paper.url);
+        paper.arxiv_id = Some(id.clone());
+
+        let Some(&position) = positions.get(&id) else {
+            positions.insert(id, collapsed.len());
+            collapsed.push(paper);
+            continue;
+        };
+        let kept = &mut collapsed[position];
+        if paper.version > kept.version {
+            paper.superseded_versions = std::mem::take(&mut kept.superseded_versions);
+            paper.superseded_versions.extend(kept.version);
+            *kept = paper;
+        } else {
+            kept.superseded_versions.extend(paper.version);
+        }
+        // The same version found through two different links isn't an older one
+        let version = kept.version;
+        kept.superseded_versions.retain(|&v| Some(v) != version);
+        kept.superseded_versions.sort_unstable();
+        kept.superseded_versions.dedup();
+    }
+    collapsed
+}
+
+// Searches run at once by multi_search, few enough to stay friendly to arXiv
+const MULTI_SEARCH_CONCURRENCY: usize = 3;
+const MAX_MULTI_QUERIES: usize = 8;
+
+// Tool running several searches at once, for questions that span more than one topic
+pub struct MultiSearchTool {
+    source: Box<dyn ArxivSource>,
+}
+
+impl MultiSearchTool {
+    pub fn new(source: Box<dyn ArxivSource>) -> Self {
+        Self { source }
+    }
+}
+
+impl Default for MultiSearchTool {
+    fn default() -> Self {
+        Self::new(source_from_env())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MultiSearchArgs {
+    pub queries: Vec<String>,
+    pub max_results: Option<i32>,
+    // How many papers each query fetches, max_results if unset
+    #[serde(default)]
+    pub max_results_per_query: Option<i32>,
+}
+
+impl Tool for MultiSearchTool {
+    const NAME: &'static str = "multi_search";
+    type Error = ArxivError;
+    type Args = MultiSearchArgs;
+    type Output = Vec<Paper>;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: "multi_search".to_string(),
+            description: "Run several arXiv searches at once and merge their results, for \
+                          questions connecting more than one topic"
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "queries": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Targeted search queries, one per topic or angle (at most 8)"
+                    },
+                    "max_results": {
+                        "type": "integer",
+                        "description": "Maximum number of merged results to return, between 1 and 2000 (default: 5, or everything found when only max_results_per_query is given)"
+                    },
+                    "max_results_per_query": {
+                        "type": "integer",
+                        "description": "Results fetched for each query before merging, between 1 and 2000 (default: max_results)"
+                    }
+                },
+                "required": ["queries"]
+            }),
+        }
+    }
+
+    #[tracing::instrument(name = "multi_search", skip_all, fields(queries = args.queries.len()))]
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let max_results = clamp_max_results(args.max_results);
+        let per_query = args
+            .max_results_per_query
+            .map_or(max_results, |n| clamp_max_results(Some(n)));
+        // Asked only for a per-query count, the caller gets every paper the queries found
+        let limit = match (args.max_results, args.max_results_per_query) {
+            (None, Some(_)) => usize::MAX,
+            _ => max_results.max(0) as usize,
+        };
+        let queries: Vec<String> = args
+            .queries
+            .iter()
+            .map(|query| query.trim().to_string())
+            .filter(|query| !query.is_empty())
+            .take(MAX_MULTI_QUERIES)
+            .collect();
+
+        let turns = tokio::sync::Semaphore::new(MULTI_SEARCH_CONCURRENCY);
+        let searches = queries.iter().map(|query| {
+            let turns = &turns;
+            async move {
+                let _permit = turns.acquire().await;
+                let search_query = format!("all:{}", query);
+                let result = search_with(self.source.as_ref(), &search_query, per_query).await;
+                (query, result)
+            }
+        });
+
+        // A query that fails or finds nothing shouldn't sink the others
+        let mut results = Vec::new();
+        let mut last_error = None;
+        for (query, result) in futures::future::join_all(searches).await {
+            match result {
+                Ok(papers) => results.push((query.clone(), papers)),
+                Err(ArxivError::NoResults) => (),
+                Err(e) => {
+                    tracing::warn!(query = %query, error = %e, "one of the searches failed");
+                    last_error = Some(e);
+                }
+            }
+        }
+        if results.is_empty() {
+            return Err(last_error.unwrap_or(ArxivError::NoResults));
+        }
+
+        let mut merged = merge_results(results);
+        merged.truncate(limit);
+        Ok(merged)
+    }
+}
+
+// Papers from several searches, each once, best combined rank first. Every search a paper
+// appears in adds the reciprocal of its position there, so papers found by several queries,
+// or near the top of one, come first
+pub fn merge_results(results: Vec<(String, Vec<Paper>)>) -> Vec<Paper> {
+    let mut merged: Vec<(Paper, f32)> = Vec::new();
+    for (query, papers) in results {
+        for (position, paper) in dedup_papers(papers).into_iter().enumerate() {
+            let id = base_arxiv_id(&paper.url).to_string();
+            let score = rank_score(position);
+            match merged
+                .iter_mut()
+                .find(|(seen, _)| base_arxiv_id(&seen.url) == id)
+            {
+                Some((seen, total)) => {
+                    seen.found_by.push(query.clone());
+                    *total += score;
+                }
+                None => {
+                    let mut paper = paper;
+                    paper.found_by = vec![query.clone()];
+                    merged.push((paper, score));
+                }
+            }
+        }
+    }
+    // Stable, so ties keep the order the queries were given in
+    merged.sort_by(|a, b| b.1.total_cmp(&a.1));
+    merged.into_iter().map(|(paper, _)| paper).collect()
+}
+
+// The first of each paper by unversioned arXiv id, in order
+pub fn dedup_papers(papers: Vec<Paper>) -> Vec<Paper> {
+    let mut seen = HashSet::new();
+    papers
+        .into_iter()
+        .filter(|paper| seen.insert(base_arxiv_id(&paper.url).to_string()))
+        .collect()
+}
+
+// What a result at this position contributes to a paper's combined rank
+pub fn rank_score(position: usize) -> f32 {
+    1.0 / (position as f32 + 1.0)
+}
+
+// Saved papers mentioning the query's words, best matches first. An empty query matches
+// every paper, in the order they were saved
+pub fn match_saved_papers(papers: &[Paper], query: &str) -> Vec<Paper> {
+    if tokenize(query).is_empty() {
+        return papers.to_vec();
+    }
+    rank_papers(papers.to_vec(), query)
+        .into_iter()
+        .filter(|paper| paper.score.is_some_and(|score| score > 0.0))
+        .collect()
+}
+
+// Tool standing in for search_arxiv offline, answering from papers saved earlier
+pub struct SavedPapersTool {
+    papers: Vec<Paper>,
+}
+
+impl SavedPapersTool {
+    pub fn new(papers: Vec<Paper>) -> Self {
+        Self { papers }
+    }
+}
+
+impl Tool for SavedPapersTool {
+    const NAME: &'static str = "lookup_saved_papers";
+    type Error = ArxivError;
+    type Args = SearchArgs;
+    type Output = Vec<Paper>;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: "lookup_saved_papers".to_string(),
+            description: "Look up papers in the set the user saved earlier, without arXiv access"
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": "Words to look for in the titles and abstracts, empty for every saved paper"
+                    },
+                    "max_results": {
+                        "type": "integer",
+                        "description": "Maximum number of results to return (default: 5)"
+                    },
+                    "filter_categories": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Keep only papers listed in one of these categories, as codes (cs.LG), names, or prefixes like cs.* for a whole archive"
+                    },
+                    "filter_authors": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Keep only papers with an author whose name contains one of these, ignoring case and accents"
+                    },
+                    "sort": {
+                        "type": "string",
+                        "enum": ["published_desc", "published_asc", "title", "first_author", "citations"],
+                        "description": "Re-sort the results: by published date (undated papers last), title, first author's surname, or citation count (most cited first)"
+                    }
+                },
+                "required": ["query"]
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        // Nothing to narrow at the source here, so categories are just another post-filter
+        let categories: Vec<String> = args
+            .categories
+            .iter()
+            .chain(&args.filter_categories)
+            .cloned()
+            .collect();
+        let mut papers: Vec<Paper> = match_saved_papers(&self.papers, &args.query)
+            .into_iter()
+            .filter(|paper| passes_filters(paper, &categories, &args.filter_authors))
+            .collect();
+        if papers.is_empty() {
+            return Err(ArxivError::NoResults);
+        }
+        if let Some(sort) = args.sort {
+            sort_papers(&mut papers, sort);
+        }
+        papers.truncate(clamp_max_results(args.max_results) as usize);
+        Ok(papers)
+    }
+}
+
+// Tool to find papers related to one the user already has
+pub struct FindRelatedTool {
+    source: Box<dyn ArxivSource>,
+}
+
+impl FindRelatedTool {
+    pub fn new(source: Box<dyn ArxivSource>) -> Self {
+        Self { source }
+    }
+}
+
+impl Default for FindRelatedTool {
+    fn default() -> Self {
+        Self::new(source_from_env())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RelatedArgs {
+    pub id: String,
+    pub max_results: Option<i32>,
+}
+
+impl Tool for FindRelatedTool {
+    const NAME: &'static str = "find_related";
+    type Error = ArxivError;
+    type Args = RelatedArgs;
+    type Output = SearchSummary;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: "find_related".to_string(),
+            description: "Find papers related to a given arXiv paper".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "id": {
+                        "type": "string",
+                        "description": "arXiv id of the paper to find related work for"
+                    },
+                    "max_results": {
+                        "type": "integer",
+                        "description": "Maximum number of results to return, between 1 and 2000 (default: 5)"
+                    }
+                },
+                "required": ["id"]
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        find_related(self.source.as_ref(), &args.id, clamp_max_results(args.max_results)).await
+    }
+}
+
+// Tool to compare two papers by arXiv id
+pub struct ComparePapersTool {
+    client: ProviderClient,
+    model: String,
+    source: Box<dyn ArxivSource>,
+}
+
+impl ComparePapersTool {
+    pub fn new(client: ProviderClient, model: &str) -> Self {
+        Self {
+            client,
+            model: model.to_string(),
+            source: source_from_env(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CompareArgs {
+    pub first_id: String,
+    pub second_id: String,
+}
+
+// What the model contributes to a comparison
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+struct ComparisonAnalysis {
+    shared_topic: String,
+    differing_methods: Vec<String>,
+    differing_datasets: Vec<String>,
+}
+
+// Structured comparison of two papers
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PaperComparison {
+    pub first: Paper,
+    pub second: Paper,
+    pub shared_topic: String,
+    pub differing_methods: Vec<String>,
+    pub differing_datasets: Vec<String>,
+    // arXiv id of the more recent paper, when both have a published date
+    pub newer: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CompareError {
+    #[error(transparent)]
+    Arxiv(#[from] ArxivError),
+    #[error("Comparison failed: {0}")]
+    Extraction(#[from] ExtractionError),
+}
+
+impl Tool for ComparePapersTool {
+    const NAME: &'static str = "compare_papers";
+    type Error = CompareError;
+    type Args = CompareArgs;
+    type Output = PaperComparison;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: "compare_papers".to_string(),
+            description: "Compare two arXiv papers: shared topic, differing methods and datasets, and which is newer".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "first_id": {
+                        "type": "string",
+                        "description": "arXiv id of the first paper (e.g. 2310.06825)"
+                    },
+                    "second_id": {
+                        "type": "string",
+                        "description": "arXiv id of the second paper"
+                    }
+                },
+                "required": ["first_id", "second_id"]
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let first = fetch_paper_by_id(self.source.as_ref(), &args.first_id).await?;
+        let second = fetch_paper_by_id(self.source.as_ref(), &args.second_id).await?;
+
+        let text = format!(
+            "Paper A: {}\nAbstract: {}\n\nPaper B: {}\nAbstract: {}",
+            first.title, first.abstract_text, second.title, second.abstract_text
+        );
+        let analysis: ComparisonAnalysis = self
+            .client
+            .extract(&self.model, COMPARE_PREAMBLE, &text)
+            .await?;
+
+        // ISO 8601 dates compare correctly as strings
+        let newer = match (&first.published, &second.published) {
+            (Some(a), Some(b)) if a >= b => Some(args.first_id),
+            (Some(_), Some(_)) => Some(args.second_id),
+            _ => None,
+        };
+
+        Ok(PaperComparison {
+            first,
+            second,
+            shared_topic: analysis.shared_topic,
+            differing_methods: analysis.differing_methods,
+            differing_datasets: analysis.differing_datasets,
+            newer,
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DownloadError {
+    #[error("Provide either an arXiv id or a pdf_url")]
+    MissingTarget,
+    #[error("Only arxiv.org URLs can be downloaded, got {0:?}")]
+    ForeignUrl(String),
+    #[error("Invalid arXiv id: {0}")]
+    InvalidId(String),
+    #[error("{0:?} already exists, set overwrite to replace it")]
+    AlreadyExists(PathBuf),
+    #[error("PDF is larger than the {0} byte limit")]
+    TooLarge(u64),
+    #[error("Expected a PDF but got content type {0:?}")]
+    UnexpectedContentType(String),
+    #[error("Network error: {0}")]
+    Network(#[from] reqwest::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+// Where a downloaded PDF ended up
+#[derive(Debug, Serialize)]
+pub struct DownloadedPdf {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+// Tool to save a paper's PDF locally
+pub struct DownloadPdfTool {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl Default for DownloadPdfTool {
+    fn default() -> Self {
+        Self {
+            dir: download_dir(),
+            max_bytes: DEFAULT_MAX_PDF_BYTES,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DownloadArgs {
+    pub id: Option<String>,
+    pub pdf_url: Option<String>,
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+impl Tool for DownloadPdfTool {
+    const NAME: &'static str = "download_pdf";
+    type Error = DownloadError;
+    type Args = DownloadArgs;
+    type Output = DownloadedPdf;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: "download_pdf".to_string(),
+            description: "Download the PDF of an arXiv paper and return its local path".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "id": {
+                        "type": "string",
+                        "description": "arXiv id of the paper (e.g. 2310.06825)"
+                    },
+                    "pdf_url": {
+                        "type": "string",
+                        "description": "arxiv.org PDF URL, used when no id is given"
+                    },
+                    "overwrite": {
+                        "type": "boolean",
+                        "description": "Replace an existing download (default: false)"
+                    }
+                }
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let url = match (args.id, args.pdf_url) {
+            (Some(id), _) => format!("{}/{}", ARXIV_PDF_URL, id.trim()),
+            (None, Some(url)) => url,
+            (None, None) => return Err(DownloadError::MissingTarget),
+        };
+        download_pdf(&url, &self.dir, self.max_bytes, args.overwrite).await
+    }
+}
+
+// ARXIV_DOWNLOAD_DIR if set, otherwise ./papers
+pub fn download_dir() -> PathBuf {
+    std::env::var("ARXIV_DOWNLOAD_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(DEFAULT_DOWNLOAD_DIR))
+}
+
+// Stream an arXiv PDF into `dir`, named after its id
+pub async fn download_pdf(
+    url: &str,
+    dir: &Path,
+    max_bytes: u64,
+    overwrite: bool,
+) -> Result<DownloadedPdf, DownloadError> {
+    // Keep the tool from becoming a general purpose downloader
+    let parsed = reqwest::Url::parse(url).map_err(|_| DownloadError::ForeignUrl(url.to_string()))?;
+    let is_arxiv = parsed
+        .host_str()
+        .is_some_and(|host| host == "arxiv.org" || host.ends_with(".arxiv.org"));
+    if !is_arxiv || !matches!(parsed.scheme(), "http" | "https") {
+        return Err(DownloadError::ForeignUrl(url.to_string()));
+    }
+
+    // The id ends up in a file path, so never let it escape `dir`
+    let id = arxiv_id_from_url(parsed.path());
+    if !is_valid_arxiv_id(id) {
+        return Err(DownloadError::InvalidId(id.to_string()));
+    }
+    // Old-style ids contain a slash (hep-th/9901001)
+    let path = dir.join(format!("{}.pdf", id.replace('/', "_")));
+    if !overwrite && tokio::fs::try_exists(&path).await? {
+        return Err(DownloadError::AlreadyExists(path));
+    }
+
+    // Redirects (e.g. to the latest version) are followed by default
+    let client = reqwest::Client::builder()
+        .timeout(PDF_DOWNLOAD_TIMEOUT)
+        .user_agent(user_agent())
+        .build()?;
+    let mut response = client.get(parsed).send().await?.error_for_status()?;
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    if !content_type.starts_with("application/pdf") {
+        return Err(DownloadError::UnexpectedContentType(content_type));
+    }
+    if response.content_length().is_some_and(|length| length > max_bytes) {
+        return Err(DownloadError::TooLarge(max_bytes));
+    }
+
+    // Write to a temporary name so an aborted download never looks complete
+    tokio::fs::create_dir_all(dir).await?;
+    let partial = path.with_extension("pdf.part");
+    let mut file = tokio::fs::File::create(&partial).await?;
+    let mut bytes = 0;
+    while let Some(chunk) = response.chunk().await? {
+        bytes += chunk.len() as u64;
+        if bytes > max_bytes {
+            drop(file);
+            tokio::fs::remove_file(&partial).await?;
+            return Err(DownloadError::TooLarge(max_bytes));
+        }
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    tokio::fs::rename(&partial, &path).await?;
+
+    Ok(DownloadedPdf { path, bytes })
+}
+
+// arXiv ids look like 2310.06825v1 or hep-th/9901001
+pub fn is_valid_arxiv_id(id: &str) -> bool {
+    !id.is_empty()
+        && !id.starts_with('/')
+        && !id.contains("..")
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '/'))
+}
+
+// LaTeX commands with a direct Unicode equivalent
+const LATEX_SYMBOLS: &[(&str, &str)] = &[
+    ("alpha", "α"),
+    ("beta", "β"),
+    ("gamma", "γ"),
+    ("delta", "δ"),
+    ("epsilon", "ε"),
+    ("varepsilon", "ε"),
+    ("theta", "θ"),
+    ("lambda", "λ"),
+    ("mu", "μ"),
+    ("pi", "π"),
+    ("rho", "ρ"),
+    ("sigma", "σ"),
+    ("tau", "τ"),
+    ("phi", "φ"),
+    ("psi", "ψ"),
+    ("omega", "ω"),
+    ("Gamma", "Γ"),
+    ("Delta", "Δ"),
+    ("Theta", "Θ"),
+    ("Lambda", "Λ"),
+    ("Sigma", "Σ"),
+    ("Phi", "Φ"),
+    ("Omega", "Ω"),
+    ("to", "→"),
+    ("rightarrow", "→"),
+    ("leftarrow", "←"),
+    ("leq", "≤"),
+    ("geq", "≥"),
+    ("neq", "≠"),
+    ("approx", "≈"),
+    ("sim", "∼"),
+    ("times", "×"),
+    ("cdot", "·"),
+    ("pm", "±"),
+    ("infty", "∞"),
+    ("partial", "∂"),
+    ("nabla", "∇"),
+    ("sum", "∑"),
+    ("in", "∈"),
+];
+
+// Formatting commands whose argument is kept and the command dropped
+const LATEX_FORMATTING: &[&str] = &[
+    "textbf", "textit", "texttt", "emph", "text", "mathrm", "mathbf", "mathit", "mathsf",
+];
+
+const SUPERSCRIPTS: &[(char, char)] = &[
+    ('0', '⁰'),
+    ('1', '¹'),
+    ('2', '²'),
+    ('3', '³'),
+    ('4', '⁴'),
+    ('5', '⁵'),
+    ('6', '⁶'),
+    ('7', '⁷'),
+    ('8', '⁸'),
+    ('9', '⁹'),
+    ('+', '⁺'),
+    ('-', '⁻'),
+    ('n', 'ⁿ'),
+    ('i', 'ⁱ'),
+];
+
+const SUBSCRIPTS: &[(char, char)] = &[
+    ('0', '₀'),
+    ('1', '₁'),
+    ('2', '₂'),
+    ('3', '₃'),
+    ('4', '₄'),
+    ('5', '₅'),
+    ('6', '₆'),
+    ('7', '₇'),
+    ('8', '₈'),
+    ('9', '₉'),
+    ('+', '₊'),
+    ('-', '₋'),
+];
+
+// Render common LaTeX in titles and abstracts as Unicode for display, leaving anything
+// unrecognized as written
+pub fn normalize_latex(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut output = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '$' => i += 1,
+            '\\' => {
+                let start = i + 1;
+                let mut end = start;
+                while end < chars.len() && chars[end].is_ascii_alphabetic() {
+                    end += 1;
+                }
+                let command: String = chars[start..end].iter().collect();
+
+                if command.is_empty() {
+                    // Escaped punctuation such as \% or \&
+                    match chars.get(start) {
+                        Some(&c) if "%&_#$".contains(c) => output.push(c),
+                        Some(&c) => {
+                            output.push('\\');
+                            output.push(c);
+                        }
+                        None => output.push('\\'),
+                    }
+                    i = start + 1;
+                } else if let Some((_, symbol)) =
+                    LATEX_SYMBOLS.iter().find(|(name, _)| *name == command)
+                {
+                    output.push_str(symbol);
+                    i = end;
+                } else if LATEX_FORMATTING.contains(&command.as_str())
+                    && chars.get(end) == Some(&'{')
+                {
+                    match braced_group(&chars, end) {
+                        Some((inner, next)) => {
+                            output.push_str(&normalize_latex(&inner));
+                            i = next;
+                        }
+                        None => {
+                            output.push('\\');
+                            output.push_str(&command);
+                            i = end;
+                        }
+                    }
+                } else {
+                    output.push('\\');
+                    output.push_str(&command);
+                    i = end;
+                }
+            }
+            marker @ ('^' | '_') => {
+                let table = if marker == '^' { SUPERSCRIPTS } else { SUBSCRIPTS };
+                let (script, next) = match chars.get(i + 1) {
+                    Some('{') => braced_group(&chars, i + 1).unwrap_or_default(),
+                    Some(&c) => (c.to_string(), i + 2),
+                    None => (String::new(), i + 1),
+                };
+                let mapped: Option<String> = script
+                    .chars()
+                    .map(|c| table.iter().find(|(from, _)| *from == c).map(|(_, to)| *to))
+                    .collect();
+                match mapped {
+                    Some(mapped) if !script.is_empty() => {
+                        output.push_str(&mapped);
+                        i = next;
+                    }
+                    // No Unicode form for every character, keep the original
+                    _ => {
+                        output.push(marker);
+                        i += 1;
+                    }
+                }
+            }
+            c => {
+                output.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    output
+}
+
+// Contents of the `{...}` group opening at `open`, and the index just past it
+fn braced_group(chars: &[char], open: usize) -> Option<(String, usize)> {
+    let mut depth = 0;
+    for (offset, &c) in chars[open..].iter().enumerate() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    let inner = chars[open + 1..open + offset].iter().collect();
+                    return Some((inner, open + offset + 1));
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+// Parse JSON out of an agent response, tolerating markdown fences and surrounding prose
+pub fn parse_agent_json<T: serde::de::DeserializeOwned>(
+    response: &str,
+) -> Result<T, serde_json::Error> {
+    let response = response.trim();
+    if let Ok(value) = serde_json::from_str(response) {
+        return Ok(value);
+    }
+
+    let unfenced = strip_code_fence(response);
+    if let Ok(value) = serde_json::from_str(unfenced) {
+        return Ok(value);
+    }
+
+    if let Ok(array) = extract_json_array(unfenced) {
+        if let Ok(value) = serde_json::from_str(array) {
+            return Ok(value);
+        }
+    }
+
+    // Fall back to the outermost JSON object or array in the text
+    match (unfenced.find(['{', '[']), unfenced.rfind(['}', ']'])) {
+        (Some(start), Some(end)) if start < end => serde_json::from_str(&unfenced[start..=end]),
+        _ => serde_json::from_str(unfenced),
+    }
+}
+
+fn strip_code_fence(text: &str) -> &str {
+    let Some(start) = text.find("```") else {
+        return text;
+    };
+    let fenced = &text[start + 3..];
+    // Skip the language tag on the opening fence line
+    let fenced = fenced.split_once('\n').map_or(fenced, |(_, rest)| rest);
+    fenced
+        .find("```")
+        .map_or(fenced, |end| &fenced[..end])
+        .trim()
+}
+
+// The first JSON array in an agent response, skipping markdown fences and any prose around it
+pub fn extract_json_array(response: &str) -> Result<&str, ArxivError> {
+    let unfenced = strip_code_fence(response.trim());
+    for (start, _) in unfenced.match_indices('[') {
+        let Some(len) = bracketed_len(&unfenced[start..]) else {
+            continue;
+        };
+        let candidate = &unfenced[start..start + len];
+        if serde_json::from_str::<serde_json::Value>(candidate).is_ok() {
+            return Ok(candidate);
+        }
+    }
+    Err(ArxivError::AgentResponseNotJson(response.chars().take(200).collect()))
+}
+
+// Length of the bracketed block opening the text, ignoring brackets inside JSON strings
+fn bracketed_len(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+#[tracing::instrument(skip_all, fields(bytes = response.len()))]
+fn parse_arxiv_response(response: &[u8]) -> Result<Vec<Paper>, ArxivError> {
+    parse_arxiv_feed(response, strict_utf8_enabled(), strict_entries_enabled())
+}
+
+// From the bytes, so invalid UTF-8 in a title or abstract is replaced rather than failing the
+// whole feed, unless `strict_utf8` asks for that. Entries without a title or id are skipped,
+// or fail the parse with `strict_entries`
+fn parse_arxiv_feed(
+    response: &[u8],
+    strict_utf8: bool,
+    strict_entries: bool,
+) -> Result<Vec<Paper>, ArxivError> {
+    let mut reader = Reader::from_reader(response);
+    reader.trim_text(true);
+
+    let mut papers = Vec::new();
+    let mut current_paper: Option<Paper> = None;
+    let mut current_authors = Vec::new();
+    let mut current_categories = Vec::new();
+    let mut buf = Vec::new();
+    let mut in_entry = false;
+    let mut current_field = None;
+    // The entry's <id>, only checked for being there
+    let mut current_id = String::new();
+    let mut lossy_replacements = 0;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) => {
+                // Without its prefix, so `arxiv:doi` is `doi` whichever prefix the feed declared
+                match e.local_name().into_inner() {
+                    b"entry" => {
+                        in_entry = true;
+                        current_paper = Some(Paper {
+                            title: String::new(),
+                            authors: Vec::new(),
+                            abstract_text: String::new(),
+                            url: String::new(),
+                            categories: Vec::new(),
+                            primary_category: None,
+                            published: None,
+                            doi: None,
+                            similarity: None,
+                            score: None,
+                            found_by: Vec::new(),
+                            arxiv_id: None,
+                            version: None,
+                            superseded_versions: Vec::new(),
+                            citation_count: None,
+                            influential_citation_count: None,
+                            semantic_scholar_url: None,
+                            journal: None,
+                            publisher: None,
+                            published_print: None,
+                        });
+                        current_authors.clear();
+                        current_categories.clear();
+                        current_field = None;
+                        current_id.clear();
+                    }
+                    // The feed's own title (the query echo), id and updated date aren't paper data
+                    b"title" | b"id" | b"updated" if !in_entry => current_field = None,
+                    b"title" if in_entry => current_field = Some("title"),
+                    b"id" if in_entry => current_field = Some("id"),
+                    b"author" if in_entry => current_field = Some("author"),
+                    b"summary" if in_entry => current_field = Some("abstract"),
+                    b"link" if in_entry => current_field = Some("link"),
+                    b"category" if in_entry => current_field = Some("category"),
+                    b"published" if in_entry => current_field = Some("published"),
+                    b"doi" if in_entry => current_field = Some("doi"),
+                    _ => (),
+                }
+            }
+            Ok(Event::Text(e)) => {
+                // Text only counts inside an entry, whatever state an earlier event left
+                if let Some(paper) = current_paper.as_mut().filter(|_| in_entry) {
+                    let text = if strict_utf8 {
+                        str::from_utf8(e.as_ref())?.to_owned()
+                    } else {
+                        let text = String::from_utf8_lossy(e.as_ref());
+                        if let Cow::Owned(_) = text {
+                            lossy_replacements += 1;
+                        }
+                        text.into_owned()
+                    };
+                    match current_field {
+                        Some("title") => paper.title = text,
+                        Some("id") => current_id = text,
+                        Some("author") => current_authors.push(text),
+                        Some("abstract") => paper.abstract_text = text,
+                        Some("published") => paper.published = Some(text),
+                        Some("doi") => paper.doi = Some(text.trim().to_string()),
+                        _ => (),
+                    }
+                }
+            }
+            Ok(Event::Empty(ref e)) => {
+                if in_entry && e.local_name().into_inner() == b"link" {
+                    if let Some(paper) = current_paper.as_mut() {
+                        for attr in e.attributes().flatten() {
+                            if attr.key.as_ref() == b"href" {
+                                paper.url = str::from_utf8(&attr.value)?.to_owned();
+                            }
+                        }
+                    }
+                }
+                if in_entry && e.local_name().into_inner() == b"primary_category" {
+                    if let Some(paper) = current_paper.as_mut() {
+                        for attr in e.attributes().flatten() {
+                            if attr.key.as_ref() == b"term" {
+                                paper.primary_category =
+                                    Some(str::from_utf8(&attr.value)?.to_owned());
+                            }
+                        }
+                    }
+                }
+                if in_entry && e.local_name().into_inner() == b"category" {
+                    for attr in e.attributes().flatten() {
+                        if attr.key.as_ref() == b"term" {
+                            current_categories.push(str::from_utf8(&attr.value)?.to_owned());
+                        }
+                    }
+                }
+            }
+            Ok(Event::End(ref e)) => {
+                match e.local_name().into_inner() {
+                    b"entry" => {
+                        if let Some(mut paper) = current_paper.take() {
+                            // An entry without a title or id is junk rather than a paper
+                            let missing = if paper.title.trim().is_empty() {
+                                Some("title")
+                            } else if current_id.trim().is_empty() {
+                                Some("id")
+                            } else {
+                                None
+                            };
+                            match missing {
+                                Some(field) if strict_entries => {
+                                    return Err(ArxivError::MalformedEntry(field))
+                                }
+                                Some(field) => tracing::warn!(
+                                    field,
+                                    "skipping an arXiv entry that has no {}",
+                                    field
+                                ),
+                                None => {
+                                    paper.authors = dedup_authors(&current_authors);
+                                    paper.categories = current_categories.clone();
+                                    papers.push(paper);
+                                }
+                            }
+                        }
+                        in_entry = false;
+                        current_field = None;
+                    }
+                    b"title" | b"id" | b"author" | b"summary" | b"link" | b"category"
+                    | b"published" | b"doi" => {
+                        current_field = None;
+                    }
+                    _ => (),
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(ArxivError::XmlParsing(e)),
+            _ => (),
+        }
+    }
+
+    if lossy_replacements > 0 {
+        tracing::warn!(
+            "Replaced invalid UTF-8 in {} text fields of the arXiv response",
+            lossy_replacements
+        );
+    }
+
+    if papers.is_empty() {
+        return Err(ArxivError::NoResults);
+    }
+
+    tracing::debug!(entries = papers.len(), "parsed arXiv feed");
+    Ok(collapse_versions(papers))
+}
+
+// Authors with whitespace collapsed and repeats dropped, keeping the first spelling seen
+fn dedup_authors(authors: &[String]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    authors
+        .iter()
+        .map(|author| author.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|author| !author.is_empty() && seen.insert(author.to_lowercase()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An Atom feed around the given entries, as raw bytes so tests can break its encoding
+    fn feed(entries: &[u8]) -> Vec<u8> {
+        let mut feed = b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <feed xmlns=\"http://www.w3.org/2005/Atom\">"
+            .to_vec();
+        feed.extend_from_slice(entries);
+        feed.extend_from_slice(b"</feed>");
+        feed
+    }
+
+    #[test]
+    fn replaces_invalid_utf8_instead_of_dropping_the_feed() {
+        let feed = feed(
+            b"<entry><id>http://arxiv.org/abs/2401.00001v1</id>\
+              <title>Caf\xe9 Transformers</title>\
+              <summary>One stray \xff byte</summary>\
+              <link href=\"http://arxiv.org/abs/2401.00001v1\" rel=\"alternate\"/></entry>",
+        );
+
+        let papers = parse_arxiv_feed(&feed, false, false).unwrap();
+        assert_eq!(papers.len(), 1);
+        assert_eq!(papers[0].title, "Caf\u{FFFD} Transformers");
+        assert_eq!(papers[0].abstract_text, "One stray \u{FFFD} byte");
+
+        assert!(matches!(
+            parse_arxiv_feed(&feed, true, false),
+            Err(ArxivError::Utf8Error(_))
+        ));
+    }
+
+    #[test]
+    fn reads_the_primary_category_apart_from_the_others() {
+        let feed = feed(
+            b"<entry><id>http://arxiv.org/abs/2401.00002v1</id><title>Cross-listed</title>\
+              <link href=\"http://arxiv.org/abs/2401.00002v1\" rel=\"alternate\"/>\
+              <category term=\"cs.LG\"/>\
+              <arxiv:primary_category xmlns:arxiv=\"http://arxiv.org/schemas/atom\" \
+                term=\"stat.ML\"/>\
+              <category term=\"stat.ML\"/></entry>\
+              <entry><id>http://arxiv.org/abs/2401.00003v1</id><title>Uncategorised</title>\
+              <link href=\"http://arxiv.org/abs/2401.00003v1\" rel=\"alternate\"/></entry>",
+        );
+
+        let papers = parse_arxiv_feed(&feed, false, false).unwrap();
+        assert_eq!(papers[0].primary_category.as_deref(), Some("stat.ML"));
+        assert_eq!(papers[0].categories, ["cs.LG", "stat.ML"]);
+        assert_eq!(papers[1].primary_category, None);
+
+        let groups = group_by_primary_category(&papers);
+        let categories: Vec<_> = groups.iter().map(|group| group.category.as_str()).collect();
+        assert_eq!(categories, ["stat.ML", OTHER_CATEGORY]);
+        assert!(related_query(&papers[0]).starts_with("cat:stat.ML AND ("));
+    }
+
+    #[test]
+    fn parses_relative_submission_ages() {
+        let days = |n: u64| Duration::from_secs(n * 24 * 60 * 60);
+        assert_eq!(parse_since("7d").unwrap(), days(7));
+        assert_eq!(parse_since(" 2W ").unwrap(), days(14));
+        assert_eq!(parse_since("1m").unwrap(), days(30));
+        assert_eq!(parse_since("3y").unwrap(), days(3 * 365));
+
+        for since in [
+            "",
+            "d",
+            "7",
+            "0d",
+            "-1d",
+            "1.5w",
+            "7h",
+            "7 d",
+            "99999999999999999d",
+        ] {
+            assert!(
+                matches!(parse_since(since), Err(ArxivError::InvalidSince(_))),
+                "{:?} should be refused",
+                since
+            );
+        }
+    }
+
+    #[test]
+    fn restricts_queries_to_the_submission_window() {
+        assert_eq!(with_since("all:llm".to_string(), None).unwrap(), "all:llm");
+        let query = with_since("all:llm".to_string(), Some("2w")).unwrap();
+        assert!(
+            query.starts_with("(all:llm) AND submittedDate:["),
+            "{}",
+            query
+        );
+        // Ages reaching back before 1970 start at the epoch rather than failing
+        let query = with_since("all:llm".to_string(), Some("9999y")).unwrap();
+        assert!(query.contains("[197001010000 TO "), "{}", query);
+    }
+
+    #[test]
+    fn clamps_max_results_to_what_arxiv_accepts() {
+        assert_eq!(clamp_max_results(None), 5);
+        assert_eq!(clamp_max_results(Some(0)), 5);
+        assert_eq!(clamp_max_results(Some(-3)), 5);
+        assert_eq!(clamp_max_results(Some(5)), 5);
+        assert_eq!(clamp_max_results(Some(2000)), 2000);
+        assert_eq!(clamp_max_results(Some(3000)), 2000);
+    }
+
+    #[test]
+    fn renders_common_latex_as_unicode() {
+        assert_eq!(
+            normalize_latex(r"$\alpha$-Divergence Minimization"),
+            "α-Divergence Minimization"
+        );
+        assert_eq!(
+            normalize_latex(r"From $A \to B$ with $\infty$ data"),
+            "From A → B with ∞ data"
+        );
+        assert_eq!(
+            normalize_latex(r"Scaling $x^2$ and $x^{10}$, CO$_2$ and H$_{2}$O"),
+            "Scaling x² and x¹⁰, CO₂ and H₂O"
+        );
+        assert_eq!(
+            normalize_latex(r"\textbf{Bold} \emph{claims}, 50\% faster"),
+            "Bold claims, 50% faster"
+        );
+    }
+
+    #[test]
+    fn leaves_latex_it_cannot_render_as_written() {
+        assert_eq!(
+            normalize_latex(r"$\mathcal{A}$ in $O(n^{1/2})$"),
+            r"\mathcal{A} in O(n^{1/2})"
+        );
+        assert_eq!(normalize_latex(r"\unknown{x}"), r"\unknown{x}");
+        assert_eq!(normalize_latex("No LaTeX at all"), "No LaTeX at all");
+    }
+
+    #[test]
+    fn finds_the_json_array_in_an_agent_response() {
+        let array = r#"[{"title": "A [short] survey", "authors": ["Ada Lovelace"]}]"#;
+        let clean = array.to_string();
+        let fenced = format!("```json\n{}\n```", array);
+        let prefixed = format!(
+            "Here are the papers I found [2 of them]:\n\n{}\n\nLet me know if you need more.",
+            array
+        );
+        for response in [&clean, &fenced, &prefixed] {
+            assert_eq!(extract_json_array(response).unwrap(), array, "{}", response);
+            let papers: Vec<serde_json::Value> = parse_agent_json(response).unwrap();
+            assert_eq!(papers[0]["title"], "A [short] survey");
+        }
+
+        assert!(matches!(
+            extract_json_array("I could not find any papers [sorry]."),
+            Err(ArxivError::AgentResponseNotJson(_))
+        ));
+    }
+
+    #[test]
+    fn drops_repeated_and_blank_author_names() {
+        let authors = [
+            "  Ada   Lovelace ",
+            "Charles Babbage",
+            "ada lovelace",
+            "",
+            "Charles\nBabbage",
+        ]
+        .map(str::to_string);
+        assert_eq!(dedup_authors(&authors), ["Ada Lovelace", "Charles Babbage"]);
+    }
+
+    #[test]
+    fn reads_retry_after_as_seconds_or_a_date() {
+        use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+
+        let with = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+            headers
+        };
+        assert_eq!(retry_after(&with("120")), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(&with(" 5 ")), Some(Duration::from_secs(5)));
+
+        let later = SystemTime::now() + Duration::from_secs(3600);
+        let wait = retry_after(&with(&httpdate::fmt_http_date(later))).unwrap();
+        assert!(wait > Duration::from_secs(3500) && wait <= Duration::from_secs(3600));
+        assert_eq!(
+            retry_after(&with("Thu, 01 Jan 1970 00:00:00 GMT")),
+            Some(Duration::ZERO)
+        );
+
+        assert_eq!(retry_after(&with("soon")), None);
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+
+    fn titles(papers: &[Paper]) -> Vec<&str> {
+        papers.iter().map(|paper| paper.title.as_str()).collect()
+    }
+
+    #[test]
+    fn merges_searches_by_combined_rank() {
+        let results = vec![
+            (
+                "attention".to_string(),
+                vec![
+                    Paper {
+                        url: "http://arxiv.org/abs/2401.00001v1".to_string(),
+                        ..Paper::fixture("X")
+                    },
+                    Paper {
+                        url: "http://arxiv.org/abs/2401.00002v1".to_string(),
+                        ..Paper::fixture("Y")
+                    },
+                    Paper {
+                        url: "http://arxiv.org/abs/2401.00001v2".to_string(),
+                        ..Paper::fixture("X again")
+                    },
+                ],
+            ),
+            (
+                "memory".to_string(),
+                vec![
+                    Paper {
+                        url: "http://arxiv.org/abs/2401.00002v1".to_string(),
+                        ..Paper::fixture("Y")
+                    },
+                    Paper {
+                        url: "http://arxiv.org/abs/2401.00003v1".to_string(),
+                        ..Paper::fixture("Z")
+                    },
+                ],
+            ),
+        ];
+        // Y scores 1/2 + 1, X 1 and Z 1/2, and X's second version counts once
+        let merged = merge_results(results);
+        assert_eq!(titles(&merged), ["Y", "X", "Z"]);
+        assert_eq!(merged[0].found_by, ["attention", "memory"]);
+        assert_eq!(merged[1].found_by, ["attention"]);
+        assert_eq!(merged[2].found_by, ["memory"]);
+    }
+
+    #[test]
+    fn collapses_versions_into_the_newest_at_the_first_rank() {
+        let collapsed = collapse_versions(vec![
+            Paper {
+                url: "http://arxiv.org/abs/2401.00001v1".to_string(),
+                ..Paper::fixture("v1")
+            },
+            Paper {
+                url: "http://arxiv.org/abs/hep-th/9901001v3".to_string(),
+                ..Paper::fixture("old-style")
+            },
+            Paper {
+                url: "http://arxiv.org/abs/2401.00001v3".to_string(),
+                ..Paper::fixture("v3")
+            },
+            Paper {
+                url: "http://arxiv.org/abs/2401.00001v2".to_string(),
+                ..Paper::fixture("v2")
+            },
+            Paper {
+                url: "https://example.org/paper".to_string(),
+                ..Paper::fixture("elsewhere")
+            },
+            Paper {
+                url: "http://arxiv.org/pdf/hep-th/9901001v3.pdf".to_string(),
+                ..Paper::fixture("old-style pdf")
+            },
+        ]);
+        assert_eq!(titles(&collapsed), ["v3", "old-style", "elsewhere"]);
+
+        assert_eq!(collapsed[0].arxiv_id.as_deref(), Some("2401.00001"));
+        assert_eq!(collapsed[0].version, Some(3));
+        assert_eq!(collapsed[0].superseded_versions, [1, 2]);
+        // The same version through another link isn't an older one
+        assert_eq!(collapsed[1].arxiv_id.as_deref(), Some("hep-th/9901001"));
+        assert_eq!(collapsed[1].version, Some(3));
+        assert!(collapsed[1].superseded_versions.is_empty());
+        assert_eq!(collapsed[2].arxiv_id, None);
+        assert_eq!(collapsed[2].version, None);
+    }
+
+    #[test]
+    fn filters_by_category_and_by_author_ignoring_diacritics() {
+        let paper = Paper {
+            authors: vec!["Kurt Gödel".to_string(), "Paul Erdős".to_string()],
+            categories: vec!["math.LO".to_string(), "cs.LO".to_string()],
+            ..Paper::fixture("On formally undecidable propositions")
+        };
+        let passes = |categories: &[&str], authors: &[&str]| {
+            let to_strings =
+                |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            passes_filters(&paper, &to_strings(categories), &to_strings(authors))
+        };
+
+        assert!(passes(&[], &[]));
+        assert!(passes(&["math.*"], &[]));
+        assert!(passes(&["cs.LG", "cs.LO"], &[]));
+        assert!(!passes(&["cs.LG"], &[]));
+        assert!(passes(&[], &["godel"]));
+        assert!(passes(&[], &[" GÖDEL "]));
+        assert!(passes(&[], &["Erdos"]));
+        assert!(!passes(&[], &["Noether"]));
+        // Both filters have to pass
+        assert!(passes(&["math.LO"], &["erdős"]));
+        assert!(!passes(&["cs.LG"], &["godel"]));
+    }
+
+    fn sortable() -> Vec<Paper> {
+        let by = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        vec![
+            Paper {
+                authors: by(&["Zoë Ångström"]),
+                published: Some("2024-03-01".to_string()),
+                ..Paper::fixture("beta")
+            },
+            Paper {
+                authors: by(&["Ada Lovelace"]),
+                ..Paper::fixture("Alpha")
+            },
+            Paper {
+                published: Some("2023-01-01".to_string()),
+                ..Paper::fixture(" gamma")
+            },
+            Paper {
+                authors: by(&["Charles Babbage"]),
+                published: Some("2024-03-01".to_string()),
+                ..Paper::fixture("delta")
+            },
+        ]
+    }
+
+    #[test]
+    fn sorts_papers_stably_with_missing_values_last() {
+        let sorted = |key| {
+            let mut papers = sortable();
+            sort_papers(&mut papers, key);
+            papers
+        };
+        assert_eq!(
+            titles(&sorted(SortKey::PublishedDesc)),
+            ["beta", "delta", " gamma", "Alpha"]
+        );
+        assert_eq!(
+            titles(&sorted(SortKey::PublishedAsc)),
+            [" gamma", "beta", "delta", "Alpha"]
+        );
+        assert_eq!(
+            titles(&sorted(SortKey::Title)),
+            ["Alpha", "beta", "delta", " gamma"]
+        );
+        // By surname with diacritics folded, so Ångström sorts with the A's
+        assert_eq!(
+            titles(&sorted(SortKey::FirstAuthor)),
+            ["beta", "delta", "Alpha", " gamma"]
+        );
+    }
+
+    #[test]
+    fn sorts_by_citations_with_unenriched_papers_last() {
+        let mut papers = sortable();
+        papers[0].citation_count = Some(10);
+        papers[0].influential_citation_count = Some(1);
+        papers[2].citation_count = Some(10);
+        papers[2].influential_citation_count = Some(5);
+        papers[3].citation_count = Some(50);
+        sort_papers(&mut papers, SortKey::Citations);
+        // Ties on citations go to the more influential paper
+        assert_eq!(titles(&papers), ["delta", " gamma", "beta", "Alpha"]);
+    }
+
+    #[test]
+    fn matches_elements_whatever_their_namespace_prefix() {
+        let feed = feed(
+            b"<entry xmlns:ax=\"http://arxiv.org/schemas/atom\">\
+              <id>http://arxiv.org/abs/1207.7214v2</id><title>Prefixed</title>\
+              <link href=\"http://arxiv.org/abs/1207.7214v2\" rel=\"alternate\"/>\
+              <ax:primary_category term=\"hep-ex\"/><category term=\"hep-ex\"/>\
+              <ax:doi>10.1016/j.physletb.2012.08.020</ax:doi></entry>",
+        );
+        let papers = parse_arxiv_feed(&feed, false, false).unwrap();
+        assert_eq!(papers[0].primary_category.as_deref(), Some("hep-ex"));
+        assert_eq!(
+            papers[0].doi.as_deref(),
+            Some("10.1016/j.physletb.2012.08.020")
+        );
+    }
+
+    #[test]
+    fn cites_one_two_and_more_authors() {
+        let published = Some("2017-06-12T17:57:34Z");
+        let title = "Attention Is All You Need";
+        let source = "(arXiv:1706.03762). arXiv. https://doi.org/10.48550/arXiv.1706.03762";
+        let cases = [
+            (vec!["Ashish Vaswani"], "Vaswani, A.", "Vaswani, Ashish."),
+            (
+                vec!["Ashish Vaswani", "Noam Shazeer"],
+                "Vaswani, A., & Shazeer, N.",
+                "Vaswani, Ashish, and Noam Shazeer.",
+            ),
+            (
+                vec!["Ashish Vaswani", "Noam Shazeer", "Niki Parmar"],
+                "Vaswani, A., Shazeer, N., & Parmar, N.",
+                "Vaswani, Ashish, et al.",
+            ),
+        ];
+        for (authors, apa_authors, mla_authors) in cases {
+            let paper = Paper {
+                authors: authors.iter().map(|author| author.to_string()).collect(),
+                published: published.map(str::to_string),
+                url: "http://arxiv.org/abs/1706.03762v7".to_string(),
+                ..Paper::fixture(title)
+            };
+            assert_eq!(
+                paper.to_citation(CitationStyle::Apa),
+                format!("{} (2017). {} {}", apa_authors, title, source)
+            );
+            assert_eq!(
+                paper.to_citation(CitationStyle::Mla),
+                format!(
+                    "{} \"{}.\" arXiv, 12 June 2017, arXiv:1706.03762.",
+                    mla_authors, title
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn cites_papers_without_a_date() {
+        let paper = Paper {
+            authors: vec!["Ashish Vaswani".to_string()],
+            url: "http://arxiv.org/abs/1706.03762v7".to_string(),
+            ..Paper::fixture("Attention Is All You Need")
+        };
+        assert!(paper
+            .to_citation(CitationStyle::Apa)
+            .starts_with("Vaswani, A. (n.d.). Attention Is All You Need (arXiv:1706.03762)."));
+        assert_eq!(
+            paper.to_citation(CitationStyle::Mla),
+            "Vaswani, Ashish. \"Attention Is All You Need.\" arXiv, arXiv:1706.03762."
+        );
+    }
+
+    #[test]
+    fn keeps_the_punctuation_a_title_ends_with() {
+        let question = Paper {
+            authors: vec!["Ashish Vaswani".to_string()],
+            published: Some("2017-06-12T17:57:34Z".to_string()),
+            url: "http://arxiv.org/abs/1706.03762v7".to_string(),
+            ..Paper::fixture("Is Attention\n  All You Need?")
+        };
+        assert!(question
+            .to_citation(CitationStyle::Mla)
+            .contains("\"Is Attention All You Need?\" arXiv, "));
+        assert!(question
+            .to_citation(CitationStyle::Apa)
+            .contains("(2017). Is Attention All You Need? (arXiv:1706.03762)."));
+
+        let statement = Paper {
+            title: "Attention is all you need.".to_string(),
+            ..question
+        };
+        assert!(statement
+            .to_citation(CitationStyle::Mla)
+            .contains("\"Attention is all you need.\" arXiv, "));
+    }
+
+    fn entry(id: &str, title: &str) -> String {
+        format!(
+            "<entry><id>http://arxiv.org/abs/{id}</id><title>{title}</title>\
+             <link href=\"http://arxiv.org/abs/{id}\" rel=\"alternate\"/></entry>"
+        )
+    }
+
+    // Canned feeds for each search query and paper id, remembering what it was asked
+    #[derive(Default)]
+    struct StubSource {
+        searches: HashMap<String, Vec<u8>>,
+        ids: HashMap<String, Vec<u8>>,
+        // Shared, so tests can still read it once the source is boxed into a tool
+        requests: std::sync::Arc<Mutex<Vec<(String, i32)>>>,
+    }
+
+    impl StubSource {
+        fn search(mut self, search_query: &str, entries: &[String]) -> Self {
+            let feed = feed(entries.concat().as_bytes());
+            self.searches.insert(search_query.to_string(), feed);
+            self
+        }
+
+        fn paper(mut self, id: &str, entry: &str) -> Self {
+            self.ids.insert(id.to_string(), feed(entry.as_bytes()));
+            self
+        }
+
+        fn requests(&self) -> std::sync::Arc<Mutex<Vec<(String, i32)>>> {
+            self.requests.clone()
+        }
+    }
+
+    impl ArxivSource for StubSource {
+        fn fetch<'a>(&'a self, search_query: &'a str, max_results: i32) -> FetchFuture<'a> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push((search_query.to_string(), max_results));
+            // An unknown query finds nothing, like an empty arXiv feed
+            let response = self
+                .searches
+                .get(search_query)
+                .cloned()
+                .unwrap_or_else(|| feed(b""));
+            Box::pin(async move { Ok(response) })
+        }
+
+        fn fetch_by_id<'a>(&'a self, id: &'a str) -> FetchFuture<'a> {
+            let response = self.ids.get(id).cloned().unwrap_or_else(|| feed(b""));
+            Box::pin(async move { Ok(response) })
+        }
+    }
+
+    #[tokio::test]
+    async fn merges_the_papers_each_query_found() {
+        let source = StubSource::default()
+            .search(
+                "all:attention",
+                &[
+                    entry("1706.03762v7", "Transformer"),
+                    entry("2010.11929v2", "ViT"),
+                ],
+            )
+            .search(
+                "all:vision",
+                &[
+                    entry("2010.11929v1", "ViT"),
+                    entry("1512.03385v1", "ResNet"),
+                ],
+            );
+        let tool = MultiSearchTool::new(Box::new(source));
+
+        let papers = tool
+            .call(MultiSearchArgs {
+                queries: vec![
+                    "attention".to_string(),
+                    " ".to_string(),
+                    "vision".to_string(),
+                    "nothing".to_string(),
+                ],
+                max_results: None,
+                max_results_per_query: Some(2),
+            })
+            .await
+            .unwrap();
+
+        // Found by both queries, so first, and only once
+        assert_eq!(titles(&papers), ["ViT", "Transformer", "ResNet"]);
+        assert_eq!(papers[0].found_by, ["attention", "vision"]);
+        assert_eq!(papers[1].found_by, ["attention"]);
+    }
+
+    #[tokio::test]
+    async fn asks_each_query_for_its_own_count_and_caps_the_merge() {
+        let source = StubSource::default()
+            .search("all:attention", &[entry("1706.03762v7", "Transformer")])
+            .search("all:vision", &[entry("1512.03385v1", "ResNet")]);
+        let requests = source.requests();
+        let tool = MultiSearchTool::new(Box::new(source));
+        let args = |max_results, max_results_per_query| MultiSearchArgs {
+            queries: vec!["attention".to_string(), "vision".to_string()],
+            max_results,
+            max_results_per_query,
+        };
+
+        let papers = tool.call(args(Some(1), Some(4))).await.unwrap();
+        assert_eq!(papers.len(), 1);
+        let papers = tool.call(args(Some(2), None)).await.unwrap();
+        assert_eq!(papers.len(), 2);
+
+        let counts: Vec<i32> = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, count)| *count)
+            .collect();
+        assert_eq!(counts, [4, 4, 2, 2]);
+    }
+
+    #[test]
+    fn ranks_title_matches_above_abstract_only_ones() {
+        let papers = vec![
+            Paper {
+                abstract_text: "We replace recurrence with attention.".to_string(),
+                ..Paper::fixture("Sequence Models")
+            },
+            Paper::fixture("Unrelated"),
+            Paper {
+                abstract_text: "A new architecture.".to_string(),
+                ..Paper::fixture("Attention, Everywhere")
+            },
+        ];
+
+        // Case and punctuation don't matter
+        let ranked = rank_papers(papers, "ATTENTION!");
+        assert_eq!(
+            titles(&ranked),
+            ["Attention, Everywhere", "Sequence Models", "Unrelated"]
+        );
+        assert!(ranked[0].score > ranked[1].score);
+        assert_eq!(ranked[2].score, Some(0.0));
+
+        // Without terms nothing is scored higher, so arXiv's order stays
+        let unranked = rank_papers(ranked, " ");
+        assert_eq!(titles(&unranked)[0], "Attention, Everywhere");
+        assert!(unranked.iter().all(|paper| paper.score == Some(0.0)));
+    }
+
+    #[tokio::test]
+    async fn reranks_papers_by_similarity_to_the_query() {
+        let reranker = SemanticReranker::new(WordCountEmbedder(&["attention", "vision", "speech"]));
+        let paper = |id: &str, title: &str, abstract_text: &str| Paper {
+            url: format!("http://arxiv.org/abs/{}", id),
+            abstract_text: abstract_text.to_string(),
+            ..Paper::fixture(title)
+        };
+        let papers = vec![
+            paper("1706.03762v7", "Transformer", "Attention only"),
+            paper("2010.11929v2", "ViT", "Transformers for vision"),
+            paper(
+                "2212.04356v1",
+                "Whisper",
+                "Speech models that can see, vision too",
+            ),
+        ];
+
+        let reranked = reranker
+            .semantic_rerank("speech and vision", papers.clone())
+            .await
+            .unwrap();
+        assert_eq!(titles(&reranked), ["Whisper", "ViT", "Transformer"]);
+        assert!((reranked[0].similarity.unwrap() - 1.0).abs() < 1e-6);
+        assert_eq!(reranked[2].similarity, Some(0.0));
+
+        // Embeddings are remembered by arXiv id, so a paper isn't embedded again
+        let mut changed = papers;
+        changed[0].abstract_text = "Speech and vision".to_string();
+        let reranked = reranker
+            .semantic_rerank("attention", changed)
+            .await
+            .unwrap();
+        assert_eq!(titles(&reranked)[0], "Transformer");
+    }
+
+    #[test]
+    fn skips_entries_without_a_title_unless_strict() {
+        let feed = include_bytes!("../tests/fixtures/search_incomplete.xml");
+
+        let papers = parse_arxiv_feed(feed, false, false).unwrap();
+        assert_eq!(titles(&papers), ["Attention Is All You Need"]);
+
+        assert!(matches!(
+            parse_arxiv_feed(feed, false, true),
+            Err(ArxivError::MalformedEntry("title"))
+        ));
+    }
+
+    #[tokio::test]
+    async fn searches_a_fixture_file_instead_of_arxiv() {
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/search_transformers.xml");
+        let source = FixtureSource::new(&path);
+
+        // Whatever is asked, the fixture is the answer
+        let papers = search_with(&source, "all:anything", 1).await.unwrap();
+        assert_eq!(
+            titles(&papers),
+            [
+                "Attention Is All You Need",
+                "BERT: Pre-training of Deep Bidirectional Transformers for Language Understanding",
+                "Observation of a new particle in the search for the Standard Model Higgs boson \
+                 with the ATLAS detector at the LHC",
+            ]
+        );
+        assert_eq!(papers[0].authors[0], "Ashish Vaswani");
+        let paper = fetch_paper_by_id(&source, "1706.03762").await.unwrap();
+        assert_eq!(paper.title, "Attention Is All You Need");
+
+        let missing = FixtureSource::new(path.with_file_name("no_such_fixture.xml"));
+        assert!(matches!(
+            search_with(&missing, "all:anything", 1).await,
+            Err(ArxivError::Io(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn finds_papers_like_a_seed_without_the_seed_itself() {
+        let seed = "<entry><id>http://arxiv.org/abs/1706.03762v7</id>\
+            <title>Attention Is All You Need</title>\
+            <link href=\"http://arxiv.org/abs/1706.03762v7\" rel=\"alternate\"/>\
+            <arxiv:primary_category xmlns:arxiv=\"http://arxiv.org/schemas/atom\" term=\"cs.CL\"/>\
+            </entry>";
+        let source = StubSource::default().paper("1706.03762", seed).search(
+            "cat:cs.CL AND (ti:attention OR ti:need)",
+            &[
+                entry("1706.03762v5", "Attention Is All You Need"),
+                entry(
+                    "1409.0473v7",
+                    "Neural Machine Translation by Jointly Learning to Align",
+                ),
+                entry(
+                    "1508.04025v5",
+                    "Effective Approaches to Attention-based NMT",
+                ),
+                entry(
+                    "1703.03130v1",
+                    "A Structured Self-attentive Sentence Embedding",
+                ),
+            ],
+        );
+        let requests = source.requests();
+
+        let related = find_related(&source, "1706.03762", 2).await.unwrap();
+        assert_eq!(
+            related.related_to.as_deref(),
+            Some("Attention Is All You Need")
+        );
+        // Another version of the seed is still the seed
+        assert_eq!(
+            titles(&related.papers),
+            [
+                "Neural Machine Translation by Jointly Learning to Align",
+                "Effective Approaches to Attention-based NMT"
+            ]
+        );
+        // One extra asked for, to make up for the seed
+        assert_eq!(requests.lock().unwrap()[0].1, 3);
+
+        assert!(matches!(
+            find_related(&source, "2401.99999", 2).await,
+            Err(ArxivError::PaperNotFound(id)) if id == "2401.99999"
+        ));
+    }
+
+    // Held by tests pointing the arXiv client at a mock through the environment
+    static ARXIV_ENV: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    #[tokio::test]
+    async fn searches_arxiv_without_an_agent() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/query"))
+            .and(query_param(
+                "search_query",
+                "(all:transformers) AND (cat:cs.CL)",
+            ))
+            .and(query_param("max_results", "3"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                include_bytes!("../tests/fixtures/search_transformers.xml").as_slice(),
+                "application/atom+xml",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let _env = ARXIV_ENV.lock().await;
+        std::env::set_var("ARXIV_BASE_URL", format!("{}/api/query", server.uri()));
+
+        let args = SearchArgs {
+            query: "transformers".to_string(),
+            max_results: Some(3),
+            categories: vec!["Computation and Language".to_string()],
+            filter_categories: vec!["nlp".to_string()],
+            sort: Some(SortKey::PublishedAsc),
+            ..SearchArgs::default()
+        };
+        let papers = search_arxiv(args).await;
+        std::env::remove_var("ARXIV_BASE_URL");
+
+        // The post-filter drops the paper arXiv matched outside cs.CL
+        assert_eq!(
+            titles(&papers.unwrap()),
+            [
+                "Attention Is All You Need",
+                "BERT: Pre-training of Deep Bidirectional Transformers for Language Understanding",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn names_the_client_to_arxiv() {
+        use wiremock::matchers::{header, method};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        for agent in [DEFAULT_USER_AGENT, "survey-bot/1.0 (mailto:me@example.org)"] {
+            Mock::given(method("GET"))
+                .and(header("user-agent", agent))
+                .respond_with(ResponseTemplate::new(200).set_body_string(agent))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        let source = LiveSource::new(format!("{}/api/query", server.uri()));
+
+        let _env = ARXIV_ENV.lock().await;
+        std::env::remove_var("ARXIV_USER_AGENT");
+        let default = source.fetch("all:transformers", 1).await;
+        std::env::set_var("ARXIV_USER_AGENT", "survey-bot/1.0 (mailto:me@example.org)");
+        let overridden = source.fetch("all:transformers", 1).await;
+        std::env::remove_var("ARXIV_USER_AGENT");
+
+        // Each mock echoes the agent it matched, any other agent gets an empty 404
+        assert_eq!(default.unwrap(), DEFAULT_USER_AGENT.as_bytes());
+        assert_eq!(
+            overridden.unwrap(),
+            b"survey-bot/1.0 (mailto:me@example.org)"
+        );
+    }
+}
@@ -0,0 +1,165 @@
+// `arxiv-agent fulltext` against a mock ar5iv serving a saved rendering, from the HTTP request
+// through the HTML extraction to the text the CLI prints
+
+use serde_json::Value;
+use std::process::Output;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+mod common;
+use common::{fixture, stderr};
+
+fn html(body: String) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_raw(body, "text/html; charset=utf-8")
+}
+
+async fn attention_server() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/html/1706.03762"))
+        .respond_with(html(fixture("ar5iv_1706.03762.html")))
+        .mount(&server)
+        .await;
+    server
+}
+
+// Run `arxiv-agent fulltext` with its config kept in a directory of the test's own
+async fn run_fulltext(server: &MockServer, test: &str, args: &[&str]) -> Output {
+    let home = std::env::temp_dir().join(format!(
+        "arxiv-agent-fulltext-{}-{}",
+        test,
+        std::process::id()
+    ));
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_arxiv-agent"))
+        .arg("fulltext")
+        .args(args)
+        .env("AR5IV_BASE_URL", format!("{}/html", server.uri()))
+        .env("XDG_CONFIG_HOME", &home)
+        .env_remove("ARXIV_AGENT_CONFIG")
+        .output()
+        .await
+        .expect("could not run arxiv-agent");
+    let _ = std::fs::remove_dir_all(&home);
+    output
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "arxiv-agent failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[tokio::test]
+async fn extracts_the_paper_without_the_page_around_it() {
+    let server = attention_server().await;
+
+    let output = run_fulltext(&server, "whole", &["1706.03762v7", "--format", "table"]).await;
+    let text = stdout(&output);
+    assert!(text.starts_with("Attention Is All You Need\n\nAshish Vaswani"));
+    assert!(text.contains("\n\nAbstract\n\nThe dominant sequence transduction models"));
+    assert!(text.contains("\n\n7 Conclusion\n\nIn this work, we presented the Transformer"));
+    // Equations come through as their LaTeX source rather than the MathML spelled out
+    assert!(text.contains(r"\mathrm{Attention}(Q,K,V)=\mathrm{softmax}"));
+    assert!(text.contains("by more than 2.0 BLEU."));
+    assert!(!text.contains("Attention = softmax"));
+    assert!(text.contains("Figure 2: (left) Scaled Dot-Product Attention."));
+    // The ar5iv header and footer
+    assert!(!text.contains("Feeling"));
+    assert!(!text.contains("Generated by"));
+}
+
+#[tokio::test]
+async fn returns_a_section_with_its_subsections() {
+    let server = attention_server().await;
+
+    let output = run_fulltext(
+        &server,
+        "training",
+        &["1706.03762", "--section", "training"],
+    )
+    .await;
+    let text = stdout(&output);
+    assert!(text.starts_with("5 Training\n\nThis section describes the training regime"));
+    assert!(text.contains("5.1 Training Data and Batching\n\nWe trained on the standard WMT"));
+    assert!(!text.contains("6 Results"));
+
+    let output = run_fulltext(
+        &server,
+        "conclusion",
+        &["1706.03762", "--section", "Conclusion", "--format", "json"],
+    )
+    .await;
+    let fulltext: Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(fulltext["section"], "Conclusion");
+    assert_eq!(fulltext["truncated"], false);
+    let text = fulltext["text"].as_str().unwrap();
+    assert!(text.starts_with("7 Conclusion\n\n"));
+    assert!(!text.contains("References"));
+    assert_eq!(
+        fulltext["sections"],
+        serde_json::json!([
+            "Attention Is All You Need",
+            "Abstract",
+            "1 Introduction",
+            "3 Model Architecture",
+            "3.2 Attention",
+            "5 Training",
+            "5.1 Training Data and Batching",
+            "6 Results",
+            "7 Conclusion",
+            "References"
+        ])
+    );
+}
+
+#[tokio::test]
+async fn lists_the_sections_when_one_is_missing() {
+    let server = attention_server().await;
+
+    let output = run_fulltext(
+        &server,
+        "missing-section",
+        &["1706.03762", "--section", "Experiments"],
+    )
+    .await;
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = stderr(&output);
+    assert!(
+        stderr.contains("no section named \"Experiments\""),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("5 Training, 5.1 Training Data and Batching, 6 Results"));
+}
+
+#[tokio::test]
+async fn reports_papers_ar5iv_has_not_rendered() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/html/2401.00001"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    // What the redirect to the arXiv abstract page ends up serving
+    Mock::given(method("GET"))
+        .and(path("/html/2401.00002"))
+        .respond_with(html(
+            "<html><body><h1>Abstract page</h1><p>No article here</p></body></html>".to_string(),
+        ))
+        .mount(&server)
+        .await;
+
+    for id in ["2401.00001", "2401.00002"] {
+        let output = run_fulltext(&server, id, &[id]).await;
+        assert!(!output.status.success());
+        assert!(
+            stderr(&output).contains(&format!("ar5iv has no HTML rendering of {}", id)),
+            "{}",
+            stderr(&output)
+        );
+    }
+}
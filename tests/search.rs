@@ -0,0 +1,393 @@
+// End-to-end runs of `arxiv-agent --no-agent` against a mock arXiv API serving canned feeds,
+// from the HTTP request through the parser to the JSON the CLI prints
+
+use serde_json::Value;
+use std::process::Output;
+use wiremock::matchers::{header_regex, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+mod common;
+use common::{fixture, stderr};
+
+fn atom(name: &str) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_raw(fixture(name), "application/atom+xml")
+}
+
+async fn run_search(server: &MockServer, test: &str, query: &str) -> Output {
+    run_search_as(server, test, query, "json").await
+}
+
+// Search `query` through the CLI, with nothing from the user's environment leaking in and the
+// history and config kept in a directory of the test's own
+async fn run_search_as(server: &MockServer, test: &str, query: &str, format: &str) -> Output {
+    let home = std::env::temp_dir().join(format!("arxiv-agent-{}-{}", test, std::process::id()));
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_arxiv-agent"))
+        .args([
+            query,
+            "--no-agent",
+            "--format",
+            format,
+            "--max-results",
+            "3",
+        ])
+        .env("ARXIV_BASE_URL", format!("{}/api/query", server.uri()))
+        .env("ARXIV_HISTORY_PATH", home.join("history.json"))
+        .env("XDG_CONFIG_HOME", &home)
+        .env_remove("ARXIV_AGENT_CONFIG")
+        .env_remove("ARXIV_FIXTURE_PATH")
+        .env_remove("ARXIV_STRICT_ENTRIES")
+        .env_remove("ENABLE_SEMANTIC_RERANK")
+        .env_remove("PROVIDER")
+        .output()
+        .await
+        .expect("could not run arxiv-agent");
+    let _ = std::fs::remove_dir_all(&home);
+    output
+}
+
+fn papers(output: &Output) -> Vec<Value> {
+    assert!(
+        output.status.success(),
+        "arxiv-agent failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    serde_json::from_slice(&output.stdout).expect("stdout is not a JSON list of papers")
+}
+
+fn stdout_lines(output: &Output) -> Vec<String> {
+    assert!(
+        output.status.success(),
+        "arxiv-agent failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+#[tokio::test]
+async fn parses_every_entry_of_a_feed() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/query"))
+        .and(query_param("search_query", "all:transformers"))
+        .and(query_param("start", "0"))
+        .and(query_param("max_results", "3"))
+        .respond_with(atom("search_transformers.xml"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = run_search(&server, "feed", "transformers").await;
+    let papers = papers(&output);
+    assert_eq!(papers.len(), 3);
+
+    let attention = &papers[0];
+    assert_eq!(attention["title"], "Attention Is All You Need");
+    let authors = attention["authors"].as_array().unwrap();
+    assert_eq!(authors.len(), 8);
+    assert_eq!(authors[0], "Ashish Vaswani");
+    assert_eq!(authors[7], "Illia Polosukhin");
+    let abstract_text = attention["abstract_text"].as_str().unwrap();
+    assert!(abstract_text.starts_with("The dominant sequence transduction models"));
+    assert!(abstract_text.ends_with("convolutions\nentirely."));
+    assert_eq!(
+        attention["categories"],
+        serde_json::json!(["cs.CL", "cs.LG"])
+    );
+    assert_eq!(attention["published"], "2017-06-12T17:57:34Z");
+    assert_eq!(attention["arxiv_id"], "1706.03762");
+    assert_eq!(attention["version"], 7);
+    assert!(attention["doi"].is_null());
+
+    let bert = &papers[1];
+    assert_eq!(
+        bert["title"],
+        "BERT: Pre-training of Deep Bidirectional Transformers for Language Understanding"
+    );
+    assert_eq!(bert["authors"].as_array().unwrap().len(), 4);
+    assert_eq!(bert["version"], 2);
+
+    // arxiv:doi is namespaced, and the entry has a third link to the DOI before its own
+    let higgs = &papers[2];
+    assert_eq!(higgs["doi"], "10.1016/j.physletb.2012.08.020");
+    assert_eq!(
+        higgs["authors"],
+        serde_json::json!(["The ATLAS Collaboration"])
+    );
+    assert_eq!(higgs["categories"], serde_json::json!(["hep-ex"]));
+    assert!(!higgs["url"].as_str().unwrap().contains("doi.org"));
+    assert_eq!(higgs["arxiv_id"], "1207.7214");
+}
+
+#[tokio::test]
+async fn inflates_a_gzip_encoded_feed() {
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    let mut gzip = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gzip.write_all(fixture("search_transformers.xml").as_bytes())
+        .unwrap();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/query"))
+        .and(header_regex("accept-encoding", "gzip"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("content-encoding", "gzip")
+                .set_body_raw(gzip.finish().unwrap(), "application/atom+xml"),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = run_search(&server, "gzip", "transformers").await;
+    let papers = papers(&output);
+    assert_eq!(papers.len(), 3);
+    assert_eq!(papers[0]["title"], "Attention Is All You Need");
+    assert_eq!(papers[2]["doi"], "10.1016/j.physletb.2012.08.020");
+}
+
+#[tokio::test]
+async fn reports_an_empty_feed_as_no_results() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/query"))
+        .respond_with(atom("search_empty.xml"))
+        .mount(&server)
+        .await;
+
+    let output = run_search(&server, "empty", "qwxzvbnmkj").await;
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(
+        stderr(&output).contains("No results found"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[tokio::test]
+async fn fails_on_a_malformed_feed() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/query"))
+        .respond_with(atom("search_malformed.xml"))
+        .mount(&server)
+        .await;
+
+    let output = run_search(&server, "malformed", "transformers").await;
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("XML parsing error"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[tokio::test]
+async fn fails_when_arxiv_is_down() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/query"))
+        .respond_with(ResponseTemplate::new(503).set_body_string("Service Unavailable"))
+        .mount(&server)
+        .await;
+
+    let output = run_search(&server, "down", "transformers").await;
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    // Reported as arXiv's failure rather than as a feed that doesn't parse
+    assert!(
+        stderr(&output).contains("Network error"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(stderr(&output).contains("503"), "{}", stderr(&output));
+}
+
+#[tokio::test]
+async fn waits_out_a_rate_limit() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/query"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/query"))
+        .respond_with(atom("search_transformers.xml"))
+        .mount(&server)
+        .await;
+
+    let output = run_search(&server, "rate-limit", "transformers").await;
+    assert_eq!(papers(&output).len(), 3);
+    assert_eq!(server.received_requests().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn cites_papers_in_apa_and_mla() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/query"))
+        .respond_with(atom("search_transformers.xml"))
+        .mount(&server)
+        .await;
+
+    let apa = stdout_lines(&run_search_as(&server, "apa", "transformers", "apa").await);
+    assert_eq!(
+        apa,
+        [
+            "Vaswani, A., Shazeer, N., Parmar, N., Uszkoreit, J., Jones, L., Gomez, A. N., \
+             Kaiser, L., & Polosukhin, I. (2017). Attention Is All You Need (arXiv:1706.03762). \
+             arXiv. https://doi.org/10.48550/arXiv.1706.03762",
+            "Devlin, J., Chang, M.-W., Lee, K., & Toutanova, K. (2018). BERT: Pre-training of \
+             Deep Bidirectional Transformers for Language Understanding (arXiv:1810.04805). \
+             arXiv. https://doi.org/10.48550/arXiv.1810.04805",
+            "The ATLAS Collaboration. (2012). Observation of a new particle in the search for the \
+             Standard Model Higgs boson with the ATLAS detector at the LHC (arXiv:1207.7214). \
+             arXiv. https://doi.org/10.48550/arXiv.1207.7214",
+        ]
+    );
+
+    let mla = stdout_lines(&run_search_as(&server, "mla", "transformers", "mla").await);
+    assert_eq!(
+        mla,
+        [
+            "Vaswani, Ashish, et al. \"Attention Is All You Need.\" arXiv, 12 June 2017, \
+             arXiv:1706.03762.",
+            "Devlin, Jacob, et al. \"BERT: Pre-training of Deep Bidirectional Transformers for \
+             Language Understanding.\" arXiv, 11 Oct. 2018, arXiv:1810.04805.",
+            "The ATLAS Collaboration. \"Observation of a new particle in the search for the \
+             Standard Model Higgs boson with the ATLAS detector at the LHC.\" arXiv, 31 July \
+             2012, arXiv:1207.7214.",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn cuts_apa_author_lists_after_nineteen() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/query"))
+        .respond_with(atom("search_many_authors.xml"))
+        .mount(&server)
+        .await;
+
+    let apa = stdout_lines(&run_search_as(&server, "apa-authors", "llama", "apa").await);
+    assert_eq!(apa.len(), 1);
+    // The first 19 of 22 authors, then an ellipsis and the last
+    assert!(apa[0].starts_with("Touvron, H., Martin, L., Stone, K., Albert, P., "));
+    assert!(
+        apa[0].contains(", Fu, J., Fu, W., . . . Scialom, T. (2023). Llama 2: Open Foundation"),
+        "{}",
+        apa[0]
+    );
+    assert!(!apa[0].contains("Fuller"));
+    assert!(!apa[0].contains("Gao"));
+
+    let mla = stdout_lines(&run_search_as(&server, "mla-authors", "llama", "mla").await);
+    assert_eq!(
+        mla,
+        ["Touvron, Hugo, et al. \"Llama 2: Open Foundation and Fine-Tuned Chat Models.\" arXiv, \
+          18 July 2023, arXiv:2307.09288."]
+    );
+}
+
+// Run the CLI without any network: arXiv is the fixture and the agent is never asked
+async fn run_offline(home: &std::path::Path, args: &[&str]) -> Output {
+    tokio::process::Command::new(env!("CARGO_BIN_EXE_arxiv-agent"))
+        .args(args)
+        .env(
+            "ARXIV_FIXTURE_PATH",
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures/search_transformers.xml"),
+        )
+        // Nothing listens there, so any request that slips past the fixture fails
+        .env("ARXIV_BASE_URL", "http://127.0.0.1:1/api/query")
+        .env("ARXIV_HISTORY_PATH", home.join("history.json"))
+        .env("XDG_CONFIG_HOME", home)
+        .env_remove("ARXIV_AGENT_CONFIG")
+        .env_remove("ARXIV_STRICT_ENTRIES")
+        .env_remove("ENABLE_SEMANTIC_RERANK")
+        .env_remove("OPENAI_API_KEY")
+        .env_remove("PROVIDER")
+        .output()
+        .await
+        .expect("could not run arxiv-agent")
+}
+
+// The fixture feed lists Attention (2017), BERT (2018) and ATLAS (2012): sorting, the saved set
+// narrowed by a query, and each output format all have to line up for this to pass
+#[tokio::test]
+async fn sorts_formats_and_narrows_papers_offline() {
+    let home = std::env::temp_dir().join(format!("arxiv-agent-offline-{}", std::process::id()));
+    std::fs::create_dir_all(&home).unwrap();
+    let saved = home.join("saved.json");
+
+    let output = run_offline(
+        &home,
+        &[
+            "transformers",
+            "--no-agent",
+            "--sort",
+            "published-desc",
+            "--format",
+            "markdown",
+            "--output",
+            saved.to_str().unwrap(),
+        ],
+    )
+    .await;
+    let rows: Vec<String> = stdout_lines(&output)
+        .into_iter()
+        .filter(|line| line.starts_with("| ["))
+        .collect();
+    assert_eq!(rows.len(), 3, "{rows:?}");
+    assert!(rows[0].starts_with("| [BERT: Pre-training"), "{}", rows[0]);
+    assert!(
+        rows[1].starts_with("| [Attention Is All You Need]"),
+        "{}",
+        rows[1]
+    );
+    assert!(
+        rows[2].starts_with("| [Observation of a new particle"),
+        "{}",
+        rows[2]
+    );
+    let written: Vec<Value> = serde_json::from_slice(&std::fs::read(&saved).unwrap()).unwrap();
+    assert_eq!(written.len(), 3);
+
+    let output = run_offline(
+        &home,
+        &[
+            "encoder",
+            "--no-agent",
+            "--input",
+            saved.to_str().unwrap(),
+            "--sort",
+            "published-asc",
+            "--format",
+            "csv",
+        ],
+    )
+    .await;
+    let _ = std::fs::remove_dir_all(&home);
+    let lines = stdout_lines(&output);
+    assert!(lines[0].starts_with("title,authors,"), "{}", lines[0]);
+    let titles: Vec<&str> = lines[1..]
+        .iter()
+        .filter(|line| line.contains(",http://arxiv.org/pdf/"))
+        .map(|line| line.split(',').next().unwrap())
+        .collect();
+    assert_eq!(
+        titles,
+        [
+            "Attention Is All You Need",
+            "BERT: Pre-training of Deep Bidirectional Transformers for Language Understanding"
+        ]
+    );
+}